@@ -5,7 +5,7 @@
 //!
 //! The `Bitswap` struct implements the `NetworkBehaviour` trait. When used, it
 //! will allow providing and reciving IPFS blocks.
-use crate::block::Block;
+use crate::block::{is_identity, Block};
 use crate::ledger::{Ledger, Message, Priority};
 use crate::protocol::{BitswapConfig, MessageWrapper};
 use cid::Cid;
@@ -187,6 +187,11 @@ impl Bitswap {
     ///
     /// A user request
     pub fn want_block(&mut self, cid: Cid, priority: Priority) {
+        if is_identity(&cid) {
+            // the block is contained in the cid, so there is nothing to ask for
+            return;
+        }
+
         for (_peer_id, ledger) in self.connected_peers.iter_mut() {
             ledger.want_block(&cid, priority);
         }
@@ -267,6 +272,7 @@ impl NetworkBehaviour for Bitswap {
             .want()
             .iter()
             .filter(|&(cid, _)| !current_wantlist.iter().map(|(c, _)| c).any(|c| c == cid))
+            .filter(|&(cid, _)| !is_identity(cid))
         {
             ledger.received_want_list.insert(cid.to_owned(), *priority);
 
@@ -277,6 +283,11 @@ impl NetworkBehaviour for Bitswap {
 
         // Process the incoming blocks.
         for block in mem::take(&mut message.blocks) {
+            if is_identity(block.cid()) {
+                // never wanted, and would only be synthesized locally
+                continue;
+            }
+
             self.cancel_block(&block.cid());
 
             let event = BitswapEvent::ReceivedBlock(source.clone(), block);
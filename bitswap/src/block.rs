@@ -1,9 +1,11 @@
+use bytes::Bytes;
 use cid::Cid;
 
+/// A block and its `Cid`. The data is reference counted, so clones of the block share it.
 #[derive(Clone, Debug)]
 pub struct Block {
     pub cid: Cid,
-    pub data: Box<[u8]>,
+    pub data: Bytes,
 }
 
 impl PartialEq for Block {
@@ -14,7 +16,8 @@ impl PartialEq for Block {
 impl Eq for Block {}
 
 impl Block {
-    pub fn new(data: Box<[u8]>, cid: Cid) -> Self {
+    pub fn new(data: impl Into<Bytes>, cid: Cid) -> Self {
+        let data = data.into();
         Self { cid, data }
     }
 
@@ -26,7 +29,27 @@ impl Block {
         &self.data
     }
 
+    /// Returns the reference counted data of the block.
+    pub fn into_bytes(self) -> Bytes {
+        self.data
+    }
+
     pub fn into_vec(self) -> Vec<u8> {
-        self.data.into()
+        self.data.to_vec()
+    }
+
+    /// Returns the block of a `Cid` using the identity multihash, which carries the data of the
+    /// block as the digest. Such blocks never need to be stored or fetched.
+    pub fn from_identity(cid: &Cid) -> Option<Self> {
+        if is_identity(cid) {
+            Some(Block::new(cid.hash().digest().to_vec(), cid.to_owned()))
+        } else {
+            None
+        }
     }
 }
+
+/// Returns true if the `Cid` uses the identity multihash and so carries its block inline.
+pub fn is_identity(cid: &Cid) -> bool {
+    cid.hash().algorithm() == multihash::Code::Identity
+}
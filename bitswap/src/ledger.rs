@@ -4,6 +4,7 @@ use crate::error::BitswapError;
 use crate::prefix::Prefix;
 use cid::Cid;
 use core::convert::TryFrom;
+use prost::encoding::{encode_key, encode_varint, encoded_len_varint, key_len, WireType};
 use prost::Message as ProstMessage;
 use std::{
     collections::{HashMap, HashSet},
@@ -142,23 +143,67 @@ impl Into<Vec<u8>> for &Message {
             entry.cancel = true;
             wantlist.entries.push(entry);
         }
-        for block in self.blocks() {
-            let mut payload = bitswap_pb::message::Block::default();
-            payload.prefix = Prefix::from(block.cid()).to_bytes();
-            payload.data = block.data().to_vec();
-            proto.payload.push(payload);
-        }
         if !wantlist.entries.is_empty() {
             proto.wantlist = Some(wantlist);
         }
-        let mut res = Vec::with_capacity(proto.encoded_len());
+
+        // the payloads are encoded by hand, as the generated message would need the data of
+        // every block copied into it before encoding
+        let payloads = self
+            .blocks()
+            .iter()
+            .map(|block| (Prefix::from(block.cid()).to_bytes(), block.data()))
+            .collect::<Vec<_>>();
+
+        let payloads_len = payloads
+            .iter()
+            .map(|(prefix, data)| {
+                let len = payload_len(prefix, data);
+                key_len(PAYLOAD_TAG) + encoded_len_varint(len as u64) + len
+            })
+            .sum::<usize>();
+
+        let mut res = Vec::with_capacity(proto.encoded_len() + payloads_len);
         proto
             .encode(&mut res)
             .expect("there is no situation in which the protobuf message can be invalid");
+
+        for (prefix, data) in payloads {
+            encode_key(PAYLOAD_TAG, WireType::LengthDelimited, &mut res);
+            encode_varint(payload_len(&prefix, data) as u64, &mut res);
+            encode_bytes_field(1, &prefix, &mut res);
+            encode_bytes_field(2, data, &mut res);
+        }
+
         res
     }
 }
 
+/// The field number of `bitswap_pb::Message::payload`.
+const PAYLOAD_TAG: u32 = 3;
+
+/// Returns the encoded length of `bitswap_pb::message::Block` with the given fields.
+fn payload_len(prefix: &[u8], data: &[u8]) -> usize {
+    bytes_field_len(1, prefix) + bytes_field_len(2, data)
+}
+
+fn bytes_field_len(tag: u32, bytes: &[u8]) -> usize {
+    if bytes.is_empty() {
+        0
+    } else {
+        key_len(tag) + encoded_len_varint(bytes.len() as u64) + bytes.len()
+    }
+}
+
+/// Encodes a proto3 `bytes` field like the generated code, which leaves out the empty fields.
+fn encode_bytes_field(tag: u32, bytes: &[u8], buf: &mut Vec<u8>) {
+    if !bytes.is_empty() {
+        encode_key(tag, WireType::LengthDelimited, buf);
+        encode_varint(bytes.len() as u64, buf);
+        buf.extend_from_slice(bytes);
+    }
+}
+
 impl Message {
     /// Turns this `Message` into a message that can be sent to a substream.
     pub fn to_bytes(&self) -> Vec<u8> {
@@ -195,7 +240,7 @@ impl TryFrom<&[u8]> for Message {
             let cid = prefix.to_cid(&payload.data)?;
             let block = Block {
                 cid,
-                data: payload.data.into_boxed_slice(),
+                data: payload.data.into(),
             };
             message.add_block(block);
         }
@@ -238,3 +283,54 @@ impl std::fmt::Debug for Message {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Message, Prefix};
+    use crate::bitswap_pb;
+    use crate::block::Block;
+    use cid::{Cid, Codec};
+    use prost::Message as ProstMessage;
+
+    #[test]
+    fn payloads_are_encoded_like_the_generated_code() {
+        let data = b"hello block".to_vec();
+        let cid = Cid::new_v1(Codec::Raw, multihash::Sha2_256::digest(&data));
+        let empty = Cid::new_v1(Codec::Raw, multihash::Sha2_256::digest(&[]));
+
+        let mut message = Message::default();
+        message.want_block(&empty, 1);
+        message.add_block(Block::new(data.clone(), cid.clone()));
+        message.add_block(Block::new(Vec::new(), empty.clone()));
+
+        let entry = bitswap_pb::message::wantlist::Entry {
+            block: empty.to_bytes(),
+            priority: 1,
+            ..Default::default()
+        };
+
+        let payload = vec![(cid, data), (empty, Vec::new())]
+            .into_iter()
+            .map(|(cid, data)| bitswap_pb::message::Block {
+                prefix: Prefix::from(&cid).to_bytes(),
+                data,
+            })
+            .collect();
+
+        let proto = bitswap_pb::Message {
+            wantlist: Some(bitswap_pb::message::Wantlist {
+                entries: vec![entry],
+                ..Default::default()
+            }),
+            payload,
+            ..Default::default()
+        };
+
+        let mut expected = Vec::new();
+        proto.encode(&mut expected).unwrap();
+
+        let encoded = message.to_bytes();
+        assert_eq!(encoded, expected);
+        assert_eq!(Message::from_bytes(&encoded).unwrap(), message);
+    }
+}
@@ -10,7 +10,7 @@ mod prefix;
 mod protocol;
 
 pub use self::behaviour::{Bitswap, BitswapEvent, Stats};
-pub use self::block::Block;
+pub use self::block::{is_identity, Block};
 pub use self::error::BitswapError;
 pub use self::ledger::Priority;
 
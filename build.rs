@@ -1,7 +1,3 @@
 fn main() {
-    prost_build::compile_protos(
-        &["src/ipns/ipns_pb.proto", "src/ipld/dag_pb.proto"],
-        &["src"],
-    )
-    .unwrap();
+    prost_build::compile_protos(&["src/ipns/ipns_pb.proto"], &["src"]).unwrap();
 }
@@ -21,7 +21,7 @@ async fn main() {
         UninitializedIpfs::default().await.start().await.unwrap();
     task::spawn(fut);
 
-    let data = b"block-want\n".to_vec().into_boxed_slice();
+    let data = b"block-want\n".to_vec();
     let wanted = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
 
     let (public_key, addresses) = ipfs.identity().await.unwrap();
@@ -41,7 +41,7 @@ async fn main() {
     eprintln!();
 
     // Create a Block
-    let data = b"block-provide\n".to_vec().into_boxed_slice();
+    let data = b"block-provide\n".to_vec();
     let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
     let provided = ipfs.put_block(Block::new(data, cid)).await.unwrap();
 
@@ -5,12 +5,15 @@ pub mod bitswap;
 pub mod block;
 pub mod dag;
 pub mod dht;
+pub mod files;
 pub mod id;
+pub mod object;
 pub mod pin;
 pub mod pubsub;
 pub mod refs;
 pub mod root_files;
 pub mod swarm;
+pub mod tar;
 pub mod version;
 
 pub mod support;
@@ -88,6 +91,7 @@ pub fn routes<T: IpfsTypes>(
         and_boxed!(warp::path!("add"), root_files::add(ipfs)),
         and_boxed!(warp::path!("cat"), root_files::cat(ipfs)),
         and_boxed!(warp::path!("get"), root_files::get(ipfs)),
+        and_boxed!(warp::path!("ls"), root_files::ls(ipfs)),
         and_boxed!(warp::path!("refs" / "local"), refs::local(ipfs)),
         and_boxed!(warp::path!("refs"), refs::refs(ipfs)),
         warp::path!("version")
@@ -104,6 +108,7 @@ pub fn routes<T: IpfsTypes>(
             and_boxed!(warp::path!("stat"), block::stat(ipfs)),
         )),
         warp::path("dag").and(combine!(
+            and_boxed!(warp::path!("diff"), dag::diff(ipfs)),
             and_boxed!(warp::path!("put"), dag::put(ipfs)),
             and_boxed!(warp::path!("resolve"), dag::resolve(ipfs)),
         )),
@@ -129,6 +134,33 @@ pub fn routes<T: IpfsTypes>(
             and_boxed!(warp::path!("disconnect"), swarm::disconnect(ipfs)),
             and_boxed!(warp::path!("peers"), swarm::peers(ipfs)),
         )),
+        warp::path("files").and(combine!(
+            and_boxed!(warp::path!("cp"), files::cp(ipfs)),
+            and_boxed!(warp::path!("flush"), files::flush(ipfs)),
+            and_boxed!(warp::path!("ls"), files::ls(ipfs)),
+            and_boxed!(warp::path!("mkdir"), files::mkdir(ipfs)),
+            and_boxed!(warp::path!("mv"), files::mv(ipfs)),
+            and_boxed!(warp::path!("read"), files::read(ipfs)),
+            and_boxed!(warp::path!("rm"), files::rm(ipfs)),
+            and_boxed!(warp::path!("stat"), files::stat(ipfs)),
+            and_boxed!(warp::path!("write"), files::write(ipfs)),
+        )),
+        warp::path("object").and(combine!(
+            and_boxed!(warp::path!("get"), object::get(ipfs)),
+            and_boxed!(warp::path!("links"), object::links(ipfs)),
+            and_boxed!(warp::path!("new"), object::new(ipfs)),
+            and_boxed!(warp::path!("put"), object::put(ipfs)),
+            warp::path("patch").and(combine!(
+                and_boxed!(warp::path!("add-link"), object::add_link(ipfs)),
+                and_boxed!(warp::path!("append-data"), object::append_data(ipfs)),
+                and_boxed!(warp::path!("rm-link"), object::rm_link(ipfs)),
+                and_boxed!(warp::path!("set-data"), object::set_data(ipfs)),
+            )),
+        )),
+        warp::path("tar").and(combine!(
+            and_boxed!(warp::path!("add"), tar::add(ipfs)),
+            and_boxed!(warp::path!("cat"), tar::cat(ipfs)),
+        )),
         warp::path("pin").and(combine!(
             and_boxed!(warp::path!("add"), pin::add(ipfs)),
             and_boxed!(warp::path!("ls"), pin::list(ipfs)),
@@ -141,7 +173,6 @@ pub fn routes<T: IpfsTypes>(
             warp::path!("dht" / "put"),
             warp::path!("key" / ..),
             warp::path!("name" / ..),
-            warp::path!("object" / ..),
             warp::path!("ping" / ..),
             warp::path!("repo" / ..),
             warp::path!("stats" / ..),
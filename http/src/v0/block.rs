@@ -1,6 +1,6 @@
 use crate::v0::support::{
-    try_only_named_multipart, with_ipfs, HandledErr, MaybeTimeoutExt, StreamResponse, StringError,
-    StringSerialized,
+    multihash_code_by_name, try_only_named_multipart, with_ipfs, HandledErr, MaybeTimeoutExt,
+    StreamResponse, StringError, StringSerialized,
 };
 use bytes::Buf;
 use cid::{Cid, Codec, Version};
@@ -9,7 +9,6 @@ use ipfs::error::Error;
 use ipfs::{Ipfs, IpfsTypes};
 use mime::Mime;
 
-use multihash::Multihash;
 use serde::{Deserialize, Serialize};
 use std::convert::TryFrom;
 use warp::{http::Response, query, reply, Filter, Rejection, Reply};
@@ -68,12 +67,9 @@ impl PutQuery {
         })
     }
 
-    fn digest(&self) -> Result<fn(&'_ [u8]) -> Multihash, Rejection> {
-        Ok(match self.mhtype.as_deref().unwrap_or("sha2-256") {
-            "sha2-256" => multihash::Sha2_256::digest,
-            "sha2-512" => multihash::Sha2_512::digest,
-            _ => return Err(StringError::from("unknown hash").into()),
-        })
+    fn digest(&self) -> Result<multihash::Code, Rejection> {
+        multihash_code_by_name(self.mhtype.as_deref().unwrap_or("sha2-256"))
+            .ok_or_else(|| StringError::from("unknown hash").into())
     }
 
     fn version(&self) -> Result<Version, Rejection> {
@@ -112,7 +108,7 @@ async fn inner_put<T: IpfsTypes>(
 
     // FIXME: digest calculation should be done in line with the reception of new blocks, but
     // because of the old multihash version we use, we don't at least yet have access to that api.
-    let digest = opts.digest()?(&data);
+    let digest = opts.digest()?.digest(&data);
 
     // cid generation can fail if we try some other hash or format with cidv0 which only supports
     // SHA2-256 and dag-pb, both are even implicit. could be that these parameters we use here are
@@ -120,14 +116,13 @@ async fn inner_put<T: IpfsTypes>(
     // Haven't researched this deeply.
     let cid = Cid::new(opts.version()?, opts.format()?, digest).map_err(StringError::from)?;
 
-    // bad thing about Box<[u8]>: converting to it forces a reallocation; do it now that we know
-    // the cid was generated successfully.
-    let data = data.into_boxed_slice();
+    // converting the Vec into Bytes does not reallocate
+    let data = bytes::Bytes::from(data);
 
     let size = data.len();
     let key = cid.to_string();
 
-    let block = ipfs::Block { cid, data };
+    let block = ipfs::Block::new(data, cid);
 
     ipfs.put_block(block).await.map_err(StringError::from)?;
 
@@ -1,14 +1,18 @@
+use crate::v0::support::option_parsing::ParseError;
 use crate::v0::support::{
-    try_only_named_multipart, with_ipfs, MaybeTimeoutExt, NotImplemented, StringError,
-    StringSerialized,
+    multihash_code_by_name, try_only_named_multipart, with_ipfs, HandledErr, MaybeTimeoutExt,
+    NotImplemented, StreamResponse, StringError, StringSerialized,
 };
 use cid::{Cid, Codec};
-use futures::stream::Stream;
+use futures::stream::{Stream, StreamExt};
+use ipfs::ipld::{dag_json::DagJsonCodec, Ipld};
+use ipfs::path::SlashedPath;
 use ipfs::{Ipfs, IpfsTypes};
 use mime::Mime;
 
 use serde::Deserialize;
 use serde_json::json;
+use std::convert::TryFrom;
 use warp::{query, reply, Buf, Filter, Rejection, Reply};
 
 #[derive(Debug, Deserialize)]
@@ -17,6 +21,14 @@ pub struct PutQuery {
     hash: Option<String>,
     #[serde(rename = "input-enc", default)]
     encoding: InputEncoding,
+    #[serde(default)]
+    pin: bool,
+    /// When true, blocks of at most `inline-limit` bytes are inlined into their Cids.
+    #[serde(default)]
+    inline: bool,
+    /// Defaults to 32 like `add` in go-ipfs.
+    #[serde(rename = "inline-limit")]
+    inline_limit: Option<usize>,
 }
 
 #[derive(PartialEq, Eq, Debug, Deserialize)]
@@ -49,8 +61,6 @@ async fn put_query<T: IpfsTypes>(
     mime: Mime,
     body: impl Stream<Item = Result<impl Buf, warp::Error>> + Unpin,
 ) -> Result<impl Reply, Rejection> {
-    use multihash::{Multihash, Sha2_256, Sha2_512, Sha3_512};
-
     if query.encoding != InputEncoding::Raw {
         return Err(NotImplemented.into());
     }
@@ -63,12 +73,9 @@ async fn put_query<T: IpfsTypes>(
         _ => return Err(StringError::from("unknown codec").into()),
     };
 
-    let (hasher, v0_hash) = match query.hash.as_deref().unwrap_or("sha2-256") {
-        "sha2-256" => (Sha2_256::digest as fn(&[u8]) -> Multihash, true),
-        "sha2-512" => (Sha2_512::digest as fn(&[u8]) -> Multihash, false),
-        "sha3-512" => (Sha3_512::digest as fn(&[u8]) -> Multihash, false),
-        _ => return Err(StringError::from("unknown hash").into()),
-    };
+    let hasher = multihash_code_by_name(query.hash.as_deref().unwrap_or("sha2-256"))
+        .ok_or_else(|| StringError::from("unknown hash"))?;
+    let v0_hash = hasher == multihash::Code::Sha2_256;
 
     let boundary = mime
         .get_param("boundary")
@@ -79,9 +86,16 @@ async fn put_query<T: IpfsTypes>(
         .await
         .map_err(StringError::from)?;
 
-    let digest = hasher(&data);
+    let inlined = query.inline && data.len() <= query.inline_limit.unwrap_or(32);
+    let hasher = if inlined {
+        multihash::Code::Identity
+    } else {
+        hasher
+    };
+
+    let digest = hasher.digest(&data);
 
-    let cid = if v0_fmt && v0_hash {
+    let cid = if v0_fmt && v0_hash && !inlined {
         // this is quite ugly way but apparently js-ipfs generates a v0 cid for this combination
         // which is also created by go-ipfs
         Cid::new_v0(digest).expect("cidv0 creation cannot fail for dag-pb and sha2-256")
@@ -93,10 +107,15 @@ async fn put_query<T: IpfsTypes>(
         "Cid": { "/": cid.to_string() }
     });
 
-    // delay reallocation until cid has been generated
-    let data = data.into_boxed_slice();
-    let block = ipfs::Block { cid, data };
-    ipfs.put_block(block).await.map_err(StringError::from)?;
+    let block = ipfs::Block::new(data, cid);
+    let cid = ipfs.put_block(block).await.map_err(StringError::from)?;
+
+    if query.pin {
+        ipfs.insert_pin(&cid, true)
+            .await
+            .map_err(StringError::from)?;
+    }
+
     Ok(reply::json(&reply))
 }
 
@@ -124,7 +143,6 @@ async fn inner_resolve<T: IpfsTypes>(
     opts: ResolveOptions,
 ) -> Result<impl Reply, Rejection> {
     use ipfs::IpfsPath;
-    use std::convert::TryFrom;
 
     let path = IpfsPath::try_from(opts.arg.as_str()).map_err(StringError::from)?;
 
@@ -147,3 +165,110 @@ async fn inner_resolve<T: IpfsTypes>(
         "RemPath": StringSerialized(remaining),
     })))
 }
+
+/// Streams the structural changes between two DAGs as newline delimited json objects. The first
+/// `arg` is the old root and the second is the new root.
+pub fn diff<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs).and(diff_options()).and_then(diff_inner)
+}
+
+#[derive(Debug)]
+struct DiffOptions {
+    before: Cid,
+    after: Cid,
+}
+
+impl<'a> TryFrom<&'a str> for DiffOptions {
+    type Error = ParseError<'a>;
+
+    fn try_from(q: &'a str) -> Result<Self, Self::Error> {
+        let parse = url::form_urlencoded::parse(q.as_bytes());
+        let mut args = Vec::with_capacity(2);
+
+        for (key, value) in parse {
+            if key == "arg" {
+                args.push(
+                    Cid::try_from(&*value).map_err(|e| ParseError::InvalidCid("arg".into(), e))?,
+                );
+            }
+        }
+
+        let mut args = args.into_iter();
+
+        match (args.next(), args.next(), args.next()) {
+            (Some(before), Some(after), None) => Ok(DiffOptions { before, after }),
+            (_, _, Some(_)) => Err(ParseError::DuplicateField("arg".into())),
+            _ => Err(ParseError::MissingArg),
+        }
+    }
+}
+
+/// Filter to perform custom `warp::query<DiffOptions>`
+fn diff_options() -> impl Filter<Extract = (DiffOptions,), Error = Rejection> + Clone {
+    warp::filters::query::raw().and_then(|q: String| {
+        let res = DiffOptions::try_from(q.as_str())
+            .map_err(StringError::from)
+            .map_err(warp::reject::custom);
+
+        futures::future::ready(res)
+    })
+}
+
+async fn diff_inner<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    opts: DiffOptions,
+) -> Result<impl Reply, Rejection> {
+    use ipfs::dag::DagChange;
+
+    let st = ipfs.dag().diff(opts.before, opts.after).map(|res| {
+        let res = match res {
+            Ok(DagChange::Added { path, value }) => render_change("added", path, None, Some(value)),
+            Ok(DagChange::Removed { path, value }) => {
+                render_change("removed", path, Some(value), None)
+            }
+            Ok(DagChange::Modified {
+                path,
+                before,
+                after,
+            }) => render_change("modified", path, Some(before), Some(after)),
+            Err(e) => serde_json::to_string(&json!({ "Err": e.to_string() })).map_err(Into::into),
+        };
+
+        match res {
+            Ok(mut s) => {
+                s.push('\n');
+                Ok(s.into_bytes())
+            }
+            Err(e) => {
+                error!("dag change serialization failed: {}", e);
+                Err(HandledErr)
+            }
+        }
+    });
+
+    Ok(StreamResponse(st))
+}
+
+/// Renders the change as a json object, with the values in their dag-json representation.
+fn render_change(
+    kind: &str,
+    path: SlashedPath,
+    before: Option<Ipld>,
+    after: Option<Ipld>,
+) -> Result<String, ipfs::Error> {
+    let to_json = |ipld: Option<Ipld>| -> Result<serde_json::Value, ipfs::Error> {
+        match ipld {
+            Some(ipld) => Ok(serde_json::from_slice(&DagJsonCodec::encode(&ipld)?)?),
+            None => Ok(serde_json::Value::Null),
+        }
+    };
+
+    Ok(serde_json::to_string(&json!({
+        "Type": kind,
+        "Path": path.to_string(),
+        "Before": to_json(before)?,
+        "After": to_json(after)?,
+    }))?)
+}
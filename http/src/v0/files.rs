@@ -0,0 +1,474 @@
+//! The mutable file system, see https://docs.ipfs.io/reference/http/api/#api-v0-files-cp
+use crate::v0::support::{option_parsing::ParseError, with_ipfs, StreamResponse, StringError};
+use bytes::{Buf, Bytes};
+use futures::stream::{Stream, TryStreamExt};
+use ipfs::unixfs::ll::dir::EntryType;
+use ipfs::unixfs::mfs::WriteOptions;
+use ipfs::{Ipfs, IpfsTypes};
+use mime::Mime;
+use mpart_async::server::MultipartStream;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use warp::{query, reply, Filter, Rejection, Reply};
+
+#[derive(Debug, Deserialize)]
+struct MkdirArgs {
+    arg: String,
+    #[serde(default)]
+    parents: bool,
+}
+
+pub fn mkdir<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(query::<MkdirArgs>())
+        .and_then(mkdir_inner)
+}
+
+async fn mkdir_inner<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    args: MkdirArgs,
+) -> Result<impl Reply, Rejection> {
+    ipfs.files_mkdir(&args.arg, args.parents)
+        .await
+        .map_err(StringError::from)?;
+
+    Ok(reply())
+}
+
+#[derive(Debug, Deserialize)]
+struct WriteArgs {
+    arg: String,
+    #[serde(default)]
+    offset: u64,
+    #[serde(default)]
+    create: bool,
+    #[serde(default)]
+    truncate: bool,
+    #[serde(default)]
+    parents: bool,
+}
+
+/// Writes the first field of the multipart body to the file.
+pub fn write<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(query::<WriteArgs>())
+        .and(warp::header::<Mime>("content-type"))
+        .and(warp::body::stream())
+        .and_then(write_inner)
+}
+
+async fn write_inner<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    args: WriteArgs,
+    mime: Mime,
+    body: impl Stream<Item = Result<impl Buf, warp::Error>> + Send + Unpin + 'static,
+) -> Result<impl Reply, Rejection> {
+    let boundary = mime
+        .get_param("boundary")
+        .map(|v| v.to_string())
+        .ok_or_else(|| StringError::from("missing 'boundary' on content-type"))?;
+
+    let mut fields =
+        MultipartStream::new(Bytes::from(boundary), body.map_ok(|mut buf| buf.to_bytes()));
+
+    let field = fields
+        .try_next()
+        .await
+        .map_err(StringError::from)?
+        .ok_or_else(|| StringError::from("missing file in the request body"))?;
+
+    let input = tokio::io::stream_reader(Box::pin(
+        field.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string())),
+    ));
+
+    let opts = WriteOptions {
+        offset: args.offset,
+        create: args.create,
+        truncate: args.truncate,
+        parents: args.parents,
+    };
+
+    ipfs.files_write(&args.arg, input, opts)
+        .await
+        .map_err(StringError::from)?;
+
+    Ok(reply())
+}
+
+#[derive(Debug, Deserialize)]
+struct ReadArgs {
+    arg: String,
+    offset: Option<u64>,
+    count: Option<u64>,
+}
+
+pub fn read<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(query::<ReadArgs>())
+        .and_then(read_inner)
+}
+
+async fn read_inner<T: IpfsTypes>(ipfs: Ipfs<T>, args: ReadArgs) -> Result<impl Reply, Rejection> {
+    let range = match (args.offset, args.count) {
+        (Some(start), Some(count)) => Some(start..(start + count)),
+        (Some(start), None) => Some(start..u64::MAX),
+        (None, Some(count)) => Some(0..count),
+        (None, None) => None,
+    };
+
+    let stream = ipfs::unixfs::mfs::read(ipfs, &args.arg, range)
+        .await
+        .map_err(StringError::from)?;
+
+    Ok(StreamResponse(stream))
+}
+
+/// The source and the destination of `files/mv` and `files/cp`, given as two `arg` fields.
+#[derive(Debug)]
+struct SrcDstArgs {
+    src: String,
+    dst: String,
+}
+
+impl<'a> TryFrom<&'a str> for SrcDstArgs {
+    type Error = ParseError<'a>;
+
+    fn try_from(q: &'a str) -> Result<Self, Self::Error> {
+        let mut args = url::form_urlencoded::parse(q.as_bytes())
+            .filter(|(key, _)| key == "arg")
+            .map(|(_, value)| value.into_owned());
+
+        match (args.next(), args.next(), args.next()) {
+            (Some(src), Some(dst), None) => Ok(SrcDstArgs { src, dst }),
+            (Some(_), Some(_), Some(_)) => Err(ParseError::DuplicateField("arg".into())),
+            _ => Err(ParseError::MissingArg),
+        }
+    }
+}
+
+fn src_dst_args() -> impl Filter<Extract = (SrcDstArgs,), Error = Rejection> + Clone {
+    warp::filters::query::raw().and_then(|q: String| {
+        let res = SrcDstArgs::try_from(q.as_str())
+            .map_err(StringError::from)
+            .map_err(warp::reject::custom);
+
+        futures::future::ready(res)
+    })
+}
+
+pub fn mv<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs).and(src_dst_args()).and_then(mv_inner)
+}
+
+async fn mv_inner<T: IpfsTypes>(ipfs: Ipfs<T>, args: SrcDstArgs) -> Result<impl Reply, Rejection> {
+    ipfs.files_mv(&args.src, &args.dst)
+        .await
+        .map_err(StringError::from)?;
+
+    Ok(reply())
+}
+
+pub fn cp<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs).and(src_dst_args()).and_then(cp_inner)
+}
+
+async fn cp_inner<T: IpfsTypes>(ipfs: Ipfs<T>, args: SrcDstArgs) -> Result<impl Reply, Rejection> {
+    ipfs.files_cp(&args.src, &args.dst)
+        .await
+        .map_err(StringError::from)?;
+
+    Ok(reply())
+}
+
+#[derive(Debug, Deserialize)]
+struct RmArgs {
+    arg: String,
+    #[serde(default)]
+    recursive: bool,
+}
+
+pub fn rm<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs).and(query::<RmArgs>()).and_then(rm_inner)
+}
+
+async fn rm_inner<T: IpfsTypes>(ipfs: Ipfs<T>, args: RmArgs) -> Result<impl Reply, Rejection> {
+    ipfs.files_rm(&args.arg, args.recursive)
+        .await
+        .map_err(StringError::from)?;
+
+    Ok(reply())
+}
+
+#[derive(Debug, Deserialize)]
+struct PathArgs {
+    arg: Option<String>,
+}
+
+impl PathArgs {
+    /// Defaults to the root like in go-ipfs.
+    fn path(&self) -> &str {
+        self.arg.as_deref().unwrap_or("/")
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct StatResponse {
+    hash: String,
+    size: u64,
+    cumulative_size: u64,
+    blocks: usize,
+    r#type: &'static str,
+}
+
+pub fn stat<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(query::<PathArgs>())
+        .and_then(stat_inner)
+}
+
+async fn stat_inner<T: IpfsTypes>(ipfs: Ipfs<T>, args: PathArgs) -> Result<impl Reply, Rejection> {
+    let stat = ipfs
+        .files_stat(args.path())
+        .await
+        .map_err(StringError::from)?;
+
+    let (r#type, size) = match stat.entry_type {
+        EntryType::File(size) => ("file", size),
+        EntryType::Directory => ("directory", 0),
+        EntryType::Symlink(_) => ("symlink", 0),
+        EntryType::Unknown => ("unknown", 0),
+    };
+
+    Ok(reply::json(&StatResponse {
+        hash: stat.cid.to_string(),
+        size,
+        cumulative_size: stat.cumulative_size,
+        blocks: stat.blocks,
+        r#type,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct LsArgs {
+    arg: Option<String>,
+    /// Include the hashes and the sizes of the entries.
+    #[serde(default)]
+    long: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct LsResponse {
+    entries: Vec<LsEntry>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct LsEntry {
+    name: String,
+    /// 0 for files and 1 for directories like in go-ipfs.
+    r#type: u8,
+    size: u64,
+    hash: String,
+}
+
+pub fn ls<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs).and(query::<LsArgs>()).and_then(ls_inner)
+}
+
+async fn ls_inner<T: IpfsTypes>(ipfs: Ipfs<T>, args: LsArgs) -> Result<impl Reply, Rejection> {
+    let path = args.arg.as_deref().unwrap_or("/");
+    let entries = ipfs
+        .files_ls(path, args.long)
+        .await
+        .map_err(StringError::from)?
+        .into_iter()
+        .map(|entry| {
+            let (r#type, size) = match entry.entry_type {
+                Some(EntryType::Directory) => (1, 0),
+                Some(EntryType::File(size)) => (0, size),
+                _ => (0, 0),
+            };
+
+            LsEntry {
+                name: entry.name,
+                r#type,
+                size,
+                hash: if args.long {
+                    entry.cid.to_string()
+                } else {
+                    String::new()
+                },
+            }
+        })
+        .collect();
+
+    Ok(reply::json(&LsResponse { entries }))
+}
+
+pub fn flush<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(query::<PathArgs>())
+        .and_then(flush_inner)
+}
+
+async fn flush_inner<T: IpfsTypes>(ipfs: Ipfs<T>, args: PathArgs) -> Result<impl Reply, Rejection> {
+    let cid = ipfs
+        .files_flush(args.path())
+        .await
+        .map_err(StringError::from)?;
+
+    Ok(reply::json(&serde_json::json!({ "Cid": cid.to_string() })))
+}
+
+#[cfg(test)]
+mod tests {
+    use ipfs::Node;
+    use warp::Filter;
+
+    #[tokio::test(max_threads = 1)]
+    async fn write_stat_and_read() {
+        let ipfs = Node::new("test_node").await;
+        let routes = routes(&ipfs);
+
+        let response = warp::test::request()
+            .path("/files/write?arg=/a/b.txt&create=true&parents=true")
+            .header(
+                "content-type",
+                "multipart/form-data; boundary=-----------------------------Z0oYi6XyTm7_x2L4ty8JL",
+            )
+            .body(
+                &b"-------------------------------Z0oYi6XyTm7_x2L4ty8JL\r\n\
+                    Content-Disposition: form-data; name=\"file\"; filename=\"b.txt\"\r\n\
+                    Content-Type: application/octet-stream\r\n\
+                    \r\n\
+                    foobar\n\
+                    \r\n-------------------------------Z0oYi6XyTm7_x2L4ty8JL--\r\n"[..],
+            )
+            .reply(&routes)
+            .await;
+
+        assert_eq!(response.status(), 200, "{:?}", response.body());
+
+        let response = warp::test::request()
+            .path("/files/stat?arg=/a/b.txt")
+            .reply(&routes)
+            .await;
+
+        assert_eq!(response.status(), 200);
+        let body = serde_json::from_slice::<serde_json::Value>(response.body()).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "Hash": "QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL",
+                "Size": 7,
+                "CumulativeSize": 15,
+                "Blocks": 0,
+                "Type": "file",
+            })
+        );
+
+        let response = warp::test::request()
+            .path("/files/mv?arg=/a/b.txt&arg=/c.txt")
+            .reply(&routes)
+            .await;
+
+        assert_eq!(response.status(), 200);
+
+        let response = warp::test::request()
+            .path("/files/read?arg=/c.txt&offset=3")
+            .reply(&routes)
+            .await;
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.body().as_ref(), b"bar\n");
+
+        let response = warp::test::request()
+            .path("/files/ls?long=true")
+            .reply(&routes)
+            .await;
+
+        assert_eq!(response.status(), 200);
+        let body = serde_json::from_slice::<serde_json::Value>(response.body()).unwrap();
+        assert_eq!(body["Entries"][0]["Name"], "a");
+        assert_eq!(body["Entries"][0]["Type"], 1);
+        assert_eq!(body["Entries"][1]["Name"], "c.txt");
+        assert_eq!(body["Entries"][1]["Size"], 7);
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn mkdir_and_rm() {
+        let ipfs = Node::new("test_node").await;
+        let routes = routes(&ipfs);
+
+        let response = warp::test::request()
+            .path("/files/mkdir?arg=/a")
+            .reply(&routes)
+            .await;
+
+        assert_eq!(response.status(), 200);
+
+        let response = warp::test::request()
+            .path("/files/rm?arg=/a")
+            .reply(&routes)
+            .await;
+
+        assert_eq!(response.status(), 500);
+
+        let response = warp::test::request()
+            .path("/files/rm?arg=/a&recursive=true")
+            .reply(&routes)
+            .await;
+
+        assert_eq!(response.status(), 200);
+
+        let response = warp::test::request()
+            .path("/files/flush")
+            .reply(&routes)
+            .await;
+
+        assert_eq!(response.status(), 200);
+        let body = serde_json::from_slice::<serde_json::Value>(response.body()).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({ "Cid": "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn" })
+        );
+    }
+
+    fn routes(
+        ipfs: &ipfs::Ipfs<ipfs::TestTypes>,
+    ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        warp::path("files")
+            .and(
+                warp::path!("mkdir")
+                    .and(super::mkdir(ipfs))
+                    .or(warp::path!("write").and(super::write(ipfs)))
+                    .or(warp::path!("read").and(super::read(ipfs)))
+                    .or(warp::path!("mv").and(super::mv(ipfs)))
+                    .or(warp::path!("rm").and(super::rm(ipfs)))
+                    .or(warp::path!("stat").and(super::stat(ipfs)))
+                    .or(warp::path!("ls").and(super::ls(ipfs)))
+                    .or(warp::path!("flush").and(super::flush(ipfs))),
+            )
+            .recover(crate::v0::recover_as_message_response)
+    }
+}
@@ -0,0 +1,625 @@
+//! Working with dag-pb nodes directly, see https://docs.ipfs.io/reference/http/api/#api-v0-object-new
+use crate::v0::support::{
+    option_parsing::ParseError, try_only_named_multipart, with_ipfs, StringError,
+};
+use bytes::Buf;
+use cid::Cid;
+use futures::stream::Stream;
+use ipfs::ipld::dag_pb::{PbLink, PbNode};
+use ipfs::object::ObjectTemplate;
+use ipfs::{Ipfs, IpfsTypes};
+use mime::Mime;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use warp::{query, reply, Filter, Rejection, Reply};
+
+/// The size limit for the uploaded nodes and data, same as with `block/put`.
+const BODY_LIMIT: usize = 1024 * 1024;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct HashResponse {
+    hash: String,
+}
+
+impl From<Cid> for HashResponse {
+    fn from(cid: Cid) -> Self {
+        HashResponse {
+            hash: cid.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Link {
+    name: String,
+    hash: String,
+    size: u64,
+}
+
+impl From<PbLink> for Link {
+    fn from(link: PbLink) -> Self {
+        Link {
+            name: link.name.unwrap_or_default(),
+            hash: link.cid.to_string(),
+            size: link.size.unwrap_or_default(),
+        }
+    }
+}
+
+impl TryFrom<Link> for PbLink {
+    type Error = cid::Error;
+
+    fn try_from(link: Link) -> Result<Self, Self::Error> {
+        Ok(PbLink {
+            cid: link.hash.parse()?,
+            name: Some(link.name),
+            size: Some(link.size),
+        })
+    }
+}
+
+/// The encodings supported for the `Data` of the nodes in JSON.
+#[derive(Debug, Clone, Copy)]
+enum DataEncoding {
+    Text,
+    Base64,
+}
+
+impl DataEncoding {
+    fn from_query(value: Option<&str>) -> Result<Self, StringError> {
+        match value.unwrap_or("text") {
+            "text" => Ok(DataEncoding::Text),
+            "base64" => Ok(DataEncoding::Base64),
+            other => Err(StringError::from(format!(
+                "unknown data encoding {:?}",
+                other
+            ))),
+        }
+    }
+
+    fn encode(self, data: &[u8]) -> String {
+        match self {
+            DataEncoding::Text => String::from_utf8_lossy(data).into_owned(),
+            DataEncoding::Base64 => multibase::Base::Base64Pad.encode(data),
+        }
+    }
+
+    fn decode(self, data: String) -> Result<Vec<u8>, StringError> {
+        match self {
+            DataEncoding::Text => Ok(data.into_bytes()),
+            DataEncoding::Base64 => multibase::Base::Base64Pad
+                .decode(data)
+                .map_err(StringError::from),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct NewArgs {
+    arg: Option<String>,
+}
+
+pub fn new<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs).and(query::<NewArgs>()).and_then(new_inner)
+}
+
+async fn new_inner<T: IpfsTypes>(ipfs: Ipfs<T>, args: NewArgs) -> Result<impl Reply, Rejection> {
+    let template = match args.arg.as_deref() {
+        None => ObjectTemplate::Empty,
+        Some("unixfs-dir") => ObjectTemplate::UnixFsDir,
+        Some(other) => {
+            return Err(StringError::from(format!("template {:?} not found", other)).into())
+        }
+    };
+
+    let cid = ipfs
+        .object()
+        .create(template)
+        .await
+        .map_err(StringError::from)?;
+
+    Ok(reply::json(&HashResponse::from(cid)))
+}
+
+#[derive(Debug, Deserialize)]
+struct GetArgs {
+    arg: String,
+    #[serde(rename = "data-encoding")]
+    data_encoding: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Node {
+    #[serde(default)]
+    links: Vec<Link>,
+    #[serde(default)]
+    data: String,
+}
+
+pub fn get<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs).and(query::<GetArgs>()).and_then(get_inner)
+}
+
+async fn get_inner<T: IpfsTypes>(ipfs: Ipfs<T>, args: GetArgs) -> Result<impl Reply, Rejection> {
+    let encoding = DataEncoding::from_query(args.data_encoding.as_deref())?;
+    let cid = args.arg.parse::<Cid>().map_err(StringError::from)?;
+
+    let node = ipfs.object().get(&cid).await.map_err(StringError::from)?;
+
+    Ok(reply::json(&Node {
+        links: node.links.into_iter().map(Link::from).collect(),
+        data: encoding.encode(node.data.as_deref().unwrap_or_default()),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct LinksArgs {
+    arg: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct LinksResponse {
+    hash: String,
+    links: Vec<Link>,
+}
+
+pub fn links<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(query::<LinksArgs>())
+        .and_then(links_inner)
+}
+
+async fn links_inner<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    args: LinksArgs,
+) -> Result<impl Reply, Rejection> {
+    let cid = args.arg.parse::<Cid>().map_err(StringError::from)?;
+
+    let links = ipfs.object().links(&cid).await.map_err(StringError::from)?;
+
+    Ok(reply::json(&LinksResponse {
+        hash: cid.to_string(),
+        links: links.into_iter().map(Link::from).collect(),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct PutArgs {
+    inputenc: Option<String>,
+    datafieldenc: Option<String>,
+}
+
+pub fn put<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(query::<PutArgs>())
+        .and(warp::header::<Mime>("content-type"))
+        .and(warp::body::stream())
+        .and_then(put_inner)
+}
+
+async fn put_inner<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    args: PutArgs,
+    mime: Mime,
+    body: impl Stream<Item = Result<impl Buf, warp::Error>> + Unpin,
+) -> Result<impl Reply, Rejection> {
+    let encoding = DataEncoding::from_query(args.datafieldenc.as_deref())?;
+    let body = multipart_body(mime, body).await?;
+
+    let node = match args.inputenc.as_deref().unwrap_or("json") {
+        "json" => {
+            let node = serde_json::from_slice::<Node>(&body).map_err(StringError::from)?;
+
+            let data = encoding.decode(node.data)?;
+            let links = node
+                .links
+                .into_iter()
+                .map(PbLink::try_from)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(StringError::from)?;
+
+            PbNode {
+                links,
+                // like in go-ipfs, empty data is left out
+                data: Some(data).filter(|data| !data.is_empty()),
+            }
+        }
+        "protobuf" => PbNode::from_bytes(&body).map_err(StringError::from)?,
+        other => {
+            return Err(StringError::from(format!("unknown input encoding {:?}", other)).into())
+        }
+    };
+
+    let cid = ipfs.object().put(node).await.map_err(StringError::from)?;
+
+    Ok(reply::json(&HashResponse::from(cid)))
+}
+
+/// Arguments for `patch/add-link`: the root, the name or path of the link and the target.
+#[derive(Debug)]
+struct AddLinkArgs {
+    root: Cid,
+    name: String,
+    target: Cid,
+    create: bool,
+}
+
+impl<'a> TryFrom<&'a str> for AddLinkArgs {
+    type Error = ParseError<'a>;
+
+    fn try_from(q: &'a str) -> Result<Self, Self::Error> {
+        use ParseError::*;
+
+        let mut args = Vec::new();
+        let mut create = None;
+
+        for (key, value) in url::form_urlencoded::parse(q.as_bytes()) {
+            match &*key {
+                "arg" => args.push(value),
+                "create" if create.is_none() => match value.parse::<bool>() {
+                    Ok(value) => create = Some(value),
+                    Err(_) => return Err(InvalidBoolean(key, value)),
+                },
+                "create" => return Err(DuplicateField(key)),
+                _ => {
+                    // ignore unknown
+                }
+            }
+        }
+
+        let mut args = args.into_iter();
+
+        match (args.next(), args.next(), args.next(), args.next()) {
+            (Some(root), Some(name), Some(target), None) => Ok(AddLinkArgs {
+                root: Cid::try_from(&*root).map_err(|e| InvalidCid("arg".into(), e))?,
+                name: name.into_owned(),
+                target: Cid::try_from(&*target).map_err(|e| InvalidCid("arg".into(), e))?,
+                create: create.unwrap_or(false),
+            }),
+            (Some(_), Some(_), Some(_), Some(_)) => Err(DuplicateField("arg".into())),
+            _ => Err(MissingArg),
+        }
+    }
+}
+
+fn add_link_args() -> impl Filter<Extract = (AddLinkArgs,), Error = Rejection> + Clone {
+    warp::filters::query::raw().and_then(|q: String| {
+        let res = AddLinkArgs::try_from(q.as_str())
+            .map_err(StringError::from)
+            .map_err(warp::reject::custom);
+
+        futures::future::ready(res)
+    })
+}
+
+pub fn add_link<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(add_link_args())
+        .and_then(add_link_inner)
+}
+
+async fn add_link_inner<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    args: AddLinkArgs,
+) -> Result<impl Reply, Rejection> {
+    let cid = ipfs
+        .object()
+        .add_link(&args.root, &args.name, &args.target, args.create)
+        .await
+        .map_err(StringError::from)?;
+
+    Ok(reply::json(&HashResponse::from(cid)))
+}
+
+/// Arguments for `patch/rm-link`: the root and the name or path of the link.
+#[derive(Debug)]
+struct RmLinkArgs {
+    root: Cid,
+    name: String,
+}
+
+impl<'a> TryFrom<&'a str> for RmLinkArgs {
+    type Error = ParseError<'a>;
+
+    fn try_from(q: &'a str) -> Result<Self, Self::Error> {
+        use ParseError::*;
+
+        let mut args = url::form_urlencoded::parse(q.as_bytes())
+            .filter(|(key, _)| key == "arg")
+            .map(|(_, value)| value);
+
+        match (args.next(), args.next(), args.next()) {
+            (Some(root), Some(name), None) => Ok(RmLinkArgs {
+                root: Cid::try_from(&*root).map_err(|e| InvalidCid("arg".into(), e))?,
+                name: name.into_owned(),
+            }),
+            (Some(_), Some(_), Some(_)) => Err(DuplicateField("arg".into())),
+            _ => Err(MissingArg),
+        }
+    }
+}
+
+fn rm_link_args() -> impl Filter<Extract = (RmLinkArgs,), Error = Rejection> + Clone {
+    warp::filters::query::raw().and_then(|q: String| {
+        let res = RmLinkArgs::try_from(q.as_str())
+            .map_err(StringError::from)
+            .map_err(warp::reject::custom);
+
+        futures::future::ready(res)
+    })
+}
+
+pub fn rm_link<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs).and(rm_link_args()).and_then(rm_link_inner)
+}
+
+async fn rm_link_inner<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    args: RmLinkArgs,
+) -> Result<impl Reply, Rejection> {
+    let cid = ipfs
+        .object()
+        .rm_link(&args.root, &args.name)
+        .await
+        .map_err(StringError::from)?;
+
+    Ok(reply::json(&HashResponse::from(cid)))
+}
+
+#[derive(Debug, Deserialize)]
+struct DataArgs {
+    arg: String,
+}
+
+pub fn set_data<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(query::<DataArgs>())
+        .and(warp::header::<Mime>("content-type"))
+        .and(warp::body::stream())
+        .and_then(set_data_inner)
+}
+
+async fn set_data_inner<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    args: DataArgs,
+    mime: Mime,
+    body: impl Stream<Item = Result<impl Buf, warp::Error>> + Unpin,
+) -> Result<impl Reply, Rejection> {
+    let root = args.arg.parse::<Cid>().map_err(StringError::from)?;
+    let data = multipart_body(mime, body).await?;
+
+    let cid = ipfs
+        .object()
+        .set_data(&root, data)
+        .await
+        .map_err(StringError::from)?;
+
+    Ok(reply::json(&HashResponse::from(cid)))
+}
+
+pub fn append_data<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(query::<DataArgs>())
+        .and(warp::header::<Mime>("content-type"))
+        .and(warp::body::stream())
+        .and_then(append_data_inner)
+}
+
+async fn append_data_inner<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    args: DataArgs,
+    mime: Mime,
+    body: impl Stream<Item = Result<impl Buf, warp::Error>> + Unpin,
+) -> Result<impl Reply, Rejection> {
+    let root = args.arg.parse::<Cid>().map_err(StringError::from)?;
+    let data = multipart_body(mime, body).await?;
+
+    let cid = ipfs
+        .object()
+        .append_data(&root, &data)
+        .await
+        .map_err(StringError::from)?;
+
+    Ok(reply::json(&HashResponse::from(cid)))
+}
+
+/// Reads the single uploaded field of the multipart body.
+async fn multipart_body(
+    mime: Mime,
+    body: impl Stream<Item = Result<impl Buf, warp::Error>> + Unpin,
+) -> Result<Vec<u8>, StringError> {
+    let boundary = mime
+        .get_param("boundary")
+        .map(|v| v.to_string())
+        .ok_or_else(|| StringError::from("missing 'boundary' on content-type"))?;
+
+    try_only_named_multipart(&["data", "file"], BODY_LIMIT, boundary, body)
+        .await
+        .map_err(StringError::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use ipfs::Node;
+    use warp::Filter;
+
+    const BOUNDARY: &str = "-----------------------------Z0oYi6XyTm7_x2L4ty8JL";
+
+    #[tokio::test(max_threads = 1)]
+    async fn new_patch_and_get() {
+        let ipfs = Node::new("test_node").await;
+        let routes = routes(&ipfs);
+
+        let dir = hash(
+            warp::test::request()
+                .path("/object/new?arg=unixfs-dir")
+                .reply(&routes)
+                .await,
+        );
+        assert_eq!(dir, "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn");
+
+        let empty = hash(
+            warp::test::request()
+                .path("/object/new")
+                .reply(&routes)
+                .await,
+        );
+        assert_eq!(empty, "QmdfTbBqBPQ7VNxZEYEj14VmRuZBkqFbiwReogJgS1zR1n");
+
+        let root = hash(
+            warp::test::request()
+                .path(&format!(
+                    "/object/patch/add-link?arg={}&arg=a/b&arg={}&create=true",
+                    dir, empty
+                ))
+                .reply(&routes)
+                .await,
+        );
+
+        let response = warp::test::request()
+            .path(&format!("/object/links?arg={}", root))
+            .reply(&routes)
+            .await;
+
+        assert_eq!(response.status(), 200);
+        let body = serde_json::from_slice::<serde_json::Value>(response.body()).unwrap();
+        assert_eq!(body["Hash"], root);
+        assert_eq!(body["Links"][0]["Name"], "a");
+        assert_eq!(body["Links"][0]["Size"], 47);
+
+        let a = body["Links"][0]["Hash"].as_str().unwrap().to_owned();
+
+        let response = warp::test::request()
+            .path(&format!("/object/get?arg={}&data-encoding=base64", a))
+            .reply(&routes)
+            .await;
+
+        assert_eq!(response.status(), 200);
+        let body = serde_json::from_slice::<serde_json::Value>(response.body()).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "Links": [{ "Name": "b", "Hash": empty, "Size": 0 }],
+                "Data": "CAE=",
+            })
+        );
+
+        let removed = hash(
+            warp::test::request()
+                .path(&format!("/object/patch/rm-link?arg={}&arg=a", root))
+                .reply(&routes)
+                .await,
+        );
+        assert_eq!(removed, dir);
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn put_and_patch_data() {
+        let ipfs = Node::new("test_node").await;
+        let routes = routes(&ipfs);
+
+        let put = hash(
+            warp::test::request()
+                .path("/object/put")
+                .header(
+                    "content-type",
+                    format!("multipart/form-data; boundary={}", BOUNDARY),
+                )
+                .body(multipart(br#"{"Data":"foo","Links":[]}"#))
+                .reply(&routes)
+                .await,
+        );
+
+        let appended = hash(
+            warp::test::request()
+                .path(&format!("/object/patch/append-data?arg={}", put))
+                .header(
+                    "content-type",
+                    format!("multipart/form-data; boundary={}", BOUNDARY),
+                )
+                .body(multipart(b"bar"))
+                .reply(&routes)
+                .await,
+        );
+
+        let response = warp::test::request()
+            .path(&format!("/object/get?arg={}", appended))
+            .reply(&routes)
+            .await;
+
+        assert_eq!(response.status(), 200);
+        let body = serde_json::from_slice::<serde_json::Value>(response.body()).unwrap();
+        assert_eq!(body, serde_json::json!({ "Links": [], "Data": "foobar" }));
+
+        let set = hash(
+            warp::test::request()
+                .path(&format!("/object/patch/set-data?arg={}", appended))
+                .header(
+                    "content-type",
+                    format!("multipart/form-data; boundary={}", BOUNDARY),
+                )
+                .body(multipart(b"foo"))
+                .reply(&routes)
+                .await,
+        );
+        assert_eq!(set, put);
+    }
+
+    fn hash(response: warp::http::Response<bytes::Bytes>) -> String {
+        assert_eq!(response.status(), 200, "{:?}", response.body());
+        let body = serde_json::from_slice::<serde_json::Value>(response.body()).unwrap();
+        body["Hash"].as_str().unwrap().to_owned()
+    }
+
+    fn multipart(content: &[u8]) -> Vec<u8> {
+        let mut body = format!(
+            "--{}\r\n\
+            Content-Disposition: form-data; name=\"data\"\r\n\
+            Content-Type: application/octet-stream\r\n\
+            \r\n",
+            BOUNDARY
+        )
+        .into_bytes();
+        body.extend_from_slice(content);
+        body.extend_from_slice(format!("\r\n--{}--\r\n", BOUNDARY).as_bytes());
+        body
+    }
+
+    fn routes(
+        ipfs: &ipfs::Ipfs<ipfs::TestTypes>,
+    ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        warp::path("object")
+            .and(
+                warp::path!("new")
+                    .and(super::new(ipfs))
+                    .or(warp::path!("get").and(super::get(ipfs)))
+                    .or(warp::path!("links").and(super::links(ipfs)))
+                    .or(warp::path!("put").and(super::put(ipfs)))
+                    .or(warp::path!("patch" / "add-link").and(super::add_link(ipfs)))
+                    .or(warp::path!("patch" / "rm-link").and(super::rm_link(ipfs)))
+                    .or(warp::path!("patch" / "set-data").and(super::set_data(ipfs)))
+                    .or(warp::path!("patch" / "append-data").and(super::append_data(ipfs))),
+            )
+            .recover(crate::v0::recover_as_message_response)
+    }
+}
@@ -1,13 +1,19 @@
 use crate::v0::support::{
-    with_ipfs, MaybeTimeoutExt, StreamResponse, StringError, StringSerialized,
+    multihash_code_by_name, with_ipfs, MaybeTimeoutExt, StreamResponse, StringError,
+    StringSerialized,
 };
 use async_stream::try_stream;
 use bytes::Bytes;
 use futures::stream::TryStream;
+use ipfs::unixfs::ll::dir::EntryType;
+use ipfs::unixfs::ll::file::adder::{
+    BalancedCollector, Chunker, ChunkerParseError, Collector, TrickleCollector,
+};
 use ipfs::unixfs::ll::walk::{self, ContinuedWalk, Walker};
-use ipfs::unixfs::{ll::file::FileReadFailed, TraversalFailed};
-use ipfs::{dag::ResolveError, Block, Ipfs, IpfsPath, IpfsTypes};
-use serde::Deserialize;
+use ipfs::unixfs::ll::CidOptions;
+use ipfs::unixfs::{ll::file::FileReadFailed, AddOptions, LsEntry, TraversalFailed};
+use ipfs::{dag::ResolveError, Block, Cid, Ipfs, IpfsPath, IpfsTypes};
+use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::path::Path;
 use warp::{query, Filter, Rejection, Reply};
@@ -28,6 +34,92 @@ pub struct AddArgs {
     /// When true, a new directory is created to hold more than 1 root level directories.
     #[serde(default, rename = "wrap-with-directory")]
     wrap_with_directory: bool,
+    /// When true, blocks of at most `inline-limit` bytes are inlined into their Cids.
+    #[serde(default)]
+    inline: bool,
+    /// Defaults to 32 like in go-ipfs.
+    #[serde(rename = "inline-limit")]
+    inline_limit: Option<usize>,
+    /// Use the trickle layout instead of the balanced one for files.
+    #[serde(default)]
+    trickle: bool,
+    /// Chunking algorithm in the go-ipfs format, for example `size-262144` or
+    /// `rabin-<min>-<avg>-<max>`.
+    chunker: Option<String>,
+    /// Store the file contents as raw blocks. Defaults to true when `cid-version` is 1.
+    #[serde(rename = "raw-leaves")]
+    raw_leaves: Option<bool>,
+    /// Cid version for the created blocks. Defaults to 0, unless `hash` is other than sha2-256.
+    #[serde(rename = "cid-version")]
+    cid_version: Option<u8>,
+    /// Name of the multihash to use, defaults to `sha2-256`.
+    hash: Option<String>,
+}
+
+impl AddArgs {
+    /// Returns the size limit for inlined blocks, if blocks should be inlined.
+    fn inline_limit(&self) -> Option<usize> {
+        if self.inline {
+            Some(self.inline_limit.unwrap_or(32))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the chunker to use for files, or the default one if none was given.
+    fn chunker(&self) -> Result<Chunker, ChunkerParseError> {
+        self.chunker.as_deref().unwrap_or_default().parse()
+    }
+
+    /// Returns the Cid version and the multihash for the blocks, following the go-ipfs rules of
+    /// defaulting to Cid version 1 when the hash is other than sha2-256.
+    fn cid_options(&self) -> Result<CidOptions, String> {
+        let hash = match self.hash.as_deref() {
+            // inlining all of the blocks would leave nothing to store
+            Some("identity") => {
+                return Err(
+                    "identity hash is not supported, use inline and inline-limit instead".into(),
+                )
+            }
+            Some(name) => multihash_code_by_name(name)
+                .ok_or_else(|| format!("unsupported hash: {:?}", name))?,
+            None => multihash::Code::Sha2_256,
+        };
+
+        let version = match (self.cid_version, hash) {
+            (None, multihash::Code::Sha2_256) | (Some(0), multihash::Code::Sha2_256) => {
+                cid::Version::V0
+            }
+            (None, _) | (Some(1), _) => cid::Version::V1,
+            (Some(0), _) => return Err("cid version 0 only supports sha2-256".into()),
+            (Some(v), _) => return Err(format!("unsupported cid version: {}", v)),
+        };
+
+        Ok(CidOptions::new(version, hash))
+    }
+
+    /// Returns the collector for the requested file layout.
+    fn collector(&self) -> Collector {
+        if self.trickle {
+            TrickleCollector::default().into()
+        } else {
+            BalancedCollector::default().into()
+        }
+    }
+
+    /// Returns the options for adding each of the files. The files are not pinned nor wrapped,
+    /// as the directories are built from all of the files of the request.
+    fn add_options(&self) -> Result<AddOptions, String> {
+        Ok(AddOptions {
+            chunker: self.chunker().map_err(|e| e.to_string())?,
+            layout: self.collector(),
+            cid_options: self.cid_options()?,
+            raw_leaves: self.raw_leaves,
+            inline_limit: self.inline_limit(),
+            pin: false,
+            wrap: None,
+        })
+    }
 }
 
 pub fn add<T: IpfsTypes>(
@@ -111,10 +203,136 @@ async fn get_inner<T: IpfsTypes>(ipfs: Ipfs<T>, args: GetArgs) -> Result<impl Re
         .map_err(StringError::from)?
         .map_err(StringError::from)?;
 
-    Ok(StreamResponse(walk(ipfs, block).into_stream()))
+    // the HTTP api uses the final Cid name as the root name in the generated tar
+    // archive.
+    let name = block.cid.to_string();
+
+    Ok(StreamResponse(walk(ipfs, block, name).into_stream()))
+}
+
+#[derive(Debug, Deserialize)]
+struct LsArgs {
+    arg: String,
+    /// Resolve the types of the entries, defaults to true.
+    #[serde(rename = "resolve-type")]
+    resolve_type: Option<bool>,
+    /// Resolve the sizes of the files, defaults to true.
+    size: Option<bool>,
+    /// Output a separate object for every entry.
+    #[serde(default)]
+    stream: bool,
+    timeout: Option<StringSerialized<humantime::Duration>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct LsResponse {
+    objects: Vec<LsObject>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct LsObject {
+    hash: String,
+    links: Vec<LsLink>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct LsLink {
+    name: String,
+    hash: StringSerialized<Cid>,
+    size: u64,
+    /// The UnixFS type of the entry, with 0 used for the unresolved and the unknown types.
+    r#type: u8,
+    target: String,
+}
+
+impl LsLink {
+    fn new(entry: LsEntry, size: bool) -> Self {
+        let (r#type, file_size, target) = match entry.entry_type {
+            Some(EntryType::Directory) => (1, 0, String::new()),
+            Some(EntryType::File(file_size)) => (2, file_size, String::new()),
+            Some(EntryType::Symlink(target)) => {
+                (4, 0, String::from_utf8_lossy(&target).into_owned())
+            }
+            Some(EntryType::Unknown) | None => (0, 0, String::new()),
+        };
+
+        LsLink {
+            name: entry.name,
+            hash: StringSerialized(entry.cid),
+            size: if size { file_size } else { 0 },
+            r#type,
+            target,
+        }
+    }
+}
+
+/// `ls` as per https://docs.ipfs.io/reference/http/api/#api-v0-ls
+pub fn ls<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs).and(query::<LsArgs>()).and_then(ls_inner)
+}
+
+async fn ls_inner<T: IpfsTypes>(ipfs: Ipfs<T>, args: LsArgs) -> Result<impl Reply, Rejection> {
+    use futures::stream::{self, StreamExt, TryStreamExt};
+
+    let path = args.arg.parse::<IpfsPath>().map_err(StringError::from)?;
+    let size = args.size.unwrap_or(true);
+    // like go-ipfs, the types are resolved for the sizes as well
+    let resolve = args.resolve_type.unwrap_or(true) || size;
+
+    let mut entries = ipfs::unixfs::ls(ipfs, path, resolve).boxed();
+
+    // FIXME: this timeout is only for the first entry, should be for the whole listing!
+    // the first entry is awaited here so that a missing or a non-directory root is reported as
+    // an error response
+    let first = entries
+        .try_next()
+        .maybe_timeout(args.timeout.map(StringSerialized::into_inner))
+        .await
+        .map_err(StringError::from)?
+        .map_err(StringError::from)?;
+
+    let entries = stream::iter(first.map(Ok)).chain(entries);
+    let hash = args.arg;
+
+    if args.stream {
+        let st = entries.map_ok(move |entry| {
+            let response = LsResponse {
+                objects: vec![LsObject {
+                    hash: hash.clone(),
+                    links: vec![LsLink::new(entry, size)],
+                }],
+            };
+
+            let mut line = serde_json::to_vec(&response).expect("serialization cannot fail");
+            line.push(b'\n');
+            Bytes::from(line)
+        });
+
+        Ok(StreamResponse(st).into_response())
+    } else {
+        let links = entries
+            .map_ok(|entry| LsLink::new(entry, size))
+            .try_collect::<Vec<_>>()
+            .await
+            .map_err(StringError::from)?;
+
+        let response = LsResponse {
+            objects: vec![LsObject { hash, links }],
+        };
+
+        Ok(warp::reply::json(&response).into_response())
+    }
 }
 
-async fn resolve_dagpb<T: IpfsTypes>(ipfs: &Ipfs<T>, path: IpfsPath) -> Result<Block, StringError> {
+pub(super) async fn resolve_dagpb<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+    path: IpfsPath,
+) -> Result<Block, StringError> {
     let (resolved, _) = ipfs
         .dag()
         .resolve(path, true)
@@ -124,19 +342,19 @@ async fn resolve_dagpb<T: IpfsTypes>(ipfs: &Ipfs<T>, path: IpfsPath) -> Result<B
     resolved.into_unixfs_block().map_err(StringError::from)
 }
 
-fn walk<Types: IpfsTypes>(
+/// Walks the tree into a tar archive where the root entry is named `name`. When the name is empty,
+/// the root directory is left out and its entries become the top level entries of the archive.
+pub(super) fn walk<Types: IpfsTypes>(
     ipfs: Ipfs<Types>,
     Block {
         cid: root,
         data: first_block_data,
     }: Block,
+    name: String,
 ) -> impl TryStream<Ok = Bytes, Error = GetError> + 'static {
     let mut cache = None;
     let mut tar_helper = TarHelper::with_capacity(16 * 1024);
 
-    // the HTTP api uses the final Cid name as the root name in the generated tar
-    // archive.
-    let name = root.to_string();
     let mut walker = Walker::new(root, name);
 
     let mut buffer = Some(first_block_data);
@@ -183,6 +401,7 @@ fn walk<Types: IpfsTypes>(
                         }
                     }
                 },
+                ContinuedWalk::RootDirectory(_, path, _) if path.as_os_str().is_empty() => {}
                 ContinuedWalk::Directory(_, path, metadata) | ContinuedWalk::RootDirectory(_, path, metadata) => {
                     for bytes in tar_helper.apply_directory(path, metadata)?.iter_mut() {
                         if let Some(bytes) = bytes.take() {
@@ -207,7 +426,7 @@ fn walk<Types: IpfsTypes>(
 }
 
 #[derive(Debug)]
-enum GetError {
+pub(super) enum GetError {
     NonUtf8Symlink,
     InvalidFileName(Vec<u8>),
     InvalidLinkName(Vec<u8>),
@@ -399,6 +618,89 @@ mod tests {
         assert_eq!(found, expected);
     }
 
+    #[tokio::test(max_threads = 1)]
+    async fn ls_wrapped_file() {
+        use ipfs::unixfs::{AddEvent, AddOptions};
+
+        let ipfs = Node::new("test_node").await;
+
+        let opts = AddOptions {
+            wrap: Some("foobar.txt".into()),
+            ..Default::default()
+        };
+
+        let events = ipfs
+            .add_unixfs(&b"foobar\n"[..], opts)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        let (root, file) = match &events[..] {
+            [.., AddEvent::Added { cid: file, .. }, AddEvent::Added { cid: root, .. }] => {
+                (root.to_string(), file.to_string())
+            }
+            x => unreachable!("{:?}", x),
+        };
+
+        let filter = super::ls(&ipfs);
+
+        let response = warp::test::request()
+            .method("POST")
+            .path(&format!("/ls?arg={}", root))
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), 200);
+
+        let expected = serde_json::json!({
+            "Objects": [{
+                "Hash": root,
+                "Links": [{
+                    "Name": "foobar.txt",
+                    "Hash": file,
+                    "Size": 7,
+                    "Type": 2,
+                    "Target": "",
+                }]
+            }]
+        });
+
+        let body = serde_json::from_slice::<serde_json::Value>(response.body()).unwrap();
+        assert_eq!(body, expected);
+
+        let response = warp::test::request()
+            .method("POST")
+            .path(&format!(
+                "/ls?arg={}&stream=true&resolve-type=false&size=false",
+                root
+            ))
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), 200);
+
+        let body = std::str::from_utf8(response.body()).unwrap();
+        let lines = body.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 1);
+
+        let mut expected = expected;
+        expected["Objects"][0]["Links"][0]["Size"] = 0.into();
+        expected["Objects"][0]["Links"][0]["Type"] = 0.into();
+
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(lines[0]).unwrap(),
+            expected
+        );
+
+        let response = warp::test::request()
+            .method("POST")
+            .path(&format!("/ls?arg={}", file))
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), 500, "{:?}", response.body());
+    }
+
     fn get_archive_entries(bytes: impl AsRef<[u8]>) -> Vec<Entry> {
         let mut cursor = std::io::Cursor::new(bytes.as_ref());
 
@@ -431,10 +733,7 @@ mod tests {
     ) -> impl std::future::Future<Output = Result<Cid, ipfs::Error>> + 'a {
         let cid = Cid::new_v0(Sha2_256::digest(block)).unwrap();
 
-        let block = Block {
-            cid,
-            data: block.into(),
-        };
+        let block = Block::new(block.to_vec(), cid);
 
         ipfs.put_block(block)
     }
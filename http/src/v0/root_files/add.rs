@@ -6,18 +6,17 @@ use bytes::{
 };
 use cid::Cid;
 use futures::stream::{Stream, StreamExt, TryStreamExt};
-use ipfs::unixfs::ll::{
-    dir::builder::{
-        BufferingTreeBuilder, TreeBuildingFailed, TreeConstructionFailed, TreeNode, TreeOptions,
-    },
-    file::adder::FileAdder,
+use ipfs::unixfs::ll::dir::builder::{
+    BufferingTreeBuilder, TreeBuildingFailed, TreeConstructionFailed, TreeOptions,
 };
-use ipfs::{Block, Ipfs, IpfsTypes};
+use ipfs::unixfs::{AddEvent, AddOptions};
+use ipfs::{Ipfs, IpfsTypes};
 use mime::Mime;
 use mpart_async::server::{MultipartError, MultipartStream};
 use serde::Serialize;
 use std::borrow::Cow;
 use std::fmt;
+use std::io;
 use warp::{Rejection, Reply};
 
 pub(super) async fn add_inner<T: IpfsTypes>(
@@ -31,9 +30,11 @@ pub(super) async fn add_inner<T: IpfsTypes>(
         .map(|v| v.to_string())
         .ok_or_else(|| StringError::from("missing 'boundary' on content-type"))?;
 
+    let add_opts = opts.add_options().map_err(StringError::from)?;
+
     let st = MultipartStream::new(Bytes::from(boundary), body.map_ok(|mut buf| buf.to_bytes()));
 
-    let st = add_stream(ipfs, st, opts);
+    let st = add_stream(ipfs, st, opts, add_opts);
 
     // map the errors into json objects; as we can't return them as trailers yet
 
@@ -69,7 +70,7 @@ enum AddError {
     UnsupportedField(String),
     UnsupportedContentType(String),
     ResponseSerialization(serde_json::Error),
-    Persisting(ipfs::Error),
+    Adding(ipfs::unixfs::AddError),
     TreeGathering(TreeBuildingFailed),
     TreeBuilding(TreeConstructionFailed),
 }
@@ -80,6 +81,12 @@ impl From<MultipartError> for AddError {
     }
 }
 
+impl From<ipfs::unixfs::AddError> for AddError {
+    fn from(e: ipfs::unixfs::AddError) -> AddError {
+        AddError::Adding(e)
+    }
+}
+
 impl fmt::Display for AddError {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         use AddError::*;
@@ -90,7 +97,16 @@ impl fmt::Display for AddError {
             UnsupportedField(name) => write!(fmt, "unsupported field name: {:?}", name),
             UnsupportedContentType(t) => write!(fmt, "unsupported content-type: {:?} (supported: application/{{octet-stream,x-directory}})", t),
             ResponseSerialization(e) => write!(fmt, "progress serialization failed: {}", e),
-            Persisting(e) => write!(fmt, "put_block failed: {}", e),
+            Adding(e) => {
+                // the sources are included as the message is the only thing the client sees
+                write!(fmt, "{}", e)?;
+                let mut source = std::error::Error::source(e);
+                while let Some(e) = source {
+                    write!(fmt, ": {}", e)?;
+                    source = e.source();
+                }
+                Ok(())
+            }
             TreeGathering(g) => write!(fmt, "invalid directory tree: {}", g),
             TreeBuilding(b) => write!(fmt, "constructed invalid directory tree: {}", b),
         }
@@ -103,6 +119,7 @@ fn add_stream<St, E>(
     ipfs: Ipfs<impl IpfsTypes>,
     mut fields: MultipartStream<St, E>,
     opts: AddArgs,
+    add_opts: AddOptions,
 ) -> impl Stream<Item = Result<Bytes, AddError>> + Send + 'static
 where
     St: Stream<Item = Result<Bytes, E>> + Send + Unpin + 'static,
@@ -114,6 +131,7 @@ where
         if opts.wrap_with_directory {
             tree_opts.wrap_with_directory();
         }
+        tree_opts.cid_options(add_opts.cid_options);
 
         let mut tree = BufferingTreeBuilder::new(tree_opts);
         let mut buffer = BytesMut::new();
@@ -142,7 +160,7 @@ where
 
             let content_type = field.content_type().map_err(AddError::Header)?;
 
-            let next = match content_type {
+            match content_type {
                 "application/octet-stream" => {
 
                     // files are of the form "file-{1,2,3,..}"
@@ -152,69 +170,44 @@ where
                         Ok(())
                     }?;
 
-                    let mut adder = FileAdder::default();
-                    // how many bytes we have stored as blocks
-                    let mut total_written = 0u64;
-                    // how many bytes of input we have read
-                    let mut total_read = 0u64;
-
-                    loop {
-                        let next = field
-                            .try_next()
-                            .await
-                            .map_err(AddError::Parsing)?;
-
-                        match next {
-                            Some(next) => {
-                                let (read, saved_any, written) = push_all(&ipfs, &mut adder, next).await?;
-                                total_written += written;
-                                total_read += read;
-
-                                if saved_any && opts.progress {
-                                    // technically we could just send messages but that'd
-                                    // require us to stop using Cow's and use Arc<String> or
-                                    // similar. not especially fond of either.
-                                    serde_json::to_writer((&mut buffer).writer(), &Response::Progress {
-                                        name: Cow::Borrowed(&filename),
-                                        bytes: total_read,
-                                    }).map_err(AddError::ResponseSerialization)?;
-
-                                    buffer.put(&b"\r\n"[..]);
-                                    yield buffer.split().freeze();
+                    let input = tokio::io::stream_reader(
+                        (&mut field).map_err(|e| io::Error::new(io::ErrorKind::Other, e)),
+                    );
+
+                    let mut added = None;
+
+                    // the file is being added while the events are received
+                    {
+                        let events = ipfs.add_unixfs(input, add_opts.clone());
+                        futures::pin_mut!(events);
+
+                        while let Some(event) = events.try_next().await? {
+                            match event {
+                                AddEvent::Progress(bytes) => {
+                                    if opts.progress {
+                                        // in the interface-http-core tests the last progress is
+                                        // expected to be the full size
+                                        serde_json::to_writer((&mut buffer).writer(), &Response::Progress {
+                                            name: Cow::Borrowed(&filename),
+                                            bytes,
+                                        }).map_err(AddError::ResponseSerialization)?;
+
+                                        buffer.put(&b"\r\n"[..]);
+                                        yield buffer.split().freeze();
+                                    }
                                 }
+                                AddEvent::Added { cid, total_size, .. } => added = Some((cid, total_size)),
                             }
-                            None => break,
                         }
                     }
 
-                    if opts.progress {
-                        // in the interface-http-core tests the subtotal is expected to be full
-                        // size, ordering w.r.t. to the "added" is not specified
-                        serde_json::to_writer((&mut buffer).writer(), &Response::Progress {
-                            name: Cow::Borrowed(&filename),
-                            bytes: total_read,
-                        }).map_err(AddError::ResponseSerialization)?;
-
-                        buffer.put(&b"\r\n"[..]);
-
-                        // it is not required to yield here so perhaps we just accumulate the next
-                        // response in as well
-                    }
-
-                    let (root, subtotal) = import_all(&ipfs, adder.finish())
-                        .await
-                        .map_err(AddError::Persisting)?
-                        // there was a bug in ipfs-unixfs however in general the "push" operation
-                        // should flush so that the final finish would still have work to do.
-                        .expect("there should always be something from finish");
-
-                    total_written += subtotal;
+                    let (root, total_size) = added.expect("adding always ends in Added");
 
-                    tracing::trace!("completed processing file of {} bytes: {:?}", total_read, filename);
+                    tracing::trace!("completed processing file of {} bytes: {:?}", total_size, filename);
 
                     // using the filename as the path since we can tolerate a single empty named file
                     // however the second one will cause issues
-                    tree.put_link(&filename, root.clone(), total_written)
+                    tree.put_link(&filename, root.clone(), total_size)
                         .map_err(AddError::TreeGathering)?;
 
                     let filename: Cow<'_, str> = if filename.is_empty() {
@@ -230,12 +223,12 @@ where
                     serde_json::to_writer((&mut buffer).writer(), &Response::Added {
                         name: filename,
                         hash: Quoted(&root),
-                        size: Quoted(total_written),
+                        size: Quoted(total_size),
                     }).map_err(AddError::ResponseSerialization)?;
 
                     buffer.put(&b"\r\n"[..]);
 
-                    Ok(buffer.split().freeze())
+                    yield buffer.split().freeze();
                 },
                 "application/x-directory" => {
                     // dirs are of the form "dir-{1,2,3,..}"
@@ -254,87 +247,34 @@ where
                     // directory which is a good thing.
                     tree.set_metadata(&filename, ipfs::unixfs::ll::Metadata::default())
                         .map_err(AddError::TreeGathering)?;
-                    continue;
                 }
                 unsupported => {
-                    Err(AddError::UnsupportedContentType(unsupported.to_string()))
+                    Err(AddError::UnsupportedContentType(unsupported.to_string()))?;
                 }
-            }?;
-
-            yield next;
+            }
         }
 
-        let mut iter = tree.build();
-
-        while let Some(res) = iter.next_borrowed() {
-            let TreeNode { path, cid, total_size, block } = res.map_err(AddError::TreeBuilding)?;
-
-            // shame we need to allocate once again here..
-            ipfs.put_block(Block { cid: cid.to_owned(), data: block.into() }).await.map_err(AddError::Persisting)?;
+        let nodes = tree
+            .build()
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(AddError::TreeBuilding)?;
 
-            serde_json::to_writer((&mut buffer).writer(), &Response::Added {
-                name: Cow::Borrowed(path),
-                hash: Quoted(cid),
-                size: Quoted(total_size),
-            }).map_err(AddError::ResponseSerialization)?;
+        for event in ipfs::unixfs::store_directories(&ipfs, nodes).await? {
+            if let AddEvent::Added { name, cid, total_size } = event {
+                serde_json::to_writer((&mut buffer).writer(), &Response::Added {
+                    name: Cow::Owned(name),
+                    hash: Quoted(&cid),
+                    size: Quoted(total_size),
+                }).map_err(AddError::ResponseSerialization)?;
 
-            buffer.put(&b"\r\n"[..]);
+                buffer.put(&b"\r\n"[..]);
 
-            yield buffer.split().freeze();
+                yield buffer.split().freeze();
+            }
         }
     }
 }
 
-async fn push_all(
-    ipfs: &Ipfs<impl IpfsTypes>,
-    adder: &mut FileAdder,
-    next: Bytes,
-) -> Result<(u64, bool, u64), AddError> {
-    let mut read = 0usize;
-    let mut saved_any = false;
-    let mut total_written = 0;
-
-    while read < next.len() {
-        let (iter, used) = adder.push(&next.slice(read..));
-        read += used;
-
-        let maybe_tuple = import_all(&ipfs, iter)
-            .await
-            .map_err(AddError::Persisting)?;
-
-        let subtotal = maybe_tuple.map(|t| t.1);
-
-        total_written += subtotal.unwrap_or(0);
-
-        saved_any |= subtotal.is_some();
-    }
-
-    Ok((read as u64, saved_any, total_written))
-}
-
-async fn import_all(
-    ipfs: &Ipfs<impl IpfsTypes>,
-    iter: impl Iterator<Item = (Cid, Vec<u8>)>,
-) -> Result<Option<(Cid, u64)>, ipfs::Error> {
-    // TODO: use FuturesUnordered
-    let mut last: Option<Cid> = None;
-    let mut total = 0u64;
-
-    for (cid, data) in iter {
-        total += data.len() as u64;
-        let block = Block {
-            cid,
-            data: data.into_boxed_slice(),
-        };
-
-        let cid = ipfs.put_block(block).await?;
-
-        last = Some(cid);
-    }
-
-    Ok(last.map(|cid| (cid, total)))
-}
-
 /// The possible response messages from /add.
 #[derive(Debug, Serialize)]
 #[serde(untagged)] // rename_all="..." doesn't seem to work at this level
@@ -408,6 +348,105 @@ mod tests {
         );
     }
 
+    #[tokio::test(max_threads = 1)]
+    async fn add_single_block_file_cid_version_1() {
+        let ipfs = tokio_ipfs().await;
+
+        let response = warp::test::request()
+            .path("/add?cid-version=1")
+            .header(
+                "content-type",
+                "multipart/form-data; boundary=-----------------------------Z0oYi6XyTm7_x2L4ty8JL",
+            )
+            .body(
+                &b"-------------------------------Z0oYi6XyTm7_x2L4ty8JL\r\n\
+                    Content-Disposition: form-data; name=\"file\"; filename=\"testfile.txt\"\r\n\
+                    Content-Type: application/octet-stream\r\n\
+                    \r\n\
+                    Plz add me!\n\
+                    \r\n-------------------------------Z0oYi6XyTm7_x2L4ty8JL--\r\n"[..],
+            )
+            .reply(&add(&ipfs))
+            .await;
+
+        let body = std::str::from_utf8(response.body()).unwrap();
+
+        // raw leaves are implied by the cid version 1 like in go-ipfs
+        assert_eq!(
+            body,
+            "{\"Hash\":\"bafkreidffqfydlguosmmyebv5rp72m45tbpbq6segnkosa45kjfnduix6u\",\"Name\":\"testfile.txt\",\"Size\":\"12\"}\r\n"
+        );
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn add_wrapped_with_progress() {
+        let ipfs = tokio_ipfs().await;
+
+        let response = warp::test::request()
+            .path("/add?progress=true&wrap-with-directory=true")
+            .header(
+                "content-type",
+                "multipart/form-data; boundary=-----------------------------Z0oYi6XyTm7_x2L4ty8JL",
+            )
+            .body(
+                &b"-------------------------------Z0oYi6XyTm7_x2L4ty8JL\r\n\
+                    Content-Disposition: form-data; name=\"file\"; filename=\"testfile.txt\"\r\n\
+                    Content-Type: application/octet-stream\r\n\
+                    \r\n\
+                    Plz add me!\n\
+                    \r\n-------------------------------Z0oYi6XyTm7_x2L4ty8JL--\r\n"[..],
+            )
+            .reply(&add(&ipfs))
+            .await;
+
+        let body = std::str::from_utf8(response.body()).unwrap();
+        let lines = body.split_terminator("\r\n").collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 3, "{:?}", lines);
+        assert_eq!(lines[0], "{\"name\":\"testfile.txt\",\"bytes\":12}");
+        assert_eq!(
+            lines[1],
+            "{\"Hash\":\"Qma4hjFTnCasJ8PVp3mZbZK5g2vGDT4LByLJ7m8ciyRFZP\",\"Name\":\"testfile.txt\",\"Size\":\"20\"}"
+        );
+        assert!(lines[2].contains("\"Name\":\"\""), "{:?}", lines[2]);
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn add_rejects_cid_version_0_with_other_hash() {
+        let ipfs = tokio_ipfs().await;
+
+        let response = warp::test::request()
+            .path("/add?cid-version=0&hash=sha2-512")
+            .header(
+                "content-type",
+                "multipart/form-data; boundary=-----------------------------Z0oYi6XyTm7_x2L4ty8JL",
+            )
+            .body(&b"-------------------------------Z0oYi6XyTm7_x2L4ty8JL--\r\n"[..])
+            .reply(&add(&ipfs))
+            .await;
+
+        assert_ne!(response.status(), 200);
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn add_rejects_identity_hash() {
+        let ipfs = tokio_ipfs().await;
+
+        let response = warp::test::request()
+            .path("/add?hash=identity")
+            .header(
+                "content-type",
+                "multipart/form-data; boundary=-----------------------------Z0oYi6XyTm7_x2L4ty8JL",
+            )
+            .body(&b"-------------------------------Z0oYi6XyTm7_x2L4ty8JL--\r\n"[..])
+            .reply(&add(&ipfs))
+            .await;
+
+        assert_ne!(response.status(), 200);
+        let body = std::str::from_utf8(response.body()).unwrap();
+        assert!(body.contains("inline-limit"), "{:?}", body);
+    }
+
     async fn tokio_ipfs() -> ipfs::Ipfs<ipfs::TestTypes> {
         let options = ipfs::IpfsOptions::inmemory_with_generated_keys();
         let (ipfs, fut) = ipfs::UninitializedIpfs::new(options, None)
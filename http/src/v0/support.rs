@@ -18,6 +18,9 @@ pub use timeout::MaybeTimeoutExt;
 mod serdesupport;
 pub use serdesupport::StringSerialized;
 
+mod hash;
+pub use hash::multihash_code_by_name;
+
 /// The common responses apparently returned by the go-ipfs HTTP api on errors.
 /// See also: https://github.com/ferristseng/rust-ipfs-api/blob/master/ipfs-api/src/response/error.rs
 #[derive(Debug, Serialize)]
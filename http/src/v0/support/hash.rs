@@ -0,0 +1,45 @@
+use multihash::Code;
+
+/// Parses the multihash function names accepted by go-ipfs in the `mhtype` and `hash` query
+/// parameters. Returns `None` for unknown or unsupported names.
+pub fn multihash_code_by_name(name: &str) -> Option<Code> {
+    Some(match name {
+        // the blocks are not stored but synthesized from their Cid, see `ipfs::Block::from_identity`
+        "identity" => Code::Identity,
+        "sha1" => Code::Sha1,
+        "sha2-256" => Code::Sha2_256,
+        "sha2-512" => Code::Sha2_512,
+        "sha3-224" => Code::Sha3_224,
+        "sha3-256" => Code::Sha3_256,
+        "sha3-384" => Code::Sha3_384,
+        "sha3-512" => Code::Sha3_512,
+        "keccak-224" => Code::Keccak224,
+        "keccak-256" => Code::Keccak256,
+        "keccak-384" => Code::Keccak384,
+        "keccak-512" => Code::Keccak512,
+        "blake2b-256" => Code::Blake2b256,
+        "blake2b-512" => Code::Blake2b512,
+        "blake2s-128" => Code::Blake2s128,
+        "blake2s-256" => Code::Blake2s256,
+        "blake3" => Code::Blake3,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::multihash_code_by_name;
+    use multihash::Code;
+
+    #[test]
+    fn known_and_unknown_names() {
+        assert_eq!(multihash_code_by_name("sha2-256"), Some(Code::Sha2_256));
+        assert_eq!(
+            multihash_code_by_name("blake2b-256"),
+            Some(Code::Blake2b256)
+        );
+        assert_eq!(multihash_code_by_name("blake3"), Some(Code::Blake3));
+        assert_eq!(multihash_code_by_name("identity"), Some(Code::Identity));
+        assert_eq!(multihash_code_by_name("md5"), None);
+    }
+}
@@ -0,0 +1,213 @@
+//! Tar archives as UnixFS trees, see https://docs.ipfs.io/reference/http/api/#api-v0-tar-add
+use crate::v0::root_files::{resolve_dagpb, walk};
+use crate::v0::support::{with_ipfs, StreamResponse, StringError, StringSerialized};
+use bytes::{Buf, Bytes};
+use futures::stream::{Stream, TryStreamExt};
+use ipfs::unixfs::ll::dir::EntryType;
+use ipfs::unixfs::{AddEvent, AddTarOptions};
+use ipfs::{Ipfs, IpfsPath, IpfsTypes};
+use mime::Mime;
+use mpart_async::server::MultipartStream;
+use serde::Deserialize;
+use warp::{query, reply, Filter, Rejection, Reply};
+
+pub fn add<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(warp::header::<Mime>("content-type"))
+        .and(warp::body::stream())
+        .and_then(add_inner)
+}
+
+async fn add_inner<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    mime: Mime,
+    body: impl Stream<Item = Result<impl Buf, warp::Error>> + Send + Unpin + 'static,
+) -> Result<impl Reply, Rejection> {
+    let boundary = mime
+        .get_param("boundary")
+        .map(|v| v.to_string())
+        .ok_or_else(|| StringError::from("missing 'boundary' on content-type"))?;
+
+    let mut fields =
+        MultipartStream::new(Bytes::from(boundary), body.map_ok(|mut buf| buf.to_bytes()));
+
+    let field = fields
+        .try_next()
+        .await
+        .map_err(StringError::from)?
+        .ok_or_else(|| StringError::from("missing file in the request body"))?;
+
+    let name = field.filename().map(String::from).unwrap_or_default();
+
+    let input = tokio::io::stream_reader(Box::pin(
+        field.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string())),
+    ));
+
+    let root = ipfs
+        .add_tar(input, AddTarOptions::default())
+        .try_fold(None, |_, event| async move {
+            Ok(match event {
+                AddEvent::Added {
+                    cid, total_size, ..
+                } => Some((cid, total_size)),
+                AddEvent::Progress(_) => None,
+            })
+        })
+        .await
+        .map_err(StringError::from)?;
+
+    let (cid, total_size) = root.expect("adding always ends with the root");
+
+    Ok(reply::json(&serde_json::json!({
+        "Name": name,
+        "Hash": cid.to_string(),
+        "Size": total_size.to_string(),
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+struct CatArgs {
+    arg: StringSerialized<IpfsPath>,
+}
+
+pub fn cat<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs).and(query::<CatArgs>()).and_then(cat_inner)
+}
+
+async fn cat_inner<T: IpfsTypes>(ipfs: Ipfs<T>, args: CatArgs) -> Result<impl Reply, Rejection> {
+    let block = resolve_dagpb(&ipfs, args.arg.into_inner()).await?;
+
+    if EntryType::from_block(&block.cid, &block.data) != EntryType::Directory {
+        return Err(StringError::from("tar/cat requires a directory").into());
+    }
+
+    // the entries of the root directory are the top level entries of the archive, like in the
+    // archive added with tar/add
+    Ok(StreamResponse(
+        walk(ipfs, block, String::new()).into_stream(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::stream::TryStreamExt;
+    use ipfs::Node;
+    use std::io::Read;
+    use warp::Filter;
+
+    const BOUNDARY: &str = "-----------------------------Z0oYi6XyTm7_x2L4ty8JL";
+
+    #[tokio::test(max_threads = 1)]
+    async fn add_and_cat() {
+        let ipfs = Node::new("test_node").await;
+        let routes = routes(&ipfs);
+
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_mode(0o755);
+        header.set_size(0);
+        builder.append_data(&mut header, "dir/", &[][..]).unwrap();
+
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_size(7);
+        builder
+            .append_data(&mut header, "dir/a.txt", &b"foobar\n"[..])
+            .unwrap();
+        let archive = builder.into_inner().unwrap();
+
+        let mut body = format!(
+            "--{}\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"a.tar\"\r\n\
+            Content-Type: application/x-tar\r\n\
+            \r\n",
+            BOUNDARY
+        )
+        .into_bytes();
+        body.extend_from_slice(&archive);
+        body.extend_from_slice(format!("\r\n--{}--\r\n", BOUNDARY).as_bytes());
+
+        let response = warp::test::request()
+            .path("/tar/add")
+            .header(
+                "content-type",
+                format!("multipart/form-data; boundary={}", BOUNDARY),
+            )
+            .body(body)
+            .reply(&routes)
+            .await;
+
+        assert_eq!(response.status(), 200, "{:?}", response.body());
+        let body = serde_json::from_slice::<serde_json::Value>(response.body()).unwrap();
+        assert_eq!(body["Name"], "a.tar");
+        let root = body["Hash"].as_str().unwrap().to_owned();
+
+        let response = warp::test::request()
+            .path(&format!("/tar/cat?arg={}", root))
+            .reply(&routes)
+            .await;
+
+        assert_eq!(response.status(), 200, "{:?}", response.body());
+
+        let mut archive = tar::Archive::new(std::io::Cursor::new(response.body().to_vec()));
+        let mut entries = archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let path = entry.path().unwrap().to_string_lossy().into_owned();
+                let mut content = Vec::new();
+                entry.read_to_end(&mut content).unwrap();
+                (path, content)
+            })
+            .collect::<Vec<_>>();
+
+        entries.sort();
+
+        assert_eq!(
+            entries,
+            vec![
+                ("dir".to_owned(), Vec::new()),
+                ("dir/a.txt".to_owned(), b"foobar\n".to_vec())
+            ]
+        );
+
+        let file = ipfs
+            .cat_unixfs(
+                format!("/ipfs/{}/dir/a.txt", root)
+                    .parse::<ipfs::IpfsPath>()
+                    .unwrap(),
+                None,
+            )
+            .await
+            .unwrap()
+            .try_concat()
+            .await
+            .unwrap();
+        assert_eq!(file, b"foobar\n");
+
+        let response = warp::test::request()
+            .path(&format!("/tar/cat?arg=/ipfs/{}/dir/a.txt", root))
+            .reply(&routes)
+            .await;
+
+        assert_eq!(response.status(), 500);
+    }
+
+    fn routes(
+        ipfs: &ipfs::Ipfs<ipfs::TestTypes>,
+    ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        warp::path("tar")
+            .and(
+                warp::path!("add")
+                    .and(super::add(ipfs))
+                    .or(warp::path!("cat").and(super::cat(ipfs))),
+            )
+            .recover(crate::v0::recover_as_message_response)
+    }
+}
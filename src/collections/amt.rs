@@ -0,0 +1,570 @@
+//! Array mapped trie in the layout of the Filecoin AMT, version 3.
+//!
+//! The root block is a tuple of `bitWidth`, `height`, `count` and the root node. Every node is a
+//! tuple of a bitfield `bmap` and the `links` or `values` for the set bits: nodes at height zero
+//! hold the values and the others hold links to child nodes. Each node has `2^bitWidth` slots.
+
+use super::{integer, load_node, store_node, CollectionError};
+use crate::ipld::Ipld;
+use crate::{Ipfs, RepoTypes};
+use async_stream::stream;
+use cid::Cid;
+use futures::future::{BoxFuture, FutureExt};
+use futures::stream::Stream;
+
+/// Options for [`Amt::create`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AmtOptions {
+    /// The number of bits of the index used for the slot within each node, from 1 to 8. Defaults
+    /// to 3.
+    pub bit_width: u32,
+}
+
+impl Default for AmtOptions {
+    fn default() -> Self {
+        AmtOptions { bit_width: 3 }
+    }
+}
+
+/// A sparse array of IPLD values indexed by `u64`.
+pub struct Amt<Types: RepoTypes> {
+    ipfs: Ipfs<Types>,
+    root: Cid,
+    bit_width: u32,
+    height: u32,
+    count: u64,
+    node: Node,
+}
+
+impl<Types: RepoTypes> Amt<Types> {
+    /// Creates and stores an empty array.
+    pub async fn create(ipfs: Ipfs<Types>, opts: AmtOptions) -> Result<Self, CollectionError> {
+        if opts.bit_width < 1 || opts.bit_width > 8 {
+            return Err(CollectionError::UnsupportedOptions(
+                "bit width must be between 1 and 8",
+            ));
+        }
+
+        let node = Node::empty(opts.bit_width);
+        let root = store_root(&ipfs, opts.bit_width, 0, 0, &node).await?;
+
+        Ok(Amt {
+            ipfs,
+            root,
+            bit_width: opts.bit_width,
+            height: 0,
+            count: 0,
+            node,
+        })
+    }
+
+    /// Loads a previously stored array.
+    pub async fn load(ipfs: Ipfs<Types>, root: Cid) -> Result<Self, CollectionError> {
+        let invalid = |reason| CollectionError::InvalidNode(root.clone(), reason);
+
+        let mut fields = match load_node(&ipfs, &root).await? {
+            Ipld::List(fields) if fields.len() == 4 => fields,
+            _ => return Err(invalid("root is not a tuple of four")),
+        };
+
+        let bit_width = integer::<u32>(fields.first())
+            .filter(|bw| (1..=8).contains(bw))
+            .ok_or_else(|| invalid("invalid bitWidth"))?;
+        let height = integer::<u32>(fields.get(1))
+            .filter(|&height| height.checked_mul(bit_width).is_some_and(|bits| bits < 64))
+            .ok_or_else(|| invalid("invalid height"))?;
+        let count = integer::<u64>(fields.get(2)).ok_or_else(|| invalid("invalid count"))?;
+
+        let node = fields
+            .pop()
+            .and_then(|node| Node::from_ipld(node, bit_width, height == 0))
+            .ok_or_else(|| invalid("invalid amt node"))?;
+
+        Ok(Amt {
+            ipfs,
+            root,
+            bit_width,
+            height,
+            count,
+            node,
+        })
+    }
+
+    /// Returns the `Cid` of the current root block.
+    pub fn root(&self) -> &Cid {
+        &self.root
+    }
+
+    /// Returns the number of values in the array.
+    pub fn len(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns true if the array has no values.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Returns the value at the index, if any.
+    pub async fn get(&self, index: u64) -> Result<Option<Ipld>, CollectionError> {
+        if !self.fits(index) {
+            return Ok(None);
+        }
+
+        let mut loaded;
+        let mut node = &self.node;
+        let mut height = self.height;
+
+        loop {
+            let slot = self.slot(index, height);
+
+            if !node.is_set(slot) {
+                return Ok(None);
+            }
+
+            let pos = node.position(slot);
+
+            if height == 0 {
+                return Ok(Some(node.values[pos].to_owned()));
+            }
+
+            loaded = self.load_child(&node.links[pos], height - 1).await?;
+            node = &loaded;
+            height -= 1;
+        }
+    }
+
+    /// Inserts the value at the index, storing the changed nodes and a new root. Returns the
+    /// previous value, if any.
+    pub async fn insert(
+        &mut self,
+        index: u64,
+        value: Ipld,
+    ) -> Result<Option<Ipld>, CollectionError> {
+        let mut node = self.node.clone();
+        let mut height = self.height;
+
+        // grow until the index fits, moving the current root to the first slot of the new root
+        while !fits(index, self.bit_width, height) {
+            if !node.is_empty() {
+                let cid = store_node(&self.ipfs, &node.to_ipld()).await?;
+                node = Node::empty(self.bit_width);
+                node.set(0, true);
+                node.links.push(cid);
+            }
+            height += 1;
+        }
+
+        let (node, previous) = self.insert_into(node, height, index, value).await?;
+        let count = self.count + if previous.is_none() { 1 } else { 0 };
+
+        self.update_root(node, height, count).await?;
+        Ok(previous)
+    }
+
+    /// Deletes the value at the index, storing the changed nodes and a new root. Returns the
+    /// removed value, if any.
+    pub async fn delete(&mut self, index: u64) -> Result<Option<Ipld>, CollectionError> {
+        if !self.fits(index) {
+            return Ok(None);
+        }
+
+        let (mut node, removed) = self
+            .remove_from(self.node.clone(), self.height, index)
+            .await?;
+
+        if removed.is_none() {
+            return Ok(None);
+        }
+
+        let mut height = self.height;
+
+        // shrink while only the first slot of the root is in use
+        while height > 0 && (node.is_empty() || (node.links.len() == 1 && node.is_set(0))) {
+            node = match node.links.pop() {
+                Some(cid) => self.load_child(&cid, height - 1).await?,
+                None => Node::empty(self.bit_width),
+            };
+            height -= 1;
+        }
+
+        self.update_root(node, height, self.count - 1).await?;
+        Ok(removed)
+    }
+
+    /// Streams all of the index-value pairs in the order of the indices.
+    pub fn iter(
+        &self,
+    ) -> impl Stream<Item = Result<(u64, Ipld), CollectionError>> + Send + 'static {
+        let ipfs = self.ipfs.clone();
+        let bit_width = self.bit_width;
+        let mut stack = vec![(self.node.clone(), self.height, 0u64)];
+
+        stream! {
+            while let Some((node, height, offset)) = stack.pop() {
+                let slots = (0..1 << bit_width)
+                    .filter(|&slot| node.is_set(slot))
+                    .collect::<Vec<_>>();
+                let stride = |slot: usize| (slot as u64) << (bit_width * height);
+
+                if height == 0 {
+                    for (slot, value) in slots.into_iter().zip(node.values) {
+                        yield Ok((offset + stride(slot), value));
+                    }
+                    continue;
+                }
+
+                let mut children = Vec::with_capacity(node.links.len());
+
+                for (slot, cid) in slots.into_iter().zip(node.links) {
+                    let child = match load_node(&ipfs, &cid).await {
+                        Ok(ipld) => Node::from_ipld(ipld, bit_width, height == 1),
+                        Err(e) => {
+                            yield Err(e);
+                            return;
+                        }
+                    };
+
+                    match child {
+                        Some(child) => children.push((child, height - 1, offset + stride(slot))),
+                        None => {
+                            yield Err(CollectionError::InvalidNode(cid, "invalid amt node"));
+                            return;
+                        }
+                    }
+                }
+
+                stack.extend(children.into_iter().rev());
+            }
+        }
+    }
+
+    fn insert_into(
+        &self,
+        mut node: Node,
+        height: u32,
+        index: u64,
+        value: Ipld,
+    ) -> BoxFuture<'_, Result<(Node, Option<Ipld>), CollectionError>> {
+        async move {
+            let slot = self.slot(index, height);
+            let pos = node.position(slot);
+            let set = node.is_set(slot);
+
+            if height == 0 {
+                if set {
+                    let previous = std::mem::replace(&mut node.values[pos], value);
+                    return Ok((node, Some(previous)));
+                }
+
+                node.set(slot, true);
+                node.values.insert(pos, value);
+                return Ok((node, None));
+            }
+
+            let child = if set {
+                self.load_child(&node.links[pos], height - 1).await?
+            } else {
+                Node::empty(self.bit_width)
+            };
+
+            let (child, previous) = self.insert_into(child, height - 1, index, value).await?;
+            let cid = store_node(&self.ipfs, &child.to_ipld()).await?;
+
+            if set {
+                node.links[pos] = cid;
+            } else {
+                node.set(slot, true);
+                node.links.insert(pos, cid);
+            }
+
+            Ok((node, previous))
+        }
+        .boxed()
+    }
+
+    fn remove_from(
+        &self,
+        mut node: Node,
+        height: u32,
+        index: u64,
+    ) -> BoxFuture<'_, Result<(Node, Option<Ipld>), CollectionError>> {
+        async move {
+            let slot = self.slot(index, height);
+
+            if !node.is_set(slot) {
+                return Ok((node, None));
+            }
+
+            let pos = node.position(slot);
+
+            if height == 0 {
+                node.set(slot, false);
+                let removed = node.values.remove(pos);
+                return Ok((node, Some(removed)));
+            }
+
+            let child = self.load_child(&node.links[pos], height - 1).await?;
+            let (child, removed) = self.remove_from(child, height - 1, index).await?;
+
+            if removed.is_none() {
+                return Ok((node, None));
+            }
+
+            if child.is_empty() {
+                node.set(slot, false);
+                node.links.remove(pos);
+            } else {
+                node.links[pos] = store_node(&self.ipfs, &child.to_ipld()).await?;
+            }
+
+            Ok((node, removed))
+        }
+        .boxed()
+    }
+
+    fn fits(&self, index: u64) -> bool {
+        fits(index, self.bit_width, self.height)
+    }
+
+    fn slot(&self, index: u64, height: u32) -> usize {
+        let shift = self.bit_width * height;
+
+        if shift >= 64 {
+            0
+        } else {
+            ((index >> shift) & ((1 << self.bit_width) - 1)) as usize
+        }
+    }
+
+    async fn load_child(&self, cid: &Cid, height: u32) -> Result<Node, CollectionError> {
+        Node::from_ipld(
+            load_node(&self.ipfs, cid).await?,
+            self.bit_width,
+            height == 0,
+        )
+        .ok_or_else(|| CollectionError::InvalidNode(cid.to_owned(), "invalid amt node"))
+    }
+
+    async fn update_root(
+        &mut self,
+        node: Node,
+        height: u32,
+        count: u64,
+    ) -> Result<(), CollectionError> {
+        self.root = store_root(&self.ipfs, self.bit_width, height, count, &node).await?;
+        self.node = node;
+        self.height = height;
+        self.count = count;
+        Ok(())
+    }
+}
+
+/// Returns true if the index fits into a tree of the given height.
+fn fits(index: u64, bit_width: u32, height: u32) -> bool {
+    let bits = bit_width * (height + 1);
+    bits >= 64 || index < 1 << bits
+}
+
+/// Returns the number of bytes needed for the bitfield of `2^bit_width` slots.
+fn bmap_len(bit_width: u32) -> usize {
+    std::cmp::max(1, (1 << bit_width) / 8)
+}
+
+async fn store_root<Types: RepoTypes>(
+    ipfs: &Ipfs<Types>,
+    bit_width: u32,
+    height: u32,
+    count: u64,
+    node: &Node,
+) -> Result<Cid, CollectionError> {
+    let root = Ipld::List(vec![
+        Ipld::Integer(bit_width.into()),
+        Ipld::Integer(height.into()),
+        Ipld::Integer(count.into()),
+        node.to_ipld(),
+    ]);
+
+    store_node(ipfs, &root).await
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Node {
+    /// Bitfield of the set slots, with the first slot in the least significant bit of the first
+    /// byte.
+    bmap: Vec<u8>,
+    links: Vec<Cid>,
+    values: Vec<Ipld>,
+}
+
+impl Node {
+    fn empty(bit_width: u32) -> Self {
+        Node {
+            bmap: vec![0; bmap_len(bit_width)],
+            links: Vec::new(),
+            values: Vec::new(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.links.is_empty() && self.values.is_empty()
+    }
+
+    fn is_set(&self, slot: usize) -> bool {
+        self.bmap[slot / 8] & (1 << (slot % 8)) != 0
+    }
+
+    fn set(&mut self, slot: usize, value: bool) {
+        if value {
+            self.bmap[slot / 8] |= 1 << (slot % 8);
+        } else {
+            self.bmap[slot / 8] &= !(1 << (slot % 8));
+        }
+    }
+
+    /// Returns the position in `links` or `values` of the slot.
+    fn position(&self, slot: usize) -> usize {
+        (0..slot).filter(|&i| self.is_set(i)).count()
+    }
+
+    fn to_ipld(&self) -> Ipld {
+        Ipld::List(vec![
+            Ipld::Bytes(self.bmap.clone()),
+            Ipld::List(self.links.iter().cloned().map(Ipld::Link).collect()),
+            Ipld::List(self.values.clone()),
+        ])
+    }
+
+    fn from_ipld(ipld: Ipld, bit_width: u32, leaf: bool) -> Option<Self> {
+        let mut fields = match ipld {
+            Ipld::List(fields) if fields.len() == 3 => fields.into_iter(),
+            _ => return None,
+        };
+
+        let (bmap, links, values) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(Ipld::Bytes(bmap)), Some(Ipld::List(links)), Some(Ipld::List(values))) => {
+                (bmap, links, values)
+            }
+            _ => return None,
+        };
+
+        let links = links
+            .into_iter()
+            .map(|link| match link {
+                Ipld::Link(cid) => Some(cid),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        let node = Node {
+            bmap,
+            links,
+            values,
+        };
+
+        let slots = 1usize << bit_width;
+        if node.bmap.len() != bmap_len(bit_width) {
+            return None;
+        }
+
+        let set = (0..node.bmap.len() * 8).filter(|&i| node.is_set(i)).count();
+        let expected = if leaf {
+            node.links.is_empty() && node.values.len() == set
+        } else {
+            node.values.is_empty() && node.links.len() == set
+        };
+
+        if !expected || (slots..node.bmap.len() * 8).any(|i| node.is_set(i)) {
+            return None;
+        }
+
+        Some(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Node as TestNode;
+    use futures::stream::TryStreamExt;
+
+    #[tokio::test(max_threads = 1)]
+    async fn insert_get_delete() {
+        let TestNode { ipfs, bg_task: _bt } = TestNode::new("test_node").await;
+
+        let mut amt = Amt::create(ipfs.clone(), AmtOptions { bit_width: 2 })
+            .await
+            .unwrap();
+        let empty = amt.root().to_owned();
+
+        let indices = [0u64, 1, 5, 17, 64, 1000, u64::MAX];
+        for &i in &indices {
+            assert_eq!(amt.insert(i, Ipld::from(i)).await.unwrap(), None);
+        }
+        assert_eq!(
+            amt.insert(5, Ipld::from("five")).await.unwrap(),
+            Some(Ipld::from(5u64))
+        );
+
+        let mut amt = Amt::load(ipfs.clone(), amt.root().to_owned())
+            .await
+            .unwrap();
+        assert_eq!(amt.len(), indices.len() as u64);
+        assert_eq!(amt.get(5).await.unwrap(), Some(Ipld::from("five")));
+        assert_eq!(amt.get(1000).await.unwrap(), Some(Ipld::from(1000u64)));
+        assert_eq!(amt.get(999).await.unwrap(), None);
+        assert_eq!(amt.get(u64::MAX).await.unwrap(), Some(Ipld::from(u64::MAX)));
+
+        let all = amt.iter().try_collect::<Vec<_>>().await.unwrap();
+        assert_eq!(
+            all.iter().map(|(i, _)| *i).collect::<Vec<_>>(),
+            indices.to_vec()
+        );
+
+        for &i in indices.iter().rev() {
+            assert!(amt.delete(i).await.unwrap().is_some());
+        }
+        assert_eq!(amt.delete(0).await.unwrap(), None);
+        assert!(amt.is_empty());
+
+        // deleting everything shrinks back into the empty array
+        assert_eq!(amt.root(), &empty);
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn shrinks_after_delete() {
+        let TestNode { ipfs, bg_task: _bt } = TestNode::new("test_node").await;
+
+        let mut small = Amt::create(ipfs.clone(), AmtOptions::default())
+            .await
+            .unwrap();
+        small.insert(3, Ipld::Null).await.unwrap();
+
+        let mut grown = Amt::create(ipfs, AmtOptions::default()).await.unwrap();
+        grown.insert(3, Ipld::Null).await.unwrap();
+        grown.insert(100, Ipld::Null).await.unwrap();
+        grown.delete(100).await.unwrap();
+
+        assert_eq!(grown.root(), small.root());
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn load_rejects_too_high_roots() {
+        let TestNode { ipfs, bg_task: _bt } = TestNode::new("test_node").await;
+
+        let root = ipfs
+            .put_dag(Ipld::List(vec![
+                Ipld::from(8u32),
+                Ipld::from(u32::MAX),
+                Ipld::from(0u64),
+                Ipld::Null,
+            ]))
+            .await
+            .unwrap();
+
+        match Amt::load(ipfs, root).await {
+            Err(CollectionError::InvalidNode(_, reason)) => assert_eq!(reason, "invalid height"),
+            other => panic!("unexpected result: {:?}", other.map(|amt| amt.len())),
+        }
+    }
+}
@@ -0,0 +1,592 @@
+//! HashMap per the IPLD HashMap specification, using sha2-256 to hash the keys.
+//!
+//! The root block is a map of `hashAlg`, `bucketSize` and the root node under `hamt`. Every node
+//! is a tuple of a bitfield `map` and the `data` elements for the set bits, each element being
+//! either a link to a child node or a bucket of at most `bucketSize` key-value pairs sorted by key.
+
+use super::{integer, load_node, store_node, CollectionError};
+use crate::ipld::Ipld;
+use crate::{Ipfs, RepoTypes};
+use async_stream::stream;
+use cid::Cid;
+use futures::future::{BoxFuture, FutureExt};
+use futures::stream::Stream;
+use multihash::Sha2_256;
+use std::collections::BTreeMap;
+
+/// Multicodec code of sha2-256, the only supported `hashAlg`.
+const SHA2_256: i128 = 0x12;
+
+/// Options for [`Hamt::create`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HamtOptions {
+    /// The number of bits of the hash used to index the elements of each node, from 3 to 8.
+    /// Defaults to 8.
+    pub bit_width: u32,
+    /// The maximum number of entries in a bucket before the bucket is replaced by a child node.
+    /// Defaults to 3.
+    pub bucket_size: usize,
+}
+
+impl Default for HamtOptions {
+    fn default() -> Self {
+        HamtOptions {
+            bit_width: 8,
+            bucket_size: 3,
+        }
+    }
+}
+
+/// A map of byte keys to IPLD values.
+pub struct Hamt<Types: RepoTypes> {
+    ipfs: Ipfs<Types>,
+    root: Cid,
+    bit_width: u32,
+    bucket_size: usize,
+    node: Node,
+}
+
+impl<Types: RepoTypes> Hamt<Types> {
+    /// Creates and stores an empty map.
+    pub async fn create(ipfs: Ipfs<Types>, opts: HamtOptions) -> Result<Self, CollectionError> {
+        if opts.bit_width < 3 || opts.bit_width > 8 {
+            return Err(CollectionError::UnsupportedOptions(
+                "bit width must be between 3 and 8",
+            ));
+        }
+
+        if opts.bucket_size == 0 {
+            return Err(CollectionError::UnsupportedOptions(
+                "bucket size must be positive",
+            ));
+        }
+
+        let node = Node::empty(opts.bit_width);
+        let root = store_root(&ipfs, opts.bucket_size, &node).await?;
+
+        Ok(Hamt {
+            ipfs,
+            root,
+            bit_width: opts.bit_width,
+            bucket_size: opts.bucket_size,
+            node,
+        })
+    }
+
+    /// Loads a previously stored map.
+    pub async fn load(ipfs: Ipfs<Types>, root: Cid) -> Result<Self, CollectionError> {
+        let invalid = |reason| CollectionError::InvalidNode(root.clone(), reason);
+
+        let mut doc = match load_node(&ipfs, &root).await? {
+            Ipld::Map(map) => map,
+            _ => return Err(invalid("root is not a map")),
+        };
+
+        if integer::<i128>(doc.get("hashAlg")) != Some(SHA2_256) {
+            return Err(invalid("unsupported hashAlg"));
+        }
+
+        let bucket_size = integer::<usize>(doc.get("bucketSize"))
+            .filter(|&size| size > 0)
+            .ok_or_else(|| invalid("invalid bucketSize"))?;
+
+        let node = doc
+            .remove("hamt")
+            .and_then(Node::from_ipld)
+            .ok_or_else(|| invalid("invalid hamt node"))?;
+
+        let bit_width = node.bit_width().ok_or_else(|| invalid("invalid map"))?;
+
+        Ok(Hamt {
+            ipfs,
+            root,
+            bit_width,
+            bucket_size,
+            node,
+        })
+    }
+
+    /// Returns the `Cid` of the current root block.
+    pub fn root(&self) -> &Cid {
+        &self.root
+    }
+
+    /// Returns the value for the key, if any.
+    pub async fn get(&self, key: &[u8]) -> Result<Option<Ipld>, CollectionError> {
+        let hash = hash(key);
+        let mut loaded;
+        let mut node = &self.node;
+        let mut depth = 0;
+
+        loop {
+            let index = hash_index(&hash, depth, self.bit_width)?;
+
+            if !node.is_set(index) {
+                return Ok(None);
+            }
+
+            match &node.data[node.position(index)] {
+                Element::Bucket(entries) => {
+                    return Ok(entries
+                        .iter()
+                        .find(|(k, _)| k.as_slice() == key)
+                        .map(|(_, v)| v.to_owned()));
+                }
+                Element::Link(cid) => {
+                    loaded = self.load_child(cid).await?;
+                    node = &loaded;
+                    depth += 1;
+                }
+            }
+        }
+    }
+
+    /// Inserts the value for the key, storing the changed nodes and a new root. Returns the
+    /// previous value, if any.
+    pub async fn insert(
+        &mut self,
+        key: Vec<u8>,
+        value: Ipld,
+    ) -> Result<Option<Ipld>, CollectionError> {
+        let (node, previous) = self.insert_into(self.node.clone(), 0, key, value).await?;
+        self.update_root(node).await?;
+        Ok(previous)
+    }
+
+    /// Deletes the key, storing the changed nodes and a new root. Returns the removed value, if
+    /// any.
+    pub async fn delete(&mut self, key: &[u8]) -> Result<Option<Ipld>, CollectionError> {
+        let (node, removed) = self.remove_from(self.node.clone(), 0, key).await?;
+
+        if removed.is_some() {
+            self.update_root(node).await?;
+        }
+
+        Ok(removed)
+    }
+
+    /// Streams all of the key-value pairs in the order of their hashes.
+    pub fn iter(
+        &self,
+    ) -> impl Stream<Item = Result<(Vec<u8>, Ipld), CollectionError>> + Send + 'static {
+        let ipfs = self.ipfs.clone();
+        let mut stack = self.node.data.iter().rev().cloned().collect::<Vec<_>>();
+
+        stream! {
+            while let Some(element) = stack.pop() {
+                match element {
+                    Element::Bucket(entries) => {
+                        for entry in entries {
+                            yield Ok(entry);
+                        }
+                    }
+                    Element::Link(cid) => {
+                        let node = match load_node(&ipfs, &cid).await {
+                            Ok(ipld) => Node::from_ipld(ipld),
+                            Err(e) => {
+                                yield Err(e);
+                                return;
+                            }
+                        };
+
+                        let node = match node {
+                            Some(node) => node,
+                            None => {
+                                yield Err(CollectionError::InvalidNode(cid, "invalid hamt node"));
+                                return;
+                            }
+                        };
+
+                        stack.extend(node.data.into_iter().rev());
+                    }
+                }
+            }
+        }
+    }
+
+    fn insert_into(
+        &self,
+        mut node: Node,
+        depth: u32,
+        key: Vec<u8>,
+        value: Ipld,
+    ) -> BoxFuture<'_, Result<(Node, Option<Ipld>), CollectionError>> {
+        async move {
+            let index = hash_index(&hash(&key), depth, self.bit_width)?;
+            let pos = node.position(index);
+
+            if !node.is_set(index) {
+                node.set(index, true);
+                node.data.insert(pos, Element::Bucket(vec![(key, value)]));
+                return Ok((node, None));
+            }
+
+            let child = match &mut node.data[pos] {
+                Element::Bucket(entries) => {
+                    match entries.binary_search_by(|(k, _)| k.cmp(&key)) {
+                        Ok(i) => {
+                            let previous = std::mem::replace(&mut entries[i].1, value);
+                            return Ok((node, Some(previous)));
+                        }
+                        Err(i) if entries.len() < self.bucket_size => {
+                            entries.insert(i, (key, value));
+                            return Ok((node, None));
+                        }
+                        Err(_) => {
+                            // the bucket overflows and is replaced by a child node holding all of
+                            // the entries one level deeper
+                            let entries = std::mem::take(entries);
+                            let mut child = Node::empty(self.bit_width);
+                            for (k, v) in entries.into_iter().chain(std::iter::once((key, value))) {
+                                child = self.insert_into(child, depth + 1, k, v).await?.0;
+                            }
+                            node.data[pos] = Element::Link(self.store_child(&child).await?);
+                            return Ok((node, None));
+                        }
+                    }
+                }
+                Element::Link(cid) => self.load_child(&cid.to_owned()).await?,
+            };
+
+            let (child, previous) = self.insert_into(child, depth + 1, key, value).await?;
+            node.data[pos] = Element::Link(self.store_child(&child).await?);
+            Ok((node, previous))
+        }
+        .boxed()
+    }
+
+    fn remove_from<'a>(
+        &'a self,
+        mut node: Node,
+        depth: u32,
+        key: &'a [u8],
+    ) -> BoxFuture<'a, Result<(Node, Option<Ipld>), CollectionError>> {
+        async move {
+            let index = hash_index(&hash(key), depth, self.bit_width)?;
+            let pos = node.position(index);
+
+            if !node.is_set(index) {
+                return Ok((node, None));
+            }
+
+            let child = match &mut node.data[pos] {
+                Element::Bucket(entries) => {
+                    let removed = match entries.binary_search_by(|(k, _)| k.as_slice().cmp(key)) {
+                        Ok(i) => entries.remove(i).1,
+                        Err(_) => return Ok((node, None)),
+                    };
+
+                    if entries.is_empty() {
+                        node.data.remove(pos);
+                        node.set(index, false);
+                    }
+
+                    return Ok((node, Some(removed)));
+                }
+                Element::Link(cid) => self.load_child(&cid.to_owned()).await?,
+            };
+
+            let (child, removed) = self.remove_from(child, depth + 1, key).await?;
+
+            if removed.is_none() {
+                return Ok((node, None));
+            }
+
+            // keep the structure canonical by collapsing a child without links back into a bucket
+            // when all of its entries fit into one
+            match child.collapse(self.bucket_size) {
+                Some(entries) if entries.is_empty() => {
+                    node.data.remove(pos);
+                    node.set(index, false);
+                }
+                Some(entries) => node.data[pos] = Element::Bucket(entries),
+                None => node.data[pos] = Element::Link(self.store_child(&child).await?),
+            }
+
+            Ok((node, removed))
+        }
+        .boxed()
+    }
+
+    async fn load_child(&self, cid: &Cid) -> Result<Node, CollectionError> {
+        Node::from_ipld(load_node(&self.ipfs, cid).await?)
+            .filter(|node| node.bit_width() == Some(self.bit_width))
+            .ok_or_else(|| CollectionError::InvalidNode(cid.to_owned(), "invalid hamt node"))
+    }
+
+    async fn store_child(&self, node: &Node) -> Result<Cid, CollectionError> {
+        store_node(&self.ipfs, &node.to_ipld()).await
+    }
+
+    async fn update_root(&mut self, node: Node) -> Result<(), CollectionError> {
+        self.root = store_root(&self.ipfs, self.bucket_size, &node).await?;
+        self.node = node;
+        Ok(())
+    }
+}
+
+async fn store_root<Types: RepoTypes>(
+    ipfs: &Ipfs<Types>,
+    bucket_size: usize,
+    node: &Node,
+) -> Result<Cid, CollectionError> {
+    let mut root = BTreeMap::new();
+    root.insert("hashAlg".to_owned(), Ipld::Integer(SHA2_256));
+    root.insert("bucketSize".to_owned(), Ipld::Integer(bucket_size as i128));
+    root.insert("hamt".to_owned(), node.to_ipld());
+
+    store_node(ipfs, &Ipld::Map(root)).await
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Node {
+    /// Bitfield of the set indices, read as a big-endian integer.
+    map: Vec<u8>,
+    data: Vec<Element>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Element {
+    Link(Cid),
+    Bucket(Vec<(Vec<u8>, Ipld)>),
+}
+
+impl Node {
+    fn empty(bit_width: u32) -> Self {
+        Node {
+            map: vec![0; (1 << bit_width) / 8],
+            data: Vec::new(),
+        }
+    }
+
+    fn bit_width(&self) -> Option<u32> {
+        let bits = self.map.len() * 8;
+
+        if bits.is_power_of_two() {
+            Some(bits.trailing_zeros()).filter(|bw| (3..=8).contains(bw))
+        } else {
+            None
+        }
+    }
+
+    fn is_set(&self, index: usize) -> bool {
+        self.map[self.map.len() - 1 - index / 8] & (1 << (index % 8)) != 0
+    }
+
+    fn set(&mut self, index: usize, value: bool) {
+        let len = self.map.len();
+        let byte = &mut self.map[len - 1 - index / 8];
+
+        if value {
+            *byte |= 1 << (index % 8);
+        } else {
+            *byte &= !(1 << (index % 8));
+        }
+    }
+
+    /// Returns the position in `data` of the element for the index.
+    fn position(&self, index: usize) -> usize {
+        (0..index).filter(|&i| self.is_set(i)).count()
+    }
+
+    /// Returns all of the entries sorted by key when the node has no links and at most
+    /// `bucket_size` entries.
+    fn collapse(&self, bucket_size: usize) -> Option<Vec<(Vec<u8>, Ipld)>> {
+        let mut entries = Vec::new();
+
+        for element in &self.data {
+            match element {
+                Element::Bucket(bucket) => entries.extend(bucket.iter().cloned()),
+                Element::Link(_) => return None,
+            }
+        }
+
+        if entries.len() > bucket_size {
+            return None;
+        }
+
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        Some(entries)
+    }
+
+    fn to_ipld(&self) -> Ipld {
+        let data = self
+            .data
+            .iter()
+            .map(|element| match element {
+                Element::Link(cid) => Ipld::Link(cid.to_owned()),
+                Element::Bucket(entries) => Ipld::List(
+                    entries
+                        .iter()
+                        .map(|(k, v)| Ipld::List(vec![Ipld::Bytes(k.to_owned()), v.to_owned()]))
+                        .collect(),
+                ),
+            })
+            .collect();
+
+        Ipld::List(vec![Ipld::Bytes(self.map.clone()), Ipld::List(data)])
+    }
+
+    fn from_ipld(ipld: Ipld) -> Option<Self> {
+        let mut fields = match ipld {
+            Ipld::List(fields) if fields.len() == 2 => fields.into_iter(),
+            _ => return None,
+        };
+
+        let (map, data) = match (fields.next(), fields.next()) {
+            (Some(Ipld::Bytes(map)), Some(Ipld::List(data))) => (map, data),
+            _ => return None,
+        };
+
+        let data = data
+            .into_iter()
+            .map(|element| match element {
+                Ipld::Link(cid) => Some(Element::Link(cid)),
+                Ipld::List(entries) if !entries.is_empty() => entries
+                    .into_iter()
+                    .map(|entry| match entry {
+                        Ipld::List(mut pair) if pair.len() == 2 => {
+                            let value = pair.pop()?;
+                            match pair.pop()? {
+                                Ipld::Bytes(key) => Some((key, value)),
+                                _ => None,
+                            }
+                        }
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<_>>>()
+                    .map(Element::Bucket),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        let node = Node { map, data };
+        node.bit_width()?;
+
+        let set = (0..node.map.len() * 8).filter(|&i| node.is_set(i)).count();
+        if set != node.data.len() {
+            return None;
+        }
+
+        Some(node)
+    }
+}
+
+fn hash(key: &[u8]) -> Vec<u8> {
+    Sha2_256::digest(key).digest().to_vec()
+}
+
+/// Reads the `bit_width` bits of the hash used as the index at the given depth, starting from the
+/// most significant bit of the first byte.
+fn hash_index(hash: &[u8], depth: u32, bit_width: u32) -> Result<usize, CollectionError> {
+    let start = (depth * bit_width) as usize;
+    let end = start + bit_width as usize;
+
+    if end > hash.len() * 8 {
+        return Err(CollectionError::HashExhausted);
+    }
+
+    Ok((start..end).fold(0, |index, bit| {
+        (index << 1) | ((hash[bit / 8] >> (7 - bit % 8)) & 1) as usize
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Node as TestNode;
+    use futures::stream::TryStreamExt;
+
+    #[test]
+    fn hash_index_bits() {
+        let hash = [0b1010_1100, 0b0111_0000];
+        assert_eq!(hash_index(&hash, 0, 3).unwrap(), 0b101);
+        assert_eq!(hash_index(&hash, 1, 3).unwrap(), 0b011);
+        assert_eq!(hash_index(&hash, 2, 3).unwrap(), 0b000);
+        assert_eq!(hash_index(&hash, 3, 3).unwrap(), 0b111);
+        hash_index(&hash, 5, 3).unwrap_err();
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn insert_get_delete() {
+        let TestNode { ipfs, bg_task: _bt } = TestNode::new("test_node").await;
+
+        let opts = HamtOptions {
+            bit_width: 3,
+            bucket_size: 1,
+        };
+        let mut hamt = Hamt::create(ipfs.clone(), opts).await.unwrap();
+        let empty = hamt.root().to_owned();
+
+        for i in 0..100u32 {
+            let previous = hamt
+                .insert(i.to_be_bytes().to_vec(), Ipld::Integer(i.into()))
+                .await
+                .unwrap();
+            assert_eq!(previous, None);
+        }
+
+        let previous = hamt
+            .insert(7u32.to_be_bytes().to_vec(), Ipld::from("seven"))
+            .await
+            .unwrap();
+        assert_eq!(previous, Some(Ipld::Integer(7)));
+
+        let hamt = Hamt::load(ipfs.clone(), hamt.root().to_owned())
+            .await
+            .unwrap();
+        assert_eq!(
+            hamt.get(&7u32.to_be_bytes()).await.unwrap(),
+            Some(Ipld::from("seven"))
+        );
+        assert_eq!(
+            hamt.get(&42u32.to_be_bytes()).await.unwrap(),
+            Some(Ipld::Integer(42))
+        );
+        assert_eq!(hamt.get(&100u32.to_be_bytes()).await.unwrap(), None);
+
+        let mut all = hamt.iter().try_collect::<Vec<_>>().await.unwrap();
+        all.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(all.len(), 100);
+        assert_eq!(all[1], (1u32.to_be_bytes().to_vec(), Ipld::Integer(1)));
+
+        let mut hamt = hamt;
+        for i in 0..100u32 {
+            assert!(hamt.delete(&i.to_be_bytes()).await.unwrap().is_some());
+        }
+        assert_eq!(hamt.delete(&0u32.to_be_bytes()).await.unwrap(), None);
+
+        // deleting everything collapses back into the empty map
+        assert_eq!(hamt.root(), &empty);
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn canonical_regardless_of_order() {
+        let TestNode { ipfs, bg_task: _bt } = TestNode::new("test_node").await;
+
+        let opts = HamtOptions {
+            bit_width: 4,
+            bucket_size: 2,
+        };
+        let mut first = Hamt::create(ipfs.clone(), opts).await.unwrap();
+        let mut second = Hamt::create(ipfs.clone(), opts).await.unwrap();
+
+        for i in 0..50u8 {
+            first
+                .insert(vec![i], Ipld::Integer(i.into()))
+                .await
+                .unwrap();
+            second
+                .insert(vec![49 - i], Ipld::Integer((49 - i).into()))
+                .await
+                .unwrap();
+        }
+
+        for i in 10..20u8 {
+            first.delete(&[i]).await.unwrap();
+            second.delete(&[i]).await.unwrap();
+        }
+
+        assert_eq!(first.root(), second.root());
+    }
+}
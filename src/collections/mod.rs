@@ -0,0 +1,74 @@
+//! Scalable collections stored as dag-cbor IPLD.
+//!
+//! [`Hamt`] follows the IPLD HashMap specification and [`Amt`] follows the Filecoin-style array
+//! mapped trie. Both load and store their nodes through the block API of [`Ipfs`], and every
+//! mutation stores the changed nodes up to a new root, leaving the previous version intact.
+
+use crate::ipld::{decode_ipld, encode_ipld, Ipld};
+use crate::{Block, Ipfs, RepoTypes};
+use cid::{Cid, Codec};
+use multihash::Sha2_256;
+use thiserror::Error;
+
+pub mod amt;
+pub mod hamt;
+
+pub use amt::{Amt, AmtOptions};
+pub use hamt::{Hamt, HamtOptions};
+
+#[derive(Debug, Error)]
+pub enum CollectionError {
+    /// Loading of a node failed.
+    #[error("loading node {0} failed")]
+    Loading(Cid, #[source] crate::Error),
+
+    /// Storing of a node failed.
+    #[error("storing node failed")]
+    Storing(#[source] crate::Error),
+
+    /// The loaded node was not of the expected shape.
+    #[error("invalid node {0}: {1}")]
+    InvalidNode(Cid, &'static str),
+
+    /// The options given to create a collection are not supported.
+    #[error("unsupported options: {0}")]
+    UnsupportedOptions(&'static str),
+
+    /// All of the bits of the hashed key were used without finding room for the key.
+    #[error("hash bits exhausted")]
+    HashExhausted,
+}
+
+/// Loads and decodes the node.
+async fn load_node<Types: RepoTypes>(
+    ipfs: &Ipfs<Types>,
+    cid: &Cid,
+) -> Result<Ipld, CollectionError> {
+    let block = ipfs
+        .get_block(cid)
+        .await
+        .map_err(|e| CollectionError::Loading(cid.to_owned(), e))?;
+
+    decode_ipld(cid, block.data()).map_err(|e| CollectionError::Loading(cid.to_owned(), e.into()))
+}
+
+/// Stores the node as dag-cbor, returning its `Cid`.
+async fn store_node<Types: RepoTypes>(
+    ipfs: &Ipfs<Types>,
+    node: &Ipld,
+) -> Result<Cid, CollectionError> {
+    let data = encode_ipld(node, Codec::DagCBOR).map_err(|e| CollectionError::Storing(e.into()))?;
+    let cid = Cid::new_v1(Codec::DagCBOR, Sha2_256::digest(&data));
+
+    ipfs.put_block(Block::new(data.into_vec(), cid))
+        .await
+        .map_err(CollectionError::Storing)
+}
+
+/// Reads a non-negative integer which fits the target type.
+fn integer<T: std::convert::TryFrom<i128>>(ipld: Option<&Ipld>) -> Option<T> {
+    match ipld {
+        Some(Ipld::Integer(i)) => T::try_from(*i).ok(),
+        _ => None,
+    }
+}
@@ -1,20 +1,29 @@
 use crate::error::Error;
+use crate::ipld::schema::Schema;
 use crate::ipld::{decode_ipld, encode_ipld, Ipld};
-use crate::path::{IpfsPath, SlashedPath};
+use crate::path::{IpfsPath, PathRoot, SlashedPath};
 use crate::repo::RepoTypes;
 use crate::Ipfs;
+use async_stream::stream;
 use bitswap::Block;
+use bytes::Bytes;
 use cid::{Cid, Codec, Version};
+use futures::stream::Stream;
 use ipfs_unixfs::{
     dagpb::{wrap_node_data, NodeData},
     dir::{Cache, ShardedLookup},
     resolve, MaybeResolved,
 };
+use std::collections::BTreeMap;
 use std::convert::TryFrom;
 use std::error::Error as StdError;
 use std::iter::Peekable;
+use std::sync::Arc;
 use thiserror::Error;
 
+/// The maximum number of IPNS or DNSLink names followed while resolving the root of a path.
+const MAX_IPNS_RESOLVE_DEPTH: usize = 32;
+
 #[derive(Debug, Error)]
 pub enum ResolveError {
     /// Loading of the block on the path failed
@@ -46,6 +55,15 @@ pub enum ResolveError {
     /// Path attempted to resolve through a property, index or link which did not exist.
     #[error("no link named {:?} under {0}", .1.iter().last().unwrap())]
     NotFound(Cid, SlashedPath),
+
+    /// Resolving the IPNS or DNSLink root of the path failed.
+    #[error("failed to resolve ipns root {0:?}")]
+    Ipns(PathRoot, #[source] crate::Error),
+
+    /// The IPNS or DNSLink root of the path did not resolve to a `Cid` within the allowed number
+    /// of indirections.
+    #[error("ipns resolution depth exceeded at {0:?}")]
+    IpnsDepthExceeded(PathRoot),
 }
 
 #[derive(Debug, Error)]
@@ -149,6 +167,73 @@ impl RawResolveLocalError {
     }
 }
 
+/// Options for [`IpldDag::put_with_options`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct PutOptions {
+    /// The hash function used to create the multihash of the encoded document. Defaults to
+    /// sha2-256.
+    pub hash: multihash::Code,
+    /// The version of the created `Cid`. When not given, `Version::V0` is used for dag-pb documents
+    /// hashed with sha2-256 and `Version::V1` for everything else. Requesting `Version::V0` for
+    /// other combinations will fail.
+    pub version: Option<Version>,
+    /// Whether the stored document should be pinned recursively.
+    pub pin: bool,
+    /// When given, the document is validated against the named type of the schema before it is
+    /// stored, and rejected if it does not conform.
+    pub schema: Option<(Arc<Schema>, String)>,
+    /// When given, documents which encode to at most this many bytes are inlined into a
+    /// `Version::V1` `Cid` using the identity multihash instead of `hash`, and are not stored.
+    pub inline_limit: Option<usize>,
+}
+
+impl Default for PutOptions {
+    fn default() -> Self {
+        PutOptions {
+            hash: multihash::Code::Sha2_256,
+            version: None,
+            pin: false,
+            schema: None,
+            inline_limit: None,
+        }
+    }
+}
+
+/// A path-level change between two DAGs, as reported by [`IpldDag::diff`].
+///
+/// Paths are relative to the compared roots and can be appended to either root to resolve the
+/// changed value. Values which were reached through a link are reported as the [`Ipld::Link`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum DagChange {
+    /// The value exists only in the second DAG.
+    Added { path: SlashedPath, value: Ipld },
+    /// The value exists only in the first DAG.
+    Removed { path: SlashedPath, value: Ipld },
+    /// The value at the path differs between the DAGs.
+    Modified {
+        path: SlashedPath,
+        before: Ipld,
+        after: Ipld,
+    },
+}
+
+impl DagChange {
+    /// Returns the path of the changed value.
+    pub fn path(&self) -> &SlashedPath {
+        match self {
+            DagChange::Added { path, .. }
+            | DagChange::Removed { path, .. }
+            | DagChange::Modified { path, .. } => path,
+        }
+    }
+}
+
+/// Pending work for [`IpldDag::diff`].
+enum DiffWork {
+    Compare(SlashedPath, Ipld, Ipld),
+    Report(DagChange),
+}
+
 /// `ipfs.dag` interface providing wrapper around Ipfs.
 #[derive(Clone, Debug)]
 pub struct IpldDag<Types: RepoTypes> {
@@ -160,33 +245,223 @@ impl<Types: RepoTypes> IpldDag<Types> {
         IpldDag { ipfs }
     }
 
+    /// Encodes the document with the given codec and stores it using the default
+    /// [`PutOptions`].
     pub async fn put(&self, data: Ipld, codec: Codec) -> Result<Cid, Error> {
+        self.put_with_options(data, codec, PutOptions::default())
+            .await
+    }
+
+    /// Encodes the document with the given codec and stores it, using the hash function and the
+    /// `Cid` version from the options. The document is validated against a schema and the stored
+    /// document is pinned recursively if requested.
+    pub async fn put_with_options(
+        &self,
+        data: Ipld,
+        codec: Codec,
+        opts: PutOptions,
+    ) -> Result<Cid, Error> {
+        if let Some((schema, type_name)) = opts.schema.as_ref() {
+            schema.validate(&data, type_name)?;
+        }
+
         let bytes = encode_ipld(&data, codec)?;
-        let hash = multihash::Sha2_256::digest(&bytes);
-        let version = if codec == Codec::DagProtobuf {
-            Version::V0
-        } else {
-            Version::V1
+        let code = match opts.inline_limit {
+            Some(limit) if bytes.len() <= limit => multihash::Code::Identity,
+            _ => opts.hash,
         };
+        let hash = code.digest(&bytes);
+        let version = opts.version.unwrap_or_else(|| {
+            if codec == Codec::DagProtobuf && code == multihash::Code::Sha2_256 {
+                Version::V0
+            } else {
+                Version::V1
+            }
+        });
         let cid = Cid::new(version, codec, hash)?;
-        let block = Block::new(bytes, cid);
+        let block = Block::new(bytes.into_vec(), cid);
         let (cid, _) = self.ipfs.repo.put_block(block).await?;
+
+        if opts.pin {
+            self.ipfs.insert_pin(&cid, true).await?;
+        }
+
         Ok(cid)
     }
 
-    /// Resolves a `Cid`-rooted path to a document "node."
+    /// Sets the value at the slash separated `path` within the DAG rooted at `root`, following
+    /// links on the path. Map keys are inserted or replaced, and list indices are replaced or, when
+    /// equal to the length of the list, appended.
+    ///
+    /// Only the blocks along the path are rewritten, each with the codec and hash function of the
+    /// block it replaces. Returns the `Cid` of the new root.
+    pub async fn set(&self, root: Cid, path: &str, value: Ipld) -> Result<Cid, Error> {
+        self.patch(root, path, Some(value)).await
+    }
+
+    /// Removes the map key or list element at the slash separated `path` within the DAG rooted at
+    /// `root`, following links on the path.
+    ///
+    /// Only the blocks along the path are rewritten, each with the codec and hash function of the
+    /// block it replaces. Returns the `Cid` of the new root.
+    pub async fn remove(&self, root: Cid, path: &str) -> Result<Cid, Error> {
+        self.patch(root, path, None).await
+    }
+
+    async fn patch(&self, root: Cid, path: &str, value: Option<Ipld>) -> Result<Cid, Error> {
+        let segments = path
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>();
+
+        let (last, parents) = match segments.split_last() {
+            Some(t) => t,
+            None => {
+                return match value {
+                    Some(value) => self.put_like(&root, value).await,
+                    None => Err(anyhow::anyhow!("cannot remove the root document")),
+                };
+            }
+        };
+
+        // (document, its contents, range of the segments within the document)
+        let mut frames = Vec::new();
+        let mut current = root;
+        let mut start = 0;
+
+        loop {
+            let mut doc = self.load_patchable(&current).await?;
+            let mut end = start;
+            let mut next = None;
+
+            {
+                let mut node = &mut doc;
+                while end < parents.len() {
+                    node = patch_step(node, &current, &segments[..=end])?;
+                    end += 1;
+
+                    if let Ipld::Link(cid) = node {
+                        next = Some(cid.to_owned());
+                        break;
+                    }
+                }
+            }
+
+            frames.push((current, doc, start..end));
+
+            match next {
+                Some(cid) => {
+                    current = cid;
+                    start = end;
+                }
+                None => break,
+            }
+        }
+
+        let (cid, mut doc, range) = frames.pop().expect("at least the root was loaded");
+
+        {
+            let mut node = &mut doc;
+            for end in range {
+                node = patch_step(node, &cid, &segments[..=end])?;
+            }
+
+            let not_found = || ResolveError::NotFound(cid.to_owned(), to_slashed(&segments));
+
+            match (node, value) {
+                (Ipld::Map(map), Some(value)) => {
+                    map.insert((*last).to_owned(), value);
+                }
+                (Ipld::Map(map), None) => {
+                    map.remove(*last).ok_or_else(not_found)?;
+                }
+                (Ipld::List(list), value) => {
+                    let index = last.parse::<usize>().map_err(|_| not_found())?;
+                    let elements = list.len();
+
+                    match value {
+                        Some(value) if index < elements => list[index] = value,
+                        Some(value) if index == elements => list.push(value),
+                        None if index < elements => {
+                            list.remove(index);
+                        }
+                        _ => {
+                            return Err(ResolveError::ListIndexOutOfRange {
+                                document: cid,
+                                path: to_slashed(&segments[..segments.len() - 1]),
+                                index,
+                                elements,
+                            }
+                            .into())
+                        }
+                    }
+                }
+                _ => {
+                    return Err(ResolveError::NoLinks(cid, to_slashed(&segments)).into());
+                }
+            }
+        }
+
+        let mut updated = self.put_like(&cid, doc).await?;
+
+        while let Some((cid, mut doc, range)) = frames.pop() {
+            let mut node = &mut doc;
+            for end in range {
+                node = patch_step(node, &cid, &segments[..=end])?;
+            }
+
+            // the path only continues in the linked document when the node was a link
+            *node = Ipld::Link(updated);
+            updated = self.put_like(&cid, doc).await?;
+        }
+
+        Ok(updated)
+    }
+
+    /// Loads and decodes the document for [`IpldDag::set`] and [`IpldDag::remove`].
+    async fn load_patchable(&self, cid: &Cid) -> Result<Ipld, ResolveError> {
+        if cid.codec() == Codec::DagProtobuf {
+            let e = anyhow::anyhow!("dag-pb documents cannot be patched");
+            return Err(ResolveError::UnsupportedDocument(cid.to_owned(), e.into()));
+        }
+
+        let block = match self.ipfs.repo.get_block(cid).await {
+            Ok(block) => block,
+            Err(e) => return Err(ResolveError::Loading(cid.to_owned(), e)),
+        };
+
+        decode_ipld(cid, block.data())
+            .map_err(|e| ResolveError::UnsupportedDocument(cid.to_owned(), e.into()))
+    }
+
+    /// Stores the document with the codec, hash function and version of `previous`. Documents
+    /// replacing inlined ones are hashed with sha2-256.
+    async fn put_like(&self, previous: &Cid, data: Ipld) -> Result<Cid, Error> {
+        let opts = match previous.hash().algorithm() {
+            multihash::Code::Identity => PutOptions {
+                version: Some(previous.version()),
+                ..Default::default()
+            },
+            hash => PutOptions {
+                hash,
+                version: Some(previous.version()),
+                ..Default::default()
+            },
+        };
+
+        self.put_with_options(data, previous.codec(), opts).await
+    }
+
+    /// Resolves a path to a document "node." IPNS and DNSLink roots are first resolved into a
+    /// `Cid`-rooted path.
     ///
     /// Returns the resolved node as `Ipld`.
     pub async fn get(&self, path: IpfsPath) -> Result<Ipld, ResolveError> {
-        // FIXME: do ipns resolve first
-        let cid = match path.root().cid() {
-            Some(cid) => cid,
-            None => panic!("Ipns resolution not implemented; expected a Cid-based path"),
-        };
+        let (cid, path) = self.resolve_root(path).await?;
 
         let mut iter = path.iter().peekable();
 
-        let (node, _) = match self.resolve0(cid, &mut iter, true).await {
+        let (node, _) = match self.resolve0(&cid, &mut iter, true).await {
             Ok(t) => t,
             Err(e) => {
                 drop(iter);
@@ -197,7 +472,8 @@ impl<Types: RepoTypes> IpldDag<Types> {
         Ipld::try_from(node)
     }
 
-    /// Resolves a `Cid`-rooted path to a document "node."
+    /// Resolves a path to a document "node." IPNS and DNSLink roots are first resolved into a
+    /// `Cid`-rooted path.
     ///
     /// The return value has two kinds of meanings depending on whether links should be followed or
     /// not: when following links, the second returned value will be the path inside the last document;
@@ -213,15 +489,11 @@ impl<Types: RepoTypes> IpldDag<Types> {
         path: IpfsPath,
         follow_links: bool,
     ) -> Result<(ResolvedNode, SlashedPath), ResolveError> {
-        // FIXME: do ipns resolve first
-        let cid = match path.root().cid() {
-            Some(cid) => cid,
-            None => panic!("Ipns resolution not implemented; expected a Cid-based path"),
-        };
+        let (cid, path) = self.resolve_root(path).await?;
 
         let (node, matched_segments) = {
             let mut iter = path.iter().peekable();
-            match self.resolve0(cid, &mut iter, follow_links).await {
+            match self.resolve0(&cid, &mut iter, follow_links).await {
                 Ok(t) => t,
                 Err(e) => {
                     drop(iter);
@@ -238,6 +510,212 @@ impl<Types: RepoTypes> IpldDag<Types> {
         Ok((node, remaining_path))
     }
 
+    /// Resolves the IPNS or DNSLink root of the path until a `Cid` root is found, following at
+    /// most `MAX_IPNS_RESOLVE_DEPTH` names. The segments of the given path are appended to the
+    /// path each name resolves to.
+    ///
+    /// Returns the `Cid` root and the `Cid`-rooted path.
+    async fn resolve_root(&self, mut path: IpfsPath) -> Result<(Cid, IpfsPath), ResolveError> {
+        let mut depth = 0;
+
+        loop {
+            let root = match path.root() {
+                PathRoot::Ipld(cid) => return Ok((cid.to_owned(), path)),
+                other => other.to_owned(),
+            };
+
+            if depth == MAX_IPNS_RESOLVE_DEPTH {
+                return Err(ResolveError::IpnsDepthExceeded(root));
+            }
+            depth += 1;
+
+            path = match self.ipfs.ipns().resolve(&path).await {
+                Ok(path) => path,
+                Err(e) => return Err(ResolveError::Ipns(root, e)),
+            };
+        }
+    }
+
+    /// Streams the path-level changes needed to turn the DAG rooted at `a` into the one rooted at
+    /// `b`, in depth-first order. Subtrees with equal `Cid`s are not descended into.
+    ///
+    /// dag-cbor and dag-json maps and lists are compared by key and index, and dag-pb nodes with
+    /// only named links are compared by link name. Any other differing documents are reported as a
+    /// modified link. dag-pb nodes with duplicate link names cannot be compared by name and end the
+    /// stream with an error. The stream ends after the first error.
+    pub fn diff(
+        &self,
+        a: Cid,
+        b: Cid,
+    ) -> impl Stream<Item = Result<DagChange, ResolveError>> + Send + 'static {
+        let dag = IpldDag::new(self.ipfs.clone());
+
+        stream! {
+            let mut work = vec![DiffWork::Compare(SlashedPath::default(), Ipld::Link(a), Ipld::Link(b))];
+
+            while let Some(next) = work.pop() {
+                let (path, before, after) = match next {
+                    DiffWork::Compare(path, before, after) => (path, before, after),
+                    DiffWork::Report(change) => {
+                        yield Ok(change);
+                        continue;
+                    }
+                };
+
+                if before == after {
+                    continue;
+                }
+
+                let links = match (&before, &after) {
+                    (Ipld::Link(x), Ipld::Link(y)) => Some((x.to_owned(), y.to_owned())),
+                    _ => None,
+                };
+
+                let (before, after) = match links {
+                    Some((x, y)) => {
+                        let (x, x_data) = match dag.load_diffable(&x).await {
+                            Ok(t) => t,
+                            Err(e) => {
+                                yield Err(e);
+                                return;
+                            }
+                        };
+
+                        let (y, y_data) = match dag.load_diffable(&y).await {
+                            Ok(t) => t,
+                            Err(e) => {
+                                yield Err(e);
+                                return;
+                            }
+                        };
+
+                        let comparable = match (&x, &y) {
+                            (Ipld::Map(_), Ipld::Map(_)) | (Ipld::List(_), Ipld::List(_)) => true,
+                            _ => false,
+                        };
+
+                        if !comparable || x_data != y_data {
+                            // dag-pb directories with changed data are still descended into
+                            yield Ok(DagChange::Modified { path: path.clone(), before, after });
+                        }
+
+                        if !comparable {
+                            continue;
+                        }
+
+                        (x, y)
+                    }
+                    None => (before, after),
+                };
+
+                let mut children = Vec::new();
+
+                match (before, after) {
+                    (Ipld::Map(mut before), Ipld::Map(mut after)) => {
+                        let mut keys = before.keys().chain(after.keys()).cloned().collect::<Vec<_>>();
+                        keys.sort();
+                        keys.dedup();
+
+                        for key in keys {
+                            let mut path = path.clone();
+                            path.push_segment(key.clone());
+
+                            children.push(match (before.remove(&key), after.remove(&key)) {
+                                (Some(x), Some(y)) => DiffWork::Compare(path, x, y),
+                                (Some(value), None) => DiffWork::Report(DagChange::Removed { path, value }),
+                                (None, Some(value)) => DiffWork::Report(DagChange::Added { path, value }),
+                                (None, None) => unreachable!("key was found in either map"),
+                            });
+                        }
+                    }
+                    (Ipld::List(before), Ipld::List(after)) => {
+                        let mut before = before.into_iter().map(Some);
+                        let mut after = after.into_iter().map(Some);
+
+                        for index in 0.. {
+                            let mut path = path.clone();
+                            path.push_segment(index.to_string());
+
+                            children.push(match (before.next().flatten(), after.next().flatten()) {
+                                (Some(x), Some(y)) => DiffWork::Compare(path, x, y),
+                                (Some(value), None) => DiffWork::Report(DagChange::Removed { path, value }),
+                                (None, Some(value)) => DiffWork::Report(DagChange::Added { path, value }),
+                                (None, None) => break,
+                            });
+                        }
+                    }
+                    (before, after) => {
+                        yield Ok(DagChange::Modified { path, before, after });
+                        continue;
+                    }
+                }
+
+                work.extend(children.into_iter().rev());
+            }
+        }
+    }
+
+    /// Loads the document for [`IpldDag::diff`]. dag-pb nodes whose links are all named are
+    /// returned as a map of names to links along with their data, while other dag-pb nodes are
+    /// returned as the opaque link.
+    async fn load_diffable(&self, cid: &Cid) -> Result<(Ipld, Option<Ipld>), ResolveError> {
+        let block = match self.ipfs.repo.get_block(cid).await {
+            Ok(block) => block,
+            Err(e) => return Err(ResolveError::Loading(cid.to_owned(), e)),
+        };
+
+        let mut ipld = match decode_ipld(cid, block.data()) {
+            Ok(ipld) => ipld,
+            Err(e) => return Err(ResolveError::UnsupportedDocument(cid.to_owned(), e.into())),
+        };
+
+        if cid.codec() != Codec::DagProtobuf {
+            return Ok((ipld, None));
+        }
+
+        let named_links = match ipld.get("Links") {
+            Some(Ipld::List(links)) if !links.is_empty() => links
+                .iter()
+                .map(|link| match (link.get("Name"), link.get("Hash")) {
+                    (Some(Ipld::String(name)), Some(hash)) if !name.is_empty() => {
+                        Some((name.to_owned(), hash.to_owned()))
+                    }
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>(),
+            _ => None,
+        };
+
+        let named_links = match named_links {
+            Some(links) => {
+                let mut map = BTreeMap::new();
+                for (name, hash) in links {
+                    if map.contains_key(&name) {
+                        let msg = format!("duplicate link name {:?}", name);
+                        return Err(ResolveError::UnsupportedDocument(
+                            cid.to_owned(),
+                            msg.into(),
+                        ));
+                    }
+                    map.insert(name, hash);
+                }
+                Some(map)
+            }
+            None => None,
+        };
+
+        match named_links {
+            Some(links) => {
+                let data = match &mut ipld {
+                    Ipld::Map(map) => map.remove("Data"),
+                    _ => None,
+                };
+                Ok((Ipld::Map(links), data))
+            }
+            None => Ok((Ipld::Link(cid.to_owned()), None)),
+        }
+    }
+
     /// Return the node where the resolving ended, and the **count** of segments matched.
     async fn resolve0<'a>(
         &self,
@@ -314,6 +792,48 @@ impl<Types: RepoTypes> IpldDag<Types> {
     }
 }
 
+/// Steps into the map value or list element named by the last of the `segments` for
+/// [`IpldDag::set`] and [`IpldDag::remove`].
+fn patch_step<'a>(
+    node: &'a mut Ipld,
+    document: &Cid,
+    segments: &[&str],
+) -> Result<&'a mut Ipld, ResolveError> {
+    let segment = *segments.last().expect("segments cannot be empty");
+
+    match node {
+        Ipld::Map(map) => map
+            .get_mut(segment)
+            .ok_or_else(|| ResolveError::NotFound(document.to_owned(), to_slashed(segments))),
+        Ipld::List(list) => {
+            let elements = list.len();
+            let index = segment
+                .parse::<usize>()
+                .map_err(|_| ResolveError::NotFound(document.to_owned(), to_slashed(segments)))?;
+
+            list.get_mut(index)
+                .ok_or_else(|| ResolveError::ListIndexOutOfRange {
+                    document: document.to_owned(),
+                    path: to_slashed(&segments[..segments.len() - 1]),
+                    index,
+                    elements,
+                })
+        }
+        _ => Err(ResolveError::NoLinks(
+            document.to_owned(),
+            to_slashed(segments),
+        )),
+    }
+}
+
+fn to_slashed(segments: &[&str]) -> SlashedPath {
+    let mut path = SlashedPath::default();
+    segments
+        .iter()
+        .for_each(|s| path.push_segment((*s).to_owned()));
+    path
+}
+
 /// `IpfsPath`'s `Cid`-based variant can be resolved to the block, projections represented by this
 /// type.
 ///
@@ -325,7 +845,7 @@ pub enum ResolvedNode {
     /// Path ended in `Data` at a dag-pb node. This is usually not interesting and should be
     /// treated as a "Not found" error since dag-pb node did not have a *link* called `Data`. The variant
     /// exists as there are interface-ipfs-http tests which require this behaviour.
-    DagPbData(Cid, NodeData<Box<[u8]>>),
+    DagPbData(Cid, NodeData<Bytes>),
     /// Path ended on a !dag-pb document which was projected.
     Projection(Cid, Ipld),
     /// Local resolving ended with a link
@@ -446,7 +966,7 @@ fn resolve_local<'a>(
 /// `ResolvedNode::DagPbData`.
 fn resolve_local_dagpb<'a>(
     cid: Cid,
-    data: Box<[u8]>,
+    data: Bytes,
     segment: &'a str,
     is_last: bool,
     cache: &mut Option<Cache>,
@@ -586,6 +1106,415 @@ mod tests {
         assert_eq!(res, data);
     }
 
+    #[tokio::test(max_threads = 1)]
+    async fn put_with_options() {
+        let Node { ipfs, bg_task: _bt } = Node::new("test_node").await;
+        let dag = IpldDag::new(ipfs.clone());
+        let data = make_ipld!([1, 2, 3]);
+
+        let opts = PutOptions {
+            hash: multihash::Code::Blake3,
+            pin: true,
+            ..Default::default()
+        };
+
+        let cid = dag
+            .put_with_options(data.clone(), Codec::DagCBOR, opts)
+            .await
+            .unwrap();
+
+        assert_eq!(cid.version(), Version::V1);
+        assert_eq!(cid.hash().algorithm(), multihash::Code::Blake3);
+        assert!(ipfs.is_pinned(&cid).await.unwrap());
+        assert_eq!(dag.get(IpfsPath::from(cid)).await.unwrap(), data);
+
+        let opts = PutOptions {
+            hash: multihash::Code::Sha2_512,
+            version: Some(Version::V0),
+            ..Default::default()
+        };
+
+        dag.put_with_options(data, Codec::DagCBOR, opts)
+            .await
+            .unwrap_err();
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn put_inline() {
+        let Node { ipfs, bg_task: _bt } = Node::new("test_node").await;
+        let dag = IpldDag::new(ipfs.clone());
+        let opts = PutOptions {
+            inline_limit: Some(32),
+            ..Default::default()
+        };
+
+        let small = dag
+            .put_with_options(make_ipld!({"a": 1}), Codec::DagCBOR, opts.clone())
+            .await
+            .unwrap();
+        assert_eq!(small.hash().algorithm(), multihash::Code::Identity);
+        assert_eq!(small.version(), Version::V1);
+        assert!(!ipfs.repo.list_blocks().await.unwrap().contains(&small));
+
+        let large = dag
+            .put_with_options(
+                make_ipld!({"link": small, "b": vec![0u8; 32]}),
+                Codec::DagCBOR,
+                opts,
+            )
+            .await
+            .unwrap();
+        assert_eq!(large.hash().algorithm(), multihash::Code::Sha2_256);
+
+        let res = dag
+            .get(IpfsPath::from(large).sub_path("link/a").unwrap())
+            .await
+            .unwrap();
+        assert_eq!(res, make_ipld!(1));
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn put_with_schema() {
+        let Node { ipfs, bg_task: _bt } = Node::new("test_node").await;
+        let dag = IpldDag::new(ipfs);
+
+        let schema = Schema::from_dsl("type Point struct { x Int y Int }").unwrap();
+        let opts = PutOptions {
+            schema: Some((Arc::new(schema), "Point".into())),
+            ..Default::default()
+        };
+
+        dag.put_with_options(make_ipld!({"x": 1, "y": 2}), Codec::DagCBOR, opts.clone())
+            .await
+            .unwrap();
+
+        dag.put_with_options(make_ipld!({"x": 1}), Codec::DagCBOR, opts)
+            .await
+            .unwrap_err();
+    }
+
+    async fn collect_diff<T: RepoTypes>(dag: &IpldDag<T>, a: Cid, b: Cid) -> Vec<DagChange> {
+        use futures::stream::TryStreamExt;
+        dag.diff(a, b).try_collect().await.unwrap()
+    }
+
+    fn path(s: &str) -> SlashedPath {
+        let mut path = SlashedPath::default();
+        s.split('/').for_each(|p| path.push_segment(p.to_owned()));
+        path
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn diff_cbor_documents() {
+        let Node { ipfs, bg_task: _bt } = Node::new("test_node").await;
+        let dag = IpldDag::new(ipfs);
+
+        // never stored, so descending into it would fail
+        let missing = Cid::new_v1(Codec::Raw, multihash::Sha2_256::digest(b"missing"));
+        let first = dag
+            .put(
+                make_ipld!({"a": 1, "b": [1, 2], "c": missing.clone()}),
+                Codec::DagCBOR,
+            )
+            .await
+            .unwrap();
+        let second = dag
+            .put(
+                make_ipld!({"a": 2, "b": [1, 2, 3], "c": missing, "d": true}),
+                Codec::DagCBOR,
+            )
+            .await
+            .unwrap();
+
+        assert!(collect_diff(&dag, first.clone(), first.clone())
+            .await
+            .is_empty());
+
+        assert_eq!(
+            collect_diff(&dag, first.clone(), second.clone()).await,
+            vec![
+                DagChange::Modified {
+                    path: path("a"),
+                    before: make_ipld!(1),
+                    after: make_ipld!(2),
+                },
+                DagChange::Added {
+                    path: path("b/2"),
+                    value: make_ipld!(3),
+                },
+                DagChange::Added {
+                    path: path("d"),
+                    value: make_ipld!(true),
+                },
+            ]
+        );
+
+        let reverse = collect_diff(&dag, second, first).await;
+        assert_eq!(
+            reverse[1],
+            DagChange::Removed {
+                path: path("b/2"),
+                value: make_ipld!(3),
+            }
+        );
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn diff_through_links() {
+        let Node { ipfs, bg_task: _bt } = Node::new("test_node").await;
+        let dag = IpldDag::new(ipfs);
+
+        let leaf1 = dag.put(make_ipld!({"v": 1}), Codec::DagCBOR).await.unwrap();
+        let leaf2 = dag.put(make_ipld!({"v": 2}), Codec::DagCBOR).await.unwrap();
+        let raw = dag
+            .put(make_ipld!(b"raw".to_vec()), Codec::Raw)
+            .await
+            .unwrap();
+
+        let first = dag
+            .put(make_ipld!({"x": leaf1, "y": leaf2.clone()}), Codec::DagCBOR)
+            .await
+            .unwrap();
+        let second = dag
+            .put(
+                make_ipld!({"x": leaf2.clone(), "y": raw.clone()}),
+                Codec::DagCBOR,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            collect_diff(&dag, first, second).await,
+            vec![
+                DagChange::Modified {
+                    path: path("x/v"),
+                    before: make_ipld!(1),
+                    after: make_ipld!(2),
+                },
+                DagChange::Modified {
+                    path: path("y"),
+                    before: Ipld::Link(leaf2),
+                    after: Ipld::Link(raw),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn diff_dagpb_named_links() {
+        let Node { ipfs, bg_task: _bt } = Node::new("test_node").await;
+        let dag = IpldDag::new(ipfs);
+
+        let file1 = dag
+            .put(make_ipld!(b"1".to_vec()), Codec::Raw)
+            .await
+            .unwrap();
+        let file2 = dag
+            .put(make_ipld!(b"2".to_vec()), Codec::Raw)
+            .await
+            .unwrap();
+
+        let link =
+            |name: &str, cid: &Cid| make_ipld!({"Hash": cid.clone(), "Name": name, "Tsize": 1});
+
+        let first = dag
+            .put(
+                make_ipld!({
+                    "Data": b"\x08\x01".to_vec(),
+                    "Links": [link("a", &file1), link("b", &file1)]
+                }),
+                Codec::DagProtobuf,
+            )
+            .await
+            .unwrap();
+        let second = dag
+            .put(
+                make_ipld!({
+                    "Data": b"\x08\x01".to_vec(),
+                    "Links": [link("a", &file2), link("c", &file1)]
+                }),
+                Codec::DagProtobuf,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            collect_diff(&dag, first, second).await,
+            vec![
+                DagChange::Modified {
+                    path: path("a"),
+                    before: Ipld::Link(file1.clone()),
+                    after: Ipld::Link(file2),
+                },
+                DagChange::Removed {
+                    path: path("b"),
+                    value: Ipld::Link(file1.clone()),
+                },
+                DagChange::Added {
+                    path: path("c"),
+                    value: Ipld::Link(file1),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn diff_dagpb_duplicate_link_names() {
+        use futures::stream::TryStreamExt;
+
+        let Node { ipfs, bg_task: _bt } = Node::new("test_node").await;
+        let dag = IpldDag::new(ipfs);
+
+        let file1 = dag
+            .put(make_ipld!(b"1".to_vec()), Codec::Raw)
+            .await
+            .unwrap();
+        let file2 = dag
+            .put(make_ipld!(b"2".to_vec()), Codec::Raw)
+            .await
+            .unwrap();
+
+        let link =
+            |name: &str, cid: &Cid| make_ipld!({"Hash": cid.clone(), "Name": name, "Tsize": 1});
+
+        let first = dag
+            .put(
+                make_ipld!({ "Links": [link("a", &file1), link("a", &file2)] }),
+                Codec::DagProtobuf,
+            )
+            .await
+            .unwrap();
+        let second = dag
+            .put(
+                make_ipld!({ "Links": [link("a", &file1)] }),
+                Codec::DagProtobuf,
+            )
+            .await
+            .unwrap();
+
+        let e = dag
+            .diff(first.clone(), second)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap_err();
+
+        match e {
+            ResolveError::UnsupportedDocument(cid, _) => assert_eq!(cid, first),
+            other => panic!("unexpected error: {}", other),
+        }
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn set_and_remove_across_links() {
+        let Node { ipfs, bg_task: _bt } = Node::new("test_node").await;
+        let dag = IpldDag::new(ipfs);
+
+        let untouched = dag.put(make_ipld!({"u": 0}), Codec::DagCBOR).await.unwrap();
+        let leaf = dag
+            .put(make_ipld!({"v": [1, 2]}), Codec::DagCBOR)
+            .await
+            .unwrap();
+        let opts = PutOptions {
+            hash: multihash::Code::Blake3,
+            ..Default::default()
+        };
+        let root = dag
+            .put_with_options(
+                make_ipld!({"a": {"leaf": leaf}, "b": untouched}),
+                Codec::DagCBOR,
+                opts,
+            )
+            .await
+            .unwrap();
+
+        let updated = dag
+            .set(root.clone(), "a/leaf/v/1", make_ipld!(3))
+            .await
+            .unwrap();
+        assert_eq!(updated.hash().algorithm(), multihash::Code::Blake3);
+        assert_eq!(
+            dag.get(
+                IpfsPath::from(updated.clone())
+                    .sub_path("a/leaf/v")
+                    .unwrap()
+            )
+            .await
+            .unwrap(),
+            make_ipld!([1, 3])
+        );
+        assert_eq!(
+            dag.get(IpfsPath::from(updated.clone()).sub_path("b").unwrap())
+                .await
+                .unwrap(),
+            make_ipld!({"u": 0})
+        );
+
+        let appended = dag.set(updated, "a/leaf/v/2", make_ipld!(4)).await.unwrap();
+        let removed = dag.remove(appended, "a/leaf/v/0").await.unwrap();
+        assert_eq!(
+            dag.get(
+                IpfsPath::from(removed.clone())
+                    .sub_path("a/leaf/v")
+                    .unwrap()
+            )
+            .await
+            .unwrap(),
+            make_ipld!([3, 4])
+        );
+
+        let removed = dag.remove(removed, "a/leaf").await.unwrap();
+        assert_eq!(
+            dag.get(IpfsPath::from(removed.clone()).sub_path("a").unwrap())
+                .await
+                .unwrap(),
+            make_ipld!({})
+        );
+
+        // the original is left as is
+        assert_eq!(
+            dag.get(IpfsPath::from(root).sub_path("a/leaf/v").unwrap())
+                .await
+                .unwrap(),
+            make_ipld!([1, 2])
+        );
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn patch_errors() {
+        let Node { ipfs, bg_task: _bt } = Node::new("test_node").await;
+        let dag = IpldDag::new(ipfs);
+
+        let root = dag
+            .put(make_ipld!({"a": [1], "s": "str"}), Codec::DagCBOR)
+            .await
+            .unwrap();
+
+        let errors = vec![
+            dag.set(root.clone(), "a/2", make_ipld!(1))
+                .await
+                .unwrap_err(),
+            dag.set(root.clone(), "b/c", make_ipld!(1))
+                .await
+                .unwrap_err(),
+            dag.set(root.clone(), "s/c", make_ipld!(1))
+                .await
+                .unwrap_err(),
+            dag.remove(root.clone(), "a/1").await.unwrap_err(),
+            dag.remove(root.clone(), "b").await.unwrap_err(),
+        ];
+
+        for e in errors {
+            match e.downcast_ref::<ResolveError>() {
+                Some(ResolveError::ListIndexOutOfRange { .. })
+                | Some(ResolveError::NotFound(..))
+                | Some(ResolveError::NoLinks(..)) => {}
+                x => panic!("unexpected error: {:?}", x),
+            }
+        }
+
+        dag.remove(root, "").await.unwrap_err();
+    }
+
     #[tokio::test(max_threads = 1)]
     async fn test_resolve_array_elem() {
         let Node { ipfs, bg_task: _bt } = Node::new("test_node").await;
@@ -850,6 +1779,68 @@ mod tests {
         }
     }
 
+    #[tokio::test(max_threads = 1)]
+    async fn resolve_through_ipns() {
+        let Node { ipfs, bg_task: _bt } = Node::new("test_node").await;
+        let dag = IpldDag::new(ipfs.clone());
+        let ipld = make_ipld!([1]);
+        let cid1 = dag.put(ipld, Codec::DagCBOR).await.unwrap();
+        let ipld = make_ipld!([cid1.clone()]);
+        let cid2 = dag.put(ipld, Codec::DagCBOR).await.unwrap();
+
+        let first = libp2p::PeerId::random();
+        let second = libp2p::PeerId::random();
+
+        // ipns names pointing to other ipns names with paths are followed as well
+        ipfs.publish_ipns(&second, &IpfsPath::from(cid2).sub_path("0").unwrap())
+            .await
+            .unwrap();
+        ipfs.publish_ipns(&first, &IpfsPath::from(second))
+            .await
+            .unwrap();
+
+        let path = IpfsPath::from(first).sub_path("0").unwrap();
+
+        assert_eq!(dag.get(path.clone()).await.unwrap(), make_ipld!(1));
+
+        match dag.resolve(path, true).await.unwrap() {
+            (ResolvedNode::Projection(cid, Ipld::Integer(1)), remaining_path) => {
+                assert_eq!(cid, cid1);
+                assert_eq!(remaining_path, ["0"][..]);
+            }
+            x => unreachable!("{:?}", x),
+        }
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn fail_resolving_ipns_loop() {
+        let Node { ipfs, bg_task: _bt } = Node::new("test_node").await;
+        let dag = IpldDag::new(ipfs.clone());
+
+        let key = libp2p::PeerId::random();
+        ipfs.publish_ipns(&key, &IpfsPath::from(key.clone()))
+            .await
+            .unwrap();
+
+        match dag.get(IpfsPath::from(key)).await.unwrap_err() {
+            ResolveError::IpnsDepthExceeded(PathRoot::Ipns(_)) => {}
+            x => unreachable!("{:?}", x),
+        }
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn fail_resolving_missing_ipns() {
+        let Node { ipfs, bg_task: _bt } = Node::new("test_node").await;
+        let dag = IpldDag::new(ipfs);
+
+        let path = IpfsPath::from(libp2p::PeerId::random());
+
+        match dag.get(path).await.unwrap_err() {
+            ResolveError::Ipns(PathRoot::Ipns(_), _) => {}
+            x => unreachable!("{:?}", x),
+        }
+    }
+
     #[tokio::test(max_threads = 1)]
     async fn fail_resolving_first_segment() {
         let Node { ipfs, bg_task: _bt } = Node::new("test_node").await;
@@ -950,7 +1941,7 @@ mod tests {
             let node = node.unwrap();
             let block = Block {
                 cid: node.cid.to_owned(),
-                data: node.block.into(),
+                data: node.block.to_vec().into(),
             };
 
             ipfs.put_block(block).await.unwrap();
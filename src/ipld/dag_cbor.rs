@@ -1,6 +1,6 @@
 //! DAG-CBOR codec.
 
-use crate::ipld::{BlockError, Ipld, IpldError};
+use crate::ipld::{BlockError, Ipld, IpldError, IpldRef};
 use byteorder::{BigEndian, ByteOrder};
 use cid::Cid;
 use std::{
@@ -24,6 +24,21 @@ impl DagCborCodec {
     pub fn decode(mut data: &[u8]) -> Result<Ipld, CborError> {
         Ipld::read_cbor(&mut data)
     }
+
+    /// Decodes the document without copying the strings and byte strings, which are borrowed
+    /// from `data` instead.
+    pub fn decode_borrowed(mut data: &[u8]) -> Result<IpldRef<'_>, CborError> {
+        read_ref(&mut data)
+    }
+
+    /// Scans the document for links, in the same order as `Ipld::iter` on the decoded document.
+    /// Only the structure and the links are validated; strings are skipped over without checking
+    /// them.
+    pub fn links(mut data: &[u8]) -> Result<Vec<Cid>, CborError> {
+        let mut links = Vec::new();
+        scan_links(&mut data, &mut links)?;
+        Ok(links)
+    }
 }
 
 /// CBOR error.
@@ -427,15 +442,17 @@ pub fn read_link<R: Read>(r: &mut R) -> CborResult<Cid> {
     if tag != 42 {
         return Err(CborError::UnknownTag);
     }
-    let ty = read_u8(r)?;
-    if ty != 0x58 {
-        return Err(CborError::UnknownTag);
-    }
-    let len = read_u8(r)?;
+    // short cids, such as ones using the identity hash, can have any length of byte string
+    let len = match read_u8(r)? {
+        ty @ 0x40..=0x57 => ty as usize - 0x40,
+        0x58 => read_u8(r)? as usize,
+        0x59 => read_u16(r)? as usize,
+        _ => return Err(CborError::UnknownTag),
+    };
     if len == 0 {
         return Err(CborError::LengthOutOfRange);
     }
-    let bytes = read_bytes(r, len as usize)?;
+    let bytes = read_bytes(r, len)?;
     if bytes[0] != 0 {
         return Err(CborError::InvalidCidPrefix(bytes[0]));
     }
@@ -855,3 +872,235 @@ impl ReadCbor for Ipld {
         Ok(Some(ipld))
     }
 }
+
+/// Reads the initial byte and the argument of the next data item, returning the major type, the
+/// additional information and the argument. Indefinite lengths are not supported.
+fn read_header(data: &mut &[u8]) -> CborResult<(u8, u8, u64)> {
+    let initial = read_u8(data)?;
+    let (major, info) = (initial >> 5, initial & 0x1f);
+    let arg = match info {
+        0..=23 => info as u64,
+        24 => read_u8(data)? as u64,
+        25 => read_u16(data)? as u64,
+        26 => read_u32(data)? as u64,
+        27 => read_u64(data)?,
+        _ => return Err(CborError::UnexpectedCode),
+    };
+    Ok((major, info, arg))
+}
+
+/// Splits `len` bytes off the front of `data`.
+fn take<'a>(data: &mut &'a [u8], len: u64) -> CborResult<&'a [u8]> {
+    if len > data.len() as u64 {
+        return Err(CborError::UnexpectedEof);
+    }
+    let (taken, rest) = data.split_at(len as usize);
+    *data = rest;
+    Ok(taken)
+}
+
+/// Reads the byte string following tag 42.
+fn read_borrowed_link(data: &mut &[u8]) -> CborResult<Cid> {
+    let bytes = match read_header(data)? {
+        (2, _, len) => take(data, len)?,
+        _ => return Err(CborError::UnknownTag),
+    };
+    match bytes.split_first() {
+        Some((0, cid)) => Ok(Cid::try_from(cid)?),
+        Some((prefix, _)) => Err(CborError::InvalidCidPrefix(*prefix)),
+        None => Err(CborError::LengthOutOfRange),
+    }
+}
+
+/// The number of items to preallocate for, as every item takes at least a byte.
+fn capacity(data: &[u8], len: u64) -> usize {
+    std::cmp::min(len, data.len() as u64) as usize
+}
+
+fn read_ref<'a>(data: &mut &'a [u8]) -> CborResult<IpldRef<'a>> {
+    let (major, info, arg) = read_header(data)?;
+    let ipld = match major {
+        0 => IpldRef::Integer(arg as i128),
+        1 => IpldRef::Integer(-1 - arg as i128),
+        2 => IpldRef::Bytes(take(data, arg)?),
+        3 => IpldRef::String(std::str::from_utf8(take(data, arg)?)?),
+        4 => {
+            let mut list = Vec::with_capacity(capacity(data, arg));
+            for _ in 0..arg {
+                list.push(read_ref(data)?);
+            }
+            IpldRef::List(list)
+        }
+        5 => {
+            let mut map = BTreeMap::new();
+            for _ in 0..arg {
+                let key = match read_header(data)? {
+                    (3, _, len) => std::str::from_utf8(take(data, len)?)?,
+                    _ => return Err(CborError::UnexpectedCode),
+                };
+                map.insert(key, read_ref(data)?);
+            }
+            IpldRef::Map(map)
+        }
+        6 if arg == 42 => IpldRef::Link(read_borrowed_link(data)?),
+        6 => return Err(CborError::UnknownTag),
+        _ => match (info, arg) {
+            (20, _) => IpldRef::Bool(false),
+            (21, _) => IpldRef::Bool(true),
+            (22, _) | (23, _) => IpldRef::Null,
+            (26, bits) => IpldRef::Float(f32::from_bits(bits as u32) as f64),
+            (27, bits) => IpldRef::Float(f64::from_bits(bits)),
+            _ => return Err(CborError::UnexpectedCode),
+        },
+    };
+    Ok(ipld)
+}
+
+fn scan_links(data: &mut &[u8], links: &mut Vec<Cid>) -> CborResult<()> {
+    let (major, info, arg) = read_header(data)?;
+    match major {
+        0 | 1 => {}
+        2 | 3 => {
+            take(data, arg)?;
+        }
+        4 => {
+            for _ in 0..arg {
+                scan_links(data, links)?;
+            }
+        }
+        5 => {
+            // the links are returned in the order of the keys in `Ipld::Map`, not in the order of
+            // the canonical encoding which orders the shorter keys first
+            let mut entries = Vec::new();
+            for _ in 0..arg {
+                let key = match read_header(data)? {
+                    (3, _, len) => take(data, len)?,
+                    _ => return Err(CborError::UnexpectedCode),
+                };
+                let mut value_links = Vec::new();
+                scan_links(data, &mut value_links)?;
+                entries.push((key, value_links));
+            }
+            // stable, so that the last one of the duplicate keys wins like when decoding
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            let mut entries = entries.into_iter().peekable();
+            while let Some((key, value_links)) = entries.next() {
+                if entries.peek().map(|(next, _)| *next) != Some(key) {
+                    links.extend(value_links);
+                }
+            }
+        }
+        6 if arg == 42 => links.push(read_borrowed_link(data)?),
+        6 => return Err(CborError::UnknownTag),
+        _ => match info {
+            20..=23 | 26 | 27 => {}
+            _ => return Err(CborError::UnexpectedCode),
+        },
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::make_ipld;
+    use multihash::Sha2_256;
+
+    fn document() -> Ipld {
+        let first = Cid::new_v1(cid::Codec::Raw, Sha2_256::digest(b"first"));
+        let second = Cid::new_v0(Sha2_256::digest(b"second")).unwrap();
+
+        make_ipld!({
+            "bytes": vec![0u8, 1, 2, 3],
+            "float": 1.5,
+            "list": [first, null, true, false, -1, 256, u64::MAX],
+            "nested": { "link": second, "name": "a longer string which needs a length byte" },
+            "negative": -4_294_967_297i64,
+        })
+    }
+
+    #[test]
+    fn borrowed_matches_owned() {
+        let encoded = DagCborCodec::encode(&document()).unwrap();
+
+        let borrowed = DagCborCodec::decode_borrowed(&encoded).unwrap();
+
+        match &borrowed {
+            IpldRef::Map(m) => match m.get("bytes") {
+                Some(IpldRef::Bytes(b)) => {
+                    // points into the encoded block instead of being a copy
+                    let range = encoded.as_ptr_range();
+                    assert!(range.contains(&b.as_ptr()));
+                }
+                x => panic!("unexpected bytes: {:?}", x),
+            },
+            x => panic!("unexpected document: {:?}", x),
+        }
+
+        assert_eq!(
+            borrowed.into_owned(),
+            DagCborCodec::decode(&encoded).unwrap()
+        );
+        assert_eq!(
+            DagCborCodec::decode(&encoded).unwrap(),
+            document(),
+            "roundtrip through the owned decoder"
+        );
+    }
+
+    #[test]
+    fn links_in_decoded_order() {
+        let first = Cid::new_v1(cid::Codec::Raw, Sha2_256::digest(b"first"));
+        let second = Cid::new_v1(cid::Codec::Raw, Sha2_256::digest(b"second"));
+        let third = Cid::new_v1(cid::Codec::Raw, Sha2_256::digest(b"third"));
+
+        // the canonical encoding has "c" before "bb", while `Ipld::Map` has "bb" first
+        let mixed = make_ipld!({
+            "c": first,
+            "bb": { "c": second, "aaa": third },
+        });
+
+        for document in &[document(), mixed] {
+            let encoded = DagCborCodec::encode(document).unwrap();
+
+            let expected = document
+                .iter()
+                .filter_map(|ipld| match ipld {
+                    Ipld::Link(cid) => Some(cid.to_owned()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+
+            assert!(expected.len() >= 2);
+            assert_eq!(DagCborCodec::links(&encoded).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn duplicate_keys_keep_the_last_link() {
+        let first = Cid::new_v1(cid::Codec::Raw, Sha2_256::digest(b"first"));
+        let second = Cid::new_v1(cid::Codec::Raw, Sha2_256::digest(b"second"));
+
+        let mut encoded = vec![0xa2];
+        for cid in &[&first, &second] {
+            encoded.extend_from_slice(&[0x61, b'a']);
+            encoded.extend(DagCborCodec::encode(&Ipld::Link((*cid).to_owned())).unwrap());
+        }
+
+        assert_eq!(DagCborCodec::links(&encoded).unwrap(), vec![second]);
+    }
+
+    #[test]
+    fn truncated_documents() {
+        let encoded = DagCborCodec::encode(&document()).unwrap();
+
+        for len in 0..encoded.len() {
+            assert!(
+                DagCborCodec::decode_borrowed(&encoded[..len]).is_err(),
+                "decoded a document truncated to {} bytes",
+                len
+            );
+            assert!(DagCborCodec::links(&encoded[..len]).is_err());
+        }
+    }
+}
@@ -1,7 +1,15 @@
 //! DAG-Protobuf codec.
+//!
+//! The encoder always produces the canonical form described in the [dag-pb spec], which is the
+//! form `go-ipfs` and `js-ipfs` produce. The regular decoder accepts the fields in any order like
+//! any protobuf decoder would, while the strict decoder only accepts the canonical form. Writing
+//! back a node read with the strict decoder results in the same bytes and the same `Cid`.
+//!
+//! [dag-pb spec]: https://github.com/ipld/specs/blob/master/block-layer/codecs/dag-pb.md
 
 use crate::ipld::{BlockError, Ipld, IpldError};
 use cid::Cid;
+use ipfs_unixfs::dagpb::{check_canonical, read_varint, NonCanonical};
 use std::{
     collections::BTreeMap,
     convert::{TryFrom, TryInto},
@@ -21,13 +29,20 @@ impl DagPbCodec {
     pub fn decode(data: &[u8]) -> Result<Ipld, ProtobufError> {
         Ok(PbNode::from_bytes(data)?.into())
     }
+
+    /// Decodes only the canonical form of dag-pb, see [`PbNode::from_bytes_strict`].
+    pub fn decode_strict(data: &[u8]) -> Result<Ipld, ProtobufError> {
+        Ok(PbNode::from_bytes_strict(data)?.into())
+    }
 }
 
 /// Protobuf error.
 #[derive(Debug, Error)]
 pub enum ProtobufError {
-    #[error("{0}")]
-    Prost(#[from] prost::DecodeError),
+    #[error("invalid protobuf: {0}")]
+    Invalid(&'static str),
+    #[error("non-canonical dag-pb: {0}")]
+    NonCanonical(#[from] NonCanonical),
     #[error("{0}")]
     Cid(#[from] cid::Error),
     #[error("{0}")]
@@ -40,58 +55,158 @@ impl From<ProtobufError> for BlockError {
     }
 }
 
-mod pb {
-    include!(concat!(env!("OUT_DIR"), "/dag_pb.rs"));
-}
-
+#[derive(Clone, Debug, PartialEq)]
 pub struct PbLink {
     pub cid: Cid,
-    pub name: String,
-    pub size: u64,
+    /// The name of the link, which is distinct from an empty name when absent.
+    pub name: Option<String>,
+    pub size: Option<u64>,
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct PbNode {
     pub links: Vec<PbLink>,
-    pub data: Vec<u8>,
+    /// The data of the node, which is distinct from empty data when absent.
+    pub data: Option<Vec<u8>>,
 }
 
-use prost::Message;
-
 impl PbNode {
-    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ProtobufError> {
-        let proto: pb::PbNode = pb::PbNode::decode(bytes)?;
-        let data = proto.data;
+    /// Decodes the node accepting the fields in any order and skipping any unknown fields.
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self, ProtobufError> {
         let mut links = Vec::new();
-        for link in proto.links {
-            let cid = Cid::try_from(link.hash)?;
-            let name = link.name;
-            let size = link.tsize;
-            links.push(PbLink { cid, name, size });
+        let mut data = None;
+
+        while !bytes.is_empty() {
+            match read_field(&mut bytes)? {
+                (2, Field::LengthDelimited(link)) => links.push(PbLink::from_bytes(link)?),
+                (1, Field::LengthDelimited(bytes)) => data = Some(bytes.to_vec()),
+                (1, _) | (2, _) => return Err(ProtobufError::Invalid("unexpected wire type")),
+                _ => {}
+            }
         }
+
         Ok(PbNode { links, data })
     }
 
-    pub fn into_bytes(self) -> Box<[u8]> {
-        let links = self
-            .links
-            .into_iter()
-            .map(|link| pb::PbLink {
-                hash: link.cid.to_bytes(),
-                name: link.name,
-                tsize: link.size,
-            })
-            .collect::<Vec<_>>();
-        let proto = pb::PbNode {
-            data: self.data,
-            links,
-        };
+    /// Decodes the node only if it is in the canonical form: links before the data, link fields
+    /// in order, links sorted by name and no unknown fields.
+    pub fn from_bytes_strict(bytes: &[u8]) -> Result<Self, ProtobufError> {
+        check_canonical(bytes)?;
+        Self::from_bytes(bytes)
+    }
+
+    /// Encodes the node in the canonical form, sorting the links by their names. Links with the
+    /// same name keep their order.
+    pub fn into_bytes(mut self) -> Box<[u8]> {
+        self.links.sort_by(|a, b| {
+            let a = a.name.as_deref().unwrap_or_default();
+            let b = b.name.as_deref().unwrap_or_default();
+            a.as_bytes().cmp(b.as_bytes())
+        });
+
+        let mut out = Vec::new();
+        let mut link_bytes = Vec::new();
+
+        for link in self.links {
+            link_bytes.clear();
+            write_bytes(&mut link_bytes, 1, &link.cid.to_bytes());
+            if let Some(name) = link.name {
+                write_bytes(&mut link_bytes, 2, name.as_bytes());
+            }
+            if let Some(size) = link.size {
+                write_varint(&mut link_bytes, 3 << 3);
+                write_varint(&mut link_bytes, size);
+            }
+            write_bytes(&mut out, 2, &link_bytes);
+        }
+
+        if let Some(data) = self.data {
+            write_bytes(&mut out, 1, &data);
+        }
+
+        out.into_boxed_slice()
+    }
+}
+
+impl PbLink {
+    fn from_bytes(mut bytes: &[u8]) -> Result<Self, ProtobufError> {
+        let mut hash = None;
+        let mut name = None;
+        let mut size = None;
+
+        while !bytes.is_empty() {
+            match read_field(&mut bytes)? {
+                (1, Field::LengthDelimited(bytes)) => hash = Some(bytes),
+                (2, Field::LengthDelimited(bytes)) => {
+                    let s = std::str::from_utf8(bytes)
+                        .map_err(|_| ProtobufError::Invalid("link name is not UTF-8"))?;
+                    name = Some(s.to_owned());
+                }
+                (3, Field::Varint(value)) => size = Some(value),
+                (1, _) | (2, _) | (3, _) => {
+                    return Err(ProtobufError::Invalid("unexpected wire type"))
+                }
+                _ => {}
+            }
+        }
+
+        let hash = hash.ok_or(ProtobufError::Invalid("link without Hash"))?;
+        let cid = Cid::try_from(hash)?;
+
+        Ok(PbLink { cid, name, size })
+    }
+}
+
+enum Field<'a> {
+    Varint(u64),
+    LengthDelimited(&'a [u8]),
+    Fixed,
+}
+
+/// Reads the next field, returning the field number and the value.
+fn read_field<'a>(bytes: &mut &'a [u8]) -> Result<(u64, Field<'a>), ProtobufError> {
+    let truncated = |_| ProtobufError::Invalid("truncated varint");
+    let key = read_varint(bytes).map_err(truncated)?;
+    let field = match key & 0x7 {
+        0 => Field::Varint(read_varint(bytes).map_err(truncated)?),
+        1 => {
+            take(bytes, 8)?;
+            Field::Fixed
+        }
+        2 => {
+            let len = read_varint(bytes).map_err(truncated)?;
+            Field::LengthDelimited(take(bytes, len)?)
+        }
+        5 => {
+            take(bytes, 4)?;
+            Field::Fixed
+        }
+        _ => return Err(ProtobufError::Invalid("unsupported wire type")),
+    };
+    Ok((key >> 3, field))
+}
 
-        let mut res = Vec::with_capacity(proto.encoded_len());
-        proto
-            .encode(&mut res)
-            .expect("there is no situation in which the protobuf message can be invalid");
-        res.into_boxed_slice()
+fn take<'a>(bytes: &mut &'a [u8], len: u64) -> Result<&'a [u8], ProtobufError> {
+    if len > bytes.len() as u64 {
+        return Err(ProtobufError::Invalid("truncated field"));
     }
+    let (taken, rest) = bytes.split_at(len as usize);
+    *bytes = rest;
+    Ok(taken)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn write_bytes(out: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    write_varint(out, field << 3 | 2);
+    write_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
 }
 
 impl Into<Ipld> for PbNode {
@@ -103,7 +218,9 @@ impl Into<Ipld> for PbNode {
             .map(|link| link.into())
             .collect::<Vec<Ipld>>();
         map.insert("Links".to_string(), links.into());
-        map.insert("Data".to_string(), self.data.into());
+        if let Some(data) = self.data {
+            map.insert("Data".to_string(), data.into());
+        }
         map.into()
     }
 }
@@ -112,8 +229,12 @@ impl Into<Ipld> for PbLink {
     fn into(self) -> Ipld {
         let mut map = BTreeMap::<String, Ipld>::new();
         map.insert("Hash".to_string(), self.cid.into());
-        map.insert("Name".to_string(), self.name.into());
-        map.insert("Tsize".to_string(), self.size.into());
+        if let Some(name) = self.name {
+            map.insert("Name".to_string(), name.into());
+        }
+        if let Some(size) = self.size {
+            map.insert("Tsize".to_string(), size.into());
+        }
         map.into()
     }
 }
@@ -130,10 +251,10 @@ impl TryFrom<&Ipld> for PbNode {
         } else {
             return Err(IpldError::NotList);
         };
-        let data = if let Ipld::Bytes(data) = ipld.get("Data").ok_or(IpldError::KeyNotFound)? {
-            data.clone()
-        } else {
-            return Err(IpldError::NotBytes);
+        let data = match ipld.get("Data") {
+            Some(Ipld::Bytes(data)) => Some(data.clone()),
+            Some(_) => return Err(IpldError::NotBytes),
+            None => None,
         };
         Ok(PbNode { links, data })
     }
@@ -148,16 +269,92 @@ impl TryFrom<&Ipld> for PbLink {
         } else {
             return Err(IpldError::NotLink);
         };
-        let name = if let Ipld::String(name) = ipld.get("Name").ok_or(IpldError::KeyNotFound)? {
-            name.clone()
-        } else {
-            return Err(IpldError::NotString);
+        let name = match ipld.get("Name") {
+            Some(Ipld::String(name)) => Some(name.clone()),
+            Some(_) => return Err(IpldError::NotString),
+            None => None,
         };
-        let size = if let Ipld::Integer(size) = ipld.get("Tsize").ok_or(IpldError::KeyNotFound)? {
-            *size as u64
-        } else {
-            return Err(IpldError::NotInteger);
+        let size = match ipld.get("Tsize") {
+            Some(Ipld::Integer(size)) => {
+                Some(u64::try_from(*size).map_err(|_| IpldError::NotInteger)?)
+            }
+            Some(_) => return Err(IpldError::NotInteger),
+            None => None,
         };
         Ok(PbLink { cid, name, size })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_literal::hex;
+
+    // a directory with links "a" and "b"
+    const DIRECTORY: [u8; 90] = hex!("12290a221220fc7fac69ddb44e39686ecfd1ecc6c52ab653f4227e533ee74a2e238f8b2143d3120161180712290a221220b924ddb19181d159c29eec7c98ec506976a76d40241ccd203b226849ce6e0b72120162183d0a020801");
+
+    #[test]
+    fn roundtrip_preserves_bytes() {
+        let node = PbNode::from_bytes_strict(&DIRECTORY).unwrap();
+        assert_eq!(&*node.clone().into_bytes(), &DIRECTORY[..]);
+
+        let ipld: Ipld = node.into();
+        assert_eq!(&*DagPbCodec::encode(&ipld).unwrap(), &DIRECTORY[..]);
+    }
+
+    #[test]
+    fn absent_fields_stay_absent() {
+        let cid = Cid::try_from("QmZDVQHwjHwA4SyzEDtJLNxmZeJVK1W8BWFAHV61x2Rs19").unwrap();
+        let node = PbNode {
+            links: vec![PbLink {
+                cid,
+                name: None,
+                size: None,
+            }],
+            data: None,
+        };
+
+        let bytes = node.clone().into_bytes();
+        assert_eq!(bytes[0], 0x12);
+        assert_eq!(bytes.len(), 2 + 2 + 34);
+
+        assert_eq!(PbNode::from_bytes_strict(&bytes).unwrap(), node);
+        assert_eq!(
+            DagPbCodec::decode(&bytes).unwrap(),
+            Ipld::Map(
+                vec![(
+                    "Links".to_owned(),
+                    Ipld::List(vec![Ipld::Map(
+                        vec![("Hash".to_owned(), Ipld::Link(node.links[0].cid.clone()))]
+                            .into_iter()
+                            .collect()
+                    )])
+                )]
+                .into_iter()
+                .collect()
+            )
+        );
+    }
+
+    #[test]
+    fn encoder_sorts_links() {
+        let mut node = PbNode::from_bytes(&DIRECTORY).unwrap();
+        node.links.reverse();
+        assert_eq!(&*node.into_bytes(), &DIRECTORY[..]);
+    }
+
+    #[test]
+    fn strict_rejects_what_lenient_accepts() {
+        // the data field moved in front of the links
+        let mut reordered = DIRECTORY[DIRECTORY.len() - 4..].to_vec();
+        reordered.extend_from_slice(&DIRECTORY[..DIRECTORY.len() - 4]);
+
+        let lenient = PbNode::from_bytes(&reordered).unwrap();
+        assert_eq!(&*lenient.into_bytes(), &DIRECTORY[..]);
+
+        match PbNode::from_bytes_strict(&reordered) {
+            Err(ProtobufError::NonCanonical(NonCanonical::FieldAfterData)) => {}
+            x => panic!("unexpected result: {:?}", x),
+        }
+    }
+}
@@ -8,6 +8,8 @@ pub mod dag_json;
 pub mod dag_pb;
 #[macro_use]
 pub mod ipld_macro;
+pub mod schema;
+pub mod selector;
 
 use cid::{Cid, Codec};
 use dag_cbor::DagCborCodec;
@@ -40,6 +42,51 @@ pub enum Ipld {
     Link(Cid),
 }
 
+/// Borrowed view of an [`Ipld`] document, where the strings and byte strings point into the
+/// encoded block. See [`DagCborCodec::decode_borrowed`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum IpldRef<'a> {
+    /// Represents the absence of a value or the value undefined.
+    Null,
+    /// Represents a boolean value.
+    Bool(bool),
+    /// Represents an integer.
+    Integer(i128),
+    /// Represents a floating point value.
+    Float(f64),
+    /// Represents an UTF-8 string.
+    String(&'a str),
+    /// Represents a sequence of bytes.
+    Bytes(&'a [u8]),
+    /// Represents a list.
+    List(Vec<IpldRef<'a>>),
+    /// Represents a map.
+    Map(BTreeMap<&'a str, IpldRef<'a>>),
+    /// Represents a link to an Ipld node
+    Link(Cid),
+}
+
+impl IpldRef<'_> {
+    /// Copies the borrowed view into an owned [`Ipld`].
+    pub fn into_owned(self) -> Ipld {
+        match self {
+            IpldRef::Null => Ipld::Null,
+            IpldRef::Bool(b) => Ipld::Bool(b),
+            IpldRef::Integer(i) => Ipld::Integer(i),
+            IpldRef::Float(f) => Ipld::Float(f),
+            IpldRef::String(s) => Ipld::String(s.to_owned()),
+            IpldRef::Bytes(b) => Ipld::Bytes(b.to_vec()),
+            IpldRef::List(l) => Ipld::List(l.into_iter().map(IpldRef::into_owned).collect()),
+            IpldRef::Map(m) => Ipld::Map(
+                m.into_iter()
+                    .map(|(k, v)| (k.to_owned(), v.into_owned()))
+                    .collect(),
+            ),
+            IpldRef::Link(cid) => Ipld::Link(cid),
+        }
+    }
+}
+
 macro_rules! derive_to_ipld_prim {
     ($enum:ident, $ty:ty, $fn:ident) => {
         impl From<$ty> for Ipld {
@@ -255,6 +302,13 @@ pub fn validate(cid: &Cid, data: &[u8]) -> Result<(), BlockError> {
     if data.len() > MAX_BLOCK_SIZE {
         return Err(BlockError::BlockTooLarge(data.len()));
     }
+    if cid.hash().algorithm() == multihash::Code::Identity {
+        // the data is the digest, there is nothing to hash
+        if cid.hash().digest() != data {
+            return Err(BlockError::InvalidHash(multihash::Identity::digest(data)));
+        }
+        return Ok(());
+    }
     let hash = cid.hash().algorithm().digest(&data);
     if hash.as_ref() != cid.hash() {
         return Err(BlockError::InvalidHash(hash));
@@ -0,0 +1,1110 @@
+//! IPLD schemas, as described in the [IPLD schemas specification].
+//!
+//! A [`Schema`] can be parsed from the schema DSL with [`Schema::from_dsl`] or from its dag-json
+//! representation (the "schema-schema" form) with [`Schema::from_dag_json`]. `Ipld` values can
+//! then be validated against any of the named types of the schema with [`Schema::validate`].
+//!
+//! Supported are the scalar kinds, lists, maps, links, copies, structs with map or tuple
+//! representation, enums with string or int representation and unions with keyed, kinded or
+//! inline representation. The `Any` type accepts any value. Advanced data layouts, field
+//! renames and implicit field values are not supported.
+//!
+//! [IPLD schemas specification]: https://github.com/ipld/specs/tree/master/schemas
+
+use crate::ipld::dag_json::DagJsonCodec;
+use crate::ipld::{BlockError, Ipld};
+use std::collections::BTreeMap;
+use std::fmt;
+use thiserror::Error;
+
+mod dsl;
+
+/// Parsed IPLD schema: a collection of named types.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Schema {
+    types: BTreeMap<String, Type>,
+    prelude: BTreeMap<String, Type>,
+}
+
+/// A reference to a type from a field, a list value, a map key or value, or a union member.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TypeRef {
+    /// Reference to a named type in the schema or in the prelude.
+    Named(String),
+    /// Anonymous list, map or link type.
+    Inline(Box<Type>),
+}
+
+/// A type definition.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Type {
+    /// Any value is accepted.
+    Any,
+    /// Boolean kind.
+    Bool,
+    /// String kind.
+    String,
+    /// Bytes kind.
+    Bytes,
+    /// Integer kind.
+    Int,
+    /// Float kind.
+    Float,
+    /// A type with the same definition as the named type.
+    Copy(String),
+    /// List with values of the same type.
+    List {
+        /// The type of the values
+        value: TypeRef,
+        /// Whether values can be null
+        nullable: bool,
+    },
+    /// Map with string keys and values of the same type.
+    Map {
+        /// The type of the keys, which needs to be representable as a string
+        key: TypeRef,
+        /// The type of the values
+        value: TypeRef,
+        /// Whether values can be null
+        nullable: bool,
+    },
+    /// Link to a document. The expected type is not verified, as that would require loading the
+    /// linked document.
+    Link(Option<String>),
+    /// Struct with the given fields, in the order of declaration.
+    Struct {
+        /// Fields in the order of declaration
+        fields: Vec<(String, Field)>,
+        /// How the struct is represented
+        representation: StructRepresentation,
+    },
+    /// Enum of the given member names.
+    Enum {
+        /// Member names in the order of declaration
+        members: Vec<String>,
+        /// How the enum is represented
+        representation: EnumRepresentation,
+    },
+    /// Union of the member types.
+    Union(UnionRepresentation),
+}
+
+/// Field of a struct.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Field {
+    /// The type of the field value
+    pub ty: TypeRef,
+    /// Whether the field can be absent
+    pub optional: bool,
+    /// Whether the field value can be null
+    pub nullable: bool,
+}
+
+/// The representations supported for structs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StructRepresentation {
+    /// Map from the field names to the field values.
+    Map,
+    /// List of the field values in the order of declaration.
+    Tuple,
+}
+
+/// The representations supported for enums.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EnumRepresentation {
+    /// Each member is represented by a string, defaulting to the member name.
+    String(BTreeMap<String, String>),
+    /// Each member is represented by an integer.
+    Int(BTreeMap<String, i128>),
+}
+
+/// The representations supported for unions, with the member types.
+#[derive(Clone, Debug, PartialEq)]
+pub enum UnionRepresentation {
+    /// Single entry map from the key to the value of the member type.
+    Keyed(BTreeMap<String, String>),
+    /// The kind of the value decides the member type.
+    Kinded(BTreeMap<Kind, TypeRef>),
+    /// Map with the discriminant key, the value of which decides the member type. Members need to
+    /// be structs with map representation.
+    Inline {
+        /// The key of the discriminant in the represented map
+        discriminant_key: String,
+        /// Discriminant values to member types
+        discriminants: BTreeMap<String, String>,
+    },
+}
+
+/// The kinds of the IPLD data model.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Kind {
+    Null,
+    Bool,
+    Int,
+    Float,
+    String,
+    Bytes,
+    List,
+    Map,
+    Link,
+}
+
+impl Kind {
+    /// Returns the kind of the value.
+    pub fn of(ipld: &Ipld) -> Self {
+        match ipld {
+            Ipld::Null => Kind::Null,
+            Ipld::Bool(_) => Kind::Bool,
+            Ipld::Integer(_) => Kind::Int,
+            Ipld::Float(_) => Kind::Float,
+            Ipld::String(_) => Kind::String,
+            Ipld::Bytes(_) => Kind::Bytes,
+            Ipld::List(_) => Kind::List,
+            Ipld::Map(_) => Kind::Map,
+            Ipld::Link(_) => Kind::Link,
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "null" => Kind::Null,
+            "bool" => Kind::Bool,
+            "int" => Kind::Int,
+            "float" => Kind::Float,
+            "string" => Kind::String,
+            "bytes" => Kind::Bytes,
+            "list" => Kind::List,
+            "map" => Kind::Map,
+            "link" => Kind::Link,
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Kind::Null => "null",
+            Kind::Bool => "bool",
+            Kind::Int => "int",
+            Kind::Float => "float",
+            Kind::String => "string",
+            Kind::Bytes => "bytes",
+            Kind::List => "list",
+            Kind::Map => "map",
+            Kind::Link => "link",
+        };
+        write!(fmt, "{}", s)
+    }
+}
+
+/// Failure to parse a schema.
+#[derive(Debug, Error)]
+pub enum SchemaError {
+    /// Decoding the dag-json document failed.
+    #[error("failed to decode schema document")]
+    Decoding(#[from] BlockError),
+
+    /// The schema DSL could not be parsed.
+    #[error("invalid schema at line {line}: {message}")]
+    Syntax {
+        /// The line of the failure, starting from 1
+        line: usize,
+        /// Description of the failure
+        message: String,
+    },
+
+    /// The dag-json representation did not follow the schema-schema.
+    #[error("invalid schema document at {0:?}: {1}")]
+    InvalidDocument(String, &'static str),
+
+    /// The same type name was defined twice.
+    #[error("type {0:?} is defined more than once")]
+    DuplicateType(String),
+
+    /// A type referenced a type which is not defined in the schema or the prelude.
+    #[error("type {0:?} references an undefined type {1:?}")]
+    UndefinedType(String, String),
+
+    /// A type was defined in an unsupported way.
+    #[error("type {0:?} is invalid: {1}")]
+    InvalidType(String, &'static str),
+}
+
+/// Failure to validate a value against a type.
+#[derive(Debug, Error, PartialEq)]
+#[error("value at {path:?} does not conform to {type_name}: {reason}")]
+pub struct ValidationError {
+    /// Path to the non-conforming value, with segments separated by slashes
+    pub path: String,
+    /// Name of the type or the description of an anonymous type the value did not conform to
+    pub type_name: String,
+    /// Description of the failure
+    pub reason: String,
+}
+
+impl Schema {
+    /// Parses a schema from the IPLD schema DSL.
+    pub fn from_dsl(dsl: &str) -> Result<Self, SchemaError> {
+        let types = dsl::parse(dsl)?;
+        Schema::new(types)
+    }
+
+    /// Parses a schema from the dag-json representation of the schema-schema, as produced by
+    /// the reference tooling from the DSL.
+    pub fn from_dag_json(data: &[u8]) -> Result<Self, SchemaError> {
+        let ipld = DagJsonCodec::decode(data)?;
+        Schema::from_ipld(&ipld)
+    }
+
+    /// Parses a schema from the `Ipld` representation of the schema-schema.
+    pub fn from_ipld(ipld: &Ipld) -> Result<Self, SchemaError> {
+        let types = match ipld.get("types") {
+            Some(Ipld::Map(types)) => types,
+            _ => {
+                return Err(SchemaError::InvalidDocument(
+                    "types".into(),
+                    "expected a map",
+                ))
+            }
+        };
+
+        let types = types
+            .iter()
+            .map(|(name, defn)| Ok((name.to_owned(), type_from_ipld(name, defn)?)))
+            .collect::<Result<Vec<_>, SchemaError>>()?;
+
+        Schema::new(types)
+    }
+
+    fn new(types: Vec<(String, Type)>) -> Result<Self, SchemaError> {
+        let prelude = prelude();
+        let mut map = BTreeMap::new();
+
+        for (name, ty) in types {
+            if prelude.contains_key(&name) || map.insert(name.clone(), ty).is_some() {
+                return Err(SchemaError::DuplicateType(name));
+            }
+        }
+
+        let schema = Schema {
+            types: map,
+            prelude,
+        };
+        schema.check_references()?;
+        Ok(schema)
+    }
+
+    /// Returns the named type, if defined in this schema. Types of the prelude are not returned.
+    pub fn get(&self, name: &str) -> Option<&Type> {
+        self.types.get(name)
+    }
+
+    /// Returns an iterator over the names of the types defined in this schema.
+    pub fn type_names(&self) -> impl Iterator<Item = &str> {
+        self.types.keys().map(|s| s.as_str())
+    }
+
+    /// Validates the value against the named type. Fails if the type is not defined in the schema
+    /// or the prelude.
+    pub fn validate(&self, ipld: &Ipld, type_name: &str) -> Result<(), ValidationError> {
+        let ty = self.resolve(type_name).ok_or_else(|| ValidationError {
+            path: String::new(),
+            type_name: type_name.to_owned(),
+            reason: "type is not defined in the schema".into(),
+        })?;
+        let mut path = Vec::new();
+        self.validate_type(ipld, ty, type_name, &mut path)
+    }
+
+    fn resolve<'a>(&'a self, name: &str) -> Option<&'a Type> {
+        self.types.get(name).or_else(|| self.prelude.get(name))
+    }
+
+    fn check_references(&self) -> Result<(), SchemaError> {
+        for (name, ty) in &self.types {
+            let mut referenced = Vec::new();
+            collect_references(ty, &mut referenced);
+
+            for target in referenced {
+                match self.resolve(target) {
+                    Some(_) => {}
+                    None => {
+                        return Err(SchemaError::UndefinedType(
+                            name.to_owned(),
+                            target.to_owned(),
+                        ))
+                    }
+                }
+            }
+
+            match ty {
+                Type::Union(UnionRepresentation::Inline { discriminants, .. }) => {
+                    for member in discriminants.values() {
+                        match self.resolve(member) {
+                            Some(Type::Struct {
+                                representation: StructRepresentation::Map,
+                                ..
+                            }) => {}
+                            _ => {
+                                return Err(SchemaError::InvalidType(
+                                    name.to_owned(),
+                                    "inline union members must be structs with map representation",
+                                ))
+                            }
+                        }
+                    }
+                }
+                Type::Copy(from) => {
+                    if let Some(Type::Copy(_)) = self.resolve(from) {
+                        return Err(SchemaError::InvalidType(
+                            name.to_owned(),
+                            "copy of a copy is not allowed",
+                        ));
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_named(
+        &self,
+        ipld: &Ipld,
+        name: &str,
+        path: &mut Vec<String>,
+    ) -> Result<(), ValidationError> {
+        let ty = self
+            .resolve(name)
+            .expect("references are checked while constructing the schema");
+        self.validate_type(ipld, ty, name, path)
+    }
+
+    fn validate_ref(
+        &self,
+        ipld: &Ipld,
+        ty: &TypeRef,
+        path: &mut Vec<String>,
+    ) -> Result<(), ValidationError> {
+        match ty {
+            TypeRef::Named(name) => self.validate_named(ipld, name, path),
+            TypeRef::Inline(ty) => self.validate_type(ipld, ty, &ty.to_string(), path),
+        }
+    }
+
+    fn validate_type(
+        &self,
+        ipld: &Ipld,
+        ty: &Type,
+        type_name: &str,
+        path: &mut Vec<String>,
+    ) -> Result<(), ValidationError> {
+        let fail = |path: &[String], reason: String| ValidationError {
+            path: path.join("/"),
+            type_name: type_name.to_owned(),
+            reason,
+        };
+
+        let expect_kind = |path: &[String], expected: Kind| {
+            let actual = Kind::of(ipld);
+            if actual == expected {
+                Ok(())
+            } else {
+                Err(fail(
+                    path,
+                    format!("expected kind {}, found {}", expected, actual),
+                ))
+            }
+        };
+
+        match ty {
+            Type::Any => Ok(()),
+            Type::Bool => expect_kind(path, Kind::Bool),
+            Type::String => expect_kind(path, Kind::String),
+            Type::Bytes => expect_kind(path, Kind::Bytes),
+            Type::Int => expect_kind(path, Kind::Int),
+            Type::Float => expect_kind(path, Kind::Float),
+            Type::Link(_) => expect_kind(path, Kind::Link),
+            Type::Copy(from) => self.validate_named(ipld, from, path),
+            Type::List { value, nullable } => {
+                expect_kind(path, Kind::List)?;
+                let list = match ipld {
+                    Ipld::List(list) => list,
+                    _ => unreachable!(),
+                };
+
+                for (i, elem) in list.iter().enumerate() {
+                    path.push(i.to_string());
+                    self.validate_nullable(elem, value, *nullable, path)?;
+                    path.pop();
+                }
+                Ok(())
+            }
+            Type::Map {
+                key,
+                value,
+                nullable,
+            } => {
+                expect_kind(path, Kind::Map)?;
+                let map = match ipld {
+                    Ipld::Map(map) => map,
+                    _ => unreachable!(),
+                };
+
+                for (k, v) in map {
+                    path.push(k.to_owned());
+                    self.validate_ref(&Ipld::String(k.to_owned()), key, path)?;
+                    self.validate_nullable(v, value, *nullable, path)?;
+                    path.pop();
+                }
+                Ok(())
+            }
+            Type::Struct {
+                fields,
+                representation: StructRepresentation::Map,
+            } => {
+                expect_kind(path, Kind::Map)?;
+                let map = match ipld {
+                    Ipld::Map(map) => map,
+                    _ => unreachable!(),
+                };
+                self.validate_struct_map(map, fields, None, type_name, path)
+            }
+            Type::Struct {
+                fields,
+                representation: StructRepresentation::Tuple,
+            } => {
+                expect_kind(path, Kind::List)?;
+                let list = match ipld {
+                    Ipld::List(list) => list,
+                    _ => unreachable!(),
+                };
+
+                if list.len() != fields.len() {
+                    return Err(fail(
+                        path,
+                        format!("expected {} elements, found {}", fields.len(), list.len()),
+                    ));
+                }
+
+                for (i, (elem, (_, field))) in list.iter().zip(fields.iter()).enumerate() {
+                    path.push(i.to_string());
+                    self.validate_nullable(elem, &field.ty, field.nullable, path)?;
+                    path.pop();
+                }
+                Ok(())
+            }
+            Type::Enum {
+                representation: EnumRepresentation::String(values),
+                ..
+            } => {
+                expect_kind(path, Kind::String)?;
+                match ipld {
+                    Ipld::String(s) if values.values().any(|v| v == s) => Ok(()),
+                    Ipld::String(s) => Err(fail(path, format!("unknown enum value {:?}", s))),
+                    _ => unreachable!(),
+                }
+            }
+            Type::Enum {
+                representation: EnumRepresentation::Int(values),
+                ..
+            } => {
+                expect_kind(path, Kind::Int)?;
+                match ipld {
+                    Ipld::Integer(i) if values.values().any(|v| v == i) => Ok(()),
+                    Ipld::Integer(i) => Err(fail(path, format!("unknown enum value {}", i))),
+                    _ => unreachable!(),
+                }
+            }
+            Type::Union(UnionRepresentation::Keyed(members)) => {
+                let (key, value) = match ipld {
+                    Ipld::Map(map) if map.len() == 1 => map.iter().next().unwrap(),
+                    _ => return Err(fail(path, "expected a single entry map".into())),
+                };
+
+                let member = members
+                    .get(key)
+                    .ok_or_else(|| fail(path, format!("unknown union key {:?}", key)))?;
+
+                path.push(key.to_owned());
+                self.validate_named(value, member, path)?;
+                path.pop();
+                Ok(())
+            }
+            Type::Union(UnionRepresentation::Kinded(members)) => {
+                let kind = Kind::of(ipld);
+                let member = members
+                    .get(&kind)
+                    .ok_or_else(|| fail(path, format!("no union member for kind {}", kind)))?;
+                self.validate_ref(ipld, member, path)
+            }
+            Type::Union(UnionRepresentation::Inline {
+                discriminant_key,
+                discriminants,
+            }) => {
+                expect_kind(path, Kind::Map)?;
+                let map = match ipld {
+                    Ipld::Map(map) => map,
+                    _ => unreachable!(),
+                };
+
+                let member = match map.get(discriminant_key) {
+                    Some(Ipld::String(discriminant)) => {
+                        discriminants.get(discriminant).ok_or_else(|| {
+                            fail(path, format!("unknown discriminant {:?}", discriminant))
+                        })?
+                    }
+                    _ => {
+                        return Err(fail(
+                            path,
+                            format!("missing string discriminant {:?}", discriminant_key),
+                        ))
+                    }
+                };
+
+                match self.resolve(member) {
+                    Some(Type::Struct { fields, .. }) => {
+                        self.validate_struct_map(map, fields, Some(discriminant_key), member, path)
+                    }
+                    _ => unreachable!("inline union members are checked to be structs"),
+                }
+            }
+        }
+    }
+
+    fn validate_nullable(
+        &self,
+        ipld: &Ipld,
+        ty: &TypeRef,
+        nullable: bool,
+        path: &mut Vec<String>,
+    ) -> Result<(), ValidationError> {
+        if nullable && ipld == &Ipld::Null {
+            Ok(())
+        } else {
+            self.validate_ref(ipld, ty, path)
+        }
+    }
+
+    fn validate_struct_map(
+        &self,
+        map: &BTreeMap<String, Ipld>,
+        fields: &[(String, Field)],
+        ignored_key: Option<&str>,
+        type_name: &str,
+        path: &mut Vec<String>,
+    ) -> Result<(), ValidationError> {
+        for (name, field) in fields {
+            match map.get(name) {
+                Some(value) => {
+                    path.push(name.to_owned());
+                    self.validate_nullable(value, &field.ty, field.nullable, path)?;
+                    path.pop();
+                }
+                None if field.optional => {}
+                None => {
+                    return Err(ValidationError {
+                        path: path.join("/"),
+                        type_name: type_name.to_owned(),
+                        reason: format!("missing field {:?}", name),
+                    })
+                }
+            }
+        }
+
+        let unknown = map.keys().find(|k| {
+            Some(k.as_str()) != ignored_key && !fields.iter().any(|(name, _)| name == *k)
+        });
+
+        match unknown {
+            Some(k) => Err(ValidationError {
+                path: path.join("/"),
+                type_name: type_name.to_owned(),
+                reason: format!("unknown field {:?}", k),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for TypeRef {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeRef::Named(name) => write!(fmt, "{}", name),
+            TypeRef::Inline(ty) => write!(fmt, "{}", ty),
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    /// Formats inline types like they are written in the DSL.
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let nullable = |nullable: bool| if nullable { "nullable " } else { "" };
+        match self {
+            Type::List { value, nullable: n } => write!(fmt, "[{}{}]", nullable(*n), value),
+            Type::Map {
+                key,
+                value,
+                nullable: n,
+            } => write!(fmt, "{{{}:{}{}}}", key, nullable(*n), value),
+            Type::Link(Some(expected)) => write!(fmt, "&{}", expected),
+            Type::Link(None) => write!(fmt, "&Any"),
+            Type::Any => write!(fmt, "Any"),
+            Type::Bool => write!(fmt, "bool"),
+            Type::String => write!(fmt, "string"),
+            Type::Bytes => write!(fmt, "bytes"),
+            Type::Int => write!(fmt, "int"),
+            Type::Float => write!(fmt, "float"),
+            Type::Copy(from) => write!(fmt, "= {}", from),
+            Type::Struct { .. } => write!(fmt, "struct"),
+            Type::Enum { .. } => write!(fmt, "enum"),
+            Type::Union(_) => write!(fmt, "union"),
+        }
+    }
+}
+
+/// The types which are always defined.
+fn prelude() -> BTreeMap<String, Type> {
+    let named = |name: &str| TypeRef::Named(name.to_owned());
+
+    vec![
+        ("Bool", Type::Bool),
+        ("String", Type::String),
+        ("Bytes", Type::Bytes),
+        ("Int", Type::Int),
+        ("Float", Type::Float),
+        ("Any", Type::Any),
+        ("Link", Type::Link(None)),
+        (
+            "Map",
+            Type::Map {
+                key: named("String"),
+                value: named("Any"),
+                nullable: true,
+            },
+        ),
+        (
+            "List",
+            Type::List {
+                value: named("Any"),
+                nullable: true,
+            },
+        ),
+    ]
+    .into_iter()
+    .map(|(name, ty)| (name.to_owned(), ty))
+    .collect()
+}
+
+fn collect_references<'a>(ty: &'a Type, out: &mut Vec<&'a str>) {
+    fn collect_ref<'a>(ty: &'a TypeRef, out: &mut Vec<&'a str>) {
+        match ty {
+            TypeRef::Named(name) => out.push(name),
+            TypeRef::Inline(ty) => collect_references(ty, out),
+        }
+    }
+
+    match ty {
+        Type::Copy(from) => out.push(from),
+        Type::List { value, .. } => collect_ref(value, out),
+        Type::Map { key, value, .. } => {
+            collect_ref(key, out);
+            collect_ref(value, out);
+        }
+        Type::Link(Some(expected)) => out.push(expected),
+        Type::Struct { fields, .. } => fields.iter().for_each(|(_, f)| collect_ref(&f.ty, out)),
+        Type::Union(UnionRepresentation::Keyed(members)) => {
+            members.values().for_each(|m| out.push(m))
+        }
+        Type::Union(UnionRepresentation::Kinded(members)) => {
+            members.values().for_each(|m| collect_ref(m, out))
+        }
+        Type::Union(UnionRepresentation::Inline { discriminants, .. }) => {
+            discriminants.values().for_each(|m| out.push(m))
+        }
+        _ => {}
+    }
+}
+
+/// Parses a type definition in the keyed schema-schema form, for example
+/// `{"struct": {"fields": {...}, "representation": {"map": {}}}}`.
+fn type_from_ipld(name: &str, ipld: &Ipld) -> Result<Type, SchemaError> {
+    let invalid = |what: &'static str| SchemaError::InvalidDocument(name.to_owned(), what);
+
+    let (kind, body) = match ipld {
+        Ipld::Map(m) if m.len() == 1 => m.iter().next().unwrap(),
+        _ => return Err(invalid("expected a single entry map")),
+    };
+
+    let string = |key: &str| match body.get(key) {
+        Some(Ipld::String(s)) => Ok(s.to_owned()),
+        _ => Err(invalid("expected a string field")),
+    };
+
+    let boolean = |key: &str| match body.get(key) {
+        Some(Ipld::Bool(b)) => Ok(*b),
+        None => Ok(false),
+        _ => Err(invalid("expected a boolean field")),
+    };
+
+    let type_ref = |ipld: Option<&Ipld>| match ipld {
+        Some(Ipld::String(s)) => Ok(TypeRef::Named(s.to_owned())),
+        Some(inline @ Ipld::Map(_)) => Ok(TypeRef::Inline(Box::new(type_from_ipld(name, inline)?))),
+        _ => Err(invalid("expected a type name or an inline type")),
+    };
+
+    let members = || match body.get("members") {
+        Some(Ipld::List(members)) => members
+            .iter()
+            .map(|m| match m {
+                Ipld::String(s) => Ok(s.to_owned()),
+                _ => Err(invalid("expected member names")),
+            })
+            .collect::<Result<Vec<_>, _>>(),
+        _ => Err(invalid("expected a list of members")),
+    };
+
+    let representation = || match body.get("representation") {
+        Some(Ipld::Map(m)) if m.len() == 1 => {
+            let (kind, body) = m.iter().next().unwrap();
+            Ok(Some((kind.as_str(), body)))
+        }
+        None => Ok(None),
+        _ => Err(invalid("expected a single entry representation map")),
+    };
+
+    let string_map = |ipld: Option<&Ipld>| match ipld {
+        Some(Ipld::Map(m)) => m
+            .iter()
+            .map(|(k, v)| match v {
+                Ipld::String(s) => Ok((k.to_owned(), s.to_owned())),
+                _ => Err(invalid("expected a map of strings")),
+            })
+            .collect::<Result<BTreeMap<_, _>, _>>(),
+        None => Ok(BTreeMap::new()),
+        _ => Err(invalid("expected a map of strings")),
+    };
+
+    Ok(match kind.as_str() {
+        "bool" => Type::Bool,
+        "string" => Type::String,
+        "bytes" => Type::Bytes,
+        "int" => Type::Int,
+        "float" => Type::Float,
+        "any" => Type::Any,
+        "copy" => Type::Copy(string("fromType")?),
+        "link" => match body.get("expectedType") {
+            Some(Ipld::String(s)) if s == "Any" => Type::Link(None),
+            Some(Ipld::String(s)) => Type::Link(Some(s.to_owned())),
+            None => Type::Link(None),
+            _ => return Err(invalid("expected a string expectedType")),
+        },
+        "list" => Type::List {
+            value: type_ref(body.get("valueType"))?,
+            nullable: boolean("valueNullable")?,
+        },
+        "map" => Type::Map {
+            key: TypeRef::Named(string("keyType")?),
+            value: type_ref(body.get("valueType"))?,
+            nullable: boolean("valueNullable")?,
+        },
+        "struct" => {
+            let fields = match body.get("fields") {
+                Some(Ipld::Map(fields)) => fields,
+                _ => return Err(invalid("expected a map of fields")),
+            };
+
+            // the map of fields loses the order of declaration; use the order of the tuple
+            // representation when given
+            let mut fields = fields
+                .iter()
+                .map(|(field_name, defn)| {
+                    let flag = |key: &str| match defn.get(key) {
+                        Some(Ipld::Bool(b)) => Ok(*b),
+                        None => Ok(false),
+                        _ => Err(invalid("expected a boolean field")),
+                    };
+
+                    Ok((
+                        field_name.to_owned(),
+                        Field {
+                            ty: type_ref(defn.get("type"))?,
+                            optional: flag("optional")?,
+                            nullable: flag("nullable")?,
+                        },
+                    ))
+                })
+                .collect::<Result<Vec<_>, SchemaError>>()?;
+
+            let representation = match representation()? {
+                None | Some(("map", _)) => StructRepresentation::Map,
+                Some(("tuple", repr)) => {
+                    if let Some(Ipld::List(order)) = repr.get("fieldOrder") {
+                        let position = |name: &str| {
+                            order
+                                .iter()
+                                .position(|o| matches!(o, Ipld::String(s) if s == name))
+                        };
+                        fields.sort_by_key(|(name, _)| position(name));
+                    }
+                    StructRepresentation::Tuple
+                }
+                Some(_) => return Err(invalid("unsupported struct representation")),
+            };
+
+            Type::Struct {
+                fields,
+                representation,
+            }
+        }
+        "enum" => {
+            let members = members()?;
+            let representation = match representation()? {
+                None => EnumRepresentation::String(BTreeMap::new()),
+                Some(("string", repr)) => EnumRepresentation::String(string_map(Some(repr))?),
+                Some(("int", repr)) => {
+                    let values = match repr {
+                        Ipld::Map(m) => m
+                            .iter()
+                            .map(|(k, v)| match v {
+                                Ipld::Integer(i) => Ok((k.to_owned(), *i)),
+                                _ => Err(invalid("expected a map of integers")),
+                            })
+                            .collect::<Result<BTreeMap<_, _>, _>>()?,
+                        _ => return Err(invalid("expected a map of integers")),
+                    };
+                    EnumRepresentation::Int(values)
+                }
+                Some(_) => return Err(invalid("unsupported enum representation")),
+            };
+
+            enum_type(name, members, representation)?
+        }
+        "union" => {
+            let representation = match representation()? {
+                Some(("keyed", repr)) => UnionRepresentation::Keyed(string_map(Some(repr))?),
+                Some(("kinded", repr)) => {
+                    let members = match repr {
+                        Ipld::Map(m) => m
+                            .iter()
+                            .map(|(k, v)| {
+                                let kind = Kind::from_name(k)
+                                    .ok_or_else(|| invalid("unknown kind in kinded union"))?;
+                                Ok((kind, type_ref(Some(v))?))
+                            })
+                            .collect::<Result<BTreeMap<_, _>, SchemaError>>()?,
+                        _ => return Err(invalid("expected a map of kinds")),
+                    };
+                    UnionRepresentation::Kinded(members)
+                }
+                Some(("inline", repr)) => UnionRepresentation::Inline {
+                    discriminant_key: match repr.get("discriminantKey") {
+                        Some(Ipld::String(s)) => s.to_owned(),
+                        _ => return Err(invalid("expected a string discriminantKey")),
+                    },
+                    discriminants: string_map(repr.get("discriminantTable"))?,
+                },
+                _ => return Err(invalid("unsupported union representation")),
+            };
+            Type::Union(representation)
+        }
+        _ => return Err(invalid("unsupported type kind")),
+    })
+}
+
+/// Completes the enum representation: in the string representation members without an explicit
+/// value are represented by their names; in the int representation every member needs a value.
+fn enum_type(
+    name: &str,
+    members: Vec<String>,
+    representation: EnumRepresentation,
+) -> Result<Type, SchemaError> {
+    let representation = match representation {
+        EnumRepresentation::String(mut values) => {
+            for member in &members {
+                values
+                    .entry(member.to_owned())
+                    .or_insert_with(|| member.to_owned());
+            }
+            EnumRepresentation::String(values)
+        }
+        EnumRepresentation::Int(values) => {
+            if members.iter().any(|m| !values.contains_key(m)) {
+                return Err(SchemaError::InvalidType(
+                    name.to_owned(),
+                    "all members of an int enum need a value",
+                ));
+            }
+            EnumRepresentation::Int(values)
+        }
+    };
+
+    Ok(Type::Enum {
+        members,
+        representation,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    const DSL: &str = r#"
+        # a document describing a file
+        type File struct {
+            name String
+            size optional Int
+            parent nullable &File
+            tags [String]
+            kind Kind
+            meta {String:nullable Meta}
+        }
+
+        type Kind enum {
+            | Regular ("regular")
+            | Symlink
+        } representation string
+
+        type Meta union {
+            | Int int
+            | String string
+            | Point list
+        } representation kinded
+
+        type Point struct {
+            x Float
+            y Float
+        } representation tuple
+
+        type Shape union {
+            | Circle "circle"
+            | Square "square"
+        } representation inline {
+            discriminantKey "type"
+        }
+
+        type Circle struct {
+            radius Int
+        }
+
+        type Square struct {
+            side Int
+        }
+    "#;
+
+    fn file() -> Ipld {
+        let cid = cid::Cid::try_from("QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL").unwrap();
+        make_ipld!({
+            "name": "foo",
+            "parent": cid,
+            "tags": ["a", "b"],
+            "kind": "regular",
+            "meta": { "a": 1, "b": "two", "c": [1.0, 2.0], "d": null },
+        })
+    }
+
+    #[test]
+    fn validate_conforming() {
+        let schema = Schema::from_dsl(DSL).unwrap();
+        schema.validate(&file(), "File").unwrap();
+
+        let circle = make_ipld!({ "type": "circle", "radius": 3 });
+        schema.validate(&circle, "Shape").unwrap();
+    }
+
+    #[test]
+    fn validate_non_conforming() {
+        let schema = Schema::from_dsl(DSL).unwrap();
+
+        let mut doc = file();
+        if let Ipld::Map(ref mut m) = doc {
+            m.insert("kind".into(), "directory".into());
+        }
+        let e = schema.validate(&doc, "File").unwrap_err();
+        assert_eq!(e.path, "kind");
+        assert_eq!(e.type_name, "Kind");
+
+        let mut doc = file();
+        if let Ipld::Map(ref mut m) = doc {
+            m.remove("name");
+        }
+        let e = schema.validate(&doc, "File").unwrap_err();
+        assert_eq!(e.path, "");
+        assert_eq!(e.reason, "missing field \"name\"");
+
+        let e = schema.validate(&doc, "Directory").unwrap_err();
+        assert_eq!(e.path, "");
+        assert_eq!(e.type_name, "Directory");
+        assert_eq!(e.reason, "type is not defined in the schema");
+
+        let mut doc = file();
+        if let Ipld::Map(ref mut m) = doc {
+            m.insert("meta".into(), make_ipld!({ "a": [1.0] }));
+        }
+        let e = schema.validate(&doc, "File").unwrap_err();
+        assert_eq!(e.path, "meta/a");
+        assert_eq!(e.type_name, "Point");
+
+        let square = make_ipld!({ "type": "square", "radius": 3 });
+        let e = schema.validate(&square, "Shape").unwrap_err();
+        assert_eq!(e.type_name, "Square");
+    }
+
+    #[test]
+    fn dsl_and_dag_json_are_equal() {
+        let json = br#"{"types":{
+            "Kind":{"enum":{"members":["Regular","Symlink"],"representation":{"string":{"Regular":"regular"}}}},
+            "Point":{"struct":{"fields":{"x":{"type":"Float"},"y":{"type":"Float"}},"representation":{"tuple":{}}}},
+            "Meta":{"union":{"members":["Int","String","Point"],"representation":{"kinded":{"int":"Int","string":"String","list":"Point"}}}},
+            "Circle":{"struct":{"fields":{"radius":{"type":"Int"}},"representation":{"map":{}}}},
+            "Square":{"struct":{"fields":{"side":{"type":"Int"}},"representation":{"map":{}}}},
+            "Shape":{"union":{"members":["Circle","Square"],"representation":{"inline":{"discriminantKey":"type","discriminantTable":{"circle":"Circle","square":"Square"}}}}},
+            "File":{"struct":{"fields":{
+                "name":{"type":"String"},
+                "size":{"type":"Int","optional":true},
+                "parent":{"type":{"link":{"expectedType":"File"}},"nullable":true},
+                "tags":{"type":{"list":{"valueType":"String"}}},
+                "kind":{"type":"Kind"},
+                "meta":{"type":{"map":{"keyType":"String","valueType":"Meta","valueNullable":true}}}
+            },"representation":{"map":{}}}}
+        }}"#;
+
+        let from_json = Schema::from_dag_json(json).unwrap();
+        let from_dsl = Schema::from_dsl(DSL).unwrap();
+
+        // field order is lost in the map of fields of the dag-json form
+        for name in from_dsl.type_names() {
+            match (from_dsl.get(name).unwrap(), from_json.get(name).unwrap()) {
+                (
+                    Type::Struct {
+                        fields: a,
+                        representation: StructRepresentation::Map,
+                    },
+                    Type::Struct { fields: b, .. },
+                ) => {
+                    let mut a = a.clone();
+                    a.sort_by(|x, y| x.0.cmp(&y.0));
+                    assert_eq!(&a, b, "{}", name);
+                }
+                (a, b) => assert_eq!(a, b, "{}", name),
+            }
+        }
+
+        assert_eq!(
+            from_dsl.type_names().collect::<Vec<_>>(),
+            from_json.type_names().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn undefined_type() {
+        match Schema::from_dsl("type Foo [Bar]").unwrap_err() {
+            SchemaError::UndefinedType(a, b) => {
+                assert_eq!((a.as_str(), b.as_str()), ("Foo", "Bar"))
+            }
+            x => unreachable!("{:?}", x),
+        }
+    }
+}
@@ -0,0 +1,471 @@
+//! Parser for the IPLD schema DSL.
+
+use super::{
+    enum_type, EnumRepresentation, Field, Kind, SchemaError, StructRepresentation, Type, TypeRef,
+    UnionRepresentation,
+};
+use std::collections::BTreeMap;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Punct(char),
+}
+
+struct Lexer<'a> {
+    input: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    line: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(input: &'a str) -> Self {
+        Lexer {
+            input,
+            chars: input.char_indices().peekable(),
+            line: 1,
+        }
+    }
+
+    /// Returns the next token with the line it started on.
+    fn next_token(&mut self) -> Result<Option<(usize, Token)>, SchemaError> {
+        loop {
+            let (start, ch) = match self.chars.next() {
+                Some(t) => t,
+                None => return Ok(None),
+            };
+
+            match ch {
+                '\n' => self.line += 1,
+                c if c.is_whitespace() => {}
+                '#' => {
+                    // comments continue until the end of the line
+                    while let Some((_, c)) = self.chars.peek() {
+                        if *c == '\n' {
+                            break;
+                        }
+                        self.chars.next();
+                    }
+                }
+                '"' => {
+                    let line = self.line;
+                    loop {
+                        match self.chars.next() {
+                            Some((end, '"')) => {
+                                let s = self.input[start + 1..end].to_owned();
+                                return Ok(Some((line, Token::Str(s))));
+                            }
+                            Some((_, '\n')) | None => {
+                                return Err(SchemaError::Syntax {
+                                    line,
+                                    message: "unterminated string".into(),
+                                })
+                            }
+                            Some(_) => {}
+                        }
+                    }
+                }
+                c if c.is_alphanumeric() || c == '_' => {
+                    let mut end = start + c.len_utf8();
+                    while let Some((i, c)) = self.chars.peek() {
+                        if c.is_alphanumeric() || *c == '_' {
+                            end = i + c.len_utf8();
+                            self.chars.next();
+                        } else {
+                            break;
+                        }
+                    }
+                    let ident = self.input[start..end].to_owned();
+                    return Ok(Some((self.line, Token::Ident(ident))));
+                }
+                '{' | '}' | '[' | ']' | '(' | ')' | '|' | ':' | '&' | '=' => {
+                    return Ok(Some((self.line, Token::Punct(ch))))
+                }
+                other => {
+                    return Err(SchemaError::Syntax {
+                        line: self.line,
+                        message: format!("unexpected character {:?}", other),
+                    })
+                }
+            }
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, t)| t)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .or_else(|| self.tokens.last())
+            .map(|(line, _)| *line)
+            .unwrap_or(1)
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, SchemaError> {
+        Err(SchemaError::Syntax {
+            line: self.line(),
+            message: message.into(),
+        })
+    }
+
+    fn next(&mut self) -> Result<Token, SchemaError> {
+        match self.tokens.get(self.pos) {
+            Some((_, t)) => {
+                self.pos += 1;
+                Ok(t.clone())
+            }
+            None => self.error("unexpected end of input"),
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, SchemaError> {
+        match self.next()? {
+            Token::Ident(s) => Ok(s),
+            t => {
+                self.pos -= 1;
+                self.error(format!("expected an identifier, found {:?}", t))
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, SchemaError> {
+        match self.next()? {
+            Token::Str(s) => Ok(s),
+            t => {
+                self.pos -= 1;
+                self.error(format!("expected a string, found {:?}", t))
+            }
+        }
+    }
+
+    fn expect(&mut self, ch: char) -> Result<(), SchemaError> {
+        match self.next()? {
+            Token::Punct(c) if c == ch => Ok(()),
+            t => {
+                self.pos -= 1;
+                self.error(format!("expected {:?}, found {:?}", ch, t))
+            }
+        }
+    }
+
+    fn eat_punct(&mut self, ch: char) -> bool {
+        if self.peek() == Some(&Token::Punct(ch)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Ident(s)) if s == keyword => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn schema(&mut self) -> Result<Vec<(String, Type)>, SchemaError> {
+        let mut types = Vec::new();
+
+        while self.peek().is_some() {
+            if !self.eat_keyword("type") {
+                return self.error("expected \"type\"");
+            }
+            let name = self.ident()?;
+            let ty = self.type_definition(&name)?;
+            types.push((name, ty));
+        }
+
+        Ok(types)
+    }
+
+    fn type_definition(&mut self, name: &str) -> Result<Type, SchemaError> {
+        match self.peek() {
+            Some(Token::Punct('[')) | Some(Token::Punct('{')) | Some(Token::Punct('&')) => {
+                let ty = self.inline_type()?;
+                if self.eat_keyword("representation") {
+                    return self.error("representations of lists, maps and links are unsupported");
+                }
+                return Ok(ty);
+            }
+            Some(Token::Punct('=')) => {
+                self.pos += 1;
+                return Ok(Type::Copy(self.ident()?));
+            }
+            _ => {}
+        }
+
+        let kind = self.ident()?;
+
+        Ok(match kind.as_str() {
+            "bool" => Type::Bool,
+            "string" => Type::String,
+            "bytes" => Type::Bytes,
+            "int" => Type::Int,
+            "float" => Type::Float,
+            "any" => Type::Any,
+            "struct" => self.struct_type()?,
+            "enum" => self.enum_type(name)?,
+            "union" => self.union_type()?,
+            other => return self.error(format!("unsupported type kind {:?}", other)),
+        })
+    }
+
+    /// Parses a type name or an inline list, map or link type.
+    fn type_ref(&mut self) -> Result<TypeRef, SchemaError> {
+        match self.peek() {
+            Some(Token::Punct('[')) | Some(Token::Punct('{')) | Some(Token::Punct('&')) => {
+                Ok(TypeRef::Inline(Box::new(self.inline_type()?)))
+            }
+            _ => Ok(TypeRef::Named(self.ident()?)),
+        }
+    }
+
+    fn inline_type(&mut self) -> Result<Type, SchemaError> {
+        match self.next()? {
+            Token::Punct('[') => {
+                let nullable = self.eat_keyword("nullable");
+                let value = self.type_ref()?;
+                self.expect(']')?;
+                Ok(Type::List { value, nullable })
+            }
+            Token::Punct('{') => {
+                let key = TypeRef::Named(self.ident()?);
+                self.expect(':')?;
+                let nullable = self.eat_keyword("nullable");
+                let value = self.type_ref()?;
+                self.expect('}')?;
+                Ok(Type::Map {
+                    key,
+                    value,
+                    nullable,
+                })
+            }
+            Token::Punct('&') => match self.ident()?.as_str() {
+                "Any" => Ok(Type::Link(None)),
+                expected => Ok(Type::Link(Some(expected.to_owned()))),
+            },
+            t => {
+                self.pos -= 1;
+                self.error(format!("expected an inline type, found {:?}", t))
+            }
+        }
+    }
+
+    fn representation(&mut self) -> Result<Option<String>, SchemaError> {
+        if self.eat_keyword("representation") {
+            Ok(Some(self.ident()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn struct_type(&mut self) -> Result<Type, SchemaError> {
+        self.expect('{')?;
+
+        let mut fields = Vec::new();
+        while !self.eat_punct('}') {
+            let name = self.ident()?;
+            let optional = self.eat_keyword("optional");
+            let nullable = self.eat_keyword("nullable");
+            let ty = self.type_ref()?;
+
+            if self.peek() == Some(&Token::Punct('(')) {
+                return self.error("field options are unsupported");
+            }
+
+            fields.push((
+                name,
+                Field {
+                    ty,
+                    optional,
+                    nullable,
+                },
+            ));
+        }
+
+        let representation = match self.representation()?.as_deref() {
+            None | Some("map") => StructRepresentation::Map,
+            Some("tuple") => StructRepresentation::Tuple,
+            Some(other) => {
+                return self.error(format!("unsupported struct representation {:?}", other))
+            }
+        };
+
+        if self.peek() == Some(&Token::Punct('{')) {
+            return self.error("struct representation options are unsupported");
+        }
+
+        Ok(Type::Struct {
+            fields,
+            representation,
+        })
+    }
+
+    fn enum_type(&mut self, name: &str) -> Result<Type, SchemaError> {
+        self.expect('{')?;
+
+        let mut members = Vec::new();
+        let mut values = Vec::new();
+
+        while !self.eat_punct('}') {
+            self.expect('|')?;
+            let member = self.ident()?;
+
+            if self.eat_punct('(') {
+                values.push((member.clone(), self.string()?));
+                self.expect(')')?;
+            }
+
+            members.push(member);
+        }
+
+        let representation = match self.representation()?.as_deref() {
+            None | Some("string") => EnumRepresentation::String(values.into_iter().collect()),
+            Some("int") => {
+                let mut ints = BTreeMap::new();
+                for (member, value) in values {
+                    match value.parse::<i128>() {
+                        Ok(i) => ints.insert(member, i),
+                        Err(_) => return self.error(format!("invalid int enum value {:?}", value)),
+                    };
+                }
+                EnumRepresentation::Int(ints)
+            }
+            Some(other) => {
+                return self.error(format!("unsupported enum representation {:?}", other))
+            }
+        };
+
+        enum_type(name, members, representation)
+    }
+
+    fn union_type(&mut self) -> Result<Type, SchemaError> {
+        self.expect('{')?;
+
+        let mut members = Vec::new();
+
+        while !self.eat_punct('}') {
+            self.expect('|')?;
+            let member = self.type_ref()?;
+            let discriminant = self.next()?;
+            members.push((member, discriminant));
+        }
+
+        let representation = match self.representation()? {
+            Some(repr) => repr,
+            None => return self.error("unions require a representation"),
+        };
+
+        let named = |parser: &Parser, member: TypeRef| match member {
+            TypeRef::Named(name) => Ok(name),
+            TypeRef::Inline(_) => {
+                parser.error("inline member types are only allowed in kinded unions")
+            }
+        };
+
+        let representation = match representation.as_str() {
+            "keyed" | "inline" => {
+                let mut discriminants = BTreeMap::new();
+                for (member, discriminant) in members {
+                    let discriminant = match discriminant {
+                        Token::Str(s) => s,
+                        t => return self.error(format!("expected a string, found {:?}", t)),
+                    };
+                    discriminants.insert(discriminant, named(self, member)?);
+                }
+
+                if representation == "keyed" {
+                    UnionRepresentation::Keyed(discriminants)
+                } else {
+                    self.expect('{')?;
+                    if !self.eat_keyword("discriminantKey") {
+                        return self.error("expected \"discriminantKey\"");
+                    }
+                    let discriminant_key = self.string()?;
+                    self.expect('}')?;
+
+                    UnionRepresentation::Inline {
+                        discriminant_key,
+                        discriminants,
+                    }
+                }
+            }
+            "kinded" => {
+                let mut kinds = BTreeMap::new();
+                for (member, discriminant) in members {
+                    let kind = match discriminant {
+                        Token::Ident(ref s) => Kind::from_name(s),
+                        _ => None,
+                    };
+
+                    match kind {
+                        Some(kind) => kinds.insert(kind, member),
+                        None => {
+                            return self.error(format!("expected a kind, found {:?}", discriminant))
+                        }
+                    };
+                }
+                UnionRepresentation::Kinded(kinds)
+            }
+            other => return self.error(format!("unsupported union representation {:?}", other)),
+        };
+
+        Ok(Type::Union(representation))
+    }
+}
+
+/// Parses the type definitions of the DSL, in the order of definition.
+pub(super) fn parse(input: &str) -> Result<Vec<(String, Type)>, SchemaError> {
+    let mut lexer = Lexer::new(input);
+    let mut tokens = Vec::new();
+
+    while let Some(token) = lexer.next_token()? {
+        tokens.push(token);
+    }
+
+    Parser { tokens, pos: 0 }.schema()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn syntax_error_line() {
+        let dsl = "type Foo struct {\n  a String\n  b [String\n}";
+        match parse(dsl).unwrap_err() {
+            SchemaError::Syntax { line, .. } => assert_eq!(line, 4),
+            x => unreachable!("{:?}", x),
+        }
+    }
+
+    #[test]
+    fn int_enum() {
+        let types = parse("type Foo enum { | A (\"1\") | B (\"2\") } representation int").unwrap();
+        let expected = Type::Enum {
+            members: vec!["A".into(), "B".into()],
+            representation: EnumRepresentation::Int(
+                vec![("A".to_owned(), 1), ("B".to_owned(), 2)]
+                    .into_iter()
+                    .collect(),
+            ),
+        };
+        assert_eq!(types, vec![("Foo".to_owned(), expected)]);
+    }
+}
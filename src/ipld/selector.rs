@@ -0,0 +1,503 @@
+//! IPLD selectors, as described in the [IPLD selectors specification].
+//!
+//! Selectors are parsed from their `Ipld` representation, usually decoded from dag-json or
+//! dag-cbor. The supported selectors are `Matcher`, `ExploreAll`, `ExploreFields`,
+//! `ExploreIndex`, `ExploreRange`, `ExploreUnion`, `ExploreRecursive` and
+//! `ExploreRecursiveEdge`. `ExploreConditional`, the `stopAt` condition of `ExploreRecursive` and
+//! the conditions of `Matcher` are not supported.
+//!
+//! [IPLD selectors specification]: https://github.com/ipld/specs/blob/master/selectors/selectors.md
+
+use crate::ipld::dag_cbor::DagCborCodec;
+use crate::ipld::dag_json::DagJsonCodec;
+use crate::ipld::{BlockError, Ipld};
+use crate::path::SlashedPath;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use thiserror::Error;
+
+/// Selector or the state of an ongoing selector evaluation.
+///
+/// The selector is evaluated by calling [`Selector::explore`] for each of the child nodes, which
+/// returns the selector to continue with the child, and [`Selector::decide`] for each of the
+/// visited nodes.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Selector {
+    /// Matches the current node.
+    Matcher,
+    /// Explores all of the fields of a map or elements of a list with the inner selector.
+    ExploreAll(Box<Selector>),
+    /// Explores the named fields of a map with the associated selectors. The field names can also
+    /// be list indices.
+    ExploreFields(BTreeMap<String, Selector>),
+    /// Explores the given list index with the inner selector.
+    ExploreIndex(usize, Box<Selector>),
+    /// Explores the list indices in the range `start..end` with the inner selector.
+    ExploreRange {
+        /// Inclusive start of the range
+        start: usize,
+        /// Exclusive end of the range
+        end: usize,
+        /// Selector for the elements in the range
+        next: Box<Selector>,
+    },
+    /// Applies all of the selectors on the current node.
+    ExploreUnion(Vec<Selector>),
+    /// Explores the `sequence` recursively; each time a [`Selector::ExploreRecursiveEdge`] is
+    /// reached within `sequence`, the evaluation continues from the beginning of `sequence`.
+    ExploreRecursive {
+        /// The selector which is repeated
+        sequence: Box<Selector>,
+        /// The current state of evaluating the `sequence`
+        current: Box<Selector>,
+        /// How many times the `sequence` can still be repeated
+        limit: RecursionLimit,
+    },
+    /// Marks the point where the closest enclosing [`Selector::ExploreRecursive`] restarts.
+    ExploreRecursiveEdge,
+}
+
+/// The limit of recursion for [`Selector::ExploreRecursive`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecursionLimit {
+    /// The sequence is repeated until there is nothing more to explore.
+    None,
+    /// The sequence is applied at most the given number of times.
+    Depth(u64),
+}
+
+/// A segment of the path from the node where the evaluation started, to the currently evaluated
+/// node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PathSegment<'a> {
+    /// Key of a field in a map
+    Field(&'a str),
+    /// Index of an element in a list
+    Index(usize),
+}
+
+impl fmt::Display for PathSegment<'_> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathSegment::Field(key) => write!(fmt, "{}", key),
+            PathSegment::Index(index) => write!(fmt, "{}", index),
+        }
+    }
+}
+
+/// Failure to parse a [`Selector`] from its `Ipld` representation.
+#[derive(Debug, Error)]
+pub enum SelectorError {
+    /// Decoding the selector document failed.
+    #[error("failed to decode selector document")]
+    Decoding(#[from] BlockError),
+
+    /// The selector was not a single entry map, with the key naming the kind of the selector.
+    #[error("expected a single entry map at {0:?}")]
+    ExpectedUnion(String),
+
+    /// The kind of the selector is unknown.
+    #[error("unknown selector {0:?}")]
+    UnknownSelector(String),
+
+    /// The selector is known but not supported.
+    #[error("unsupported selector or option {0:?}")]
+    Unsupported(&'static str),
+
+    /// A required field was missing.
+    #[error("missing field {1:?} for {0}")]
+    MissingField(&'static str, &'static str),
+
+    /// A field had an invalid value.
+    #[error("invalid value for field {1:?} of {0}")]
+    InvalidField(&'static str, &'static str),
+
+    /// `ExploreRecursiveEdge` was found outside of an `ExploreRecursive`.
+    #[error("recursion edge outside of ExploreRecursive")]
+    EdgeOutsideRecursion,
+}
+
+impl Selector {
+    /// Parses a selector from a dag-json document.
+    pub fn from_dag_json(data: &[u8]) -> Result<Self, SelectorError> {
+        let ipld = DagJsonCodec::decode(data)?;
+        Selector::try_from(&ipld)
+    }
+
+    /// Parses a selector from a dag-cbor document.
+    pub fn from_dag_cbor(data: &[u8]) -> Result<Self, SelectorError> {
+        let ipld = DagCborCodec::decode(data).map_err(BlockError::from)?;
+        Selector::try_from(&ipld)
+    }
+
+    /// Creates the `ExploreRecursive` selector repeating the `sequence` at most `limit` times.
+    pub fn recursive(sequence: Selector, limit: RecursionLimit) -> Self {
+        Selector::ExploreRecursive {
+            current: Box::new(sequence.clone()),
+            sequence: Box::new(sequence),
+            limit,
+        }
+    }
+
+    /// Returns `true` if the node currently being visited is matched by this selector.
+    pub fn decide(&self) -> bool {
+        match self {
+            Selector::Matcher => true,
+            Selector::ExploreUnion(members) => members.iter().any(Selector::decide),
+            Selector::ExploreRecursive { current, .. } => current.decide(),
+            _ => false,
+        }
+    }
+
+    /// Returns the selector to apply on the child node at `segment`, or `None` if the child node
+    /// should not be explored.
+    pub fn explore(&self, segment: PathSegment<'_>) -> Option<Selector> {
+        use Selector::*;
+
+        match self {
+            Matcher | ExploreRecursiveEdge => None,
+            ExploreAll(next) => Some((**next).clone()),
+            ExploreFields(fields) => match segment {
+                PathSegment::Field(key) => fields.get(key).cloned(),
+                PathSegment::Index(index) => fields.get(&index.to_string()).cloned(),
+            },
+            ExploreIndex(index, next) => match segment {
+                PathSegment::Index(i) if i == *index => Some((**next).clone()),
+                _ => None,
+            },
+            ExploreRange { start, end, next } => match segment {
+                PathSegment::Index(i) if *start <= i && i < *end => Some((**next).clone()),
+                _ => None,
+            },
+            ExploreUnion(members) => {
+                let mut next = members
+                    .iter()
+                    .filter_map(|s| s.explore(segment))
+                    .collect::<Vec<_>>();
+
+                match next.len() {
+                    0 => None,
+                    1 => next.pop(),
+                    _ => Some(ExploreUnion(next)),
+                }
+            }
+            ExploreRecursive {
+                sequence,
+                current,
+                limit,
+            } => {
+                let next = current.explore(segment)?;
+
+                if !next.has_recursive_edge() {
+                    return Some(ExploreRecursive {
+                        sequence: sequence.clone(),
+                        current: Box::new(next),
+                        limit: *limit,
+                    });
+                }
+
+                let limit = match limit {
+                    RecursionLimit::Depth(depth) if *depth < 2 => {
+                        // the sequence cannot be repeated anymore; continue with whatever remains
+                        // after removing the edge
+                        return next.replace_recursive_edge(None);
+                    }
+                    RecursionLimit::Depth(depth) => RecursionLimit::Depth(depth - 1),
+                    RecursionLimit::None => RecursionLimit::None,
+                };
+
+                let current = next.replace_recursive_edge(Some(sequence))?;
+
+                Some(ExploreRecursive {
+                    sequence: sequence.clone(),
+                    current: Box::new(current),
+                    limit,
+                })
+            }
+        }
+    }
+
+    fn has_recursive_edge(&self) -> bool {
+        match self {
+            Selector::ExploreRecursiveEdge => true,
+            Selector::ExploreUnion(members) => members
+                .iter()
+                .any(|s| matches!(s, Selector::ExploreRecursiveEdge)),
+            _ => false,
+        }
+    }
+
+    /// Replaces the recursive edges on the top level or as direct union members with the given
+    /// `sequence`, or removes them if there is none.
+    fn replace_recursive_edge(self, sequence: Option<&Selector>) -> Option<Selector> {
+        match self {
+            Selector::ExploreRecursiveEdge => sequence.cloned(),
+            Selector::ExploreUnion(members) => {
+                let mut members = members
+                    .into_iter()
+                    .filter_map(|s| match s {
+                        Selector::ExploreRecursiveEdge => sequence.cloned(),
+                        other => Some(other),
+                    })
+                    .collect::<Vec<_>>();
+
+                match members.len() {
+                    0 => None,
+                    1 => members.pop(),
+                    _ => Some(Selector::ExploreUnion(members)),
+                }
+            }
+            other => Some(other),
+        }
+    }
+
+    /// Evaluates the selector over a single document, without following any links. Links which
+    /// would be explored are visited as `Ipld::Link` nodes.
+    ///
+    /// Returns the matched nodes in depth-first pre-order, along with their paths from the root of
+    /// the document.
+    pub fn select<'a>(&self, ipld: &'a Ipld) -> Vec<(SlashedPath, &'a Ipld)> {
+        let mut matched = Vec::new();
+        let mut work = vec![(SlashedPath::default(), ipld, self.clone())];
+
+        while let Some((path, node, selector)) = work.pop() {
+            if selector.decide() {
+                matched.push((path.clone(), node));
+            }
+
+            let mut children = explored_children(node, &selector)
+                .into_iter()
+                .map(|(segment, child, next)| {
+                    let mut path = path.clone();
+                    path.push_segment(segment.to_string());
+                    (path, child, next)
+                })
+                .collect::<Vec<_>>();
+
+            // reversed to keep the depth-first pre-order
+            children.reverse();
+            work.extend(children);
+        }
+
+        matched
+    }
+}
+
+/// Returns the child nodes of `node` which `selector` explores, along with the selector to apply
+/// on each of them.
+fn explored_children<'a>(
+    node: &'a Ipld,
+    selector: &Selector,
+) -> Vec<(PathSegment<'a>, &'a Ipld, Selector)> {
+    let children: Box<dyn Iterator<Item = (PathSegment<'a>, &'a Ipld)>> = match node {
+        Ipld::Map(m) => Box::new(m.iter().map(|(k, v)| (PathSegment::Field(k.as_str()), v))),
+        Ipld::List(l) => Box::new(
+            l.iter()
+                .enumerate()
+                .map(|(i, v)| (PathSegment::Index(i), v)),
+        ),
+        _ => Box::new(std::iter::empty()),
+    };
+
+    children
+        .filter_map(|(segment, child)| selector.explore(segment).map(|next| (segment, child, next)))
+        .collect()
+}
+
+impl TryFrom<&Ipld> for Selector {
+    type Error = SelectorError;
+
+    fn try_from(ipld: &Ipld) -> Result<Self, Self::Error> {
+        parse(ipld, 0)
+    }
+}
+
+/// Parses a selector; `recursions` is the number of enclosing `ExploreRecursive` selectors.
+fn parse(ipld: &Ipld, recursions: usize) -> Result<Selector, SelectorError> {
+    let (kind, body) = match ipld {
+        Ipld::Map(m) if m.len() == 1 => m.iter().next().unwrap(),
+        x => return Err(SelectorError::ExpectedUnion(format!("{:?}", x))),
+    };
+
+    let fields = match body {
+        Ipld::Map(m) => m,
+        _ if kind == "|" => {
+            return match body {
+                Ipld::List(members) => members
+                    .iter()
+                    .map(|member| parse(member, recursions))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(Selector::ExploreUnion),
+                _ => Err(SelectorError::InvalidField("ExploreUnion", "|")),
+            };
+        }
+        x => return Err(SelectorError::ExpectedUnion(format!("{:?}", x))),
+    };
+
+    let next = |name: &'static str, key: &'static str, recursions: usize| {
+        fields
+            .get(key)
+            .ok_or(SelectorError::MissingField(name, key))
+            .and_then(|next| parse(next, recursions))
+            .map(Box::new)
+    };
+
+    let index = |name: &'static str, key: &'static str| match fields.get(key) {
+        Some(Ipld::Integer(i)) => {
+            usize::try_from(*i).map_err(|_| SelectorError::InvalidField(name, key))
+        }
+        Some(_) => Err(SelectorError::InvalidField(name, key)),
+        None => Err(SelectorError::MissingField(name, key)),
+    };
+
+    Ok(match kind.as_str() {
+        "." => {
+            if fields.contains_key("onlyIf") {
+                return Err(SelectorError::Unsupported("Matcher onlyIf"));
+            }
+            Selector::Matcher
+        }
+        "a" => Selector::ExploreAll(next("ExploreAll", ">", recursions)?),
+        "f" => {
+            let explored = match fields.get("f>") {
+                Some(Ipld::Map(m)) => m,
+                Some(_) => return Err(SelectorError::InvalidField("ExploreFields", "f>")),
+                None => return Err(SelectorError::MissingField("ExploreFields", "f>")),
+            };
+
+            let explored = explored
+                .iter()
+                .map(|(k, v)| Ok((k.to_owned(), parse(v, recursions)?)))
+                .collect::<Result<BTreeMap<_, _>, SelectorError>>()?;
+
+            Selector::ExploreFields(explored)
+        }
+        "i" => Selector::ExploreIndex(
+            index("ExploreIndex", "i")?,
+            next("ExploreIndex", ">", recursions)?,
+        ),
+        "r" => {
+            let start = index("ExploreRange", "^")?;
+            let end = index("ExploreRange", "$")?;
+
+            if start > end {
+                return Err(SelectorError::InvalidField("ExploreRange", "$"));
+            }
+
+            Selector::ExploreRange {
+                start,
+                end,
+                next: next("ExploreRange", ">", recursions)?,
+            }
+        }
+        "R" => {
+            if fields.contains_key("!") {
+                return Err(SelectorError::Unsupported("ExploreRecursive stopAt"));
+            }
+
+            let limit = match fields.get("l") {
+                Some(Ipld::Map(m)) if m.len() == 1 => match m.iter().next().unwrap() {
+                    (k, Ipld::Integer(depth)) if k == "depth" => RecursionLimit::Depth(
+                        u64::try_from(*depth)
+                            .map_err(|_| SelectorError::InvalidField("ExploreRecursive", "l"))?,
+                    ),
+                    (k, Ipld::Map(_)) if k == "none" => RecursionLimit::None,
+                    _ => return Err(SelectorError::InvalidField("ExploreRecursive", "l")),
+                },
+                Some(_) => return Err(SelectorError::InvalidField("ExploreRecursive", "l")),
+                None => return Err(SelectorError::MissingField("ExploreRecursive", "l")),
+            };
+
+            let sequence = next("ExploreRecursive", ":>", recursions + 1)?;
+
+            Selector::recursive(*sequence, limit)
+        }
+        "@" if recursions > 0 => Selector::ExploreRecursiveEdge,
+        "@" => return Err(SelectorError::EdgeOutsideRecursion),
+        "&" => return Err(SelectorError::Unsupported("ExploreConditional")),
+        other => return Err(SelectorError::UnknownSelector(other.to_owned())),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(matched: Vec<(SlashedPath, &Ipld)>) -> Vec<String> {
+        matched
+            .into_iter()
+            .map(|(path, _)| path.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn parse_dag_json() {
+        let json = br#"{"R":{"l":{"depth":2},":>":{"|":[{".":{}},{"a":{">":{"@":{}}}}]}}}"#;
+        let selector = Selector::from_dag_json(json).unwrap();
+
+        let expected = Selector::recursive(
+            Selector::ExploreUnion(vec![
+                Selector::Matcher,
+                Selector::ExploreAll(Box::new(Selector::ExploreRecursiveEdge)),
+            ]),
+            RecursionLimit::Depth(2),
+        );
+
+        assert_eq!(selector, expected);
+    }
+
+    #[test]
+    fn edge_outside_recursion() {
+        let json = br#"{"a":{">":{"@":{}}}}"#;
+        match Selector::from_dag_json(json).unwrap_err() {
+            SelectorError::EdgeOutsideRecursion => {}
+            x => unreachable!("{:?}", x),
+        }
+    }
+
+    #[test]
+    fn explore_fields_index_and_range() {
+        let doc = make_ipld!({
+            "a": [0, 1, 2, 3],
+            "b": { "c": "d" },
+        });
+
+        let json = br#"{"f":{"f>":{
+            "a":{"|":[{"i":{"i":0,">":{".":{}}}},{"r":{"^":2,"$":4,">":{".":{}}}}]},
+            "b":{"f":{"f>":{"c":{".":{}}}}}
+        }}}"#;
+
+        let selector = Selector::from_dag_json(json).unwrap();
+
+        assert_eq!(paths(selector.select(&doc)), &["a/0", "a/2", "a/3", "b/c"]);
+    }
+
+    #[test]
+    fn recursion_depth_limit() {
+        let doc = make_ipld!([[[["deepest"]]]]);
+
+        let selector = Selector::recursive(
+            Selector::ExploreUnion(vec![
+                Selector::Matcher,
+                Selector::ExploreAll(Box::new(Selector::ExploreRecursiveEdge)),
+            ]),
+            RecursionLimit::Depth(3),
+        );
+
+        assert_eq!(paths(selector.select(&doc)), &["", "0", "0/0"]);
+
+        let selector = Selector::recursive(
+            Selector::ExploreUnion(vec![
+                Selector::Matcher,
+                Selector::ExploreAll(Box::new(Selector::ExploreRecursiveEdge)),
+            ]),
+            RecursionLimit::None,
+        );
+
+        assert_eq!(
+            paths(selector.select(&doc)),
+            &["", "0", "0/0", "0/0/0", "0/0/0/0"]
+        );
+    }
+}
@@ -21,17 +21,26 @@ impl<Types: RepoTypes> Ipns<Types> {
         Ipns { ipfs }
     }
 
-    /// Resolves a ipns path to an ipld path.
+    /// Resolves a ipns path to an ipld path; the segments following the root of the given path
+    /// are appended to the path the root resolves to.
+    ///
+    /// The returned path can still have an ipns or dnslink root, which needs to be resolved again.
     pub async fn resolve(&self, path: &IpfsPath) -> Result<IpfsPath, Error> {
         let path = path.to_owned();
-        match path.root() {
-            PathRoot::Ipld(_) => Ok(path),
+        let mut resolved = match path.root() {
+            PathRoot::Ipld(_) => return Ok(path),
             PathRoot::Ipns(peer_id) => match self.ipfs.repo.get_ipns(peer_id).await? {
-                Some(path) => Ok(path),
-                None => Err(anyhow::anyhow!("unimplemented")),
+                Some(path) => path,
+                None => return Err(anyhow::anyhow!("no ipns record found for {}", peer_id)),
             },
-            PathRoot::Dns(domain) => Ok(dns::resolve(domain).await?),
+            PathRoot::Dns(domain) => dns::resolve(domain).await?,
+        };
+
+        for segment in path.iter() {
+            resolved.push_str(segment)?;
         }
+
+        Ok(resolved)
     }
 
     /// Publishes an ipld path.
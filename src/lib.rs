@@ -34,12 +34,14 @@ use std::pin::Pin;
 use std::sync::{atomic::Ordering, Arc};
 use std::task::{Context, Poll};
 
+pub mod collections;
 mod config;
 pub mod dag;
 pub mod error;
 #[macro_use]
 pub mod ipld;
 pub mod ipns;
+pub mod object;
 pub mod p2p;
 pub mod path;
 pub mod refs;
@@ -50,6 +52,7 @@ pub mod unixfs;
 use self::dag::IpldDag;
 pub use self::error::Error;
 use self::ipns::Ipns;
+use self::object::ObjectApi;
 pub use self::p2p::pubsub::{PubsubMessage, SubscriptionStream};
 use self::p2p::{create_swarm, SwarmOptions, TSwarm};
 pub use self::p2p::{Connection, KadResult, MultiaddrWithPeerId, MultiaddrWithoutPeerId};
@@ -364,6 +367,11 @@ impl<Types: IpfsTypes> Ipfs<Types> {
         IpldDag::new(self.clone())
     }
 
+    /// Returns the interface for working with dag-pb nodes directly, see [`ObjectApi`].
+    pub fn object(&self) -> ObjectApi<Types> {
+        ObjectApi::new(self.clone())
+    }
+
     fn ipns(&self) -> Ipns<Types> {
         Ipns::new(self.clone())
     }
@@ -382,6 +390,22 @@ impl<Types: IpfsTypes> Ipfs<Types> {
             .map(|(cid, _put_status)| cid)
     }
 
+    /// Puts a batch of blocks into the ipfs repo, returning their Cids in the same order. The
+    /// directories of the new blocks are synced once per batch instead of once per block, which is
+    /// faster than putting the blocks one by one.
+    ///
+    /// # Forget safety
+    ///
+    /// Forgetting the returned future will not result in memory unsafety, but it can
+    /// deadlock other tasks.
+    pub async fn put_blocks(&self, blocks: Vec<Block>) -> Result<Vec<Cid>, Error> {
+        self.repo
+            .put_blocks(blocks)
+            .instrument(self.span.clone())
+            .await
+            .map(|puts| puts.into_iter().map(|(cid, _put_status)| cid).collect())
+    }
+
     /// Retrieves a block from the local blockstore, or starts fetching from the network or join an
     /// already started fetch.
     pub async fn get_block(&self, cid: &Cid) -> Result<Block, Error> {
@@ -426,11 +450,9 @@ impl<Types: IpfsTypes> Ipfs<Types> {
             if !recursive {
                 self.repo.insert_direct_pin(cid).await
             } else {
-                let ipld = crate::ipld::decode_ipld(&cid, &data)?;
-
                 let st = crate::refs::IpldRefs::default()
                     .with_only_unique()
-                    .refs_of_resolved(self, vec![(cid.clone(), ipld.clone())].into_iter())
+                    .refs_of_block(self, &cid, &data)?
                     .map_ok(|crate::refs::Edge { destination, .. }| destination)
                     .into_stream()
                     .instrument(refs_span)
@@ -465,14 +487,10 @@ impl<Types: IpfsTypes> Ipfs<Types> {
                     }
                 };
 
-                let ipld = crate::ipld::decode_ipld(&cid, &data)?;
                 let st = crate::refs::IpldRefs::default()
                     .with_only_unique()
                     .with_existing_blocks()
-                    .refs_of_resolved(
-                        self.to_owned(),
-                        vec![(cid.clone(), ipld.clone())].into_iter(),
-                    )
+                    .refs_of_block(self.to_owned(), &cid, &data)?
                     .map_ok(|crate::refs::Edge { destination, .. }| destination)
                     .into_stream()
                     .boxed();
@@ -568,6 +586,177 @@ impl<Types: IpfsTypes> Ipfs<Types> {
             .await
     }
 
+    /// Opens an UnixFS file for reading with `AsyncRead` and seeking with `AsyncSeek`, see
+    /// [`unixfs::UnixfsFile`].
+    pub async fn open_unixfs(
+        &self,
+        starting_point: impl Into<unixfs::StartingPoint>,
+    ) -> Result<unixfs::UnixfsFile<Types>, unixfs::TraversalFailed> {
+        unixfs::open(self.clone(), starting_point)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Writes the UnixFS file, directory tree or symlink to the `target` path on the filesystem,
+    /// creating the symlinks and applying the stored modes and modification times. Fails if any
+    /// of the entries would be written outside of the `target`.
+    pub async fn get_to_path(
+        &self,
+        starting_point: impl Into<unixfs::StartingPoint>,
+        target: impl Into<PathBuf>,
+    ) -> Result<(), unixfs::GetError> {
+        unixfs::get(self, starting_point, target)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Creates a stream of the entries of an UnixFS directory, normal or HAMT sharded. When
+    /// `resolve_types` is true, the type of every entry is read from its first block, which
+    /// includes the size of the files.
+    ///
+    /// To create an owned version of the stream, please use `ipfs::unixfs::ls` directly.
+    pub fn ls(
+        &self,
+        starting_point: impl Into<unixfs::StartingPoint>,
+        resolve_types: bool,
+    ) -> impl Stream<Item = Result<unixfs::LsEntry, unixfs::LsError>> + Send + '_ {
+        unixfs::ls(self, starting_point, resolve_types)
+    }
+
+    /// Adds the bytes read from the input as an UnixFS file, storing the created blocks. Returns a
+    /// stream of progress events, where the last `AddEvent::Added` has the root Cid. A stream of
+    /// bytes can be added by converting it with `tokio::io::stream_reader`.
+    ///
+    /// To create an owned version of the stream, please use `ipfs::unixfs::add` directly.
+    pub fn add_unixfs<'a>(
+        &'a self,
+        input: impl tokio::io::AsyncRead + Send + Unpin + 'a,
+        opts: unixfs::AddOptions,
+    ) -> impl Stream<Item = Result<unixfs::AddEvent, unixfs::AddError>> + Send + 'a {
+        unixfs::add(self, input, opts)
+    }
+
+    /// Adds the file or the directory tree at the path, storing the created blocks. Returns a
+    /// stream of progress events, where the last `AddEvent::Added` has the root Cid. This is the
+    /// library equivalent of `ipfs add -r`.
+    ///
+    /// To create an owned version of the stream, please use `ipfs::unixfs::add_path` directly.
+    pub fn add_path(
+        &self,
+        path: impl Into<PathBuf>,
+        opts: unixfs::AddPathOptions,
+    ) -> impl Stream<Item = Result<unixfs::AddEvent, unixfs::AddError>> + Send + '_ {
+        unixfs::add_path(self, path, opts)
+    }
+
+    /// Adds the files, directories and symlinks of the tar archive read from the input as an
+    /// UnixFS tree, storing the created blocks. Returns a stream of progress events, where the
+    /// last `AddEvent::Added` has the Cid of the root directory.
+    ///
+    /// To create an owned version of the stream, please use `ipfs::unixfs::add_tar` directly.
+    pub fn add_tar<'a>(
+        &'a self,
+        input: impl tokio::io::AsyncRead + Send + Unpin + 'a,
+        opts: unixfs::AddTarOptions,
+    ) -> impl Stream<Item = Result<unixfs::AddEvent, unixfs::AddError>> + Send + 'a {
+        unixfs::add_tar(self, input, opts)
+    }
+
+    /// Creates a directory in the mutable file system. When `parents` is true, the missing parent
+    /// directories are created, and an existing directory is not an error.
+    pub async fn files_mkdir(
+        &self,
+        path: &str,
+        parents: bool,
+    ) -> Result<(), unixfs::mfs::MfsError> {
+        unixfs::mfs::mkdir(self, path, parents)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Writes the bytes read from the input to a file in the mutable file system.
+    pub async fn files_write(
+        &self,
+        path: &str,
+        input: impl tokio::io::AsyncRead + Send + Unpin,
+        opts: unixfs::mfs::WriteOptions,
+    ) -> Result<(), unixfs::mfs::MfsError> {
+        unixfs::mfs::write(self, path, input, opts)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Reads a file in the mutable file system, producing a stream of its bytes within the
+    /// optional range.
+    ///
+    /// To create an owned version of the stream, please use `ipfs::unixfs::mfs::read` directly.
+    pub async fn files_read(
+        &self,
+        path: &str,
+        range: Option<std::ops::Range<u64>>,
+    ) -> Result<
+        impl Stream<Item = Result<Vec<u8>, unixfs::TraversalFailed>> + Send + '_,
+        unixfs::mfs::MfsError,
+    > {
+        unixfs::mfs::read(self, path, range)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Moves a file or a directory in the mutable file system, into `dst` if it is an existing
+    /// directory.
+    pub async fn files_mv(&self, src: &str, dst: &str) -> Result<(), unixfs::mfs::MfsError> {
+        unixfs::mfs::mv(self, src, dst)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Copies a file or a directory to the mutable file system, into `dst` if it is an existing
+    /// directory. The source can be a path in the mutable file system or an `/ipfs/` path.
+    pub async fn files_cp(&self, src: &str, dst: &str) -> Result<(), unixfs::mfs::MfsError> {
+        unixfs::mfs::cp(self, src, dst)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Removes a file or a directory from the mutable file system. Directories are only removed
+    /// when `recursive` is true.
+    pub async fn files_rm(&self, path: &str, recursive: bool) -> Result<(), unixfs::mfs::MfsError> {
+        unixfs::mfs::rm(self, path, recursive)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Returns information about a file or a directory in the mutable file system.
+    pub async fn files_stat(
+        &self,
+        path: &str,
+    ) -> Result<unixfs::mfs::MfsStat, unixfs::mfs::MfsError> {
+        unixfs::mfs::stat(self, path)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Lists a directory in the mutable file system. When `resolve_types` is true, the type of
+    /// every entry is read from its first block.
+    pub async fn files_ls(
+        &self,
+        path: &str,
+        resolve_types: bool,
+    ) -> Result<Vec<unixfs::LsEntry>, unixfs::mfs::MfsError> {
+        unixfs::mfs::list(self, path, resolve_types)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Returns the Cid of a file or a directory in the mutable file system. The modifications are
+    /// stored as they are made, so there is nothing to write.
+    pub async fn files_flush(&self, path: &str) -> Result<Cid, unixfs::mfs::MfsError> {
+        unixfs::mfs::flush(self, path)
+            .instrument(self.span.clone())
+            .await
+    }
+
     /// Resolves a ipns path to an ipld path.
     pub async fn resolve_ipns(&self, path: &IpfsPath) -> Result<IpfsPath, Error> {
         self.ipns()
@@ -982,6 +1171,18 @@ impl<Types: IpfsTypes> Ipfs<Types> {
         refs::iplds_refs(self, iplds, max_depth, unique)
     }
 
+    /// Walk the documents starting from `root` as the `selector` explores them, reporting the
+    /// loaded blocks and the matched nodes.
+    ///
+    /// More information and a `'static` lifetime version available at [`refs::walk_selected`].
+    pub fn walk_selected<'a>(
+        &'a self,
+        root: Cid,
+        selector: ipld::selector::Selector,
+    ) -> impl Stream<Item = Result<refs::SelectedItem, refs::IpldRefsError>> + Send + 'a {
+        refs::walk_selected(self, root, selector)
+    }
+
     /// Exit daemon.
     pub async fn exit_daemon(self) {
         // FIXME: this is a stopgap measure needed while repo is part of the struct Ipfs instead of
@@ -1084,6 +1285,24 @@ impl<TRepoTypes: RepoTypes> IpfsFuture<TRepoTypes> {
         }
     }
 
+    /// Handles a block which was added to the repo, for both `RepoEvent::ProvideBlock` and the
+    /// batched `RepoEvent::ProvideBlocks`.
+    fn provide_new_block(
+        &mut self,
+        cid: Cid,
+    ) -> Result<SubscriptionFuture<KadResult, String>, Error> {
+        // TODO: consider if cancel is applicable in cases where we provide the
+        // associated Block ourselves
+        self.swarm.bitswap().cancel_block(&cid);
+        // currently disabled; see https://github.com/rs-ipfs/rust-ipfs/pull/281#discussion_r465583345
+        // for details regarding the concerns about enabling this functionality as-is
+        if false {
+            self.swarm.start_providing(cid)
+        } else {
+            Err(anyhow!("not actively providing blocks yet"))
+        }
+    }
+
     fn start_add_listener_address(&mut self, addr: Multiaddr, ret: Channel<Multiaddr>) {
         use libp2p::Swarm;
         use std::collections::hash_map::Entry;
@@ -1304,16 +1523,14 @@ impl<TRepoTypes: RepoTypes> Future for IpfsFuture<TRepoTypes> {
                     RepoEvent::WantBlock(cid) => self.swarm.want_block(cid),
                     RepoEvent::UnwantBlock(cid) => self.swarm.bitswap().cancel_block(&cid),
                     RepoEvent::ProvideBlock(cid, ret) => {
-                        // TODO: consider if cancel is applicable in cases where we provide the
-                        // associated Block ourselves
-                        self.swarm.bitswap().cancel_block(&cid);
-                        // currently disabled; see https://github.com/rs-ipfs/rust-ipfs/pull/281#discussion_r465583345
-                        // for details regarding the concerns about enabling this functionality as-is
-                        if false {
-                            let _ = ret.send(self.swarm.start_providing(cid));
-                        } else {
-                            let _ = ret.send(Err(anyhow!("not actively providing blocks yet")));
-                        }
+                        let _ = ret.send(self.provide_new_block(cid));
+                    }
+                    RepoEvent::ProvideBlocks(cids, ret) => {
+                        let results = cids
+                            .into_iter()
+                            .map(|cid| self.provide_new_block(cid))
+                            .collect();
+                        let _ = ret.send(results);
                     }
                     RepoEvent::UnprovideBlock(cid) => self.swarm.stop_providing_block(&cid),
                 }
@@ -1576,7 +1793,7 @@ mod tests {
     async fn test_put_and_get_block() {
         let ipfs = Node::new("test_node").await;
 
-        let data = b"hello block\n".to_vec().into_boxed_slice();
+        let data = b"hello block\n".to_vec();
         let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
         let block = Block::new(data, cid);
 
@@ -1585,6 +1802,37 @@ mod tests {
         assert_eq!(block, new_block);
     }
 
+    #[tokio::test(max_threads = 1)]
+    async fn test_put_blocks() {
+        let ipfs = Node::new("test_node").await;
+
+        let blocks = [&b"first"[..], b"second", b"third"]
+            .iter()
+            .map(|data| {
+                Block::new(
+                    data.to_vec(),
+                    Cid::new_v1(Codec::Raw, Sha2_256::digest(data)),
+                )
+            })
+            .chain(std::iter::once({
+                let data = b"inline";
+                let cid = Cid::new_v1(Codec::Raw, multihash::Code::Identity.digest(data));
+                Block::new(data.to_vec(), cid)
+            }))
+            .collect::<Vec<_>>();
+
+        let cids = ipfs.put_blocks(blocks.clone()).await.unwrap();
+
+        assert_eq!(cids.len(), blocks.len());
+        for (block, cid) in blocks.iter().zip(&cids) {
+            assert_eq!(block.cid(), cid);
+            assert_eq!(&ipfs.get_block(cid).await.unwrap(), block);
+        }
+
+        // the identity block was not stored
+        assert_eq!(ipfs.repo.list_blocks().await.unwrap().len(), 3);
+    }
+
     #[tokio::test(max_threads = 1)]
     async fn test_put_and_get_dag() {
         let ipfs = Node::new("test_node").await;
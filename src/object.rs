@@ -0,0 +1,294 @@
+//! `ipfs.object` interface for creating and modifying dag-pb nodes directly, like the `object`
+//! commands of go-ipfs.
+use crate::error::Error;
+use crate::ipld::dag_pb::{PbLink, PbNode};
+use crate::repo::RepoTypes;
+use crate::Ipfs;
+use bitswap::Block;
+use cid::{Cid, Codec};
+use ipfs_unixfs::dagpb::cumulative_size;
+
+/// The templates for the nodes created with [`ObjectApi::create`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectTemplate {
+    /// A node without links or data.
+    Empty,
+    /// An empty UnixFS directory.
+    UnixFsDir,
+}
+
+impl ObjectTemplate {
+    fn into_node(self) -> PbNode {
+        match self {
+            ObjectTemplate::Empty => PbNode {
+                links: Vec::new(),
+                data: None,
+            },
+            ObjectTemplate::UnixFsDir => PbNode {
+                links: Vec::new(),
+                // UnixFS Data with the Type of Directory
+                data: Some(vec![0x08, 0x01]),
+            },
+        }
+    }
+}
+
+/// `ipfs.object` interface providing wrapper around Ipfs.
+#[derive(Clone, Debug)]
+pub struct ObjectApi<Types: RepoTypes> {
+    ipfs: Ipfs<Types>,
+}
+
+impl<Types: RepoTypes> ObjectApi<Types> {
+    pub fn new(ipfs: Ipfs<Types>) -> Self {
+        ObjectApi { ipfs }
+    }
+
+    /// Creates and stores a new node from the template.
+    pub async fn create(&self, template: ObjectTemplate) -> Result<Cid, Error> {
+        self.put(template.into_node()).await
+    }
+
+    /// Encodes and stores the node, returning its `Cid` version 0.
+    pub async fn put(&self, node: PbNode) -> Result<Cid, Error> {
+        self.store(node).await.map(|(cid, _)| cid)
+    }
+
+    /// Loads and decodes the dag-pb node.
+    pub async fn get(&self, cid: &Cid) -> Result<PbNode, Error> {
+        if cid.codec() != Codec::DagProtobuf {
+            return Err(anyhow::anyhow!("{} is not a dag-pb node", cid));
+        }
+
+        let block = self.ipfs.repo.get_block(cid).await?;
+        Ok(PbNode::from_bytes(block.data())?)
+    }
+
+    /// Returns the links of the dag-pb node.
+    pub async fn links(&self, cid: &Cid) -> Result<Vec<PbLink>, Error> {
+        Ok(self.get(cid).await?.links)
+    }
+
+    /// Adds a link named by the last segment of the slash separated `path` to the `target`,
+    /// replacing any links with the same name. The nodes on the path are followed by the names
+    /// of their links; when `create` is true the missing ones are created as empty UnixFS
+    /// directories. Returns the `Cid` of the new root.
+    pub async fn add_link(
+        &self,
+        root: &Cid,
+        path: &str,
+        target: &Cid,
+        create: bool,
+    ) -> Result<Cid, Error> {
+        let block = self.ipfs.repo.get_block(target).await?;
+        let size = match target.codec() {
+            Codec::DagProtobuf => cumulative_size(block.data())?.0,
+            _ => block.data().len() as u64,
+        };
+
+        self.patch_link(root, path, Some((target.to_owned(), size)), create)
+            .await
+    }
+
+    /// Removes the links named by the last segment of the slash separated `path`. Returns the
+    /// `Cid` of the new root.
+    pub async fn rm_link(&self, root: &Cid, path: &str) -> Result<Cid, Error> {
+        self.patch_link(root, path, None, false).await
+    }
+
+    /// Replaces the data of the node. Returns the `Cid` of the new node.
+    pub async fn set_data(&self, root: &Cid, data: Vec<u8>) -> Result<Cid, Error> {
+        let mut node = self.get(root).await?;
+        node.data = Some(data);
+        self.put(node).await
+    }
+
+    /// Appends the bytes to the data of the node. Returns the `Cid` of the new node.
+    pub async fn append_data(&self, root: &Cid, data: &[u8]) -> Result<Cid, Error> {
+        let mut node = self.get(root).await?;
+        node.data
+            .get_or_insert_with(Vec::new)
+            .extend_from_slice(data);
+        self.put(node).await
+    }
+
+    /// Replaces or removes the link at the path and stores the nodes on the path again.
+    async fn patch_link(
+        &self,
+        root: &Cid,
+        path: &str,
+        link: Option<(Cid, u64)>,
+        create: bool,
+    ) -> Result<Cid, Error> {
+        let segments = path.split('/').collect::<Vec<_>>();
+
+        if segments.iter().any(|s| s.is_empty()) {
+            return Err(anyhow::anyhow!("invalid link path {:?}", path));
+        }
+
+        let (name, parents) = segments
+            .split_last()
+            .expect("split always returns a segment");
+
+        let mut current = self.get(root).await?;
+        let mut nodes = Vec::with_capacity(parents.len());
+
+        for parent in parents {
+            let next = match find_link(&current, parent) {
+                Some(link) => self.get(&link.cid).await?,
+                None if create => ObjectTemplate::UnixFsDir.into_node(),
+                None => return Err(anyhow::anyhow!("no link named {:?}", parent)),
+            };
+            nodes.push(std::mem::replace(&mut current, next));
+        }
+
+        let before = current.links.len();
+        current.links.retain(|l| l.name.as_deref() != Some(*name));
+
+        match link {
+            Some((cid, size)) => current.links.push(PbLink {
+                cid,
+                name: Some((*name).to_owned()),
+                size: Some(size),
+            }),
+            None if current.links.len() == before => {
+                return Err(anyhow::anyhow!("no link named {:?}", name))
+            }
+            None => {}
+        }
+
+        let mut stored = self.store(current).await?;
+
+        for (mut node, parent) in nodes.into_iter().zip(parents).rev() {
+            node.links.retain(|l| l.name.as_deref() != Some(*parent));
+            node.links.push(PbLink {
+                cid: stored.0,
+                name: Some((*parent).to_owned()),
+                size: Some(stored.1),
+            });
+            stored = self.store(node).await?;
+        }
+
+        Ok(stored.0)
+    }
+
+    /// Stores the node, returning its `Cid` and cumulative size.
+    async fn store(&self, node: PbNode) -> Result<(Cid, u64), Error> {
+        let linked = node
+            .links
+            .iter()
+            .map(|link| link.size.unwrap_or_default())
+            .sum::<u64>();
+        let bytes = node.into_bytes();
+        let size = bytes.len() as u64 + linked;
+
+        let cid = Cid::new_v0(multihash::Sha2_256::digest(&bytes))?;
+        let (cid, _) = self
+            .ipfs
+            .repo
+            .put_block(Block::new(bytes.into_vec(), cid))
+            .await?;
+
+        Ok((cid, size))
+    }
+}
+
+fn find_link<'a>(node: &'a PbNode, name: &str) -> Option<&'a PbLink> {
+    node.links
+        .iter()
+        .find(|link| link.name.as_deref() == Some(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ObjectTemplate;
+    use crate::ipld::dag_pb::PbNode;
+    use crate::Node;
+
+    #[tokio::test(max_threads = 1)]
+    async fn templates() {
+        let ipfs = Node::new("test_node").await;
+
+        let empty = ipfs.object().create(ObjectTemplate::Empty).await.unwrap();
+        assert_eq!(
+            empty.to_string(),
+            "QmdfTbBqBPQ7VNxZEYEj14VmRuZBkqFbiwReogJgS1zR1n"
+        );
+
+        let dir = ipfs
+            .object()
+            .create(ObjectTemplate::UnixFsDir)
+            .await
+            .unwrap();
+        assert_eq!(
+            dir.to_string(),
+            "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn"
+        );
+
+        let node = ipfs.object().get(&dir).await.unwrap();
+        assert_eq!(node.links, Vec::new());
+        assert_eq!(node.data, Some(vec![0x08, 0x01]));
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn patch_links() {
+        let ipfs = Node::new("test_node").await;
+        let object = ipfs.object();
+
+        let empty = object.create(ObjectTemplate::Empty).await.unwrap();
+        let dir = object.create(ObjectTemplate::UnixFsDir).await.unwrap();
+
+        let err = object.add_link(&dir, "a/b", &empty, false).await;
+        assert!(err.is_err());
+
+        let root = object.add_link(&dir, "a/b", &empty, true).await.unwrap();
+
+        let links = object.links(&root).await.unwrap();
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].name.as_deref(), Some("a"));
+        // the created directory block, the empty node linked from it has no size
+        assert_eq!(links[0].size, Some(47));
+
+        let a = object.links(&links[0].cid).await.unwrap();
+        assert_eq!(a.len(), 1);
+        assert_eq!(a[0].cid, empty);
+        assert_eq!(a[0].name.as_deref(), Some("b"));
+        assert_eq!(a[0].size, Some(0));
+
+        let replaced = object.add_link(&root, "a/b", &dir, false).await.unwrap();
+        let a = object.links(&replaced).await.unwrap();
+        let a = object.links(&a[0].cid).await.unwrap();
+        assert_eq!(a.len(), 1);
+        assert_eq!(a[0].cid, dir);
+
+        let removed = object.rm_link(&root, "a/b").await.unwrap();
+        let a = object.links(&removed).await.unwrap();
+        assert_eq!(a[0].cid, dir);
+
+        assert!(object.rm_link(&root, "c").await.is_err());
+        assert!(object.add_link(&root, "a//b", &dir, true).await.is_err());
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn put_and_patch_data() {
+        let ipfs = Node::new("test_node").await;
+        let object = ipfs.object();
+
+        let node = PbNode {
+            links: Vec::new(),
+            data: Some(b"foo".to_vec()),
+        };
+
+        let cid = object.put(node.clone()).await.unwrap();
+        assert_eq!(object.get(&cid).await.unwrap(), node);
+
+        let appended = object.append_data(&cid, b"bar").await.unwrap();
+        assert_eq!(
+            object.get(&appended).await.unwrap().data.as_deref(),
+            Some(&b"foobar"[..])
+        );
+
+        let set = object.set_data(&appended, b"foo".to_vec()).await.unwrap();
+        assert_eq!(set, cid);
+    }
+}
@@ -196,6 +196,11 @@ impl SlashedPath {
         Ok(())
     }
 
+    /// Appends a single segment, which is expected not to contain any slashes.
+    pub(crate) fn push_segment(&mut self, segment: String) {
+        self.path.push(segment);
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = &String> {
         self.path.iter()
     }
@@ -1,4 +1,6 @@
-use crate::ipld::{decode_ipld, Ipld};
+use crate::ipld::selector::{PathSegment, Selector};
+use crate::ipld::{dag_cbor::DagCborCodec, decode_ipld, BlockError, Ipld, IpldRef};
+use crate::path::SlashedPath;
 use crate::{Block, Ipfs, IpfsTypes};
 use async_stream::stream;
 use cid::{self, Cid};
@@ -39,6 +41,9 @@ pub enum IpldRefsError {
     BlockNotFound(Cid),
 }
 
+/// The optionally named links of a document.
+type Links = Vec<(Option<String>, Cid)>;
+
 pub(crate) struct IpldRefs {
     max_depth: Option<u64>,
     unique: bool,
@@ -79,6 +84,7 @@ impl IpldRefs {
         self
     }
 
+    #[allow(dead_code)]
     pub fn refs_of_resolved<'a, Types, MaybeOwned, Iter>(
         self,
         ipfs: MaybeOwned,
@@ -91,6 +97,22 @@ impl IpldRefs {
     {
         iplds_refs_inner(ipfs, iplds, self)
     }
+
+    /// Like `refs_of_resolved` but starts from the encoded block, which only needs to be scanned
+    /// for links instead of being fully decoded.
+    pub fn refs_of_block<'a, Types, MaybeOwned>(
+        self,
+        ipfs: MaybeOwned,
+        cid: &Cid,
+        data: &[u8],
+    ) -> Result<impl Stream<Item = Result<Edge, IpldRefsError>> + Send + 'a, BlockError>
+    where
+        Types: IpfsTypes,
+        MaybeOwned: Borrow<Ipfs<Types>> + Send + 'a,
+    {
+        let links = block_links(cid, data)?;
+        Ok(links_refs_inner(ipfs, vec![(cid.to_owned(), links)], self))
+    }
 }
 
 /// Gather links as edges between two documents from all of the `iplds` which represent the
@@ -145,6 +167,28 @@ where
     Types: IpfsTypes,
     MaybeOwned: Borrow<Ipfs<Types>> + Send + 'a,
     Iter: IntoIterator<Item = (Cid, Ipld)>,
+{
+    // not building these before moving the work and hashset into the stream would impose
+    // apparently impossible bounds on `Iter`, in addition to `Send + 'a`.
+    let roots = iplds
+        .into_iter()
+        .map(|(origin, ipld)| {
+            let links = ipld_links(&origin, ipld).collect();
+            (origin, links)
+        })
+        .collect();
+
+    links_refs_inner(ipfs, roots, opts)
+}
+
+fn links_refs_inner<'a, Types, MaybeOwned>(
+    ipfs: MaybeOwned,
+    roots: Vec<(Cid, Links)>,
+    opts: IpldRefs,
+) -> impl Stream<Item = Result<Edge, IpldRefsError>> + Send + 'a
+where
+    Types: IpfsTypes,
+    MaybeOwned: Borrow<Ipfs<Types>> + Send + 'a,
 {
     let mut work = VecDeque::new();
     let mut queued_or_visited = HashSet::new();
@@ -160,10 +204,8 @@ where
     // double check the max_depth before filling the work and queued_or_visited up just in case we
     // are going to be returning an empty stream
     if !empty_stream {
-        // not building these before moving the work and hashset into the stream would impose
-        // apparently impossible bounds on `Iter`, in addition to `Send + 'a`.
-        for (origin, ipld) in iplds {
-            for (link_name, next_cid) in ipld_links(&origin, ipld) {
+        for (origin, links) in roots {
+            for (link_name, next_cid) in links {
                 if unique && !queued_or_visited.insert(next_cid.clone()) {
                     trace!("skipping already queued {}", next_cid);
                     continue;
@@ -221,8 +263,8 @@ where
 
             trace!(cid = %cid, "loaded next");
 
-            let ipld = match decode_ipld(&cid, &data) {
-                Ok(ipld) => ipld,
+            let links = match block_links(&cid, &data) {
+                Ok(links) => links,
                 Err(e) => {
                     warn!(cid = %cid, source = %cid, "failed to parse: {}", e);
                     // go-ipfs on raw Qm hash:
@@ -233,7 +275,7 @@ where
             };
 
             if traverse_links {
-                for (link_name, next_cid) in ipld_links(&cid, ipld) {
+                for (link_name, next_cid) in links {
                     if unique && !queued_or_visited.insert(next_cid.clone()) {
                         trace!(queued = %next_cid, "skipping already queued");
                         continue;
@@ -248,6 +290,192 @@ where
     }
 }
 
+/// Item of the stream returned by [`walk_selected`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum SelectedItem {
+    /// A block was loaded while following a link which the selector explored. Blocks are reported
+    /// in the order of loading, and the same block is reported every time it is reached. Loaded
+    /// blocks make up the partial DAG selected by the selector.
+    Block(Cid),
+    /// A node matched by the selector.
+    Matched {
+        /// The block containing the matched node
+        block: Cid,
+        /// The path from the root of the walk to the matched node
+        path: SlashedPath,
+        /// The matched node
+        node: Ipld,
+    },
+}
+
+/// Evaluates the selector starting from the `root` document, loading the linked blocks as the
+/// selector explores them. Links are transparent for the selector: the linked document takes the
+/// place of the link in the walk. dag-pb documents are walked in their `Ipld` representation.
+///
+/// The walk is depth-first, in the order of the map keys and list indices. The returned stream
+/// does not stop on **error**.
+pub fn walk_selected<'a, Types, MaybeOwned>(
+    ipfs: MaybeOwned,
+    root: Cid,
+    selector: Selector,
+) -> impl Stream<Item = Result<SelectedItem, IpldRefsError>> + Send + 'a
+where
+    Types: IpfsTypes,
+    MaybeOwned: Borrow<Ipfs<Types>> + Send + 'a,
+{
+    stream! {
+        let mut work = vec![(root.clone(), SlashedPath::default(), Ipld::Link(root), selector)];
+
+        while let Some((block, path, node, selector)) = work.pop() {
+            let (block, node) = match node {
+                Ipld::Link(cid) => {
+                    // see iplds_refs_inner for the reason of binding this
+                    let borrowed = ipfs.borrow();
+
+                    let data = match borrowed.get_block(&cid).await {
+                        Ok(Block { data, .. }) => data,
+                        Err(e) => {
+                            yield Err(IpldRefsError::from(e));
+                            continue;
+                        }
+                    };
+
+                    let ipld = if cid.codec() == cid::Codec::DagCBOR {
+                        let node = match DagCborCodec::decode_borrowed(&data) {
+                            Ok(node) => node,
+                            Err(e) => {
+                                warn!(cid = %cid, "failed to parse: {}", e);
+                                yield Err(BlockError::from(e).into());
+                                continue;
+                            }
+                        };
+
+                        if !selector.decide() {
+                            // only the explored children need to be copied out of the block
+                            yield Ok(SelectedItem::Block(cid.clone()));
+
+                            let mut children = explored_children_borrowed(node, &selector)
+                                .into_iter()
+                                .map(|(segment, child, next)| {
+                                    let mut path = path.clone();
+                                    path.push_segment(segment);
+                                    (cid.clone(), path, child, next)
+                                })
+                                .collect::<Vec<_>>();
+
+                            children.reverse();
+                            work.extend(children);
+                            continue;
+                        }
+
+                        node.into_owned()
+                    } else {
+                        match decode_ipld(&cid, &data) {
+                            Ok(ipld) => ipld,
+                            Err(e) => {
+                                warn!(cid = %cid, "failed to parse: {}", e);
+                                yield Err(e.into());
+                                continue;
+                            }
+                        }
+                    };
+
+                    yield Ok(SelectedItem::Block(cid.clone()));
+
+                    (cid, ipld)
+                }
+                other => (block, other),
+            };
+
+            if selector.decide() {
+                yield Ok(SelectedItem::Matched {
+                    block: block.clone(),
+                    path: path.clone(),
+                    node: node.clone(),
+                });
+            }
+
+            let mut children = explored_children_owned(node, &selector)
+                .into_iter()
+                .map(|(segment, child, next)| {
+                    let mut path = path.clone();
+                    path.push_segment(segment);
+                    (block.clone(), path, child, next)
+                })
+                .collect::<Vec<_>>();
+
+            // reversed to keep the depth-first pre-order
+            children.reverse();
+            work.extend(children);
+        }
+    }
+}
+
+/// Returns the child nodes explored by the selector along with the selector to apply on each of
+/// them. Consumes the node to avoid cloning the children.
+fn explored_children_owned(node: Ipld, selector: &Selector) -> Vec<(String, Ipld, Selector)> {
+    match node {
+        Ipld::Map(m) => m
+            .into_iter()
+            .filter_map(|(k, v)| {
+                selector
+                    .explore(PathSegment::Field(&k))
+                    .map(|next| (k, v, next))
+            })
+            .collect(),
+        Ipld::List(l) => l
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, v)| {
+                selector
+                    .explore(PathSegment::Index(i))
+                    .map(|next| (i.to_string(), v, next))
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Like `explored_children_owned` but copies only the explored children out of the borrowed
+/// node.
+fn explored_children_borrowed(
+    node: IpldRef<'_>,
+    selector: &Selector,
+) -> Vec<(String, Ipld, Selector)> {
+    match node {
+        IpldRef::Map(m) => m
+            .into_iter()
+            .filter_map(|(k, v)| {
+                selector
+                    .explore(PathSegment::Field(k))
+                    .map(|next| (k.to_owned(), v.into_owned(), next))
+            })
+            .collect(),
+        IpldRef::List(l) => l
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, v)| {
+                selector
+                    .explore(PathSegment::Index(i))
+                    .map(|next| (i.to_string(), v.into_owned(), next))
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Returns the links of the encoded block in the same order as `ipld_links` on the decoded
+/// block. dag-cbor blocks are only scanned for the links instead of being decoded.
+fn block_links(cid: &Cid, data: &[u8]) -> Result<Links, BlockError> {
+    if cid.codec() == cid::Codec::DagCBOR {
+        let links = DagCborCodec::links(data)?;
+        Ok(links.into_iter().map(|cid| (None, cid)).collect())
+    } else {
+        let ipld = decode_ipld(cid, data)?;
+        Ok(ipld_links(cid, ipld).collect())
+    }
+}
+
 fn ipld_links(
     cid: &Cid,
     ipld: Ipld,
@@ -316,10 +544,8 @@ fn dagpb_links(ipld: Ipld) -> Vec<(Option<String>, Cid)> {
                             "Expected dag-pb2ipld \"Links[{}]/Name\" to be a string, got: {:?}",
                             i, x
                         ),
-                        // not too sure of this, this could be the index as string as well?
-                        None => unimplemented!(
-                            "Default name for dag-pb2ipld links, should it be index?"
-                        ),
+                        // an absent name reads as empty, like with any protobuf decoder
+                        None => Some(String::new()),
                     };
 
                     Some((name, link))
@@ -335,7 +561,8 @@ fn dagpb_links(ipld: Ipld) -> Vec<(Option<String>, Cid)> {
 
 #[cfg(test)]
 mod tests {
-    use super::{ipld_links, iplds_refs, Edge};
+    use super::{ipld_links, iplds_refs, walk_selected, Edge, SelectedItem};
+    use crate::ipld::selector::Selector;
     use crate::ipld::{decode_ipld, validate};
     use crate::{Block, Node};
     use cid::Cid;
@@ -414,6 +641,73 @@ mod tests {
         assert_edges(&expected, all_edges.as_slice());
     }
 
+    #[tokio::test(max_threads = 1)]
+    async fn same_link_order_at_every_depth() {
+        use super::IpldRefs;
+        use crate::ipld::dag_cbor::DagCborCodec;
+        use cid::Codec;
+        use multihash::Sha2_256;
+
+        let Node { ipfs, bg_task: _bt } = Node::new("test_node").await;
+
+        let mut leaves = Vec::new();
+        for data in &[&b"a"[..], b"b", b"c"] {
+            let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(data));
+            leaves.push(
+                ipfs.put_block(Block::new(data.to_vec(), cid))
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        // the canonical encoding has the shorter "c" before "bb" on both levels
+        let child = make_ipld!({ "c": leaves[0].clone(), "bb": leaves[1].clone() });
+        let child = DagCborCodec::encode(&child).unwrap();
+        let child_cid = Cid::new_v1(Codec::DagCBOR, Sha2_256::digest(&child));
+        ipfs.put_block(Block::new(child.into_vec(), child_cid.clone()))
+            .await
+            .unwrap();
+
+        let root = make_ipld!({ "c": child_cid.clone(), "bb": leaves[2].clone() });
+        let encoded = DagCborCodec::encode(&root).unwrap();
+        let root_cid = Cid::new_v1(Codec::DagCBOR, Sha2_256::digest(&encoded));
+
+        let expected = vec![
+            (root_cid.clone(), leaves[2].clone()),
+            (root_cid.clone(), child_cid.clone()),
+            (child_cid.clone(), leaves[1].clone()),
+            (child_cid, leaves[0].clone()),
+        ];
+
+        let from_ipld: Vec<_> =
+            iplds_refs(&ipfs, vec![(root_cid.clone(), root.clone())], None, false)
+                .map_ok(|edge| (edge.source, edge.destination))
+                .try_collect()
+                .await
+                .unwrap();
+
+        assert_eq!(from_ipld, expected);
+
+        let from_resolved: Vec<_> = IpldRefs::default()
+            .refs_of_resolved(&ipfs, vec![(root_cid.clone(), root)])
+            .map_ok(|edge| (edge.source, edge.destination))
+            .try_collect()
+            .await
+            .unwrap();
+
+        assert_eq!(from_resolved, expected);
+
+        let from_block: Vec<_> = IpldRefs::default()
+            .refs_of_block(&ipfs, &root_cid, &encoded)
+            .unwrap()
+            .map_ok(|edge| (edge.source, edge.destination))
+            .try_collect()
+            .await
+            .unwrap();
+
+        assert_eq!(from_block, expected);
+    }
+
     #[tokio::test(max_threads = 1)]
     async fn all_unique_refs_from_root() {
         let Node { ipfs, bg_task: _bt } = preloaded_testing_ipfs().await;
@@ -457,6 +751,65 @@ mod tests {
         assert!(diff.is_empty(), "{:?}", diff);
     }
 
+    #[tokio::test(max_threads = 1)]
+    async fn walk_selected_path() {
+        let Node { ipfs, bg_task: _bt } = preloaded_testing_ipfs().await;
+
+        let (root, dag0, dag1, unixfs1) = (
+            "bafyreihpc3vupfos5yqnlakgpjxtyx3smkg26ft7e2jnqf3qkyhromhb64",
+            "bafyreidquig3arts3bmee53rutt463hdyu6ff4zeas2etf2h2oh4dfms44",
+            "bafyreibvjvcv745gig4mvqs4hctx4zfkono4rjejm2ta6gtyzkqxfjeily",
+            "QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL",
+        );
+
+        // root/0/foo/foo
+        let selector = Selector::from_dag_json(
+            br#"{"i":{"i":0,">":{"f":{"f>":{"foo":{"f":{"f>":{"foo":{".":{}}}}}}}}}}"#,
+        )
+        .unwrap();
+
+        let items: Vec<_> = walk_selected(ipfs, Cid::try_from(root).unwrap(), selector)
+            .map_ok(|item| match item {
+                SelectedItem::Block(cid) => cid.to_string(),
+                SelectedItem::Matched { block, path, .. } => format!("{} {}", block, path),
+            })
+            .try_collect()
+            .await
+            .unwrap();
+
+        let matched = format!("{} 0/foo/foo", unixfs1);
+        assert_eq!(items, &[root, dag0, dag1, unixfs1, matched.as_str()]);
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn walk_selected_matches_dag_cbor() {
+        let Node { ipfs, bg_task: _bt } = preloaded_testing_ipfs().await;
+
+        let (root, dag0) = (
+            "bafyreihpc3vupfos5yqnlakgpjxtyx3smkg26ft7e2jnqf3qkyhromhb64",
+            "bafyreidquig3arts3bmee53rutt463hdyu6ff4zeas2etf2h2oh4dfms44",
+        );
+
+        // root/0
+        let selector = Selector::from_dag_json(br#"{"i":{"i":0,">":{".":{}}}}"#).unwrap();
+
+        let matched: Vec<_> = walk_selected(&ipfs, Cid::try_from(root).unwrap(), selector)
+            .try_filter_map(|item| async move {
+                Ok(match item {
+                    SelectedItem::Matched { node, .. } => Some(node),
+                    _ => None,
+                })
+            })
+            .try_collect()
+            .await
+            .unwrap();
+
+        let dag0 = ipfs.get_block(&Cid::try_from(dag0).unwrap()).await.unwrap();
+        let expected = decode_ipld(dag0.cid(), dag0.data()).unwrap();
+
+        assert_eq!(matched, vec![expected]);
+    }
+
     fn assert_edges(expected: &[(&str, &str)], actual: &[(String, String)]) {
         let expected: HashSet<_> = expected.iter().map(|&(a, b)| (a, b)).collect();
 
@@ -16,7 +16,7 @@ pub use blocks::FsBlockStore;
 
 /// Path mangling done for pins and blocks
 mod paths;
-use paths::{block_path, filestem_to_block_cid, filestem_to_pin_cid, pin_path};
+use paths::{block_path, column_path, filestem_to_block_cid, filestem_to_pin_cid, pin_path};
 
 /// FsDataStore which uses the filesystem as a lockable key-value store. Maintains a similar to
 /// blockstore sharded two level storage. Direct have empty files, recursive pins record all of
@@ -24,6 +24,9 @@ use paths::{block_path, filestem_to_block_cid, filestem_to_pin_cid, pin_path};
 ///
 /// When modifying, single write lock is used.
 ///
+/// The column values are stored as files named after the keys, in a directory per column next to
+/// the pins.
+///
 /// For the PinStore implementation, please see `fs/pinstore.rs`.
 #[derive(Debug)]
 pub struct FsDataStore {
@@ -31,6 +34,9 @@ pub struct FsDataStore {
     /// blocks are stored under the shard. See unixfs/examples/cat.rs for read example.
     path: PathBuf,
 
+    /// The base directory of the column directories.
+    columns: PathBuf,
+
     /// Start with simple, conservative solution, allows concurrent queries but single writer.
     /// It is assumed the reads do not require permit as non-empty writes are done through
     /// tempfiles and the consistency regarding reads is not a concern right now. For garbage
@@ -42,14 +48,12 @@ pub struct FsDataStore {
     written_bytes: AtomicU64,
 }
 
-/// The column operations are all unimplemented pending at least downscoping of the
-/// DataStore trait itself.
 #[async_trait]
 impl DataStore for FsDataStore {
-    fn new(mut root: PathBuf) -> Self {
-        root.push("pins");
+    fn new(root: PathBuf) -> Self {
         FsDataStore {
-            path: root,
+            path: root.join("pins"),
+            columns: root,
             lock: Arc::new(Semaphore::new(1)),
             written_bytes: Default::default(),
         }
@@ -64,20 +68,73 @@ impl DataStore for FsDataStore {
         Ok(())
     }
 
-    async fn contains(&self, _col: Column, _key: &[u8]) -> Result<bool, Error> {
-        Err(anyhow::anyhow!("not implemented"))
+    async fn contains(&self, col: Column, key: &[u8]) -> Result<bool, Error> {
+        let path = column_path(self.columns.clone(), col, key);
+
+        match tokio::fs::metadata(path).await {
+            Ok(m) => Ok(m.is_file()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
     }
 
-    async fn get(&self, _col: Column, _key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
-        Err(anyhow::anyhow!("not implemented"))
+    async fn get(&self, col: Column, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        let path = column_path(self.columns.clone(), col, key);
+
+        match tokio::fs::read(path).await {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
     }
 
-    async fn put(&self, _col: Column, _key: &[u8], _value: &[u8]) -> Result<(), Error> {
-        Err(anyhow::anyhow!("not implemented"))
+    async fn put(&self, col: Column, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        let permit = Semaphore::acquire_owned(Arc::clone(&self.lock)).await;
+
+        let path = column_path(self.columns.clone(), col, key);
+        let value = value.to_vec();
+
+        let span = tracing::Span::current();
+
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            let _entered = span.enter();
+
+            std::fs::create_dir_all(path.parent().expect("column directory has to exist"))?;
+
+            // written through a tempfile so that the readers never see a partial value
+            let temp_path = path.with_extension("temp");
+            let write = std::fs::File::create(&temp_path).and_then(|mut file| {
+                use std::io::Write;
+                file.write_all(&value)?;
+                file.sync_all()
+            });
+
+            match write.and_then(|_| std::fs::rename(&temp_path, &path)) {
+                Ok(_) => Ok(()),
+                Err(e) => {
+                    if let Err(e) = std::fs::remove_file(&temp_path) {
+                        warn!("failed to cleanup temporary file: {}", e);
+                    }
+                    Err(e)
+                }
+            }
+        })
+        .await??;
+
+        Ok(())
     }
 
-    async fn remove(&self, _col: Column, _key: &[u8]) -> Result<(), Error> {
-        Err(anyhow::anyhow!("not implemented"))
+    async fn remove(&self, col: Column, key: &[u8]) -> Result<(), Error> {
+        let _permit = self.lock.acquire().await;
+
+        let path = column_path(self.columns.clone(), col, key);
+
+        match tokio::fs::remove_file(path).await {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
     }
 
     async fn wipe(&self) {
@@ -87,3 +144,40 @@ impl DataStore for FsDataStore {
 
 #[cfg(test)]
 crate::pinstore_interface_tests!(common_tests, crate::repo::fs::FsDataStore::new);
+
+#[cfg(test)]
+mod tests {
+    use super::FsDataStore;
+    use crate::repo::{Column, DataStore};
+
+    #[tokio::test(max_threads = 1)]
+    async fn columns_survive_reopening() {
+        let tmp = tempfile::tempdir().unwrap();
+        let col = Column::Mfs;
+        let key = b"root";
+
+        {
+            let store = FsDataStore::new(tmp.path().to_owned());
+            store.init().await.unwrap();
+            store.open().await.unwrap();
+
+            assert!(!store.contains(col, key).await.unwrap());
+            assert_eq!(store.get(col, key).await.unwrap(), None);
+            store.remove(col, key).await.unwrap();
+
+            store.put(col, key, b"first").await.unwrap();
+            store.put(col, key, b"second").await.unwrap();
+            assert_eq!(store.get(Column::Ipns, key).await.unwrap(), None);
+        }
+
+        let store = FsDataStore::new(tmp.path().to_owned());
+        store.open().await.unwrap();
+
+        assert!(store.contains(col, key).await.unwrap());
+        assert_eq!(store.get(col, key).await.unwrap(), Some(b"second".to_vec()));
+
+        store.remove(col, key).await.unwrap();
+        assert!(!store.contains(col, key).await.unwrap());
+        assert_eq!(store.get(col, key).await.unwrap(), None);
+    }
+}
@@ -17,6 +17,7 @@ use tokio::sync::broadcast;
 use tracing_futures::Instrument;
 
 type ArcMutexMap<A, B> = Arc<Mutex<HashMap<A, B>>>;
+type WriteSender = broadcast::Sender<Result<(), ()>>;
 
 /// File system backed block store.
 ///
@@ -30,7 +31,7 @@ pub struct FsBlockStore {
     /// Synchronize concurrent reads and writes to the same Cid.
     /// If the write ever happens, the message sent will be Ok(()), on failure it'll be an Err(()).
     /// Since this is a broadcast channel, the late arriving receiver might not get any messages.
-    writes: ArcMutexMap<RepoCid, broadcast::Sender<Result<(), ()>>>,
+    writes: ArcMutexMap<RepoCid, WriteSender>,
 
     /// Initially used to demonstrate a bug, not really needed anymore. Could be used as a basis
     /// for periodic synching to disk to know much space we have used.
@@ -151,7 +152,7 @@ impl BlockStore for FsBlockStore {
 
                 let mut data = Vec::with_capacity(len as usize);
                 file.read_to_end(&mut data)?;
-                let block = Block::new(data.into_boxed_slice(), cid);
+                let block = Block::new(data, cid);
                 Ok(Some(block))
             })
             .await?
@@ -312,6 +313,99 @@ impl BlockStore for FsBlockStore {
         .await
     }
 
+    async fn put_many(&self, blocks: Vec<Block>) -> Result<Vec<(Cid, BlockPut)>, Error> {
+        use std::collections::hash_map::Entry;
+
+        let span = tracing::trace_span!("put blocks", count = blocks.len());
+
+        async move {
+            // the blocks which are being written by someone else, or which appear multiple times
+            // in this batch; these are synchronized with after our own writes.
+            let mut joined = Vec::new();
+            // the blocks we are responsible for writing
+            let mut owned = Vec::new();
+            let mut cleanups = Vec::new();
+
+            {
+                let mut g = self.writes.lock().expect("cant support poisoned");
+
+                for (i, block) in blocks.into_iter().enumerate() {
+                    match g.entry(RepoCid(block.cid.clone())) {
+                        Entry::Occupied(oe) => {
+                            trace!(cid = %block.cid, "joining in on another already writing the block");
+                            joined.push((i, block.cid, oe.get().subscribe()));
+                        }
+                        Entry::Vacant(ve) => {
+                            let (tx, _) = broadcast::channel(1);
+                            ve.insert(tx.clone());
+                            cleanups.push(RemoveOnDrop(
+                                self.writes.clone(),
+                                Some(RepoCid(block.cid.clone())),
+                            ));
+                            let target_path = block_path(self.path.clone(), &block.cid);
+                            owned.push((i, block, target_path, tx));
+                        }
+                    }
+                }
+            }
+
+            let count = owned.len() + joined.len();
+            let base = self.path.clone();
+            let span = tracing::Span::current();
+
+            let je = tokio::task::spawn_blocking(move || {
+                let _entered = span.enter();
+                write_batch(&base, owned)
+            })
+            .await;
+
+            drop(cleanups);
+
+            let mut ret = Vec::with_capacity(count);
+            ret.resize_with(count, || None);
+
+            let written = match je {
+                Ok(Ok(outcomes)) => outcomes,
+                Ok(Err(e)) => return Err(Error::new(e)),
+                Err(e) => return Err(e.into()),
+            };
+
+            let mut total = 0;
+            for (i, cid, outcome) in written {
+                if let BlockPut::NewBlock = outcome {
+                    total += 1;
+                }
+                ret[i] = Some((cid, outcome));
+            }
+
+            trace!(new_blocks = total, "wrote batch");
+
+            for (i, cid, mut rx) in joined {
+                let message = match rx.recv().await {
+                    Ok(message) => message,
+                    // the writer finished before we subscribed, or it was the file which existed
+                    Err(broadcast::RecvError::Closed) => Ok(()),
+                    Err(broadcast::RecvError::Lagged(_)) => {
+                        unreachable!("broadcast channel should only be messaged once here")
+                    }
+                };
+
+                if message.is_err() {
+                    return Err(anyhow::anyhow!("other concurrent write failed"));
+                }
+
+                ret[i] = Some((cid, BlockPut::Existed));
+            }
+
+            Ok(ret
+                .into_iter()
+                .map(|outcome| outcome.expect("all blocks were either written or joined"))
+                .collect())
+        }
+        .instrument(span)
+        .await
+    }
+
     async fn remove(&self, cid: &Cid) -> Result<Result<BlockRm, BlockRmError>, Error> {
         let path = block_path(self.path.clone(), cid);
 
@@ -380,6 +474,135 @@ impl BlockStore for FsBlockStore {
     }
 }
 
+/// Writes the blocks of a batch through tempfiles which are made durable before being renamed
+/// into place, after which the directories of the renamed files are synced once per batch.
+/// Returns the outcome for each of the blocks along with their position in the batch.
+fn write_batch(
+    base: &std::path::Path,
+    blocks: Vec<(usize, Block, PathBuf, WriteSender)>,
+) -> Result<Vec<(usize, Cid, BlockPut)>, std::io::Error> {
+    use std::io::Write;
+
+    let mut outcomes = Vec::with_capacity(blocks.len());
+    let mut pending = Vec::with_capacity(blocks.len());
+
+    let res = (|| {
+        for (i, block, target_path, tx) in blocks {
+            let sharded = target_path
+                .parent()
+                .expect("we already have at least the shard parent");
+
+            std::fs::create_dir_all(sharded)?;
+
+            // the empty target file is only used to pick the winning writer, like with `put`
+            match std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&target_path)
+            {
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    // dropping the sender without a message signals the joined readers that there
+                    // was nothing to write
+                    outcomes.push((i, block.cid, BlockPut::Existed));
+                    continue;
+                }
+                Err(e) => return Err(e),
+            }
+
+            let temp_path = target_path.with_extension("tmp");
+            pending.push((i, block.cid.clone(), target_path, temp_path, tx));
+
+            let mut temp = std::fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&pending.last().unwrap().3)?;
+
+            temp.write_all(block.data())?;
+            temp.flush()?;
+            temp.sync_data()?;
+        }
+
+        let mut renamed = Vec::with_capacity(pending.len());
+        let mut failed = None;
+
+        while let Some((i, cid, target_path, temp_path, tx)) = pending.pop() {
+            if let Err(e) = std::fs::rename(&temp_path, &target_path) {
+                remove_partially_written(&temp_path, &target_path, tx);
+                failed = Some(e);
+                break;
+            }
+
+            renamed.push((i, cid, target_path, tx));
+        }
+
+        // the renames, and the shard directories created for them, are durable only once the
+        // directories have been synced
+        let mut directories = renamed
+            .iter()
+            .filter_map(|(_, _, target_path, _)| target_path.parent())
+            .collect::<Vec<_>>();
+        directories.sort();
+        directories.dedup();
+
+        let synced = directories
+            .into_iter()
+            .chain(std::iter::once(base))
+            .try_for_each(sync_directory);
+
+        for (i, cid, target_path, tx) in renamed {
+            if synced.is_ok() {
+                let _ = tx.send(Ok(()));
+                outcomes.push((i, cid, BlockPut::NewBlock));
+            } else {
+                let _ = std::fs::remove_file(&target_path);
+                let _ = tx.send(Err(()));
+            }
+        }
+
+        match failed {
+            Some(e) => Err(e),
+            None => synced,
+        }
+    })();
+
+    if let Err(e) = res {
+        for (_, _, target_path, temp_path, tx) in pending {
+            remove_partially_written(&temp_path, &target_path, tx);
+        }
+        return Err(e);
+    }
+
+    Ok(outcomes)
+}
+
+fn remove_partially_written(
+    temp_path: &std::path::Path,
+    target_path: &std::path::Path,
+    tx: WriteSender,
+) {
+    let _ = std::fs::remove_file(temp_path);
+    match std::fs::remove_file(target_path) {
+        Ok(_) => debug!("removed partially written {:?}", target_path),
+        Err(removal) => warn!(
+            "failed to remove partially written {:?}: {}",
+            target_path, removal
+        ),
+    }
+    let _ = tx.send(Err(()));
+}
+
+/// Makes the changes to the entries of the directory durable. Directories cannot be opened as
+/// files on all platforms, in which case the entries are made durable by the filesystem itself.
+fn sync_directory(path: &std::path::Path) -> Result<(), std::io::Error> {
+    if cfg!(unix) {
+        std::fs::File::open(path)?.sync_all()
+    } else {
+        Ok(())
+    }
+}
+
 fn write_through_tempfile(
     target: std::fs::File,
     target_path: impl AsRef<std::path::Path>,
@@ -428,7 +651,7 @@ mod tests {
         std::fs::remove_dir_all(tmp.clone()).ok();
         let store = FsBlockStore::new(tmp.clone());
 
-        let data = b"1".to_vec().into_boxed_slice();
+        let data = b"1".to_vec();
         let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
         let block = Block::new(data, cid.clone());
 
@@ -465,7 +688,7 @@ mod tests {
         tmp.push("blockstore2");
         std::fs::remove_dir_all(&tmp).ok();
 
-        let data = b"1".to_vec().into_boxed_slice();
+        let data = b"1".to_vec();
         let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
         let block = Block::new(data, cid);
 
@@ -495,7 +718,7 @@ mod tests {
         block_store.open().await.unwrap();
 
         for data in &[b"1", b"2", b"3"] {
-            let data_slice = data.to_vec().into_boxed_slice();
+            let data_slice = data.to_vec();
             let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data_slice));
             let block = Block::new(data_slice, cid);
             block_store.put(block.clone()).await.unwrap();
@@ -508,6 +731,49 @@ mod tests {
         }
     }
 
+    #[tokio::test(max_threads = 1)]
+    async fn put_many() {
+        let mut tmp = temp_dir();
+        tmp.push("blockstore_put_many");
+        std::fs::remove_dir_all(&tmp).ok();
+
+        let block_store = FsBlockStore::new(tmp.clone());
+        block_store.init().await.unwrap();
+        block_store.open().await.unwrap();
+
+        let blocks = [&b"1"[..], b"2", b"3", b"2"]
+            .iter()
+            .map(|data| {
+                let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(data));
+                Block::new(data.to_vec(), cid)
+            })
+            .collect::<Vec<_>>();
+
+        // one of the blocks exists before the batch
+        block_store.put(blocks[0].clone()).await.unwrap();
+
+        let outcomes = block_store.put_many(blocks.clone()).await.unwrap();
+
+        let expected = [
+            BlockPut::Existed,
+            BlockPut::NewBlock,
+            BlockPut::NewBlock,
+            BlockPut::Existed,
+        ];
+
+        assert_eq!(outcomes.len(), blocks.len());
+        for ((block, (cid, outcome)), expected) in blocks.iter().zip(outcomes).zip(&expected) {
+            assert_eq!(&cid, block.cid());
+            assert_eq!(&outcome, expected, "{}", cid);
+            assert_eq!(block_store.get(&cid).await.unwrap().as_ref(), Some(block));
+        }
+
+        assert_eq!(block_store.list().await.unwrap().len(), 3);
+        assert!(block_store.writes.lock().unwrap().is_empty());
+
+        std::fs::remove_dir_all(&tmp).ok();
+    }
+
     #[tokio::test(max_threads = 1)]
     async fn race_to_insert_new() {
         // FIXME: why not tempdir?
@@ -525,7 +791,7 @@ mod tests {
 
         let block = Block {
             cid,
-            data: data.into(),
+            data: data.to_vec().into(),
         };
 
         let count = 10;
@@ -556,7 +822,7 @@ mod tests {
 
         let block = Block {
             cid,
-            data: data.into(),
+            data: data.to_vec().into(),
         };
 
         single.put(block.clone()).await.unwrap();
@@ -628,7 +894,7 @@ mod tests {
 
         let block = Block {
             cid: cid.clone(),
-            data: data.into(),
+            data: data.to_vec().into(),
         };
 
         assert_eq!(single.list().await.unwrap().len(), 0);
@@ -1,3 +1,4 @@
+use crate::repo::Column;
 use cid::Cid;
 use core::convert::TryFrom;
 use std::path::PathBuf;
@@ -57,6 +58,17 @@ pub fn filestem_to_pin_cid(file_stem: Option<&std::ffi::OsStr>) -> Option<Cid> {
     })
 }
 
+/// Path of the file holding the value of the key in the column, under a directory per column.
+/// The keys are base32 encoded as they can be any bytes.
+pub fn column_path(mut base: PathBuf, col: Column, key: &[u8]) -> PathBuf {
+    base.push(match col {
+        Column::Ipns => "ipns",
+        Column::Mfs => "mfs",
+    });
+    base.push(multibase::Base::Base32Lower.encode(key));
+    base
+}
+
 /// second-to-last/2 sharding, just by taking the two characters from suffix ignoring the last
 /// character from an ASCII encoded key string to be prepended as the directory or "shard".
 ///
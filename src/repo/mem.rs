@@ -71,6 +71,23 @@ impl BlockStore for MemBlockStore {
         }
     }
 
+    async fn put_many(&self, blocks: Vec<Block>) -> Result<Vec<(Cid, BlockPut)>, Error> {
+        use std::collections::hash_map::Entry;
+        let mut g = self.blocks.lock().await;
+        let ret = blocks
+            .into_iter()
+            .map(|block| match g.entry(RepoCid(block.cid.clone())) {
+                Entry::Occupied(_) => (block.cid, BlockPut::Existed),
+                Entry::Vacant(ve) => {
+                    let cid = ve.key().0.clone();
+                    ve.insert(block);
+                    (cid, BlockPut::NewBlock)
+                }
+            })
+            .collect();
+        Ok(ret)
+    }
+
     async fn remove(&self, cid: &Cid) -> Result<Result<BlockRm, BlockRmError>, Error> {
         match self.blocks.lock().await.remove(&RepoCid(cid.to_owned())) {
             Some(_block) => Ok(Ok(BlockRm::Removed(cid.clone()))),
@@ -91,6 +108,7 @@ impl BlockStore for MemBlockStore {
 #[derive(Debug, Default)]
 pub struct MemDataStore {
     ipns: Mutex<HashMap<Vec<u8>, Vec<u8>>>,
+    mfs: Mutex<HashMap<Vec<u8>, Vec<u8>>>,
     // this could also be PinDocument however doing any serialization allows to see the required
     // error types easier
     pin: Arc<Mutex<HashMap<Vec<u8>, Vec<u8>>>>,
@@ -399,6 +417,7 @@ impl DataStore for MemDataStore {
     async fn contains(&self, col: Column, key: &[u8]) -> Result<bool, Error> {
         let map = match col {
             Column::Ipns => &self.ipns,
+            Column::Mfs => &self.mfs,
         };
         let contains = map.lock().await.contains_key(key);
         Ok(contains)
@@ -407,6 +426,7 @@ impl DataStore for MemDataStore {
     async fn get(&self, col: Column, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
         let map = match col {
             Column::Ipns => &self.ipns,
+            Column::Mfs => &self.mfs,
         };
         let value = map.lock().await.get(key).map(|value| value.to_owned());
         Ok(value)
@@ -415,6 +435,7 @@ impl DataStore for MemDataStore {
     async fn put(&self, col: Column, key: &[u8], value: &[u8]) -> Result<(), Error> {
         let map = match col {
             Column::Ipns => &self.ipns,
+            Column::Mfs => &self.mfs,
         };
         map.lock().await.insert(key.to_owned(), value.to_owned());
         Ok(())
@@ -423,6 +444,7 @@ impl DataStore for MemDataStore {
     async fn remove(&self, col: Column, key: &[u8]) -> Result<(), Error> {
         let map = match col {
             Column::Ipns => &self.ipns,
+            Column::Mfs => &self.mfs,
         };
         map.lock().await.remove(key);
         Ok(())
@@ -430,6 +452,7 @@ impl DataStore for MemDataStore {
 
     async fn wipe(&self) {
         self.ipns.lock().await.clear();
+        self.mfs.lock().await.clear();
         self.pin.lock().await.clear();
     }
 }
@@ -663,7 +686,7 @@ mod tests {
     async fn test_mem_blockstore() {
         let tmp = temp_dir();
         let store = MemBlockStore::new(tmp);
-        let data = b"1".to_vec().into_boxed_slice();
+        let data = b"1".to_vec();
         let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
         let block = Block::new(data, cid.clone());
 
@@ -692,6 +715,26 @@ mod tests {
         assert_eq!(get.await.unwrap(), None);
     }
 
+    #[tokio::test(max_threads = 1)]
+    async fn test_mem_blockstore_shares_data() {
+        let tmp = temp_dir();
+        let store = MemBlockStore::new(tmp);
+        let data = b"shared".to_vec();
+        let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
+        let block = Block::new(data, cid.clone());
+
+        store.init().await.unwrap();
+        store.open().await.unwrap();
+        store.put(block.clone()).await.unwrap();
+
+        let first = store.get(&cid).await.unwrap().unwrap();
+        let second = store.get(&cid).await.unwrap().unwrap();
+
+        // the stored data is not copied when the block is handed out
+        assert_eq!(first.data().as_ptr(), block.data().as_ptr());
+        assert_eq!(second.data().as_ptr(), block.data().as_ptr());
+    }
+
     #[tokio::test(max_threads = 1)]
     async fn test_mem_blockstore_list() {
         let tmp = temp_dir();
@@ -701,7 +744,7 @@ mod tests {
         mem_store.open().await.unwrap();
 
         for data in &[b"1", b"2", b"3"] {
-            let data_slice = data.to_vec().into_boxed_slice();
+            let data_slice = data.to_vec();
             let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data_slice));
             let block = Block::new(data_slice, cid);
             mem_store.put(block.clone()).await.unwrap();
@@ -100,6 +100,17 @@ pub trait BlockStore: Debug + Send + Sync + Unpin + 'static {
     async fn contains(&self, cid: &Cid) -> Result<bool, Error>;
     async fn get(&self, cid: &Cid) -> Result<Option<Block>, Error>;
     async fn put(&self, block: Block) -> Result<(Cid, BlockPut), Error>;
+    /// Puts all of the blocks, returning the outcomes in the same order. Implementations should
+    /// amortize the cost of durability over the batch; the filesystem store still syncs the data of
+    /// each new block, but syncs each of the touched directories only once per batch. The default
+    /// implementation falls back to `put` for each block.
+    async fn put_many(&self, blocks: Vec<Block>) -> Result<Vec<(Cid, BlockPut)>, Error> {
+        let mut ret = Vec::with_capacity(blocks.len());
+        for block in blocks {
+            ret.push(self.put(block).await?);
+        }
+        Ok(ret)
+    }
     async fn remove(&self, cid: &Cid) -> Result<Result<BlockRm, BlockRmError>, Error>;
     async fn list(&self) -> Result<Vec<Cid>, Error>;
     async fn wipe(&self);
@@ -161,6 +172,7 @@ pub trait PinStore: Debug + Send + Sync + Unpin + 'static {
 #[derive(Clone, Copy, Debug)]
 pub enum Column {
     Ipns,
+    Mfs,
 }
 
 /// `PinMode` is the description of pin type for quering purposes.
@@ -212,6 +224,8 @@ pub struct Repo<TRepoTypes: RepoTypes> {
     data_store: TRepoTypes::TDataStore,
     events: Sender<RepoEvent>,
     pub(crate) subscriptions: SubscriptionRegistry<Block, String>,
+    /// Held while modifying the root of the mutable file system, see [`crate::unixfs::mfs`].
+    pub(crate) files_lock: tokio::sync::Mutex<()>,
 }
 
 /// Events used to communicate to the swarm on repo changes.
@@ -223,6 +237,11 @@ pub enum RepoEvent {
         Cid,
         oneshot::Sender<Result<SubscriptionFuture<KadResult, String>, anyhow::Error>>,
     ),
+    /// Coalesced `ProvideBlock` for a batch of new blocks.
+    ProvideBlocks(
+        Vec<Cid>,
+        oneshot::Sender<Vec<Result<SubscriptionFuture<KadResult, String>, anyhow::Error>>>,
+    ),
     UnprovideBlock(Cid),
 }
 
@@ -253,6 +272,7 @@ impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
                 data_store,
                 events: sender,
                 subscriptions: Default::default(),
+                files_lock: Default::default(),
             },
             receiver,
         )
@@ -288,6 +308,16 @@ impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
 
     /// Puts a block into the block store.
     pub async fn put_block(&self, block: Block) -> Result<(Cid, BlockPut), Error> {
+        if let Some(inline) = Block::from_identity(&block.cid) {
+            // identity blocks are synthesized from the cid when needed, never stored
+            if inline.data() != block.data() {
+                return Err(anyhow::anyhow!(
+                    "identity block data does not match the cid"
+                ));
+            }
+            return Ok((block.cid, BlockPut::Existed));
+        }
+
         let cid = block.cid.clone();
         let (_cid, res) = self.block_store.put(block.clone()).await?;
         self.subscriptions
@@ -316,13 +346,84 @@ impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
         Ok((cid, res))
     }
 
+    /// Puts a batch of blocks into the block store, returning the outcomes in the same order as
+    /// the blocks. Unlike calling `put_block` for each block, the directories of the new blocks are
+    /// synced once per batch and the swarm is notified once about all of the new blocks.
+    pub async fn put_blocks(&self, blocks: Vec<Block>) -> Result<Vec<(Cid, BlockPut)>, Error> {
+        let mut ret = Vec::with_capacity(blocks.len());
+        let mut stored = Vec::with_capacity(blocks.len());
+        let mut positions = Vec::with_capacity(blocks.len());
+
+        for (i, block) in blocks.into_iter().enumerate() {
+            if let Some(inline) = Block::from_identity(&block.cid) {
+                // identity blocks are synthesized from the cid when needed, never stored
+                if inline.data() != block.data() {
+                    return Err(anyhow::anyhow!(
+                        "identity block data does not match the cid"
+                    ));
+                }
+                ret.push(Some((block.cid, BlockPut::Existed)));
+            } else {
+                ret.push(None);
+                positions.push(i);
+                stored.push(block);
+            }
+        }
+
+        if stored.is_empty() {
+            return Ok(ret.into_iter().map(Option::unwrap).collect());
+        }
+
+        let results = self.block_store.put_many(stored.clone()).await?;
+
+        let mut new_blocks = Vec::new();
+        let mut results = results.into_iter();
+
+        for (block, i) in stored.into_iter().zip(positions) {
+            let cid = block.cid.clone();
+            self.subscriptions
+                .finish_subscription(cid.clone().into(), Ok(block));
+
+            let (cid, res) = results
+                .next()
+                .expect("block store returned fewer outcomes than there were blocks");
+
+            if let BlockPut::NewBlock = res {
+                new_blocks.push(cid.clone());
+            }
+
+            ret[i] = Some((cid, res));
+        }
+
+        if !new_blocks.is_empty() {
+            // see put_block on why failures to send are ignored
+            let (tx, rx) = oneshot::channel();
+
+            self.events
+                .clone()
+                .send(RepoEvent::ProvideBlocks(new_blocks, tx))
+                .await
+                .ok();
+
+            if let Ok(kad_subscriptions) = rx.await {
+                for kad_subscription in kad_subscriptions.into_iter().filter_map(Result::ok) {
+                    kad_subscription.await?;
+                }
+            }
+        }
+
+        Ok(ret.into_iter().map(Option::unwrap).collect())
+    }
+
     /// Retrives a block from the block store, or starts fetching it from the network and awaits
     /// until it has been fetched.
     pub async fn get_block(&self, cid: &Cid) -> Result<Block, Error> {
         // FIXME: here's a race: block_store might give Ok(None) and we get to create our
         // subscription after the put has completed. So maybe create the subscription first, then
         // cancel it?
-        if let Some(block) = self.block_store.get(&cid).await? {
+        if let Some(block) = Block::from_identity(cid) {
+            Ok(block)
+        } else if let Some(block) = self.block_store.get(&cid).await? {
             Ok(block)
         } else {
             let subscription = self
@@ -341,6 +442,9 @@ impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
 
     /// Retrives a block from the block store if it's available locally.
     pub async fn get_block_now(&self, cid: &Cid) -> Result<Option<Block>, Error> {
+        if let Some(block) = Block::from_identity(cid) {
+            return Ok(Some(block));
+        }
         Ok(self.block_store.get(&cid).await?)
     }
 
@@ -406,6 +510,22 @@ impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
         self.data_store.remove(Column::Ipns, ipns.as_bytes()).await
     }
 
+    /// Get the root of the mutable file system from the datastore.
+    pub async fn get_files_root(&self) -> Result<Option<Cid>, Error> {
+        let bytes = self.data_store.get(Column::Mfs, b"root").await?;
+        match bytes {
+            Some(bytes) => Ok(Some(Cid::try_from(bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Put the root of the mutable file system into the datastore.
+    pub async fn put_files_root(&self, root: &Cid) -> Result<(), Error> {
+        self.data_store
+            .put(Column::Mfs, b"root", &root.to_bytes())
+            .await
+    }
+
     pub async fn insert_direct_pin(&self, cid: &Cid) -> Result<(), Error> {
         self.data_store.insert_direct_pin(cid).await
     }
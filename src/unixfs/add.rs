@@ -0,0 +1,514 @@
+use crate::{Error, Ipfs, IpfsTypes, PinMode};
+use async_stream::try_stream;
+use bitswap::Block;
+use cid::Cid;
+use futures::pin_mut;
+use futures::stream::{Stream, TryStreamExt};
+use ipfs_unixfs::dir::builder::{
+    OwnedTreeNode, StreamingTreeBuilder, StreamingTreeFailed, TreeBuildingFailed,
+    TreeConstructionFailed, TreeOptions,
+};
+use ipfs_unixfs::file::adder::{Chunker, Collector, FileAdder};
+use ipfs_unixfs::{CidOptions, Metadata};
+use std::borrow::Borrow;
+use std::path::PathBuf;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// The size of the buffer the input is read into.
+const READ_BUFFER_SIZE: usize = 256 * 1024;
+
+/// Options for adding a file with [`add`], also used for the files added with
+/// [`super::add_path`].
+#[derive(Debug, Clone)]
+pub struct AddOptions {
+    /// The chunker splitting the file into leaves, defaults to 256 KiB chunks.
+    pub chunker: Chunker,
+    /// The layout of the file tree, defaults to the balanced layout.
+    pub layout: Collector,
+    /// The Cid version and multihash of the created blocks, defaults to Cid version 0 with
+    /// sha2-256.
+    pub cid_options: CidOptions,
+    /// Store the file contents as raw blocks. When unset, defaults to true for Cid version 1 like
+    /// in go-ipfs.
+    pub raw_leaves: Option<bool>,
+    /// When set, blocks of at most this many bytes are inlined into their Cids.
+    pub inline_limit: Option<usize>,
+    /// Pin the root recursively once all of the blocks have been stored. Defaults to true like in
+    /// go-ipfs.
+    pub pin: bool,
+    /// When set, the file is wrapped in a directory under this name, and the directory becomes
+    /// the root.
+    pub wrap: Option<String>,
+}
+
+impl Default for AddOptions {
+    fn default() -> Self {
+        AddOptions {
+            chunker: Chunker::default(),
+            layout: Collector::default(),
+            cid_options: CidOptions::default(),
+            raw_leaves: None,
+            inline_limit: None,
+            pin: true,
+            wrap: None,
+        }
+    }
+}
+
+/// The events produced while adding files with [`add`] or [`super::add_path`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum AddEvent {
+    /// The total number of bytes read from the input or the files so far, reported when some of
+    /// them have been stored as blocks.
+    Progress(u64),
+    /// A file, a symlink or a directory has been stored. The last one is the root, which has been
+    /// pinned if requested.
+    Added {
+        /// The path of the added entry, starting with the name of the root entry. Empty for the
+        /// wrapping directory, and for the file added with [`add`] when it is not wrapped.
+        name: String,
+        /// The Cid of the file or directory.
+        cid: Cid,
+        /// Cumulative total size of the blocks in bytes.
+        total_size: u64,
+    },
+}
+
+impl AddOptions {
+    /// Returns the file adder for a file with the metadata.
+    pub(super) fn file_adder(&self, metadata: Metadata) -> FileAdder {
+        let raw_leaves = self
+            .raw_leaves
+            .unwrap_or_else(|| self.cid_options.version() == cid::Version::V1);
+
+        let builder = FileAdder::builder()
+            .with_chunker(self.chunker.clone())
+            .with_collector(self.layout.clone())
+            .with_cid_options(self.cid_options)
+            .with_raw_leaves(raw_leaves)
+            .with_metadata(metadata);
+
+        match self.inline_limit {
+            Some(limit) => builder.with_inline_limit(limit).build(),
+            None => builder.build(),
+        }
+    }
+
+    /// Returns the options for the directories, which are wrapped in a new root directory when
+    /// `wrap` has been given.
+    pub(super) fn tree_options(&self) -> TreeOptions {
+        let mut tree_opts = TreeOptions::default();
+        if self.wrap.is_some() {
+            tree_opts.wrap_with_directory();
+        }
+        tree_opts.cid_options(self.cid_options);
+        tree_opts
+    }
+}
+
+/// Types of failures which can occur while adding files.
+#[derive(Debug, thiserror::Error)]
+pub enum AddError {
+    /// Reading the input failed.
+    #[error("reading the input failed")]
+    Reading(#[source] std::io::Error),
+
+    /// Accessing the file or directory at the path failed.
+    #[error("accessing {:?} failed", .0)]
+    Filesystem(PathBuf, #[source] std::io::Error),
+
+    /// The path has no file name, or it is not valid unicode.
+    #[error("invalid path {:?}", .0)]
+    InvalidPath(PathBuf),
+
+    /// The path is not a file, a directory nor a symlink.
+    #[error("unsupported file type at {:?}", .0)]
+    UnsupportedFileType(PathBuf),
+
+    /// Following the symlinks led to the directory containing the symlink.
+    #[error("symlink loop at {:?}", .0)]
+    SymlinkLoop(PathBuf),
+
+    /// The tar archive is malformed, or an entry cannot be stored.
+    #[error("invalid tar archive: {}", .0)]
+    InvalidArchive(String),
+
+    /// Storing the created blocks failed.
+    #[error("storing the blocks failed")]
+    Persisting(#[source] Error),
+
+    /// Building the directories failed.
+    #[error("building the directory tree failed")]
+    TreeBuilding(#[source] StreamingTreeFailed),
+
+    /// Pinning the root failed.
+    #[error("pinning {} failed", .0)]
+    Pinning(Cid, #[source] Error),
+}
+
+impl From<StreamingTreeFailed> for AddError {
+    fn from(e: StreamingTreeFailed) -> Self {
+        AddError::TreeBuilding(e)
+    }
+}
+
+impl From<TreeBuildingFailed> for AddError {
+    fn from(e: TreeBuildingFailed) -> Self {
+        AddError::TreeBuilding(e.into())
+    }
+}
+
+impl From<TreeConstructionFailed> for AddError {
+    fn from(e: TreeConstructionFailed) -> Self {
+        AddError::TreeBuilding(e.into())
+    }
+}
+
+/// IPFS add operation, storing the bytes read from the input as an UnixFS file. This is generic
+/// over the different kinds of ways to own an `Ipfs` value in the same way as [`super::cat`].
+///
+/// Returns a stream of [`AddEvent`]s, where the last `AddEvent::Progress` has the size of the
+/// input and the last `AddEvent::Added` has the root Cid. The file has been added only once the
+/// stream has been driven to completion.
+pub fn add<'a, Types, MaybeOwned>(
+    ipfs: MaybeOwned,
+    input: impl AsyncRead + Send + Unpin + 'a,
+    opts: AddOptions,
+) -> impl Stream<Item = Result<AddEvent, AddError>> + Send + 'a
+where
+    Types: IpfsTypes,
+    MaybeOwned: Borrow<Ipfs<Types>> + Send + 'a,
+{
+    try_stream! {
+        let ipfs = ipfs.borrow();
+
+        let imported = import_file(ipfs, input, opts.file_adder(Metadata::default()));
+        pin_mut!(imported);
+
+        let mut root = None;
+        let mut reported = None;
+
+        while let Some(next) = imported.try_next().await? {
+            match next {
+                Imported::Progress(read) => {
+                    reported = Some(read);
+                    yield AddEvent::Progress(read);
+                }
+                Imported::Done { cid, total_size, read } => {
+                    // the last progress is always the size of the file, like in go-ipfs
+                    if reported != Some(read) {
+                        yield AddEvent::Progress(read);
+                    }
+                    root = Some((cid, total_size));
+                }
+            }
+        }
+
+        let (mut root, mut total_size) = root.expect("import always ends in Done");
+
+        if let Some(wrap) = opts.wrap.as_deref() {
+            yield AddEvent::Added {
+                name: wrap.to_owned(),
+                cid: root.clone(),
+                total_size,
+            };
+
+            let mut tree = StreamingTreeBuilder::new(opts.tree_options());
+            let mut nodes = tree.put_link(wrap, root, total_size)?;
+            nodes.extend(tree.finish()?);
+
+            let mut events = store_directories(ipfs, nodes).await?;
+
+            match events.pop() {
+                Some(AddEvent::Added { cid, total_size: size, .. }) => {
+                    root = cid;
+                    total_size = size;
+                }
+                _ => unreachable!("the wrapping directory is always rendered"),
+            }
+
+            for event in events {
+                yield event;
+            }
+        }
+
+        pin_root(ipfs, &opts, &root).await?;
+
+        yield AddEvent::Added {
+            name: String::new(),
+            cid: root,
+            total_size,
+        };
+    }
+}
+
+/// The events of importing a single file with [`import_file`].
+pub(super) enum Imported {
+    /// The number of bytes read from the input so far, reported when some of them have been
+    /// stored as blocks.
+    Progress(u64),
+    /// The file has been stored.
+    Done {
+        cid: Cid,
+        total_size: u64,
+        /// The number of bytes read from the input.
+        read: u64,
+    },
+}
+
+/// Stores the bytes read from the input as an UnixFS file created with the adder. The last event
+/// is always `Imported::Done`.
+pub(super) fn import_file<'a, Types: IpfsTypes>(
+    ipfs: &'a Ipfs<Types>,
+    mut input: impl AsyncRead + Send + Unpin + 'a,
+    mut adder: FileAdder,
+) -> impl Stream<Item = Result<Imported, AddError>> + Send + 'a {
+    try_stream! {
+        let mut buffer = vec![0u8; READ_BUFFER_SIZE];
+        // how many bytes of input we have read
+        let mut total_read = 0u64;
+        // how many bytes we have stored as blocks
+        let mut total_written = 0u64;
+
+        loop {
+            let read = input.read(&mut buffer).await.map_err(AddError::Reading)?;
+
+            if read == 0 {
+                break;
+            }
+
+            let mut consumed = 0;
+            let mut stored_any = false;
+
+            while consumed < read {
+                let (blocks, used) = adder.push(&buffer[consumed..read]);
+                consumed += used;
+
+                if let Some((_, written)) = store_all(ipfs, blocks).await? {
+                    total_written += written;
+                    stored_any = true;
+                }
+            }
+
+            total_read += read as u64;
+
+            if stored_any {
+                yield Imported::Progress(total_read);
+            }
+        }
+
+        let (cid, written) = store_all(ipfs, adder.finish())
+            .await?
+            .expect("finish always produces the root block");
+
+        yield Imported::Done {
+            cid,
+            total_size: total_written + written,
+            read: total_read,
+        };
+    }
+}
+
+/// Stores the blocks of the directories built from the files added with [`add`], returning the
+/// events for all of them except for the buckets of the HAMT sharded directories. The root is not
+/// pinned.
+pub async fn store_directories<Types: IpfsTypes>(
+    ipfs: &Ipfs<Types>,
+    nodes: Vec<OwnedTreeNode>,
+) -> Result<Vec<AddEvent>, AddError> {
+    let mut events = Vec::new();
+
+    let blocks = nodes.into_iter().map(|node| {
+        if !node.bucket {
+            events.push(AddEvent::Added {
+                name: node.path,
+                cid: node.cid.clone(),
+                total_size: node.total_size,
+            });
+        }
+        (node.cid, node.block.into_vec())
+    });
+
+    store_all(ipfs, blocks).await?;
+
+    Ok(events)
+}
+
+/// Pins the root recursively, if requested.
+pub(super) async fn pin_root<Types: IpfsTypes>(
+    ipfs: &Ipfs<Types>,
+    opts: &AddOptions,
+    root: &Cid,
+) -> Result<(), AddError> {
+    if !opts.pin {
+        return Ok(());
+    }
+
+    let pinning_failed = |e| AddError::Pinning(root.clone(), e);
+
+    // querying fails for the roots which are not pinned, so it is only done for the pinned ones
+    let pinned_recursively = if ipfs.is_pinned(root).await.map_err(pinning_failed)? {
+        ipfs.query_pins(vec![root.clone()], None)
+            .await
+            .map_err(pinning_failed)?
+            .iter()
+            .any(|(_, kind)| *kind == PinMode::Recursive)
+    } else {
+        false
+    };
+
+    if pinned_recursively {
+        // adding the same content again is not an error
+        return Ok(());
+    }
+
+    ipfs.insert_pin(root, true).await.map_err(pinning_failed)
+}
+
+/// Stores the blocks, returning the Cid of the last one and the total size of the blocks.
+pub(super) async fn store_all<Types: IpfsTypes>(
+    ipfs: &Ipfs<Types>,
+    blocks: impl Iterator<Item = (Cid, Vec<u8>)>,
+) -> Result<Option<(Cid, u64)>, AddError> {
+    let mut total = 0u64;
+
+    let blocks = blocks
+        .map(|(cid, data)| {
+            total += data.len() as u64;
+            Block::new(data, cid)
+        })
+        .collect::<Vec<_>>();
+
+    if blocks.is_empty() {
+        return Ok(None);
+    }
+
+    let last = ipfs
+        .put_blocks(blocks)
+        .await
+        .map_err(AddError::Persisting)?
+        .pop();
+
+    Ok(last.map(|cid| (cid, total)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AddEvent, AddOptions};
+    use crate::unixfs::added_root;
+    use crate::Node;
+    use futures::stream::TryStreamExt;
+    use ipfs_unixfs::file::adder::Chunker;
+
+    #[tokio::test(max_threads = 1)]
+    async fn add_and_cat() {
+        let ipfs = Node::new("test_node").await;
+
+        // same content as in `unixfs::tests::test_file_cid`
+        let content = "\u{8}\u{2}\u{12}\u{12}Here is some data\n\u{18}\u{12}";
+
+        let events = ipfs
+            .add_unixfs(content.as_bytes(), AddOptions::default())
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        let root = added_root(&events);
+        assert_eq!(
+            root.to_string(),
+            "QmQZE72h2Vdm3F5gWr9RLuzSw3rUJEkKedWEa8t8XVygT5"
+        );
+        assert!(ipfs.is_pinned(&root).await.unwrap());
+
+        let bytes = ipfs
+            .cat_unixfs(root, None)
+            .await
+            .unwrap()
+            .try_concat()
+            .await
+            .unwrap();
+
+        assert_eq!(bytes, content.as_bytes());
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn add_wrapped() {
+        let ipfs = Node::new("test_node").await;
+
+        let opts = AddOptions {
+            pin: false,
+            wrap: Some("foobar.txt".into()),
+            ..Default::default()
+        };
+
+        let events = ipfs
+            .add_unixfs(&b"foobar\n"[..], opts)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        let names = events
+            .iter()
+            .filter_map(|event| match event {
+                AddEvent::Added { name, .. } => Some(name.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(names, &["foobar.txt", ""]);
+
+        let root = added_root(&events);
+        assert!(!ipfs.is_pinned(&root).await.unwrap());
+
+        let path = crate::IpfsPath::from(root).sub_path("foobar.txt").unwrap();
+
+        let bytes = ipfs
+            .cat_unixfs(path, None)
+            .await
+            .unwrap()
+            .try_concat()
+            .await
+            .unwrap();
+
+        assert_eq!(bytes, b"foobar\n");
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn progress_is_reported() {
+        let ipfs = Node::new("test_node").await;
+
+        let content = (0..1000u32).map(|n| n as u8).collect::<Vec<_>>();
+
+        let opts = AddOptions {
+            chunker: Chunker::Size(100),
+            ..Default::default()
+        };
+
+        let events = ipfs
+            .add_unixfs(&content[..], opts)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        assert!(
+            matches!(events[0], AddEvent::Progress(1000)),
+            "{:?}",
+            events
+        );
+
+        match events.last() {
+            Some(AddEvent::Added { total_size, .. }) => assert!(*total_size > 1000),
+            other => panic!("unexpected last event: {:?}", other),
+        }
+
+        let bytes = ipfs
+            .cat_unixfs(added_root(&events), None)
+            .await
+            .unwrap()
+            .try_concat()
+            .await
+            .unwrap();
+
+        assert_eq!(bytes, content);
+    }
+}
@@ -0,0 +1,650 @@
+use super::add::{import_file, pin_root, store_all, store_directories, Imported};
+use super::ignore::IgnoreRules;
+use super::{blocking, AddError, AddEvent, AddOptions};
+use crate::{Ipfs, IpfsTypes};
+use async_stream::try_stream;
+use futures::pin_mut;
+use futures::stream::{Stream, TryStreamExt};
+use ipfs_unixfs::dir::builder::StreamingTreeBuilder;
+use ipfs_unixfs::symlink::serialize_symlink_block;
+use ipfs_unixfs::Metadata;
+use std::borrow::Borrow;
+use std::io;
+use std::path::PathBuf;
+
+/// Options for adding a file or a directory tree from the filesystem with [`add_path`].
+#[derive(Debug, Clone, Default)]
+pub struct AddPathOptions {
+    /// Options for the files, and for pinning and wrapping the root. When wrapped, the root entry
+    /// is named after `AddOptions::wrap` instead of the last component of the path.
+    pub add: AddOptions,
+    /// Add the hidden files and directories, whose names start with a dot. Defaults to false
+    /// like in go-ipfs.
+    pub hidden: bool,
+    /// Names of the ignore files read from every directory, for example `.gitignore`. The rules
+    /// of an ignore file apply to the directory it was read from and its subdirectories.
+    pub ignore_files: Vec<String>,
+    /// Additional ignore rules, applying to the added directory and its subdirectories.
+    pub ignore_rules: Vec<String>,
+    /// Add the targets of the symlinks instead of the symlinks. Defaults to false, in which case
+    /// the symlinks are stored as UnixFS symlinks.
+    pub follow_symlinks: bool,
+    /// Store the permission bits of the files and directories. Only supported on unix.
+    pub preserve_mode: bool,
+    /// Store the modification times of the files and directories.
+    pub preserve_mtime: bool,
+}
+
+/// IPFS add operation for a file or a directory tree from the filesystem, like `ipfs add -r`.
+/// This is generic over the different kinds of ways to own an `Ipfs` value in the same way as
+/// [`super::cat`].
+///
+/// The filesystem is accessed in the blocking threads of the runtime, while the files are read
+/// asynchronously. The directory entries are added in the order of their names, which allows
+/// storing each directory as soon as all of its entries have been added.
+///
+/// Returns a stream of [`AddEvent`]s, where the last `AddEvent::Added` has the root Cid. The tree
+/// has been added only once the stream has been driven to completion.
+pub fn add_path<'a, Types, MaybeOwned>(
+    ipfs: MaybeOwned,
+    path: impl Into<PathBuf>,
+    opts: AddPathOptions,
+) -> impl Stream<Item = Result<AddEvent, AddError>> + Send + 'a
+where
+    Types: IpfsTypes,
+    MaybeOwned: Borrow<Ipfs<Types>> + Send + 'a,
+{
+    let path = path.into();
+
+    try_stream! {
+        let ipfs = ipfs.borrow();
+
+        let root = {
+            let path = path.clone();
+            let follow = opts.follow_symlinks;
+            blocking(move || stat(path, follow)).await
+        }
+        .map_err(|e| AddError::Filesystem(path.clone(), e))?;
+
+        let root_name = match &opts.add.wrap {
+            Some(name) => name.clone(),
+            None => root
+                .path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map(String::from)
+                .ok_or_else(|| AddError::InvalidPath(root.path.clone()))?,
+        };
+
+        let mut tree = StreamingTreeBuilder::new(opts.add.tree_options());
+        // the total number of bytes read from the files
+        let mut total_read = 0u64;
+        // the root level entry, when it is not a directory and not wrapped
+        let mut unwrapped_root = None;
+
+        // the pseudo directory containing only the root entry, followed by the directories on the
+        // path to the current entry
+        let mut stack = vec![Directory {
+            tree_path: String::new(),
+            entries: vec![(root_name, root)].into_iter(),
+            rules: None,
+            canonical: None,
+        }];
+
+        while let Some(directory) = stack.last_mut() {
+            let (name, entry) = match directory.entries.next() {
+                Some(next) => next,
+                None => {
+                    stack.pop();
+                    continue;
+                }
+            };
+
+            let tree_path = if directory.tree_path.is_empty() {
+                name.clone()
+            } else {
+                format!("{}/{}", directory.tree_path, name)
+            };
+
+            let is_root = stack.len() == 1;
+
+            if !is_root && is_ignored(&stack, &tree_path, entry.kind.is_directory()) {
+                continue;
+            }
+
+            let (completed, added) = match entry.kind {
+                Kind::File => {
+                    let metadata = entry.metadata(&opts);
+                    let file = tokio::fs::File::open(&entry.path)
+                        .await
+                        .map_err(|e| AddError::Filesystem(entry.path.clone(), e))?;
+
+                    let imported = import_file(ipfs, file, opts.add.file_adder(metadata));
+                    pin_mut!(imported);
+
+                    let mut done = None;
+
+                    while let Some(next) = imported.try_next().await.map_err(|e| match e {
+                        AddError::Reading(e) => AddError::Filesystem(entry.path.clone(), e),
+                        e => e,
+                    })? {
+                        match next {
+                            Imported::Progress(read) => {
+                                yield AddEvent::Progress(total_read + read);
+                            }
+                            Imported::Done { cid, total_size, read } => {
+                                total_read += read;
+                                done = Some((cid, total_size));
+                            }
+                        }
+                    }
+
+                    let (cid, total_size) = done.expect("import always ends in Done");
+                    let completed = tree.put_link(&tree_path, cid.clone(), total_size)?;
+
+                    (completed, Some((tree_path, cid, total_size)))
+                }
+                Kind::Symlink(ref target) => {
+                    let target = target
+                        .to_str()
+                        .ok_or_else(|| AddError::InvalidPath(entry.path.clone()))?;
+
+                    let mut block = Vec::new();
+                    serialize_symlink_block(target, &mut block);
+
+                    let cid = opts
+                        .add
+                        .cid_options
+                        .cid(cid::Codec::DagProtobuf, &block);
+                    let total_size = block.len() as u64;
+
+                    store_all(ipfs, std::iter::once((cid.clone(), block))).await?;
+
+                    let completed = tree.put_link(&tree_path, cid.clone(), total_size)?;
+
+                    (completed, Some((tree_path, cid, total_size)))
+                }
+                Kind::Directory => {
+                    if let Some(canonical) = &entry.canonical {
+                        if stack.iter().any(|dir| dir.canonical.as_ref() == Some(canonical)) {
+                            Err(AddError::SymlinkLoop(entry.path.clone()))?;
+                        }
+                    }
+
+                    let completed = tree.set_metadata(&tree_path, entry.metadata(&opts))?;
+
+                    let listing = {
+                        let path = entry.path.clone();
+                        let hidden = opts.hidden;
+                        let follow = opts.follow_symlinks;
+                        let ignore_files = opts.ignore_files.clone();
+                        blocking(move || list(path, hidden, follow, &ignore_files))
+                            .await
+                            .map_err(|e| AddError::Filesystem(entry.path.clone(), e))?
+                    };
+
+                    let mut entries = Vec::with_capacity(listing.entries.len());
+                    for (name, entry) in listing.entries {
+                        let name = name
+                            .into_string()
+                            .map_err(|_| AddError::InvalidPath(entry.path.clone()))?;
+                        entries.push((name, entry));
+                    }
+
+                    // the entries need to be added in the order of their names
+                    entries.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+                    let rules = if is_root {
+                        let lines = opts
+                            .ignore_rules
+                            .iter()
+                            .map(String::as_str)
+                            .chain(listing.ignore_rules.iter().flat_map(|s| s.lines()));
+                        IgnoreRules::parse(lines)
+                    } else {
+                        IgnoreRules::parse(listing.ignore_rules.iter().flat_map(|s| s.lines()))
+                    };
+
+                    stack.push(Directory {
+                        tree_path,
+                        entries: entries.into_iter(),
+                        rules: if rules.is_empty() { None } else { Some(rules) },
+                        canonical: entry.canonical,
+                    });
+
+                    (completed, None)
+                }
+                Kind::Unsupported => Err(AddError::UnsupportedFileType(entry.path.clone()))?,
+            };
+
+            for event in store_directories(ipfs, completed).await? {
+                yield event;
+            }
+
+            if let Some((name, cid, total_size)) = added {
+                let event = AddEvent::Added {
+                    name,
+                    cid,
+                    total_size,
+                };
+
+                if is_root && opts.add.wrap.is_none() {
+                    // the root is reported only once it has been pinned
+                    unwrapped_root = Some(event);
+                } else {
+                    yield event;
+                }
+            }
+        }
+
+        let mut events = store_directories(ipfs, tree.finish()?).await?;
+
+        let root = match events.pop().or(unwrapped_root) {
+            Some(root) => root,
+            None => unreachable!("the root entry is always added"),
+        };
+
+        for event in events {
+            yield event;
+        }
+
+        if let AddEvent::Added { cid, .. } = &root {
+            pin_root(ipfs, &opts.add, cid).await?;
+        }
+
+        yield root;
+    }
+}
+
+/// A directory on the path to the current entry.
+struct Directory {
+    /// The path of the directory in the tree, starting with the name of the root entry.
+    tree_path: String,
+    /// The remaining entries, in the order of their names.
+    entries: std::vec::IntoIter<(String, Entry)>,
+    /// The rules read from the ignore files of this directory.
+    rules: Option<IgnoreRules>,
+    /// The canonical path of the directory when following symlinks, used to detect loops.
+    canonical: Option<PathBuf>,
+}
+
+/// An entry found on the filesystem.
+struct Entry {
+    path: PathBuf,
+    kind: Kind,
+    metadata: std::fs::Metadata,
+    /// The canonical path of a directory when following symlinks.
+    canonical: Option<PathBuf>,
+}
+
+enum Kind {
+    File,
+    Directory,
+    Symlink(PathBuf),
+    Unsupported,
+}
+
+impl Kind {
+    fn is_directory(&self) -> bool {
+        matches!(self, Kind::Directory)
+    }
+}
+
+impl Entry {
+    /// Returns the metadata to store for the entry.
+    fn metadata(&self, opts: &AddPathOptions) -> Metadata {
+        let mut metadata = Metadata::default();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            if opts.preserve_mode {
+                // like go-ipfs, only the permission bits are stored
+                metadata = metadata.with_mode(self.metadata.permissions().mode() & 0o7777);
+            }
+        }
+
+        if opts.preserve_mtime {
+            if let Ok(modified) = self.metadata.modified() {
+                let (seconds, nanos) = match modified.duration_since(std::time::UNIX_EPOCH) {
+                    Ok(after) => (after.as_secs() as i64, after.subsec_nanos()),
+                    Err(e) => {
+                        // the nanoseconds are always counted forward from the seconds
+                        let before = e.duration();
+                        match before.subsec_nanos() {
+                            0 => (-(before.as_secs() as i64), 0),
+                            nanos => (-(before.as_secs() as i64) - 1, 1_000_000_000 - nanos),
+                        }
+                    }
+                };
+
+                metadata = metadata.with_mtime(seconds, nanos);
+            }
+        }
+
+        metadata
+    }
+}
+
+/// The entries of a directory and the contents of its ignore files.
+struct Listing {
+    entries: Vec<(std::ffi::OsString, Entry)>,
+    ignore_rules: Vec<String>,
+}
+
+fn stat(path: PathBuf, follow: bool) -> io::Result<Entry> {
+    let mut metadata = std::fs::symlink_metadata(&path)?;
+    let mut target = None;
+
+    if metadata.file_type().is_symlink() {
+        if follow {
+            metadata = std::fs::metadata(&path)?;
+        } else {
+            target = Some(std::fs::read_link(&path)?);
+        }
+    }
+
+    let kind = match target {
+        Some(target) => Kind::Symlink(target),
+        None if metadata.is_dir() => Kind::Directory,
+        None if metadata.is_file() => Kind::File,
+        None => Kind::Unsupported,
+    };
+
+    let canonical = if follow && kind.is_directory() {
+        Some(std::fs::canonicalize(&path)?)
+    } else {
+        None
+    };
+
+    Ok(Entry {
+        path,
+        kind,
+        metadata,
+        canonical,
+    })
+}
+
+fn list(path: PathBuf, hidden: bool, follow: bool, ignore_files: &[String]) -> io::Result<Listing> {
+    let mut entries = Vec::new();
+
+    for dirent in std::fs::read_dir(&path)? {
+        let dirent = dirent?;
+        let name = dirent.file_name();
+
+        if !hidden && name.to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        entries.push((name, stat(dirent.path(), follow)?));
+    }
+
+    let mut ignore_rules = Vec::new();
+
+    for name in ignore_files {
+        match std::fs::read_to_string(path.join(name)) {
+            Ok(rules) => ignore_rules.push(rules),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(Listing {
+        entries,
+        ignore_rules,
+    })
+}
+
+/// Returns true if the rules of the closest directory with a matching rule ignore the path.
+fn is_ignored(stack: &[Directory], tree_path: &str, is_dir: bool) -> bool {
+    stack
+        .iter()
+        .rev()
+        .filter_map(|dir| dir.rules.as_ref().map(|rules| (dir, rules)))
+        .find_map(|(dir, rules)| rules.matches(&tree_path[dir.tree_path.len() + 1..], is_dir))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AddPathOptions;
+    use crate::unixfs::{added_root, AddError, AddEvent, AddOptions};
+    use crate::{Cid, Ipfs, Node};
+    use futures::stream::TryStreamExt;
+    use ipfs_unixfs::walk::{ContinuedWalk, Walker};
+    use std::fs;
+    use std::path::Path;
+
+    #[tokio::test(max_threads = 1)]
+    async fn add_directory_tree() {
+        let ipfs = Node::new("test_node").await;
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("tree");
+
+        fs::create_dir_all(root.join("b/d")).unwrap();
+        fs::write(root.join("a.txt"), b"a\n").unwrap();
+        fs::write(root.join("b/c.txt"), b"c\n").unwrap();
+        fs::write(root.join(".hidden"), b"hidden\n").unwrap();
+
+        let events = add(&ipfs, &root, AddPathOptions::default()).await.unwrap();
+
+        let mut names = added_names(&events);
+        assert_eq!(names.last(), Some(&"tree"));
+        names.sort_unstable();
+        assert_eq!(
+            names,
+            &["tree", "tree/a.txt", "tree/b", "tree/b/c.txt", "tree/b/d"]
+        );
+
+        let root_cid = added_root(&events);
+        assert!(ipfs.is_pinned(&root_cid).await.unwrap());
+
+        assert_eq!(
+            walk(&ipfs, &root_cid).await,
+            &["", "a.txt", "b", "b/c.txt", "b/d"]
+        );
+
+        let path = crate::IpfsPath::from(root_cid).sub_path("b/c.txt").unwrap();
+        let bytes = ipfs
+            .cat_unixfs(path, None)
+            .await
+            .unwrap()
+            .try_concat()
+            .await
+            .unwrap();
+
+        assert_eq!(bytes, b"c\n");
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn add_hidden() {
+        let ipfs = Node::new("test_node").await;
+        let tmp = tempfile::tempdir().unwrap();
+
+        fs::create_dir(tmp.path().join(".dir")).unwrap();
+        fs::write(tmp.path().join(".dir/a.txt"), b"a\n").unwrap();
+        fs::write(tmp.path().join(".hidden"), b"hidden\n").unwrap();
+
+        let opts = AddPathOptions {
+            hidden: true,
+            ..Default::default()
+        };
+
+        let events = add(&ipfs, tmp.path(), opts).await.unwrap();
+
+        assert_eq!(
+            walk(&ipfs, &added_root(&events)).await,
+            &["", ".dir", ".dir/a.txt", ".hidden"]
+        );
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn ignore_files_and_rules() {
+        let ipfs = Node::new("test_node").await;
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        fs::create_dir_all(root.join("b/target")).unwrap();
+        fs::create_dir_all(root.join("target")).unwrap();
+        fs::write(root.join(".gitignore"), b"*.log\n/target/\n").unwrap();
+        fs::write(root.join("b/.gitignore"), b"!keep.log\n").unwrap();
+
+        for file in &["a.log", "a.txt", "a.tmp", "b/keep.log", "b/other.log"] {
+            fs::write(root.join(file), b"content\n").unwrap();
+        }
+
+        let opts = AddPathOptions {
+            ignore_files: vec![".gitignore".into()],
+            ignore_rules: vec!["*.tmp".into()],
+            ..Default::default()
+        };
+
+        let events = add(&ipfs, root, opts).await.unwrap();
+
+        assert_eq!(
+            walk(&ipfs, &added_root(&events)).await,
+            &["", "a.txt", "b", "b/keep.log", "b/target"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test(max_threads = 1)]
+    async fn symlinks() {
+        let ipfs = Node::new("test_node").await;
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        fs::write(root.join("a.txt"), b"a\n").unwrap();
+        std::os::unix::fs::symlink("a.txt", root.join("link")).unwrap();
+
+        let events = add(&ipfs, root, AddPathOptions::default()).await.unwrap();
+        let root_cid = added_root(&events);
+        assert_eq!(
+            walk(&ipfs, &root_cid).await,
+            &["", "a.txt", "link -> a.txt"]
+        );
+
+        let opts = AddPathOptions {
+            follow_symlinks: true,
+            ..Default::default()
+        };
+
+        let events = add(&ipfs, root, opts.clone()).await.unwrap();
+        let root_cid = added_root(&events);
+        assert_eq!(walk(&ipfs, &root_cid).await, &["", "a.txt", "link"]);
+
+        fs::create_dir(root.join("b")).unwrap();
+        std::os::unix::fs::symlink("..", root.join("b/loop")).unwrap();
+
+        match add(&ipfs, root, opts).await {
+            Err(AddError::SymlinkLoop(path)) => assert_eq!(path, root.join("b/loop")),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test(max_threads = 1)]
+    async fn preserved_metadata() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let ipfs = Node::new("test_node").await;
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        fs::write(root.join("a.txt"), b"a\n").unwrap();
+        fs::set_permissions(root.join("a.txt"), fs::Permissions::from_mode(0o640)).unwrap();
+
+        let opts = AddPathOptions {
+            preserve_mode: true,
+            preserve_mtime: true,
+            ..Default::default()
+        };
+
+        let events = add(&ipfs, root.join("a.txt"), opts).await.unwrap();
+        let root_cid = added_root(&events);
+
+        let block = ipfs.get_block(&root_cid).await.unwrap();
+        let mut walker = Walker::new(root_cid, String::new());
+
+        match walker.next(&block.data, &mut None).unwrap() {
+            ContinuedWalk::File(_, _, _, metadata, _) => {
+                assert_eq!(metadata.mode(), Some(0o640));
+                assert!(metadata.mtime().is_some());
+            }
+            other => panic!("unexpected walk: {:?}", other),
+        }
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn single_file_is_the_same_as_added_from_reader() {
+        let ipfs = Node::new("test_node").await;
+        let tmp = tempfile::tempdir().unwrap();
+
+        fs::write(tmp.path().join("a.txt"), b"foobar\n").unwrap();
+
+        let events = add(&ipfs, tmp.path().join("a.txt"), AddPathOptions::default())
+            .await
+            .unwrap();
+
+        assert_eq!(added_names(&events), &["a.txt"]);
+        let root_cid = added_root(&events);
+        assert!(ipfs.is_pinned(&root_cid).await.unwrap());
+
+        let expected = ipfs
+            .add_unixfs(&b"foobar\n"[..], AddOptions::default())
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        assert_eq!(root_cid, added_root(&expected));
+    }
+
+    async fn add(
+        ipfs: &Ipfs<crate::TestTypes>,
+        path: impl AsRef<Path>,
+        opts: AddPathOptions,
+    ) -> Result<Vec<AddEvent>, AddError> {
+        ipfs.add_path(path.as_ref(), opts).try_collect().await
+    }
+
+    fn added_names(events: &[AddEvent]) -> Vec<&str> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                AddEvent::Added { name, .. } => Some(name.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns the paths of the walked tree, with the targets of the symlinks.
+    async fn walk(ipfs: &Ipfs<crate::TestTypes>, root: &Cid) -> Vec<String> {
+        let mut walker = Walker::new(root.clone(), String::new());
+        let mut paths = Vec::new();
+
+        while walker.should_continue() {
+            let (next, _) = walker.pending_links();
+            let block = ipfs.get_block(next).await.unwrap();
+
+            let path = match walker.next(&block.data, &mut None).unwrap() {
+                ContinuedWalk::File(segment, _, path, ..) if segment.is_first() => {
+                    path.to_string_lossy().into_owned()
+                }
+                ContinuedWalk::Directory(_, path, _) | ContinuedWalk::RootDirectory(_, path, _) => {
+                    path.to_string_lossy().into_owned()
+                }
+                ContinuedWalk::Symlink(target, _, path, _) => format!(
+                    "{} -> {}",
+                    path.to_string_lossy(),
+                    String::from_utf8_lossy(target)
+                ),
+                _ => continue,
+            };
+
+            paths.push(path);
+        }
+
+        paths
+    }
+}
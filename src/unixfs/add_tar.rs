@@ -0,0 +1,686 @@
+use super::add::{import_file, pin_root, store_all, store_directories, Imported};
+use super::{AddError, AddEvent, AddOptions};
+use crate::{Ipfs, IpfsTypes};
+use async_stream::try_stream;
+use cid::Cid;
+use futures::pin_mut;
+use futures::stream::{Stream, TryStreamExt};
+use ipfs_unixfs::dir::builder::BufferingTreeBuilder;
+use ipfs_unixfs::symlink::serialize_symlink_block;
+use ipfs_unixfs::Metadata;
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use tar::{EntryType, Header};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// The size of the tar headers and the alignment of the entries.
+const BLOCK_SIZE: u64 = 512;
+
+/// The largest accepted long name or PAX extended header.
+const EXTENSION_LIMIT: u64 = 1024 * 1024;
+
+/// Options for adding a tar archive with [`add_tar`].
+#[derive(Debug, Clone, Default)]
+pub struct AddTarOptions {
+    /// Options for the files, and for pinning the root. When `AddOptions::wrap` is given, the
+    /// entries of the archive are placed in a directory of that name within the root.
+    pub add: AddOptions,
+    /// Store the permission bits of the entries.
+    pub preserve_mode: bool,
+    /// Store the modification times of the entries.
+    pub preserve_mtime: bool,
+}
+
+/// IPFS add operation for a tar archive, storing its files, directories and symlinks as an UnixFS
+/// tree. This is generic over the different kinds of ways to own an `Ipfs` value in the same way
+/// as [`super::cat`].
+///
+/// The archive is read as a stream, and the contents of the files are stored as they are read.
+/// The root is always a directory containing the top level entries of the archive. The hard links
+/// are stored as additional links to the files they point to, while the other kinds of special
+/// files are not supported.
+///
+/// Returns a stream of [`AddEvent`]s, where the last `AddEvent::Added` has the root Cid. The tree
+/// has been added only once the stream has been driven to completion.
+pub fn add_tar<'a, Types, MaybeOwned>(
+    ipfs: MaybeOwned,
+    mut input: impl AsyncRead + Send + Unpin + 'a,
+    opts: AddTarOptions,
+) -> impl Stream<Item = Result<AddEvent, AddError>> + Send + 'a
+where
+    Types: IpfsTypes,
+    MaybeOwned: Borrow<Ipfs<Types>> + Send + 'a,
+{
+    try_stream! {
+        let ipfs = ipfs.borrow();
+
+        let mut tree_opts = opts.add.tree_options();
+        tree_opts.wrap_with_directory();
+        let mut tree = BufferingTreeBuilder::new(tree_opts);
+
+        // the files added so far, for the hard links
+        let mut files = HashMap::<String, (Cid, u64)>::new();
+        // the total number of bytes read from the files
+        let mut total_read = 0u64;
+        // the extensions read for the next entry
+        let mut extensions = Extensions::default();
+        let mut header = [0u8; BLOCK_SIZE as usize];
+
+        while read_header(&mut input, &mut header).await? {
+            let header = Header::from_byte_slice(&header);
+            verify_checksum(header)?;
+
+            let entry_type = header.entry_type();
+            let size = match extensions.size.take() {
+                Some(size) => size,
+                None => header.entry_size().map_err(invalid_archive)?,
+            };
+
+            match entry_type {
+                EntryType::GNULongName | EntryType::GNULongLink | EntryType::XHeader => {
+                    let data = read_extension(&mut input, size).await?;
+
+                    match entry_type {
+                        EntryType::GNULongName => extensions.path = Some(trim_nul(data)),
+                        EntryType::GNULongLink => extensions.link_name = Some(trim_nul(data)),
+                        _ => extensions.apply_pax(&data)?,
+                    }
+                    continue;
+                }
+                EntryType::XGlobalHeader => {
+                    // nothing of interest is stored in the global headers
+                    skip(&mut input, padded(size)).await?;
+                    continue;
+                }
+                _ => {}
+            }
+
+            let path_bytes = match extensions.path.take() {
+                Some(path) => path,
+                None => header.path_bytes().into_owned(),
+            };
+            let link_name = match extensions.link_name.take() {
+                Some(link_name) => Some(link_name),
+                None => header.link_name_bytes().map(|name| name.into_owned()),
+            };
+            let metadata = extensions.metadata(header, &opts)?;
+            extensions = Extensions::default();
+
+            let path = normalize(&path_bytes)?;
+            let tree_path = match (&opts.add.wrap, &path) {
+                (Some(wrap), Some(path)) => format!("{}/{}", wrap, path),
+                (Some(wrap), None) => wrap.clone(),
+                (None, Some(path)) => path.clone(),
+                (None, None) => String::new(),
+            };
+
+            let is_directory = entry_type == EntryType::Directory
+                || (entry_type == EntryType::Regular && path_bytes.ends_with(b"/"));
+
+            if is_directory {
+                skip(&mut input, padded(size)).await?;
+
+                if !tree_path.is_empty() {
+                    tree.set_metadata(&tree_path, metadata)?;
+                }
+                continue;
+            }
+
+            let path = path.ok_or_else(|| {
+                let path = String::from_utf8_lossy(&path_bytes);
+                invalid_archive(format!("{:?} is not a directory", path))
+            })?;
+
+            let (cid, total_size) = match entry_type {
+                EntryType::Regular | EntryType::Continuous => {
+                    let mut done = None;
+
+                    // the import borrows the input until it has been completed
+                    {
+                        let imported = import_file(
+                            ipfs,
+                            (&mut input).take(size),
+                            opts.add.file_adder(metadata),
+                        );
+                        pin_mut!(imported);
+
+                        while let Some(next) = imported.try_next().await? {
+                            match next {
+                                Imported::Progress(read) => {
+                                    yield AddEvent::Progress(total_read + read);
+                                }
+                                Imported::Done { cid, total_size, read } => {
+                                    total_read += read;
+                                    done = Some((cid, total_size, read));
+                                }
+                            }
+                        }
+                    }
+
+                    let (cid, total_size, read) = done.expect("import always ends in Done");
+
+                    if read != size {
+                        Err(AddError::Reading(io::ErrorKind::UnexpectedEof.into()))?;
+                    }
+
+                    skip(&mut input, padded(size) - size).await?;
+
+                    files.insert(path.clone(), (cid.clone(), total_size));
+                    (cid, total_size)
+                }
+                EntryType::Link => {
+                    skip(&mut input, padded(size)).await?;
+
+                    let target = link_name
+                        .as_deref()
+                        .map(normalize)
+                        .transpose()?
+                        .flatten()
+                        .and_then(|target| files.get(&target))
+                        .cloned()
+                        .ok_or_else(|| {
+                            invalid_archive(format!("hard link {:?} to an unknown file", path))
+                        })?;
+
+                    files.insert(path.clone(), target.clone());
+                    target
+                }
+                EntryType::Symlink => {
+                    skip(&mut input, padded(size)).await?;
+
+                    let target = link_name
+                        .as_deref()
+                        .map(std::str::from_utf8)
+                        .transpose()
+                        .ok()
+                        .flatten()
+                        .ok_or_else(|| {
+                            invalid_archive(format!("symlink {:?} has an invalid target", path))
+                        })?;
+
+                    let mut block = Vec::new();
+                    serialize_symlink_block(target, &mut block);
+
+                    let cid = opts
+                        .add
+                        .cid_options
+                        .cid(cid::Codec::DagProtobuf, &block);
+                    let total_size = block.len() as u64;
+
+                    store_all(ipfs, std::iter::once((cid.clone(), block))).await?;
+
+                    (cid, total_size)
+                }
+                _ => Err(AddError::UnsupportedFileType(PathBuf::from(path.clone())))?,
+            };
+
+            tree.put_link(&tree_path, cid.clone(), total_size)?;
+
+            yield AddEvent::Added {
+                name: tree_path,
+                cid,
+                total_size,
+            };
+        }
+
+        let nodes = tree.build().collect::<Result<Vec<_>, _>>()?;
+        let mut events = store_directories(ipfs, nodes).await?;
+
+        let root = match events.pop() {
+            Some(root) => root,
+            None => unreachable!("the wrapping directory is always rendered"),
+        };
+
+        for event in events {
+            yield event;
+        }
+
+        if let AddEvent::Added { cid, .. } = &root {
+            pin_root(ipfs, &opts.add, cid).await?;
+        }
+
+        yield root;
+    }
+}
+
+/// The values of the GNU long name headers and the PAX extended headers, which apply to the next
+/// entry.
+#[derive(Default)]
+struct Extensions {
+    path: Option<Vec<u8>>,
+    link_name: Option<Vec<u8>>,
+    size: Option<u64>,
+    mtime: Option<(i64, u32)>,
+}
+
+impl Extensions {
+    fn apply_pax(&mut self, data: &[u8]) -> Result<(), AddError> {
+        let mut rest = data;
+
+        while !rest.is_empty() {
+            // the records are "<length> <key>=<value>\n", where the length includes itself
+            let (key, value, next) =
+                pax_record(rest).ok_or_else(|| invalid_archive("malformed pax extended header"))?;
+
+            match key {
+                b"path" => self.path = Some(value.to_vec()),
+                b"linkpath" => self.link_name = Some(value.to_vec()),
+                b"size" => {
+                    self.size = Some(
+                        parse_utf8::<u64>(value)
+                            .ok_or_else(|| invalid_archive("invalid pax size"))?,
+                    )
+                }
+                b"mtime" => {
+                    self.mtime = Some(
+                        parse_mtime(value).ok_or_else(|| invalid_archive("invalid pax mtime"))?,
+                    )
+                }
+                _ => {}
+            }
+
+            rest = next;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the metadata to store for the entry.
+    fn metadata(&self, header: &Header, opts: &AddTarOptions) -> Result<Metadata, AddError> {
+        let mut metadata = Metadata::default();
+
+        if opts.preserve_mode {
+            // like go-ipfs, only the permission bits are stored
+            metadata = metadata.with_mode(header.mode().map_err(invalid_archive)? & 0o7777);
+        }
+
+        if opts.preserve_mtime {
+            let (seconds, nanos) = match self.mtime {
+                Some(mtime) => mtime,
+                None => (header.mtime().map_err(invalid_archive)? as i64, 0),
+            };
+            metadata = metadata.with_mtime(seconds, nanos);
+        }
+
+        Ok(metadata)
+    }
+}
+
+/// Splits the first PAX record into the key, the value and the rest of the records.
+fn pax_record(data: &[u8]) -> Option<(&[u8], &[u8], &[u8])> {
+    let space = data.iter().position(|&b| b == b' ')?;
+    let len = parse_utf8::<usize>(&data[..space])?;
+
+    if len <= space || len > data.len() || data[len - 1] != b'\n' {
+        return None;
+    }
+
+    let record = &data[space + 1..len - 1];
+    let equals = record.iter().position(|&b| b == b'=')?;
+
+    Some((&record[..equals], &record[equals + 1..], &data[len..]))
+}
+
+fn parse_utf8<T: std::str::FromStr>(value: &[u8]) -> Option<T> {
+    std::str::from_utf8(value).ok()?.parse().ok()
+}
+
+/// Parses the PAX mtime of seconds with an optional fraction, like `1600000000.5`.
+fn parse_mtime(value: &[u8]) -> Option<(i64, u32)> {
+    let value = std::str::from_utf8(value).ok()?;
+
+    let (seconds, fraction) = match value.find('.') {
+        Some(dot) => (&value[..dot], &value[dot + 1..]),
+        None => (value, ""),
+    };
+
+    let seconds = seconds.parse::<i64>().ok()?;
+
+    if !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    // only the nanoseconds are kept
+    let digits = &fraction[..fraction.len().min(9)];
+    let nanos = format!("{:0<9}", digits).parse::<u32>().ok()?;
+
+    if seconds < 0 && nanos > 0 {
+        // the nanoseconds are always counted forward from the seconds
+        Some((seconds - 1, 1_000_000_000 - nanos))
+    } else {
+        Some((seconds, nanos))
+    }
+}
+
+/// Returns the path of the entry relative to the root, without the empty and `.` segments. The
+/// root itself has no path.
+fn normalize(path: &[u8]) -> Result<Option<String>, AddError> {
+    let path = std::str::from_utf8(path).map_err(|_| {
+        invalid_archive(format!("non-utf8 path {:?}", String::from_utf8_lossy(path)))
+    })?;
+
+    let mut segments = Vec::new();
+
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                return Err(invalid_archive(format!(
+                    "path {:?} is outside of the archive",
+                    path
+                )))
+            }
+            segment => segments.push(segment),
+        }
+    }
+
+    if segments.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(segments.join("/")))
+    }
+}
+
+fn trim_nul(mut data: Vec<u8>) -> Vec<u8> {
+    while data.last() == Some(&0) {
+        data.pop();
+    }
+    data
+}
+
+fn verify_checksum(header: &Header) -> Result<(), AddError> {
+    let bytes = header.as_bytes();
+
+    // the checksum is calculated with the checksum field filled with spaces
+    let expected = bytes[..148]
+        .iter()
+        .chain(&[b' '; 8])
+        .chain(&bytes[156..])
+        .map(|&b| b as u32)
+        .sum::<u32>();
+
+    if header.cksum().map_err(invalid_archive)? != expected {
+        return Err(invalid_archive("header checksum mismatch"));
+    }
+
+    Ok(())
+}
+
+/// Reads the next header, returning false at the end of the archive: at the first zeroed block
+/// or at the end of the input.
+async fn read_header(
+    input: &mut (impl AsyncRead + Unpin),
+    header: &mut [u8; BLOCK_SIZE as usize],
+) -> Result<bool, AddError> {
+    let mut filled = 0;
+
+    while filled < header.len() {
+        match input.read(&mut header[filled..]).await {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(AddError::Reading(io::ErrorKind::UnexpectedEof.into())),
+            Ok(n) => filled += n,
+            Err(e) => return Err(AddError::Reading(e)),
+        }
+    }
+
+    Ok(header.iter().any(|&b| b != 0))
+}
+
+/// Reads the data of a long name or PAX extended header entry.
+async fn read_extension(
+    input: &mut (impl AsyncRead + Unpin),
+    size: u64,
+) -> Result<Vec<u8>, AddError> {
+    if size > EXTENSION_LIMIT {
+        return Err(invalid_archive("too large extended header"));
+    }
+
+    let mut data = vec![0u8; size as usize];
+    input
+        .read_exact(&mut data)
+        .await
+        .map_err(AddError::Reading)?;
+
+    skip(input, padded(size) - size).await?;
+
+    Ok(data)
+}
+
+/// Reads and discards the bytes.
+async fn skip(input: &mut (impl AsyncRead + Unpin), amount: u64) -> Result<(), AddError> {
+    let skipped = tokio::io::copy(&mut input.take(amount), &mut tokio::io::sink())
+        .await
+        .map_err(AddError::Reading)?;
+
+    if skipped != amount {
+        return Err(AddError::Reading(io::ErrorKind::UnexpectedEof.into()));
+    }
+
+    Ok(())
+}
+
+/// Returns the size rounded up to the next block.
+fn padded(size: u64) -> u64 {
+    match size % BLOCK_SIZE {
+        0 => size,
+        rem => size + (BLOCK_SIZE - rem),
+    }
+}
+
+fn invalid_archive(reason: impl ToString) -> AddError {
+    AddError::InvalidArchive(reason.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize, parse_mtime, AddTarOptions};
+    use crate::unixfs::{added_root, AddEvent, AddOptions};
+    use crate::Node;
+    use futures::stream::TryStreamExt;
+    use ipfs_unixfs::dir::EntryType;
+
+    fn archive() -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_mode(0o755);
+        header.set_mtime(1_600_000_000);
+        header.set_size(0);
+        builder.append_data(&mut header, "./dir/", &[][..]).unwrap();
+
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_mtime(1_600_000_000);
+        header.set_size(7);
+        builder
+            .append_data(&mut header, "./dir/a.txt", &b"foobar\n"[..])
+            .unwrap();
+
+        // long enough to require a GNU long name entry
+        let long = format!("dir/{}.txt", "b".repeat(120));
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_size(4);
+        builder
+            .append_data(&mut header, &long, &b"bar\n"[..])
+            .unwrap();
+
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_mode(0o777);
+        header.set_size(0);
+        header.set_link_name("a.txt").unwrap();
+        builder.append_data(&mut header, "dir/c", &[][..]).unwrap();
+
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Link);
+        header.set_mode(0o644);
+        header.set_size(0);
+        header.set_link_name("dir/a.txt").unwrap();
+        builder.append_data(&mut header, "d.txt", &[][..]).unwrap();
+
+        builder.into_inner().unwrap()
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn add_archive() {
+        let ipfs = Node::new("test_node").await;
+        let archive = archive();
+
+        let opts = AddTarOptions {
+            preserve_mode: true,
+            preserve_mtime: true,
+            ..Default::default()
+        };
+
+        let events = ipfs
+            .add_tar(&archive[..], opts)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        let root = added_root(&events);
+        assert!(ipfs.is_pinned(&root).await.unwrap());
+
+        let names = events
+            .iter()
+            .filter_map(|e| match e {
+                AddEvent::Added { name, .. } => Some(name.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        let long = format!("dir/{}.txt", "b".repeat(120));
+        assert_eq!(names, vec!["dir/a.txt", &long, "dir/c", "d.txt", "dir", ""]);
+
+        let entries = ipfs
+            .ls(root.clone(), true)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].name, "d.txt");
+        assert_eq!(entries[0].entry_type, Some(EntryType::File(7)));
+        assert_eq!(entries[1].name, "dir");
+
+        let dir = ipfs
+            .ls(entries[1].cid.clone(), true)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        assert_eq!(dir.len(), 3);
+        assert_eq!(dir[0].cid, entries[0].cid, "hard link shares the file");
+        assert_eq!(dir[2].name, "c");
+        assert_eq!(
+            dir[2].entry_type,
+            Some(EntryType::Symlink(b"a.txt".to_vec()))
+        );
+
+        let bytes = ipfs
+            .cat_unixfs(dir[1].cid.clone(), None)
+            .await
+            .unwrap()
+            .try_concat()
+            .await
+            .unwrap();
+        assert_eq!(bytes, b"bar\n");
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn metadata_changes_cids() {
+        let ipfs = Node::new("test_node").await;
+        let archive = archive();
+
+        let plain = ipfs
+            .add_tar(&archive[..], AddTarOptions::default())
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        let opts = AddTarOptions {
+            preserve_mtime: true,
+            ..Default::default()
+        };
+
+        let with_mtime = ipfs
+            .add_tar(&archive[..], opts)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        assert_ne!(added_root(&plain), added_root(&with_mtime));
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn wrapped_and_empty_archives() {
+        let ipfs = Node::new("test_node").await;
+
+        let empty = tar::Builder::new(Vec::new()).into_inner().unwrap();
+
+        let events = ipfs
+            .add_tar(&empty[..], AddTarOptions::default())
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            added_root(&events).to_string(),
+            "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn"
+        );
+
+        let opts = AddTarOptions {
+            add: AddOptions {
+                wrap: Some("archive".into()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let archive = archive();
+        let events = ipfs
+            .add_tar(&archive[..], opts)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        assert!(events
+            .iter()
+            .any(|e| matches!(e, AddEvent::Added { name, .. } if name == "archive/dir/a.txt")));
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn truncated_archive() {
+        let ipfs = Node::new("test_node").await;
+        let archive = archive();
+
+        let res = ipfs
+            .add_tar(&archive[..1000], AddTarOptions::default())
+            .try_collect::<Vec<_>>()
+            .await;
+
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn normalized_paths() {
+        assert_eq!(normalize(b"./a//b/").unwrap().as_deref(), Some("a/b"));
+        assert_eq!(normalize(b"./").unwrap(), None);
+        assert!(normalize(b"a/../../b").is_err());
+    }
+
+    #[test]
+    fn pax_mtimes() {
+        assert_eq!(parse_mtime(b"1600000000"), Some((1_600_000_000, 0)));
+        assert_eq!(
+            parse_mtime(b"1600000000.5"),
+            Some((1_600_000_000, 500_000_000))
+        );
+        assert_eq!(parse_mtime(b"-1.25"), Some((-2, 750_000_000)));
+        assert_eq!(parse_mtime(b"1.x"), None);
+    }
+}
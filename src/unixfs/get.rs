@@ -0,0 +1,429 @@
+use super::{blocking, StartingPoint};
+use crate::{
+    dag::{ResolveError, UnexpectedResolved},
+    Error, Ipfs, IpfsTypes,
+};
+use bitswap::Block;
+use cid::Cid;
+use ipfs_unixfs::walk::{self, ContinuedWalk, Walker};
+use ipfs_unixfs::Metadata;
+use std::collections::HashSet;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+
+/// IPFS get operation, writing the UnixFS file, directory tree or symlink to the filesystem, like
+/// `ipfs get -o target`. The root entry is written to the `target` path, which can be an existing
+/// directory when the root is a directory.
+///
+/// The paths of the entries are checked not to escape the `target`, and the entries are only
+/// written to the directories created during the operation. Existing files are never overwritten.
+/// The stored modes and modification times are applied after all of the entries have been
+/// written.
+pub async fn get<Types: IpfsTypes>(
+    ipfs: &Ipfs<Types>,
+    starting_point: impl Into<StartingPoint>,
+    target: impl Into<PathBuf>,
+) -> Result<(), GetError> {
+    let target = target.into();
+
+    let Block { cid, data } = match starting_point.into() {
+        StartingPoint::Left(path) => {
+            let (resolved, _) = ipfs
+                .dag()
+                .resolve(path, true)
+                .await
+                .map_err(GetError::Resolving)?;
+            resolved.into_unixfs_block().map_err(GetError::Path)?
+        }
+        StartingPoint::Right(block) => block,
+    };
+
+    let mut walker = Walker::new(cid, String::new());
+    let mut cache = None;
+    let mut buffer = Some(data);
+
+    // the directories created so far, relative to the target
+    let mut created = HashSet::new();
+    // the file being written
+    let mut file = None;
+    // the metadata to apply once everything has been written
+    let mut deferred = Vec::new();
+
+    while walker.should_continue() {
+        let data = match buffer.take() {
+            Some(first) => first,
+            None => {
+                let (next, _) = walker.pending_links();
+                let next = next.to_owned();
+                ipfs.get_block(&next)
+                    .await
+                    .map_err(|e| GetError::Loading(next, e))?
+                    .data
+            }
+        };
+
+        match walker.next(&data, &mut cache)? {
+            ContinuedWalk::Bucket(..) => {}
+            ContinuedWalk::File(segment, _, path, metadata, _) => {
+                if segment.is_first() {
+                    let path = target_path(&target, path, &created)
+                        .ok_or_else(|| GetError::InvalidPath(path.to_owned()))?;
+
+                    let opened = tokio::fs::OpenOptions::new()
+                        .write(true)
+                        .create_new(true)
+                        .open(&path)
+                        .await
+                        .map_err(|e| GetError::Filesystem(path.clone(), e))?;
+
+                    defer(&mut deferred, &path, metadata, false);
+                    file = Some((path, opened));
+                }
+
+                let (path, opened) = file.as_mut().expect("file is opened on the first segment");
+
+                opened
+                    .write_all(segment.as_bytes())
+                    .await
+                    .map_err(|e| GetError::Filesystem(path.clone(), e))?;
+
+                if segment.is_last() {
+                    let (path, mut opened) = file.take().unwrap();
+                    opened
+                        .flush()
+                        .await
+                        .map_err(|e| GetError::Filesystem(path, e))?;
+                }
+            }
+            ContinuedWalk::Directory(_, path, metadata)
+            | ContinuedWalk::RootDirectory(_, path, metadata) => {
+                let relative = path.to_owned();
+                let path = target_path(&target, path, &created)
+                    .ok_or_else(|| GetError::InvalidPath(path.to_owned()))?;
+
+                create_dir(&path)
+                    .await
+                    .map_err(|e| GetError::Filesystem(path.clone(), e))?;
+
+                defer(&mut deferred, &path, metadata, false);
+                created.insert(relative);
+            }
+            ContinuedWalk::Symlink(bytes, _, path, metadata) => {
+                let path = target_path(&target, path, &created)
+                    .ok_or_else(|| GetError::InvalidPath(path.to_owned()))?;
+                let link_target = std::str::from_utf8(bytes)
+                    .map_err(|_| GetError::NonUtf8Symlink(path.clone()))?;
+
+                symlink(link_target, &path)
+                    .await
+                    .map_err(|e| GetError::Filesystem(path.clone(), e))?;
+
+                defer(&mut deferred, &path, metadata, true);
+            }
+        }
+    }
+
+    // the entries were walked parents first, so the children are updated before the parents
+    blocking(move || {
+        deferred
+            .iter()
+            .rev()
+            .try_for_each(|(path, metadata, is_symlink)| {
+                apply_metadata(path, metadata, *is_symlink).map_err(|e| (path.to_owned(), e))
+            })
+            .map_err(|(path, e)| io::Error::new(e.kind(), format!("{:?}: {}", path, e)))
+    })
+    .await
+    .map_err(|e| GetError::Filesystem(target, e))
+}
+
+/// Returns the path of the entry under the target, or `None` if the path would escape the
+/// target or be written to a directory not created while walking.
+fn target_path(target: &Path, path: &Path, created: &HashSet<PathBuf>) -> Option<PathBuf> {
+    if path.as_os_str().is_empty() {
+        return Some(target.to_owned());
+    }
+
+    let valid = path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+
+    let parent_created = path
+        .parent()
+        .map(|parent| created.contains(parent))
+        .unwrap_or(false);
+
+    if valid && parent_created {
+        Some(target.join(path))
+    } else {
+        None
+    }
+}
+
+/// Creates the directory, allowing existing directories but not symlinks to directories.
+async fn create_dir(path: &Path) -> io::Result<()> {
+    match tokio::fs::create_dir(path).await {
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            let metadata = tokio::fs::symlink_metadata(path).await?;
+            if metadata.is_dir() {
+                Ok(())
+            } else {
+                Err(e)
+            }
+        }
+        other => other,
+    }
+}
+
+#[cfg(unix)]
+async fn symlink(target: &str, path: &Path) -> io::Result<()> {
+    tokio::fs::os::unix::symlink(target, path).await
+}
+
+#[cfg(not(unix))]
+async fn symlink(_target: &str, _path: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "symlinks are only supported on unix",
+    ))
+}
+
+fn defer(
+    deferred: &mut Vec<(PathBuf, Metadata, bool)>,
+    path: &Path,
+    metadata: &Metadata,
+    is_symlink: bool,
+) {
+    if metadata != &Metadata::default() {
+        deferred.push((path.to_owned(), metadata.clone(), is_symlink));
+    }
+}
+
+fn apply_metadata(path: &Path, metadata: &Metadata, is_symlink: bool) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        // the mode of the symlinks themselves is not used on most of the systems
+        if let (Some(mode), false) = (metadata.mode(), is_symlink) {
+            let permissions = std::fs::Permissions::from_mode(mode & 0o7777);
+            std::fs::set_permissions(path, permissions)?;
+        }
+    }
+
+    if let Some(mtime) = metadata.mtime_as_filetime() {
+        if is_symlink {
+            let current = std::fs::symlink_metadata(path)?;
+            let atime = filetime::FileTime::from_last_access_time(&current);
+            filetime::set_symlink_file_times(path, atime, mtime)?;
+        } else {
+            filetime::set_file_mtime(path, mtime)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Types of failures which can occur while writing an UnixFS tree to the filesystem.
+#[derive(Debug, thiserror::Error)]
+pub enum GetError {
+    /// Failure to resolve the given path; does not happen when given a block.
+    #[error("path resolving failed")]
+    Resolving(#[source] ResolveError),
+
+    /// The given path was resolved to non dag-pb block, does not happen when starting the walk
+    /// from a block.
+    #[error("path resolved to unexpected")]
+    Path(#[source] UnexpectedResolved),
+
+    /// Loading of a block during walk failed.
+    #[error("loading of {} failed", .0)]
+    Loading(Cid, #[source] Error),
+
+    /// Processing of the block failed.
+    #[error("walk failed")]
+    Walking(#[source] walk::Error),
+
+    /// The path of an entry would escape the target, or be written through a directory which was
+    /// not created by the operation.
+    #[error("invalid path {:?}", .0)]
+    InvalidPath(PathBuf),
+
+    /// The target of a symlink was not valid UTF-8.
+    #[error("symlink target is not valid UTF-8 at {:?}", .0)]
+    NonUtf8Symlink(PathBuf),
+
+    /// Writing to the filesystem failed.
+    #[error("writing to {:?} failed", .0)]
+    Filesystem(PathBuf, #[source] io::Error),
+}
+
+impl From<walk::Error> for GetError {
+    fn from(e: walk::Error) -> Self {
+        GetError::Walking(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GetError;
+    use crate::ipld::dag_pb::{PbLink, PbNode};
+    use crate::unixfs::{added_root, AddOptions, AddPathOptions};
+    use crate::{Block, Cid, Ipfs, Node, TestTypes};
+    use futures::stream::TryStreamExt;
+    use multihash::Sha2_256;
+    use std::fs;
+    use std::path::Path;
+
+    #[cfg(unix)]
+    #[tokio::test(max_threads = 1)]
+    async fn directory_tree_roundtrip() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let ipfs = Node::new("test_node").await;
+        let tmp = tempfile::tempdir().unwrap();
+        let source = tmp.path().join("source");
+
+        fs::create_dir_all(source.join("b/empty")).unwrap();
+        fs::write(source.join("a.txt"), b"a\n").unwrap();
+        fs::write(source.join("b/c.txt"), vec![7u8; 600 * 1024]).unwrap();
+        std::os::unix::fs::symlink("../a.txt", source.join("b/link")).unwrap();
+        fs::set_permissions(source.join("a.txt"), fs::Permissions::from_mode(0o600)).unwrap();
+        fs::set_permissions(source.join("b"), fs::Permissions::from_mode(0o700)).unwrap();
+
+        let opts = AddPathOptions {
+            preserve_mode: true,
+            preserve_mtime: true,
+            ..Default::default()
+        };
+
+        let root = added_root(
+            &ipfs
+                .add_path(&source, opts)
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap(),
+        );
+
+        let target = tmp.path().join("target");
+        ipfs.get_to_path(root, &target).await.unwrap();
+
+        assert_eq!(fs::read(target.join("a.txt")).unwrap(), b"a\n");
+        assert_eq!(
+            fs::read(target.join("b/c.txt")).unwrap(),
+            vec![7u8; 600 * 1024]
+        );
+        assert!(target.join("b/empty").is_dir());
+        assert_eq!(
+            fs::read_link(target.join("b/link")).unwrap(),
+            Path::new("../a.txt")
+        );
+
+        for path in &["a.txt", "b", "b/c.txt", "b/empty"] {
+            let original = fs::metadata(source.join(path)).unwrap();
+            let written = fs::metadata(target.join(path)).unwrap();
+
+            assert_eq!(
+                original.permissions().mode() & 0o7777,
+                written.permissions().mode() & 0o7777,
+                "mode of {}",
+                path
+            );
+
+            // the seconds and nanoseconds are stored, but not all filesystems have nanoseconds
+            assert_eq!(
+                filetime::FileTime::from_last_modification_time(&original).unix_seconds(),
+                filetime::FileTime::from_last_modification_time(&written).unix_seconds(),
+                "mtime of {}",
+                path
+            );
+        }
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn single_file() {
+        let ipfs = Node::new("test_node").await;
+        let tmp = tempfile::tempdir().unwrap();
+
+        let events = ipfs
+            .add_unixfs(&b"foobar\n"[..], AddOptions::default())
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        let target = tmp.path().join("foobar.txt");
+        ipfs.get_to_path(added_root(&events), &target)
+            .await
+            .unwrap();
+
+        assert_eq!(fs::read(&target).unwrap(), b"foobar\n");
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn existing_files_are_not_overwritten() {
+        let ipfs = Node::new("test_node").await;
+        let tmp = tempfile::tempdir().unwrap();
+
+        let events = ipfs
+            .add_unixfs(&b"foobar\n"[..], AddOptions::default())
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        let target = tmp.path().join("foobar.txt");
+        fs::write(&target, b"original").unwrap();
+
+        match ipfs.get_to_path(added_root(&events), &target).await {
+            Err(GetError::Filesystem(path, _)) => assert_eq!(path, target),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        assert_eq!(fs::read(&target).unwrap(), b"original");
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn path_traversal_is_refused() {
+        let ipfs = Node::new("test_node").await;
+        let tmp = tempfile::tempdir().unwrap();
+
+        let events = ipfs
+            .add_unixfs(&b"foobar\n"[..], AddOptions::default())
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        let file = added_root(&events);
+
+        for name in &["..", "."] {
+            let root = put_directory(&ipfs, name, &file).await;
+            let target = tmp.path().join("target");
+
+            match ipfs.get_to_path(root, &target).await {
+                Err(GetError::InvalidPath(path)) => assert_eq!(path, Path::new(name)),
+                other => panic!("unexpected result for {:?}: {:?}", name, other),
+            }
+
+            assert_eq!(fs::read_dir(&target).unwrap().count(), 0);
+            fs::remove_dir_all(&target).unwrap();
+        }
+    }
+
+    /// Stores a directory with a single link, bypassing the validation of the link names.
+    async fn put_directory(ipfs: &Ipfs<TestTypes>, name: &str, cid: &Cid) -> Cid {
+        let node = PbNode {
+            links: vec![PbLink {
+                cid: cid.to_owned(),
+                name: Some(name.to_owned()),
+                size: Some(15),
+            }],
+            // unixfs directory without any other fields
+            data: Some(vec![0x08, 0x01]),
+        };
+
+        let data = node.into_bytes();
+        let cid = Cid::new_v0(Sha2_256::digest(&data)).unwrap();
+        ipfs.put_block(Block::new(data.into_vec(), cid))
+            .await
+            .unwrap()
+    }
+}
@@ -0,0 +1,282 @@
+//! Matching of the `.gitignore` style rules used to ignore files when adding a directory tree.
+//!
+//! Supported are the comments, negated rules, rules matching only directories, rules anchored to
+//! the directory of the rules and the `*`, `?`, `[...]` and `**` wildcards.
+
+/// Rules read from the ignore files of a single directory, or given directly.
+#[derive(Debug, Default)]
+pub(super) struct IgnoreRules {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    pattern: Vec<char>,
+    negated: bool,
+    directory_only: bool,
+    /// Anchored rules match the path relative to the directory of the rules, others only the
+    /// last segment of it.
+    anchored: bool,
+}
+
+impl IgnoreRules {
+    /// Parses the rules from the lines, skipping the comments and the empty lines.
+    pub(super) fn parse<'a>(lines: impl IntoIterator<Item = &'a str>) -> Self {
+        let rules = lines.into_iter().filter_map(Rule::parse).collect();
+        IgnoreRules { rules }
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Returns `Some(true)` when the path should be ignored, `Some(false)` when a negated rule
+    /// includes it again, and `None` when no rule matches the path. The path is relative to the
+    /// directory of the rules and separated by slashes.
+    pub(super) fn matches(&self, path: &str, is_dir: bool) -> Option<bool> {
+        let path = path.chars().collect::<Vec<_>>();
+        let basename_at = path
+            .iter()
+            .rposition(|&ch| ch == '/')
+            .map(|at| at + 1)
+            .unwrap_or(0);
+
+        // the last matching rule decides
+        self.rules
+            .iter()
+            .rev()
+            .filter(|rule| is_dir || !rule.directory_only)
+            .find(|rule| {
+                let subject = if rule.anchored {
+                    &path[..]
+                } else {
+                    &path[basename_at..]
+                };
+                glob_match(&rule.pattern, subject)
+            })
+            .map(|rule| !rule.negated)
+    }
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Self> {
+        let mut line = line.trim_end_matches('\r');
+
+        // trailing spaces are ignored unless escaped
+        while line.ends_with(' ') && !line.ends_with("\\ ") {
+            line = &line[..line.len() - 1];
+        }
+
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+
+        let (directory_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+
+        if line.is_empty() {
+            return None;
+        }
+
+        Some(Rule {
+            pattern: line.chars().collect(),
+            negated,
+            directory_only,
+            anchored,
+        })
+    }
+}
+
+/// Matches the text against the glob pattern, where the wildcards other than `**` do not match
+/// the slashes.
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    let (first, rest) = match pattern.split_first() {
+        Some(split) => split,
+        None => return text.is_empty(),
+    };
+
+    match first {
+        '*' if rest.first() == Some(&'*') => {
+            let rest = &rest[rest.iter().take_while(|&&ch| ch == '*').count()..];
+
+            match rest.split_first() {
+                // trailing `**` matches everything
+                None => true,
+                // `**/` matches zero or more directories
+                Some(('/', rest)) => {
+                    glob_match(rest, text)
+                        || text
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, &ch)| ch == '/')
+                            .any(|(at, _)| glob_match(rest, &text[at + 1..]))
+                }
+                // otherwise the same as a single `*`
+                Some(_) => match_star(rest, text),
+            }
+        }
+        '*' => match_star(rest, text),
+        '?' => match text.split_first() {
+            Some((&ch, text)) if ch != '/' => glob_match(rest, text),
+            _ => false,
+        },
+        '[' => match match_class(rest, text.first().copied()) {
+            Some((true, rest)) => glob_match(rest, &text[1..]),
+            Some((false, _)) => false,
+            // an unterminated class is matched literally
+            None => text.first() == Some(&'[') && glob_match(rest, &text[1..]),
+        },
+        '\\' if !rest.is_empty() => {
+            text.first() == Some(&rest[0]) && glob_match(&rest[1..], &text[1..])
+        }
+        ch => text.first() == Some(ch) && glob_match(rest, &text[1..]),
+    }
+}
+
+/// Matches a `*` followed by the rest of the pattern.
+fn match_star(rest: &[char], text: &[char]) -> bool {
+    for at in 0..=text.len() {
+        if glob_match(rest, &text[at..]) {
+            return true;
+        }
+
+        if text.get(at) == Some(&'/') {
+            break;
+        }
+    }
+    false
+}
+
+/// Matches the character against the class, which starts after the opening `[`. Returns whether
+/// the character matched and the rest of the pattern after the class, or `None` if the class is
+/// not terminated.
+fn match_class(class: &[char], ch: Option<char>) -> Option<(bool, &[char])> {
+    let (negated, class) = match class.first() {
+        Some('!') | Some('^') => (true, &class[1..]),
+        _ => (false, class),
+    };
+
+    // a closing bracket as the first character is a part of the class
+    let end = class
+        .iter()
+        .skip(1)
+        .position(|&ch| ch == ']')
+        .map(|at| at + 1)?;
+
+    let (members, rest) = (&class[..end], &class[end + 1..]);
+
+    let ch = match ch {
+        Some(ch) if ch != '/' => ch,
+        _ => return Some((false, rest)),
+    };
+
+    let mut matched = false;
+    let mut at = 0;
+
+    while at < members.len() {
+        if at + 2 < members.len() && members[at + 1] == '-' {
+            matched |= members[at] <= ch && ch <= members[at + 2];
+            at += 3;
+        } else {
+            matched |= members[at] == ch;
+            at += 1;
+        }
+    }
+
+    Some((matched != negated, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IgnoreRules;
+
+    fn ignored(rules: &[&str], path: &str, is_dir: bool) -> bool {
+        IgnoreRules::parse(rules.iter().copied())
+            .matches(path, is_dir)
+            .unwrap_or(false)
+    }
+
+    #[test]
+    fn basename_rules() {
+        assert!(ignored(&["*.o"], "a.o", false));
+        assert!(ignored(&["*.o"], "src/nested/a.o", false));
+        assert!(!ignored(&["*.o"], "a.os", false));
+        assert!(ignored(&["target"], "nested/target", true));
+        assert!(ignored(&["a?c"], "abc", false));
+        assert!(!ignored(&["a?c"], "a/c", false));
+    }
+
+    #[test]
+    fn anchored_rules() {
+        assert!(ignored(&["/target"], "target", true));
+        assert!(!ignored(&["/target"], "nested/target", true));
+        assert!(ignored(&["doc/*.txt"], "doc/a.txt", false));
+        assert!(!ignored(&["doc/*.txt"], "doc/nested/a.txt", false));
+        assert!(!ignored(&["doc/*.txt"], "nested/doc/a.txt", false));
+    }
+
+    #[test]
+    fn double_star() {
+        assert!(ignored(&["**/foo"], "foo", false));
+        assert!(ignored(&["**/foo"], "a/b/foo", false));
+        assert!(ignored(&["a/**/b"], "a/b", false));
+        assert!(ignored(&["a/**/b"], "a/x/y/b", false));
+        assert!(ignored(&["abc/**"], "abc/x/y", false));
+        assert!(!ignored(&["abc/**"], "abc", true));
+    }
+
+    #[test]
+    fn directory_only() {
+        assert!(ignored(&["build/"], "build", true));
+        assert!(!ignored(&["build/"], "build", false));
+        assert!(ignored(&["build/"], "nested/build", true));
+    }
+
+    #[test]
+    fn negation_and_order() {
+        let rules = &["*.log", "!keep.log"];
+        assert!(ignored(rules, "a.log", false));
+        assert!(!ignored(rules, "keep.log", false));
+
+        let rules = &["!keep.log", "*.log"];
+        assert!(ignored(rules, "keep.log", false));
+
+        assert_eq!(
+            IgnoreRules::parse(vec!["!keep.log"]).matches("keep.log", false),
+            Some(false)
+        );
+        assert_eq!(
+            IgnoreRules::parse(vec!["*.log"]).matches("a.txt", false),
+            None
+        );
+    }
+
+    #[test]
+    fn classes_and_escapes() {
+        assert!(ignored(&["[abc].txt"], "b.txt", false));
+        assert!(!ignored(&["[abc].txt"], "d.txt", false));
+        assert!(ignored(&["[!abc].txt"], "d.txt", false));
+        assert!(ignored(&["[a-c]x"], "bx", false));
+        assert!(ignored(&["[]]"], "]", false));
+        assert!(ignored(&["[abc"], "[abc", false));
+        assert!(ignored(&["\\#hash"], "#hash", false));
+        assert!(ignored(&["\\!bang"], "!bang", false));
+        assert!(ignored(&["space\\ "], "space ", false));
+    }
+
+    #[test]
+    fn comments_and_empty_lines() {
+        let rules = IgnoreRules::parse(vec!["# comment", "", "   ", "\r"]);
+        assert!(rules.is_empty());
+    }
+}
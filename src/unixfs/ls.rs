@@ -0,0 +1,256 @@
+use super::StartingPoint;
+use crate::{
+    dag::{ResolveError, UnexpectedResolved},
+    Error, Ipfs, IpfsTypes,
+};
+use async_stream::try_stream;
+use bitswap::Block;
+use cid::Cid;
+use futures::stream::Stream;
+use ipfs_unixfs::dir::{list, EntryType, ListingError};
+use std::borrow::Borrow;
+
+/// An entry of a listed directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LsEntry {
+    /// The name of the entry in the directory.
+    pub name: String,
+    /// The Cid of the entry.
+    pub cid: Cid,
+    /// The cumulative size of the entry as recorded in the directory.
+    pub total_size: u64,
+    /// The type of the entry, including the size of the files and the targets of the symlinks.
+    /// Only available when the types were resolved.
+    pub entry_type: Option<EntryType>,
+}
+
+/// IPFS ls operation, producing a stream of the entries of a UnixFS directory, normal or HAMT
+/// sharded, or the links of any other `dag-pb` node. This is generic over the different kinds of
+/// ways to own an `Ipfs` value in the same way as [`super::cat`].
+///
+/// When `resolve_types` is true, the first block of every entry is loaded to find out its type.
+/// The entries of sharded directories are not listed in the order of their names.
+pub fn ls<'a, Types, MaybeOwned>(
+    ipfs: MaybeOwned,
+    starting_point: impl Into<StartingPoint>,
+    resolve_types: bool,
+) -> impl Stream<Item = Result<LsEntry, LsError>> + Send + 'a
+where
+    Types: IpfsTypes,
+    MaybeOwned: Borrow<Ipfs<Types>> + Send + 'a,
+{
+    let starting_point = starting_point.into();
+
+    try_stream! {
+        let ipfs = ipfs.borrow();
+
+        let Block { cid, data } = match starting_point {
+            StartingPoint::Left(path) => {
+                let (resolved, _) = ipfs
+                    .dag()
+                    .resolve(path, true)
+                    .await
+                    .map_err(LsError::Resolving)?;
+                resolved.into_unixfs_block().map_err(LsError::Path)?
+            }
+            StartingPoint::Right(block) => block,
+        };
+
+        let (mut links, mut rest) = list(&data).map_err(|e| LsError::Listing(cid, e))?;
+
+        loop {
+            for link in links {
+                let entry_type = if resolve_types {
+                    let Block { data, .. } = load(ipfs, &link.cid).await?;
+                    Some(EntryType::from_block(&link.cid, &data))
+                } else {
+                    None
+                };
+
+                yield LsEntry {
+                    name: link.name,
+                    cid: link.cid,
+                    total_size: link.total_size,
+                    entry_type,
+                };
+            }
+
+            let listing = match rest {
+                Some(listing) => listing,
+                None => break,
+            };
+
+            let (next, _) = listing.pending_links();
+            let Block { cid, data } = load(ipfs, next).await?;
+
+            let (next_links, next_rest) = listing
+                .continue_walk(&data)
+                .map_err(|e| LsError::Listing(cid, e))?;
+
+            links = next_links;
+            rest = next_rest;
+        }
+    }
+}
+
+async fn load<Types: IpfsTypes>(ipfs: &Ipfs<Types>, cid: &Cid) -> Result<Block, LsError> {
+    ipfs.get_block(cid)
+        .await
+        .map_err(|e| LsError::Loading(cid.to_owned(), e))
+}
+
+/// Types of failures which can occur while listing a directory.
+#[derive(Debug, thiserror::Error)]
+pub enum LsError {
+    /// Failure to resolve the given path; does not happen when given a block.
+    #[error("path resolving failed")]
+    Resolving(#[source] ResolveError),
+
+    /// The given path was resolved to non dag-pb block, does not happen when starting the walk
+    /// from a block.
+    #[error("path resolved to unexpected")]
+    Path(#[source] UnexpectedResolved),
+
+    /// Loading of a block during the listing failed.
+    #[error("loading of {} failed", .0)]
+    Loading(Cid, #[source] Error),
+
+    /// The block was not a directory, or listing it failed.
+    #[error("listing {} failed", .0)]
+    Listing(Cid, #[source] ListingError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LsEntry;
+    use crate::unixfs::{added_root, AddPathOptions};
+    use crate::{Block, Node};
+    use futures::stream::TryStreamExt;
+    use ipfs_unixfs::dir::builder::{BufferingTreeBuilder, TreeOptions};
+    use ipfs_unixfs::dir::EntryType;
+    use std::fs;
+
+    #[tokio::test(max_threads = 1)]
+    async fn list_directory() {
+        let ipfs = Node::new("test_node").await;
+        let tmp = tempfile::tempdir().unwrap();
+
+        fs::create_dir(tmp.path().join("b")).unwrap();
+        fs::write(tmp.path().join("a.txt"), b"foobar\n").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("a.txt", tmp.path().join("c")).unwrap();
+
+        let root = added_root(
+            &ipfs
+                .add_path(tmp.path(), AddPathOptions::default())
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap(),
+        );
+
+        let entries = ipfs
+            .ls(root.clone(), true)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        let mut expected = vec![
+            ("a.txt", Some(EntryType::File(7))),
+            ("b", Some(EntryType::Directory)),
+        ];
+        #[cfg(unix)]
+        expected.push(("c", Some(EntryType::Symlink(b"a.txt".to_vec()))));
+
+        assert_eq!(
+            entries
+                .iter()
+                .map(|e| (e.name.as_str(), e.entry_type.clone()))
+                .collect::<Vec<_>>(),
+            expected
+        );
+        assert!(entries.iter().all(|e| e.total_size > 0));
+
+        let unresolved = ipfs.ls(root, false).try_collect::<Vec<_>>().await.unwrap();
+
+        assert_eq!(
+            unresolved,
+            entries
+                .into_iter()
+                .map(|e| LsEntry {
+                    entry_type: None,
+                    ..e
+                })
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn list_sharded_directory() {
+        let ipfs = Node::new("test_node").await;
+
+        let file = added_root(
+            &ipfs
+                .add_unixfs(&b"foobar\n"[..], Default::default())
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap(),
+        );
+
+        let mut opts = TreeOptions::default();
+        opts.sharding_threshold(Some(0));
+        opts.hamt_fanout(8);
+
+        let mut tree = BufferingTreeBuilder::new(opts);
+        let mut names = (0..100)
+            .map(|i| format!("file-{:03}", i))
+            .collect::<Vec<_>>();
+
+        for name in &names {
+            tree.put_link(&format!("dir/{}", name), file.clone(), 15)
+                .unwrap();
+        }
+
+        let mut root = None;
+
+        for node in tree.build() {
+            let node = node.unwrap();
+            let block = Block::new(node.block.into_vec(), node.cid);
+            root = Some(ipfs.put_block(block).await.unwrap());
+        }
+
+        let mut listed = ipfs
+            .ls(root.unwrap(), false)
+            .map_ok(|e| e.name)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        listed.sort_unstable();
+        names.sort_unstable();
+        assert_eq!(listed, names);
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn file_is_not_a_directory() {
+        let ipfs = Node::new("test_node").await;
+
+        let root = added_root(
+            &ipfs
+                .add_unixfs(&b"foobar\n"[..], Default::default())
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap(),
+        );
+
+        let err = ipfs
+            .ls(root, false)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, super::LsError::Listing(..)),
+            "unexpected error: {:?}",
+            err
+        );
+    }
+}
@@ -0,0 +1,1420 @@
+//! The mutable file system, a path addressed view over an UnixFS directory tree whose root Cid is
+//! stored in the datastore. The paths are absolute, starting with a slash like in go-ipfs.
+//!
+//! Every modification renders the directories from the modified entry up to the root again and
+//! stores the new root before returning, so there is nothing left to flush.
+use super::add::store_all;
+use super::{cat, ls, AddError, AddOptions, LsEntry, LsError, TraversalFailed};
+use crate::dag::{ResolveError, ResolvedNode, UnexpectedResolved};
+use crate::{Error, Ipfs, IpfsPath, IpfsTypes};
+use bitswap::Block;
+use bytes::Bytes;
+use cid::{Cid, Codec};
+use futures::stream::{Stream, TryStreamExt};
+use ipfs_unixfs::dagpb::cumulative_size;
+use ipfs_unixfs::dir::builder::{
+    BufferingTreeBuilder, TreeBuildingFailed, TreeConstructionFailed, TreeOptions,
+};
+use ipfs_unixfs::dir::EntryType;
+use ipfs_unixfs::file::adder::{Chunker, Collector, FileAdder, TrickleCollector};
+use ipfs_unixfs::file::visit::{read_block, FileBlock};
+use ipfs_unixfs::{CidOptions, Metadata};
+use std::borrow::Borrow;
+use std::collections::btree_map::{BTreeMap, Entry};
+use std::collections::HashSet;
+use std::ops::Range;
+use std::str::FromStr;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// The size of the buffer the written input is read into.
+const READ_BUFFER_SIZE: usize = 256 * 1024;
+
+/// The number of links in the link blocks of the files, same as in the default layouts.
+const BRANCHING_FACTOR: usize = 174;
+
+/// The size limit for inlined blocks when writing to a file with inlined blocks, the default of
+/// go-ipfs.
+const INLINE_LIMIT: usize = 32;
+
+/// The links of a directory by their names, with the cumulative sizes of the linked subtrees.
+type Entries = BTreeMap<String, (Cid, u64)>;
+
+/// Options for writing a file with [`write`].
+#[derive(Debug, Clone, Default)]
+pub struct WriteOptions {
+    /// The offset in the existing file where the input is written, which cannot be past the end
+    /// of the file. The rest of the existing file is kept after the written bytes.
+    pub offset: u64,
+    /// Create the file if it does not exist.
+    pub create: bool,
+    /// Discard the existing contents of the file before writing.
+    pub truncate: bool,
+    /// Create the missing parent directories.
+    pub parents: bool,
+}
+
+/// Information about a file or a directory, returned by [`stat`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MfsStat {
+    /// The Cid of the root block of the entry.
+    pub cid: Cid,
+    /// The type of the entry, including the size of a file.
+    pub entry_type: EntryType,
+    /// The size of the root block added to the cumulative sizes of the linked subtrees.
+    pub cumulative_size: u64,
+    /// The number of links in the root block.
+    pub blocks: usize,
+}
+
+/// Creates the directory at the path. When `parents` is true the missing parent directories are
+/// created as well, and an existing directory at the path is not an error.
+pub async fn mkdir<Types: IpfsTypes>(
+    ipfs: &Ipfs<Types>,
+    path: &str,
+    parents: bool,
+) -> Result<(), MfsError> {
+    let segments = segments(path)?;
+    let _guard = ipfs.repo.files_lock.lock().await;
+    let root = root(ipfs).await?;
+
+    if parents {
+        match lookup(ipfs, &root, path, &segments).await {
+            Ok(block) if is_directory(&block) => return Ok(()),
+            Ok(_) => return Err(MfsError::AlreadyExists(path.to_owned())),
+            Err(MfsError::NotFound(_)) => {}
+            Err(e) => return Err(e),
+        }
+    } else if segments.is_empty() {
+        return Err(MfsError::AlreadyExists(path.to_owned()));
+    }
+
+    let empty = render(ipfs, &Entries::new()).await?;
+
+    let root = update(
+        ipfs,
+        &root,
+        &segments,
+        parents,
+        |entries, name| match entries.entry(name.to_owned()) {
+            Entry::Vacant(ve) => {
+                ve.insert(empty);
+                Ok(())
+            }
+            Entry::Occupied(_) => Err(MfsError::AlreadyExists(path.to_owned())),
+        },
+    )
+    .await?;
+
+    commit(ipfs, &root).await
+}
+
+/// Writes the bytes read from the input to the file at the path with the `FileAdder`. Unless
+/// truncating, the contents of the existing file before the `offset` and after the written bytes
+/// are kept.
+///
+/// Only the leaves around the written bytes are read and chunked again, while the other leaves of
+/// an existing file are linked to as they are. The link blocks are rendered again in the layout of
+/// the file, but only the changed ones are stored. The metadata of an existing file is kept, and
+/// the new blocks are created with the Cid version, the multihash and the kind of leaves of the
+/// file, chunked to the size of its first leaf.
+pub async fn write<Types: IpfsTypes>(
+    ipfs: &Ipfs<Types>,
+    path: &str,
+    mut input: impl AsyncRead + Send + Unpin,
+    opts: WriteOptions,
+) -> Result<(), MfsError> {
+    let segments = segments(path)?;
+    let _guard = ipfs.repo.files_lock.lock().await;
+    let root = root(ipfs).await?;
+
+    let existing = match lookup(ipfs, &root, path, &segments).await {
+        Ok(block) => match EntryType::from_block(&block.cid, &block.data) {
+            EntryType::File(size) => Some(ExistingFile::read(ipfs, block, size).await?),
+            _ => return Err(MfsError::NotAFile(path.to_owned())),
+        },
+        Err(MfsError::NotFound(_)) if opts.create => None,
+        Err(e) => return Err(e),
+    };
+
+    let (add_opts, metadata) = match existing.as_ref() {
+        Some(file) => (file.opts.clone(), file.metadata.clone()),
+        None => (
+            AddOptions {
+                pin: false,
+                ..Default::default()
+            },
+            Metadata::default(),
+        ),
+    };
+
+    let existing = existing.filter(|_| !opts.truncate);
+    let size = existing.as_ref().map(|file| file.size).unwrap_or_default();
+
+    if opts.offset > size {
+        return Err(MfsError::InvalidOffset(opts.offset, size));
+    }
+
+    let mut writer = FileWriter {
+        ipfs,
+        adder: add_opts.file_adder(metadata),
+        known: HashSet::new(),
+    };
+
+    match existing {
+        Some(file) => writer.modify(file, opts.offset, &mut input).await?,
+        None => {
+            writer.push_input(&mut input).await?;
+        }
+    }
+
+    let file = writer.finish().await?;
+
+    let root = update(ipfs, &root, &segments, opts.parents, |entries, name| {
+        entries.insert(name.to_owned(), file);
+        Ok(())
+    })
+    .await?;
+
+    commit(ipfs, &root).await
+}
+
+/// Reads the file at the path, producing a stream of its bytes within the optional range like
+/// [`super::cat`].
+pub async fn read<'a, Types, MaybeOwned>(
+    ipfs: MaybeOwned,
+    path: &str,
+    range: Option<Range<u64>>,
+) -> Result<impl Stream<Item = Result<Vec<u8>, TraversalFailed>> + Send + 'a, MfsError>
+where
+    Types: IpfsTypes,
+    MaybeOwned: Borrow<Ipfs<Types>> + Send + 'a,
+{
+    let segments = segments(path)?;
+    let block = {
+        let ipfs = ipfs.borrow();
+        let root = root(ipfs).await?;
+        lookup(ipfs, &root, path, &segments).await?
+    };
+
+    if !matches!(
+        EntryType::from_block(&block.cid, &block.data),
+        EntryType::File(_)
+    ) {
+        return Err(MfsError::NotAFile(path.to_owned()));
+    }
+
+    cat(ipfs, block, range)
+        .await
+        .map_err(|e| MfsError::Reading(Box::new(e)))
+}
+
+/// Moves the file or directory at `src` to `dst`, or into `dst` when it is an existing directory.
+pub async fn mv<Types: IpfsTypes>(
+    ipfs: &Ipfs<Types>,
+    src: &str,
+    dst: &str,
+) -> Result<(), MfsError> {
+    let src_segments = segments(src)?;
+    let dst_segments = segments(dst)?;
+    let _guard = ipfs.repo.files_lock.lock().await;
+    let root = root(ipfs).await?;
+
+    let source = entry(ipfs, &root, src, &src_segments).await?;
+    let target = target(ipfs, &root, dst, dst_segments, &src_segments).await?;
+
+    if target.len() >= src_segments.len() && target.iter().zip(&src_segments).all(|(a, b)| a == b) {
+        return Err(MfsError::InvalidPath(dst.to_owned()));
+    }
+
+    let root = update(ipfs, &root, &src_segments, false, |entries, name| {
+        entries.remove(name);
+        Ok(())
+    })
+    .await?;
+
+    let root = insert(ipfs, &root, dst, &target, source).await?;
+
+    commit(ipfs, &root).await
+}
+
+/// Copies the file or directory at `src` to `dst`, or into `dst` when it is an existing
+/// directory. The source can also be an `/ipfs/` or `/ipns/` path.
+pub async fn cp<Types: IpfsTypes>(
+    ipfs: &Ipfs<Types>,
+    src: &str,
+    dst: &str,
+) -> Result<(), MfsError> {
+    let dst_segments = segments(dst)?;
+    let _guard = ipfs.repo.files_lock.lock().await;
+    let root = root(ipfs).await?;
+
+    let (source, name): (_, Vec<String>) = if src.starts_with("/ipfs/") || src.starts_with("/ipns/")
+    {
+        let path = IpfsPath::from_str(src).map_err(|_| MfsError::InvalidPath(src.to_owned()))?;
+        let name = path
+            .iter()
+            .last()
+            .map(String::from)
+            .or_else(|| path.root().cid().map(Cid::to_string));
+
+        let (resolved, _) = ipfs
+            .dag()
+            .resolve(path, true)
+            .await
+            .map_err(MfsError::Resolving)?;
+        let block = into_block(resolved)?;
+        let (size, _) = sizes(&block)?;
+
+        ((block.cid, size), name.into_iter().collect())
+    } else {
+        let src_segments = segments(src)?;
+        let source = entry(ipfs, &root, src, &src_segments).await?;
+        let name = src_segments.last().map(|s| s.to_string());
+        (source, name.into_iter().collect())
+    };
+
+    let target = target(ipfs, &root, dst, dst_segments, &name).await?;
+    let root = insert(ipfs, &root, dst, &target, source).await?;
+
+    commit(ipfs, &root).await
+}
+
+/// Removes the file or the directory at the path. Directories are only removed when `recursive`
+/// is true.
+pub async fn rm<Types: IpfsTypes>(
+    ipfs: &Ipfs<Types>,
+    path: &str,
+    recursive: bool,
+) -> Result<(), MfsError> {
+    let segments = segments(path)?;
+    let _guard = ipfs.repo.files_lock.lock().await;
+    let root = root(ipfs).await?;
+
+    if segments.is_empty() {
+        return Err(MfsError::InvalidPath(path.to_owned()));
+    }
+
+    let block = lookup(ipfs, &root, path, &segments).await?;
+
+    if !recursive && is_directory(&block) {
+        return Err(MfsError::IsADirectory(path.to_owned()));
+    }
+
+    let root = update(ipfs, &root, &segments, false, |entries, name| {
+        entries.remove(name);
+        Ok(())
+    })
+    .await?;
+
+    commit(ipfs, &root).await
+}
+
+/// Returns information about the file or directory at the path.
+pub async fn stat<Types: IpfsTypes>(ipfs: &Ipfs<Types>, path: &str) -> Result<MfsStat, MfsError> {
+    let segments = segments(path)?;
+    let root = root(ipfs).await?;
+    let block = lookup(ipfs, &root, path, &segments).await?;
+    let (cumulative_size, blocks) = sizes(&block)?;
+
+    Ok(MfsStat {
+        entry_type: EntryType::from_block(&block.cid, &block.data),
+        cid: block.cid,
+        cumulative_size,
+        blocks,
+    })
+}
+
+/// Lists the entries of the directory at the path, or the file itself. When `resolve_types` is
+/// true, the types of the entries are resolved like with [`super::ls`].
+pub async fn list<Types: IpfsTypes>(
+    ipfs: &Ipfs<Types>,
+    path: &str,
+    resolve_types: bool,
+) -> Result<Vec<LsEntry>, MfsError> {
+    let segments = segments(path)?;
+    let root = root(ipfs).await?;
+    let block = lookup(ipfs, &root, path, &segments).await?;
+
+    if is_directory(&block) {
+        return ls(ipfs, block, resolve_types)
+            .try_collect()
+            .await
+            .map_err(|e| MfsError::Listing(Box::new(e)));
+    }
+
+    let (total_size, _) = sizes(&block)?;
+    let entry_type = EntryType::from_block(&block.cid, &block.data);
+
+    Ok(vec![LsEntry {
+        name: segments.last().map(|s| s.to_string()).unwrap_or_default(),
+        cid: block.cid,
+        total_size,
+        entry_type: Some(entry_type).filter(|_| resolve_types),
+    }])
+}
+
+/// Returns the Cid of the file or directory at the path. As the modifications are stored as they
+/// are made, there is nothing to write.
+pub async fn flush<Types: IpfsTypes>(ipfs: &Ipfs<Types>, path: &str) -> Result<Cid, MfsError> {
+    let segments = segments(path)?;
+    let root = root(ipfs).await?;
+    let block = lookup(ipfs, &root, path, &segments).await?;
+    Ok(block.cid)
+}
+
+/// Splits the absolute path into its segments, ignoring any repeated or trailing slashes.
+fn segments(path: &str) -> Result<Vec<&str>, MfsError> {
+    if !path.starts_with('/') {
+        return Err(MfsError::InvalidPath(path.to_owned()));
+    }
+
+    let segments = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>();
+
+    if segments.iter().any(|s| *s == "." || *s == "..") {
+        return Err(MfsError::InvalidPath(path.to_owned()));
+    }
+
+    Ok(segments)
+}
+
+/// Returns the current root, rendering an empty directory for a new repository.
+async fn root<Types: IpfsTypes>(ipfs: &Ipfs<Types>) -> Result<Cid, MfsError> {
+    match ipfs
+        .repo
+        .get_files_root()
+        .await
+        .map_err(MfsError::Persisting)?
+    {
+        Some(root) => Ok(root),
+        None => render(ipfs, &Entries::new()).await.map(|(cid, _)| cid),
+    }
+}
+
+async fn commit<Types: IpfsTypes>(ipfs: &Ipfs<Types>, root: &Cid) -> Result<(), MfsError> {
+    ipfs.repo
+        .put_files_root(root)
+        .await
+        .map_err(MfsError::Persisting)
+}
+
+/// Resolves the block at the path starting from the given root.
+async fn lookup<Types: IpfsTypes>(
+    ipfs: &Ipfs<Types>,
+    root: &Cid,
+    path: &str,
+    segments: &[&str],
+) -> Result<Block, MfsError> {
+    let mut ipfs_path = IpfsPath::from(root.to_owned());
+    for segment in segments {
+        ipfs_path
+            .push_str(segment)
+            .map_err(|_| MfsError::InvalidPath(path.to_owned()))?;
+    }
+
+    match ipfs.dag().resolve(ipfs_path, true).await {
+        // the directory had no link named "Data"
+        Ok((ResolvedNode::DagPbData(..), _)) => Err(MfsError::NotFound(path.to_owned())),
+        Ok((resolved, _)) => into_block(resolved),
+        Err(ResolveError::NotFound(..)) => Err(MfsError::NotFound(path.to_owned())),
+        Err(e) => Err(MfsError::Resolving(e)),
+    }
+}
+
+/// Returns the link to the file or directory at the path from its parent directory.
+async fn entry<Types: IpfsTypes>(
+    ipfs: &Ipfs<Types>,
+    root: &Cid,
+    path: &str,
+    segments: &[&str],
+) -> Result<(Cid, u64), MfsError> {
+    let (name, dirs) = segments
+        .split_last()
+        .ok_or_else(|| MfsError::InvalidPath(path.to_owned()))?;
+
+    let parent = lookup(ipfs, root, path, dirs).await?;
+    let mut entries = directory_entries(ipfs, parent, path).await?;
+
+    entries
+        .remove(*name)
+        .ok_or_else(|| MfsError::NotFound(path.to_owned()))
+}
+
+/// Returns the segments of the path where an entry is moved or copied to: into the destination
+/// when it is an existing directory, otherwise at the destination.
+async fn target<Types: IpfsTypes, S: AsRef<str>>(
+    ipfs: &Ipfs<Types>,
+    root: &Cid,
+    dst: &str,
+    mut dst_segments: Vec<&str>,
+    src_segments: &[S],
+) -> Result<Vec<String>, MfsError> {
+    match lookup(ipfs, root, dst, &dst_segments).await {
+        Ok(block) if is_directory(&block) => match src_segments.last() {
+            Some(name) => dst_segments.push(name.as_ref()),
+            None => return Err(MfsError::AlreadyExists(dst.to_owned())),
+        },
+        Ok(_) => return Err(MfsError::AlreadyExists(dst.to_owned())),
+        Err(MfsError::NotFound(_)) if !dst_segments.is_empty() => {}
+        Err(e) => return Err(e),
+    }
+
+    Ok(dst_segments.into_iter().map(String::from).collect())
+}
+
+/// Inserts the link at the target path, failing if there already is an entry.
+async fn insert<Types: IpfsTypes>(
+    ipfs: &Ipfs<Types>,
+    root: &Cid,
+    dst: &str,
+    target: &[String],
+    link: (Cid, u64),
+) -> Result<Cid, MfsError> {
+    let target = target.iter().map(String::as_str).collect::<Vec<_>>();
+
+    update(ipfs, root, &target, false, |entries, name| {
+        match entries.entry(name.to_owned()) {
+            Entry::Vacant(ve) => {
+                ve.insert(link);
+                Ok(())
+            }
+            Entry::Occupied(_) => Err(MfsError::AlreadyExists(dst.to_owned())),
+        }
+    })
+    .await
+}
+
+/// Modifies the entries of the parent directory of the last segment with `f`, which is given the
+/// name of the last segment, and renders the directories on the path up to the root again.
+/// Returns the new root. When `parents` is true, missing directories on the path are created.
+async fn update<Types, F>(
+    ipfs: &Ipfs<Types>,
+    root: &Cid,
+    segments: &[&str],
+    parents: bool,
+    f: F,
+) -> Result<Cid, MfsError>
+where
+    Types: IpfsTypes,
+    F: FnOnce(&mut Entries, &str) -> Result<(), MfsError>,
+{
+    let (name, dirs) = segments
+        .split_last()
+        .expect("the root directory cannot be replaced");
+
+    let mut chain = Vec::with_capacity(dirs.len());
+    let mut current = Some(load(ipfs, root).await?);
+
+    for (depth, dir) in dirs.iter().enumerate() {
+        let path = format!("/{}", segments[..depth].join("/"));
+
+        let entries = match current.take() {
+            Some(block) => directory_entries(ipfs, block, &path).await?,
+            None => Entries::new(),
+        };
+
+        current = match entries.get(*dir) {
+            Some((cid, _)) => Some(load(ipfs, cid).await?),
+            None if parents => None,
+            None => {
+                let path = format!("/{}", segments[..=depth].join("/"));
+                return Err(MfsError::NotFound(path));
+            }
+        };
+
+        chain.push(entries);
+    }
+
+    let mut entries = match current {
+        Some(block) => {
+            let path = format!("/{}", dirs.join("/"));
+            directory_entries(ipfs, block, &path).await?
+        }
+        None => Entries::new(),
+    };
+
+    f(&mut entries, name)?;
+
+    let mut rendered = render(ipfs, &entries).await?;
+
+    for (mut entries, dir) in chain.into_iter().zip(dirs).rev() {
+        entries.insert((*dir).to_owned(), rendered);
+        rendered = render(ipfs, &entries).await?;
+    }
+
+    Ok(rendered.0)
+}
+
+/// Lists the entries of the directory, failing if the block is not a directory.
+async fn directory_entries<Types: IpfsTypes>(
+    ipfs: &Ipfs<Types>,
+    block: Block,
+    path: &str,
+) -> Result<Entries, MfsError> {
+    if !is_directory(&block) {
+        return Err(MfsError::NotADirectory(path.to_owned()));
+    }
+
+    ls(ipfs, block, false)
+        .map_ok(|entry| (entry.name, (entry.cid, entry.total_size)))
+        .try_collect()
+        .await
+        .map_err(|e| MfsError::Listing(Box::new(e)))
+}
+
+/// Renders and stores the directory with the entries, returning its Cid and cumulative size.
+async fn render<Types: IpfsTypes>(
+    ipfs: &Ipfs<Types>,
+    entries: &Entries,
+) -> Result<(Cid, u64), MfsError> {
+    let mut opts = TreeOptions::default();
+    opts.wrap_with_directory();
+
+    let mut tree = BufferingTreeBuilder::new(opts);
+
+    for (name, (cid, total_size)) in entries {
+        tree.put_link(name, cid.to_owned(), *total_size)
+            .map_err(MfsError::TreeBuilding)?;
+    }
+
+    let mut root = None;
+
+    for node in tree.build() {
+        let node = node.map_err(MfsError::TreeConstruction)?;
+        let block = Block::new(node.block.into_vec(), node.cid.clone());
+        ipfs.put_block(block).await.map_err(MfsError::Persisting)?;
+        root = Some((node.cid, node.total_size));
+    }
+
+    Ok(root.expect("building a tree always produces the root"))
+}
+
+async fn load<Types: IpfsTypes>(ipfs: &Ipfs<Types>, cid: &Cid) -> Result<Block, MfsError> {
+    ipfs.get_block(cid)
+        .await
+        .map_err(|e| MfsError::Loading(cid.to_owned(), e))
+}
+
+fn into_block(resolved: ResolvedNode) -> Result<Block, MfsError> {
+    match resolved {
+        ResolvedNode::Block(block) => Ok(block),
+        other => Err(MfsError::Path(Box::new(UnexpectedResolved::NonBlock(
+            other,
+        )))),
+    }
+}
+
+fn is_directory(block: &Block) -> bool {
+    EntryType::from_block(&block.cid, &block.data) == EntryType::Directory
+}
+
+/// Returns the cumulative size of the block and the number of its links.
+fn sizes(block: &Block) -> Result<(u64, usize), MfsError> {
+    match block.cid.codec() {
+        Codec::DagProtobuf => {
+            cumulative_size(&block.data).map_err(|_| MfsError::NotUnixFs(block.cid.to_owned()))
+        }
+        Codec::Raw => Ok((block.data.len() as u64, 0)),
+        _ => Err(MfsError::NotUnixFs(block.cid.to_owned())),
+    }
+}
+
+/// An existing file being written to, with the options it was added with as far as they can be
+/// told from its blocks.
+struct ExistingFile {
+    root: Block,
+    size: u64,
+    /// The height of the root block, zero when the root is the only leaf.
+    height: usize,
+    /// True for the trickle layout, where the leaves are not all at the same depth.
+    trickle: bool,
+    opts: AddOptions,
+    metadata: Metadata,
+}
+
+impl ExistingFile {
+    /// Reads the metadata and the options of the file from the root block and the blocks on the
+    /// path to the first leaf.
+    async fn read<Types: IpfsTypes>(
+        ipfs: &Ipfs<Types>,
+        root: Block,
+        size: u64,
+    ) -> Result<Self, MfsError> {
+        let (mut node, metadata) = file_node(&root, 0)?;
+
+        let mut height = 0;
+        let mut root_links = 0;
+        let mut first_leaf = 0..size;
+        let mut block = root.clone();
+
+        while let FileNode::Links(links) = node {
+            if height == 0 {
+                root_links = links.len();
+            }
+            height += 1;
+
+            let (cid, _, range) = links.into_iter().next().expect("link blocks have links");
+            first_leaf = range;
+            block = load(ipfs, &cid).await?;
+            node = file_node(&block, 0)?.0;
+        }
+
+        // the trickle layout uses the Raw UnixFS type for the leaves which are not raw blocks;
+        // with raw blocks it is told apart only once the root links to more than the leaves,
+        // before which the trees are the same as in the balanced layout
+        let raw_typed_leaf = block.cid.codec() != Codec::Raw
+            && matches!(read_block(&block.data), Ok((FileBlock::RawBytes(_), _)));
+        let trickle = raw_typed_leaf || height == 1 && root_links > BRANCHING_FACTOR;
+        let layout = if trickle {
+            TrickleCollector::default().into()
+        } else {
+            Collector::default()
+        };
+
+        // the chunk size can only be told from a leaf which is not the last one
+        let chunk_size = first_leaf.end - first_leaf.start;
+        let chunker = if first_leaf.end < size && chunk_size > 0 {
+            Chunker::Size(chunk_size as usize)
+        } else {
+            Chunker::default()
+        };
+
+        let is_inline = |cid: &Cid| cid.hash().algorithm() == multihash::Code::Identity;
+
+        let hash = [&root.cid, &block.cid]
+            .iter()
+            .find(|cid| !is_inline(cid))
+            .map(|cid| cid.hash().algorithm())
+            .unwrap_or(multihash::Code::Sha2_256);
+
+        let inline_limit = if is_inline(&root.cid) || is_inline(&block.cid) {
+            Some(INLINE_LIMIT)
+        } else {
+            None
+        };
+
+        let opts = AddOptions {
+            chunker,
+            layout,
+            cid_options: CidOptions::new(root.cid.version(), hash),
+            raw_leaves: Some(block.cid.codec() == Codec::Raw),
+            inline_limit,
+            pin: false,
+            wrap: None,
+        };
+
+        Ok(ExistingFile {
+            root,
+            size,
+            height,
+            trickle,
+            opts,
+            metadata,
+        })
+    }
+
+    /// Returns the subtrees linked from a link block of the given height.
+    fn subtrees(&self, links: Vec<(Cid, u64, Range<u64>)>, height: usize) -> Vec<Subtree> {
+        links
+            .into_iter()
+            .enumerate()
+            .map(|(index, (cid, total_size, range))| {
+                let height = if cid.codec() == Codec::Raw {
+                    0
+                } else if self.trickle {
+                    // the link blocks of the trickle layout first link to the leaves
+                    if index < BRANCHING_FACTOR {
+                        0
+                    } else {
+                        1
+                    }
+                } else {
+                    height.saturating_sub(1)
+                };
+
+                Subtree {
+                    cid,
+                    total_size,
+                    range,
+                    height,
+                }
+            })
+            .collect()
+    }
+}
+
+/// A subtree of an existing file, covering the range of the file bytes.
+struct Subtree {
+    cid: Cid,
+    total_size: u64,
+    range: Range<u64>,
+    /// Zero for the leaves, which are linked to without loading them when possible.
+    height: usize,
+}
+
+/// The contents of a block of a file.
+enum FileNode {
+    /// The bytes of a leaf.
+    Leaf(Bytes),
+    /// The links of a link block, with their cumulative sizes and the ranges of the file bytes
+    /// they cover starting from the offset of the block.
+    Links(Vec<(Cid, u64, Range<u64>)>),
+}
+
+/// Reads the bytes or the links of a block of a file, which starts at the `offset` of the file.
+fn file_node(block: &Block, offset: u64) -> Result<(FileNode, Metadata), MfsError> {
+    if block.cid.codec() == Codec::Raw {
+        return Ok((FileNode::Leaf(block.data.clone()), Metadata::default()));
+    }
+
+    let (content, metadata) =
+        read_block(&block.data).map_err(|_| MfsError::NotUnixFs(block.cid.to_owned()))?;
+
+    let node = match content {
+        FileBlock::Bytes(bytes) | FileBlock::RawBytes(bytes) => {
+            FileNode::Leaf(block.data.slice_ref(bytes))
+        }
+        FileBlock::Links(links) => FileNode::Links(
+            links
+                .into_iter()
+                .map(|(cid, total_size, range)| {
+                    (cid, total_size, offset + range.start..offset + range.end)
+                })
+                .collect(),
+        ),
+    };
+
+    Ok((node, metadata))
+}
+
+/// Creates the new blocks of a written file, storing the ones which were not loaded from the
+/// existing file.
+struct FileWriter<'a, Types: IpfsTypes> {
+    ipfs: &'a Ipfs<Types>,
+    adder: FileAdder,
+    /// The blocks loaded from the existing file.
+    known: HashSet<Cid>,
+}
+
+impl<Types: IpfsTypes> FileWriter<'_, Types> {
+    /// Writes the input at the offset of the existing file, visiting its leaves in order. The
+    /// leaves before the offset and after the written bytes are linked to as long as the chunks
+    /// line up with them.
+    async fn modify(
+        &mut self,
+        file: ExistingFile,
+        offset: u64,
+        input: &mut (impl AsyncRead + Unpin),
+    ) -> Result<(), MfsError> {
+        // the end of the written bytes, once the input has been written
+        let mut written = None;
+
+        // the subtrees left to visit, the next one last
+        let mut pending = vec![Subtree {
+            cid: file.root.cid.clone(),
+            total_size: 0,
+            range: 0..file.size,
+            height: file.height,
+        }];
+
+        while let Some(subtree) = pending.pop() {
+            let Range { start, end } = subtree.range.clone();
+
+            // the last leaf is always chunked again so that appending does not leave it short
+            let kept = match written {
+                None => end <= offset && end < file.size,
+                Some(written) if end <= written => continue,
+                Some(written) => start >= written,
+            };
+
+            if kept && subtree.height == 0 && self.push_leaf(&subtree).await? {
+                continue;
+            }
+
+            let block = load(self.ipfs, &subtree.cid).await?;
+            self.known.insert(block.cid.clone());
+
+            let bytes = match file_node(&block, start)?.0 {
+                FileNode::Links(links) => {
+                    pending.extend(file.subtrees(links, subtree.height).into_iter().rev());
+                    continue;
+                }
+                FileNode::Leaf(bytes) => bytes,
+            };
+
+            let rest = match written {
+                None if kept => 0,
+                None => {
+                    let before = (offset - start) as usize;
+                    self.push(&bytes[..before]).await?;
+
+                    let end_of_input = offset + self.push_input(input).await?;
+                    written = Some(end_of_input);
+
+                    end_of_input - start
+                }
+                Some(written) => written.saturating_sub(start),
+            };
+
+            if let Some(rest) = bytes.get(rest as usize..) {
+                self.push(rest).await?;
+            }
+        }
+
+        if written.is_none() {
+            self.push_input(input).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Links to the leaf, returning false when the bytes of the leaf need to be pushed instead.
+    async fn push_leaf(&mut self, leaf: &Subtree) -> Result<bool, MfsError> {
+        let file_size = leaf.range.end - leaf.range.start;
+
+        match self
+            .adder
+            .push_leaf(leaf.cid.clone(), leaf.total_size, file_size)
+        {
+            Some(blocks) => {
+                let blocks = blocks.collect::<Vec<_>>();
+                self.store(blocks).await?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Pushes the bytes to the adder, storing the completed blocks.
+    async fn push(&mut self, mut bytes: &[u8]) -> Result<(), MfsError> {
+        while !bytes.is_empty() {
+            let (blocks, used) = self.adder.push(bytes);
+            let blocks = blocks.collect::<Vec<_>>();
+            bytes = &bytes[used..];
+            self.store(blocks).await?;
+        }
+        Ok(())
+    }
+
+    /// Pushes all of the input, returning the number of bytes read.
+    async fn push_input(&mut self, input: &mut (impl AsyncRead + Unpin)) -> Result<u64, MfsError> {
+        let mut buffer = vec![0u8; READ_BUFFER_SIZE];
+        let mut written = 0u64;
+
+        loop {
+            let read = input
+                .read(&mut buffer)
+                .await
+                .map_err(|e| MfsError::Adding(AddError::Reading(e)))?;
+
+            if read == 0 {
+                return Ok(written);
+            }
+
+            self.push(&buffer[..read]).await?;
+            written += read as u64;
+        }
+    }
+
+    /// Stores the last blocks, returning the Cid and the cumulative size of the file.
+    async fn finish(mut self) -> Result<(Cid, u64), MfsError> {
+        let blocks = std::mem::take(&mut self.adder).finish().collect::<Vec<_>>();
+
+        let root = blocks
+            .last()
+            .map(|(cid, data)| Block::new(data.clone(), cid.clone()))
+            .expect("finish always produces the root block");
+        let (total_size, _) = sizes(&root)?;
+
+        self.store(blocks).await?;
+
+        Ok((root.cid, total_size))
+    }
+
+    async fn store(&self, blocks: Vec<(Cid, Vec<u8>)>) -> Result<(), MfsError> {
+        let known = &self.known;
+        let blocks = blocks.into_iter().filter(|(cid, _)| !known.contains(cid));
+
+        store_all(self.ipfs, blocks)
+            .await
+            .map_err(MfsError::Adding)?;
+        Ok(())
+    }
+}
+
+/// Types of failures which can occur with the mutable file system operations.
+#[derive(Debug, thiserror::Error)]
+pub enum MfsError {
+    /// The path was not absolute, or it contained `.` or `..` segments, or an entry would had been
+    /// moved into itself.
+    #[error("invalid path {:?}", .0)]
+    InvalidPath(String),
+
+    /// There was no file or directory at the path.
+    #[error("file does not exist: {:?}", .0)]
+    NotFound(String),
+
+    /// There already was a file or directory at the path.
+    #[error("file already exists: {:?}", .0)]
+    AlreadyExists(String),
+
+    /// The path was expected to be a directory.
+    #[error("not a directory: {:?}", .0)]
+    NotADirectory(String),
+
+    /// The path was expected to be a file.
+    #[error("not a file: {:?}", .0)]
+    NotAFile(String),
+
+    /// Removing a directory requires removing it recursively.
+    #[error("{:?} is a directory, use recursive to remove it", .0)]
+    IsADirectory(String),
+
+    /// The offset of a write was past the end of the file.
+    #[error("offset {} is past the end of the file of {} bytes", .0, .1)]
+    InvalidOffset(u64, u64),
+
+    /// The block is not an UnixFS block.
+    #[error("{} is not an UnixFS block", .0)]
+    NotUnixFs(Cid),
+
+    /// Resolving the path failed.
+    #[error("path resolving failed")]
+    Resolving(#[source] ResolveError),
+
+    /// The path was resolved to something else than a block.
+    #[error("path resolved to unexpected")]
+    Path(#[source] Box<UnexpectedResolved>),
+
+    /// Loading of a block failed.
+    #[error("loading of {} failed", .0)]
+    Loading(Cid, #[source] Error),
+
+    /// Listing a directory failed.
+    #[error("listing failed")]
+    Listing(#[source] Box<LsError>),
+
+    /// Reading a file failed.
+    #[error("reading failed")]
+    Reading(#[source] Box<TraversalFailed>),
+
+    /// Adding the written file failed.
+    #[error("adding the file failed")]
+    Adding(#[source] AddError),
+
+    /// The entries of a directory could not be added to the directory builder.
+    #[error("building the directory failed")]
+    TreeBuilding(#[source] TreeBuildingFailed),
+
+    /// Rendering a directory failed.
+    #[error("rendering the directory failed")]
+    TreeConstruction(#[source] TreeConstructionFailed),
+
+    /// Storing the blocks or the root failed.
+    #[error("storing failed")]
+    Persisting(#[source] Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MfsError, WriteOptions};
+    use crate::unixfs::{added_root, AddOptions};
+    use crate::{Block, Cid, Ipfs, IpfsOptions, IpfsTypes, Node, Types, UninitializedIpfs};
+    use cid::Version;
+    use futures::stream::TryStreamExt;
+    use ipfs_unixfs::dir::EntryType;
+    use ipfs_unixfs::file::adder::{Chunker, Collector, FileAdder, TrickleCollector};
+    use ipfs_unixfs::file::visit::read_block;
+    use ipfs_unixfs::{CidOptions, Metadata};
+    use multihash::Code;
+    use std::path::Path;
+
+    #[tokio::test(max_threads = 1)]
+    async fn write_and_read_files() {
+        let ipfs = Node::new("test_node").await;
+
+        ipfs.files_mkdir("/a/b", true).await.unwrap();
+        ipfs.files_mkdir("/a/b", true).await.unwrap();
+        write(&ipfs, "/a/b/c.txt", b"foobar\n", create()).await;
+
+        assert_eq!(read(&ipfs, "/a/b/c.txt").await, b"foobar\n");
+
+        let stat = ipfs.files_stat("/a/b/c.txt").await.unwrap();
+        assert_eq!(stat.entry_type, EntryType::File(7));
+        assert_eq!(
+            stat.cid.to_string(),
+            "QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL"
+        );
+
+        let listed = ipfs.files_ls("/a", true).await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].name, "b");
+        assert_eq!(listed[0].entry_type, Some(EntryType::Directory));
+
+        let listed = ipfs.files_ls("/a/b/c.txt", false).await.unwrap();
+        assert_eq!(listed[0].name, "c.txt");
+        assert_eq!(listed[0].cid, stat.cid);
+
+        let root = ipfs.files_flush("/").await.unwrap();
+        assert_eq!(
+            ipfs.repo.get_files_root().await.unwrap(),
+            Some(root.clone())
+        );
+
+        let path = format!("/ipfs/{}/a/b/c.txt", root);
+        let contents = ipfs
+            .cat_unixfs(path.parse::<crate::IpfsPath>().unwrap(), None)
+            .await
+            .unwrap()
+            .try_concat()
+            .await
+            .unwrap();
+        assert_eq!(contents, b"foobar\n");
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn overwrite_at_offsets() {
+        let ipfs = Node::new("test_node").await;
+
+        write(&ipfs, "/f", b"foobar", create()).await;
+        write(&ipfs, "/f", b"XY", at(2)).await;
+        assert_eq!(read(&ipfs, "/f").await, b"foXYar");
+
+        write(&ipfs, "/f", b"!", at(6)).await;
+        assert_eq!(read(&ipfs, "/f").await, b"foXYar!");
+
+        let truncate = WriteOptions {
+            truncate: true,
+            ..Default::default()
+        };
+        write(&ipfs, "/f", b"new", truncate).await;
+        assert_eq!(read(&ipfs, "/f").await, b"new");
+
+        let err = ipfs.files_write("/f", &b"x"[..], at(4)).await.unwrap_err();
+        assert!(
+            matches!(err, MfsError::InvalidOffset(4, 3)),
+            "unexpected error: {:?}",
+            err
+        );
+
+        let range = ipfs
+            .files_read("/f", Some(1..2))
+            .await
+            .unwrap()
+            .try_concat()
+            .await
+            .unwrap();
+        assert_eq!(range, b"e");
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn overwrite_stores_only_the_changed_blocks() {
+        let ipfs = Node::new("test_node").await;
+
+        let opts = AddOptions {
+            chunker: Chunker::Size(4),
+            pin: false,
+            ..Default::default()
+        };
+
+        copy_added(&ipfs, "/f", b"foobarbazqux", opts.clone()).await;
+        let before = ipfs.repo.list_blocks().await.unwrap();
+
+        write(&ipfs, "/f", b"XY", at(5)).await;
+
+        let after = ipfs.repo.list_blocks().await.unwrap();
+        let file = ipfs.files_flush("/f").await.unwrap();
+
+        assert_eq!(file, added(&ipfs, b"foobaXYazqux", opts).await);
+        // the second leaf, the root of the file and the root directory
+        assert_eq!(after.len() - before.len(), 3, "{:?}", after);
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn writes_keep_the_layout_and_the_options() {
+        let ipfs = Node::new("test_node").await;
+
+        let content = (0..200u8).collect::<Vec<_>>();
+
+        let layouts: [Collector; 2] = [Collector::default(), TrickleCollector::default().into()];
+
+        for layout in layouts.iter() {
+            let opts = AddOptions {
+                chunker: Chunker::Size(1),
+                layout: layout.clone(),
+                cid_options: CidOptions::new(Version::V1, Code::Sha2_512),
+                pin: false,
+                ..Default::default()
+            };
+
+            // writing over the leaves in the middle, and past the end of the file
+            for (offset, data) in [(100, &b"middle"[..]), (197, b"end of the file")].iter() {
+                copy_added(&ipfs, "/f", &content, opts.clone()).await;
+                write(&ipfs, "/f", data, at(*offset)).await;
+
+                let mut expected = content.clone();
+                expected.truncate(*offset as usize);
+                expected.extend_from_slice(data);
+                if expected.len() < content.len() {
+                    expected.extend_from_slice(&content[expected.len()..]);
+                }
+
+                assert_eq!(
+                    ipfs.files_flush("/f").await.unwrap(),
+                    added(&ipfs, &expected, opts.clone()).await,
+                    "{:?} at {}",
+                    layout,
+                    offset
+                );
+
+                ipfs.files_rm("/f", false).await.unwrap();
+            }
+        }
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn writes_keep_the_trickle_layout_of_small_files() {
+        let ipfs = Node::new("test_node").await;
+
+        let content = (0..80u8).collect::<Vec<_>>();
+
+        // without raw leaves the trickle layout is told apart by the type of the leaves, while
+        // the root links only to the leaves like in the balanced layout
+        let opts = AddOptions {
+            chunker: Chunker::Size(4),
+            layout: TrickleCollector::default().into(),
+            raw_leaves: Some(false),
+            pin: false,
+            ..Default::default()
+        };
+
+        for single_leaf in [false, true].iter() {
+            let content = if *single_leaf {
+                &content[..2]
+            } else {
+                &content[..]
+            };
+
+            copy_added(&ipfs, "/f", content, opts.clone()).await;
+            write(&ipfs, "/f", b"XY", at(content.len() as u64 - 1)).await;
+
+            let mut expected = content.to_vec();
+            expected.pop();
+            expected.extend_from_slice(b"XY");
+
+            assert_eq!(
+                ipfs.files_flush("/f").await.unwrap(),
+                added(&ipfs, &expected, opts.clone()).await,
+                "single leaf: {}",
+                single_leaf
+            );
+
+            ipfs.files_rm("/f", false).await.unwrap();
+        }
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn writes_keep_the_metadata() {
+        let ipfs = Node::new("test_node").await;
+
+        let mut adder = FileAdder::builder()
+            .with_chunker(Chunker::Size(4))
+            .with_metadata(Metadata::default().with_mode(0o600).with_mtime(1, 2))
+            .build();
+
+        let mut blocks = Vec::new();
+        let mut content = &b"foobar"[..];
+
+        while !content.is_empty() {
+            let (ready, pushed) = adder.push(content);
+            blocks.extend(ready);
+            content = &content[pushed..];
+        }
+
+        blocks.extend(adder.finish());
+        let root = blocks.last().unwrap().0.clone();
+
+        for (cid, data) in blocks {
+            ipfs.put_block(Block::new(data, cid)).await.unwrap();
+        }
+
+        ipfs.files_cp(&format!("/ipfs/{}", root), "/f")
+            .await
+            .unwrap();
+        write(&ipfs, "/f", b"!", at(6)).await;
+
+        let root = ipfs.files_flush("/f").await.unwrap();
+        let block = ipfs.get_block(&root).await.unwrap();
+        let (_, metadata) = read_block(&block.data).unwrap();
+
+        assert_eq!(metadata.mode(), Some(0o600));
+        assert_eq!(metadata.mtime(), Some((1, 2)));
+        assert_eq!(read(&ipfs, "/f").await, b"foobar!");
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn move_copy_and_remove() {
+        let ipfs = Node::new("test_node").await;
+
+        write(&ipfs, "/a/f", b"foobar\n", create_parents()).await;
+        ipfs.files_mkdir("/b", false).await.unwrap();
+
+        ipfs.files_mv("/a/f", "/b").await.unwrap();
+        ipfs.files_cp("/b/f", "/c").await.unwrap();
+        assert_eq!(read(&ipfs, "/b/f").await, b"foobar\n");
+        assert_eq!(read(&ipfs, "/c").await, b"foobar\n");
+        assert!(ipfs.files_ls("/a", false).await.unwrap().is_empty());
+
+        let err = ipfs.files_mv("/b", "/b/d").await.unwrap_err();
+        assert!(matches!(err, MfsError::InvalidPath(_)), "{:?}", err);
+
+        let err = ipfs.files_cp("/c", "/b/f").await.unwrap_err();
+        assert!(matches!(err, MfsError::AlreadyExists(_)), "{:?}", err);
+
+        let err = ipfs.files_rm("/b", false).await.unwrap_err();
+        assert!(matches!(err, MfsError::IsADirectory(_)), "{:?}", err);
+
+        let dir = ipfs.files_flush("/b").await.unwrap();
+        ipfs.files_rm("/b", true).await.unwrap();
+        ipfs.files_rm("/c", false).await.unwrap();
+
+        let err = ipfs.files_stat("/b").await.unwrap_err();
+        assert!(matches!(err, MfsError::NotFound(_)), "{:?}", err);
+
+        ipfs.files_cp(&format!("/ipfs/{}", dir), "/a")
+            .await
+            .unwrap();
+        assert_eq!(read(&ipfs, &format!("/a/{}/f", dir)).await, b"foobar\n");
+
+        let names = ipfs
+            .files_ls("/", false)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| e.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, &["a"]);
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn invalid_operations() {
+        let ipfs = Node::new("test_node").await;
+
+        ipfs.files_mkdir("/Data", false).await.unwrap();
+
+        let err = ipfs.files_mkdir("relative", false).await.unwrap_err();
+        assert!(matches!(err, MfsError::InvalidPath(_)), "{:?}", err);
+
+        let err = ipfs.files_mkdir("/a/../b", true).await.unwrap_err();
+        assert!(matches!(err, MfsError::InvalidPath(_)), "{:?}", err);
+
+        let err = ipfs.files_mkdir("/Data", false).await.unwrap_err();
+        assert!(matches!(err, MfsError::AlreadyExists(_)), "{:?}", err);
+
+        let err = ipfs.files_mkdir("/a/b", false).await.unwrap_err();
+        assert!(matches!(err, MfsError::NotFound(_)), "{:?}", err);
+
+        let err = ipfs
+            .files_write("/f", &b"foobar"[..], Default::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, MfsError::NotFound(_)), "{:?}", err);
+
+        let err = ipfs
+            .files_write("/Data", &b"foobar"[..], create())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, MfsError::NotAFile(_)), "{:?}", err);
+
+        let err = ipfs.files_rm("/", true).await.unwrap_err();
+        assert!(matches!(err, MfsError::InvalidPath(_)), "{:?}", err);
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn root_survives_restart() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        let ipfs = start(tmp.path()).await;
+        write(&ipfs, "/a/f", b"foobar\n", create_parents()).await;
+        let root = ipfs.files_flush("/").await.unwrap();
+        ipfs.exit_daemon().await;
+
+        let ipfs = start(tmp.path()).await;
+        assert_eq!(ipfs.files_flush("/").await.unwrap(), root);
+        assert_eq!(read(&ipfs, "/a/f").await, b"foobar\n");
+        ipfs.exit_daemon().await;
+    }
+
+    async fn start(path: &Path) -> Ipfs<Types> {
+        let mut opts = IpfsOptions::inmemory_with_generated_keys();
+        opts.ipfs_path = path.to_owned();
+
+        let (ipfs, fut) = UninitializedIpfs::<Types>::new(opts, None)
+            .await
+            .start()
+            .await
+            .unwrap();
+        tokio::task::spawn(fut);
+        ipfs
+    }
+
+    async fn write<T: IpfsTypes>(ipfs: &Ipfs<T>, path: &str, data: &[u8], opts: WriteOptions) {
+        ipfs.files_write(path, data, opts).await.unwrap();
+    }
+
+    async fn read<T: IpfsTypes>(ipfs: &Ipfs<T>, path: &str) -> Vec<u8> {
+        ipfs.files_read(path, None)
+            .await
+            .unwrap()
+            .try_concat()
+            .await
+            .unwrap()
+    }
+
+    /// Adds the content with the options, and copies the added file to the path.
+    async fn copy_added<T: IpfsTypes>(
+        ipfs: &Ipfs<T>,
+        path: &str,
+        content: &[u8],
+        opts: AddOptions,
+    ) {
+        let cid = added(ipfs, content, opts).await;
+        ipfs.files_cp(&format!("/ipfs/{}", cid), path)
+            .await
+            .unwrap();
+    }
+
+    async fn added<T: IpfsTypes>(ipfs: &Ipfs<T>, content: &[u8], opts: AddOptions) -> Cid {
+        let events = ipfs
+            .add_unixfs(content, opts)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        added_root(&events)
+    }
+
+    fn create() -> WriteOptions {
+        WriteOptions {
+            create: true,
+            ..Default::default()
+        }
+    }
+
+    fn create_parents() -> WriteOptions {
+        WriteOptions {
+            create: true,
+            parents: true,
+            ..Default::default()
+        }
+    }
+
+    fn at(offset: u64) -> WriteOptions {
+        WriteOptions {
+            offset,
+            ..Default::default()
+        }
+    }
+}
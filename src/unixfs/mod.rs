@@ -1,8 +1,50 @@
 pub use ipfs_unixfs as ll;
 
+mod add;
+pub use add::{add, store_directories, AddError, AddEvent, AddOptions};
+
+mod add_path;
+pub use add_path::{add_path, AddPathOptions};
+
+mod add_tar;
+pub use add_tar::{add_tar, AddTarOptions};
+
+mod ignore;
+
 mod cat;
 pub use cat::{cat, StartingPoint, TraversalFailed};
 
+mod get;
+pub use get::{get, GetError};
+
+mod open;
+pub use open::{open, UnixfsFile};
+
+mod ls;
+pub use ls::{ls, LsEntry, LsError};
+
+pub mod mfs;
+
+/// Runs the filesystem access on a blocking thread.
+async fn blocking<T, F>(f: F) -> std::io::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> std::io::Result<T> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+}
+
+/// Returns the root `Cid` from the events of an add, which is the last one reported.
+#[cfg(test)]
+pub(crate) fn added_root(events: &[AddEvent]) -> cid::Cid {
+    match events.last() {
+        Some(AddEvent::Added { cid, .. }) => cid.clone(),
+        other => panic!("unexpected last event: {:?}", other),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
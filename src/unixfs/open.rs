@@ -0,0 +1,385 @@
+use super::{StartingPoint, TraversalFailed};
+use crate::{Ipfs, IpfsTypes};
+use bitswap::Block;
+use cid::Cid;
+use futures::future::BoxFuture;
+use ipfs_unixfs::file::visit::IdleFileVisit;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io::{self, SeekFrom};
+use std::ops::Range;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncSeek};
+
+/// The minimum amount of bytes read at once, regardless of the size of the buffer given to
+/// `poll_read`. Same as the default chunk size of the files added with `ipfs::unixfs::add`.
+const READ_AHEAD: u64 = 256 * 1024;
+
+/// The number of the link blocks kept around in order not to load them again when reading or
+/// seeking to a nearby offset.
+const LINK_CACHE_SIZE: usize = 32;
+
+/// Opens a UnixFS file for reading at arbitrary offsets; see [`UnixfsFile`].
+pub async fn open<Types: IpfsTypes>(
+    ipfs: Ipfs<Types>,
+    starting_point: impl Into<StartingPoint>,
+) -> Result<UnixfsFile<Types>, TraversalFailed> {
+    let root = match starting_point.into() {
+        StartingPoint::Left(path) => {
+            let (resolved, _) = ipfs
+                .dag()
+                .resolve(path, true)
+                .await
+                .map_err(TraversalFailed::Resolving)?;
+            resolved
+                .into_unixfs_block()
+                .map_err(TraversalFailed::Path)?
+        }
+        StartingPoint::Right(block) => block,
+    };
+
+    // walk nothing of the root block only to validate it and to find out the size of the file
+    let size = match IdleFileVisit::default()
+        .with_target_range(0..0)
+        .start(&root.data)
+    {
+        Ok((_, size, _, _)) => size,
+        Err(e) => return Err(TraversalFailed::Walking(root.cid, e)),
+    };
+
+    Ok(UnixfsFile {
+        ipfs,
+        root,
+        size,
+        position: 0,
+        buffer: Vec::new(),
+        buffer_start: 0,
+        links: Default::default(),
+        pending: None,
+    })
+}
+
+type PendingRead = BoxFuture<'static, Result<Vec<u8>, TraversalFailed>>;
+
+/// A handle to a UnixFS file implementing `AsyncRead` and `AsyncSeek`, created with
+/// [`open`] or `Ipfs::open_unixfs`.
+///
+/// Every read past the buffered bytes walks the file tree from the root block to the blocks
+/// containing the bytes at the current position, skipping the unrelated blocks. The recently
+/// visited link blocks are kept in memory, so reading forward or seeking back and forth near the
+/// same offsets will only load the missing leaf blocks.
+pub struct UnixfsFile<Types: IpfsTypes> {
+    ipfs: Ipfs<Types>,
+    root: Block,
+    size: u64,
+    position: u64,
+    /// Bytes of the file starting from `buffer_start`.
+    buffer: Vec<u8>,
+    buffer_start: u64,
+    links: Arc<Mutex<LinkCache>>,
+    /// The offset and the read in progress.
+    pending: Option<(u64, PendingRead)>,
+}
+
+impl<Types: IpfsTypes> UnixfsFile<Types> {
+    /// Returns the Cid of the root block of the file.
+    pub fn cid(&self) -> &Cid {
+        &self.root.cid
+    }
+
+    /// Returns the total size of the file in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns the current position in the file.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    fn buffered(&self) -> Option<&[u8]> {
+        let offset = self.position.checked_sub(self.buffer_start)?;
+        self.buffer
+            .get(offset as usize..)
+            .filter(|bytes| !bytes.is_empty())
+    }
+}
+
+impl<Types: IpfsTypes> AsyncRead for UnixfsFile<Types> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        loop {
+            if this.position >= this.size || buf.is_empty() {
+                return Poll::Ready(Ok(0));
+            }
+
+            if let Some(bytes) = this.buffered() {
+                let n = bytes.len().min(buf.len());
+                buf[..n].copy_from_slice(&bytes[..n]);
+                this.position += n as u64;
+                return Poll::Ready(Ok(n));
+            }
+
+            let position = this.position;
+
+            // a read started before seeking elsewhere is of no use
+            if this.pending.as_ref().map(|(start, _)| *start) != Some(position) {
+                let end = position
+                    .saturating_add(READ_AHEAD.max(buf.len() as u64))
+                    .min(this.size);
+
+                let fut = read_range(
+                    this.ipfs.clone(),
+                    this.root.clone(),
+                    Arc::clone(&this.links),
+                    position..end,
+                );
+
+                this.pending = Some((position, Box::pin(fut)));
+            }
+
+            let (_, fut) = this.pending.as_mut().expect("pending read was just set");
+
+            let bytes = match Pin::new(fut).poll(cx) {
+                Poll::Ready(res) => {
+                    this.pending = None;
+                    res.map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
+                }
+                Poll::Pending => return Poll::Pending,
+            };
+
+            if bytes.is_empty() {
+                // the blocks do not add up to the size of the file recorded in the root block
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "file ended before its recorded size",
+                )));
+            }
+
+            this.buffer = bytes;
+            this.buffer_start = position;
+        }
+    }
+}
+
+impl<Types: IpfsTypes> AsyncSeek for UnixfsFile<Types> {
+    fn start_seek(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        position: SeekFrom,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        let (base, offset) = match position {
+            SeekFrom::Start(offset) => {
+                this.position = offset;
+                return Poll::Ready(Ok(()));
+            }
+            SeekFrom::End(offset) => (this.size, offset),
+            SeekFrom::Current(offset) => (this.position, offset),
+        };
+
+        let position = if offset >= 0 {
+            base.checked_add(offset as u64)
+        } else {
+            base.checked_sub(offset.wrapping_neg() as u64)
+        };
+
+        match position {
+            Some(position) => {
+                this.position = position;
+                Poll::Ready(Ok(()))
+            }
+            None => Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            ))),
+        }
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.position))
+    }
+}
+
+/// Reads the bytes of the range by walking the file from the root block, using and updating the
+/// cached link blocks on the way.
+async fn read_range<Types: IpfsTypes>(
+    ipfs: Ipfs<Types>,
+    root: Block,
+    links: Arc<Mutex<LinkCache>>,
+    range: Range<u64>,
+) -> Result<Vec<u8>, TraversalFailed> {
+    let mut buffer = Vec::with_capacity((range.end - range.start) as usize);
+
+    let (bytes, _, _, mut visit) = IdleFileVisit::default()
+        .with_target_range(range)
+        .start(&root.data)
+        .map_err(|e| TraversalFailed::Walking(root.cid.clone(), e))?;
+
+    buffer.extend_from_slice(bytes);
+
+    let mut cache = None;
+
+    while let Some(current) = visit {
+        let next = current.pending_links().0.to_owned();
+
+        let cached = links.lock().unwrap().get(&next);
+
+        let block = match cached {
+            Some(block) => block,
+            None => ipfs
+                .get_block(&next)
+                .await
+                .map_err(|e| TraversalFailed::Loading(next.clone(), e))?,
+        };
+
+        let (bytes, next_visit) = current
+            .continue_walk(&block.data, &mut cache)
+            .map_err(|e| TraversalFailed::Walking(next, e))?;
+
+        let is_link = bytes.is_empty() && next_visit.is_some();
+        buffer.extend_from_slice(bytes);
+
+        if is_link {
+            links.lock().unwrap().put(block);
+        }
+
+        visit = next_visit;
+    }
+
+    Ok(buffer)
+}
+
+/// The most recently used link blocks, the latest one at the back.
+#[derive(Default)]
+struct LinkCache {
+    blocks: VecDeque<Block>,
+}
+
+impl LinkCache {
+    fn get(&mut self, cid: &Cid) -> Option<Block> {
+        let index = self.blocks.iter().position(|block| &block.cid == cid)?;
+        let block = self.blocks.remove(index)?;
+        self.blocks.push_back(block.clone());
+        Some(block)
+    }
+
+    fn put(&mut self, block: Block) {
+        if self.blocks.iter().any(|cached| cached.cid == block.cid) {
+            return;
+        }
+
+        if self.blocks.len() == LINK_CACHE_SIZE {
+            self.blocks.pop_front();
+        }
+
+        self.blocks.push_back(block);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LINK_CACHE_SIZE;
+    use crate::unixfs::added_root;
+    use crate::{Cid, Node};
+    use futures::stream::TryStreamExt;
+    use std::io::SeekFrom;
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+    /// Content which differs at every offset within a block.
+    fn content(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    async fn added(ipfs: &Node, content: &[u8]) -> Cid {
+        let events = ipfs
+            .add_unixfs(content, Default::default())
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        added_root(&events)
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn read_to_end() {
+        let ipfs = Node::new("test_node").await;
+        let content = content(1024 * 1024 + 1);
+        let cid = added(&ipfs, &content).await;
+
+        let mut file = ipfs.open_unixfs(cid).await.unwrap();
+        assert_eq!(file.size(), content.len() as u64);
+
+        let mut read = Vec::new();
+        file.read_to_end(&mut read).await.unwrap();
+        assert!(read == content, "read content differs");
+        assert_eq!(file.position(), content.len() as u64);
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn seek_and_read() {
+        let ipfs = Node::new("test_node").await;
+        let content = content(3 * 256 * 1024 + 100);
+        let cid = added(&ipfs, &content).await;
+
+        let mut file = ipfs.open_unixfs(cid).await.unwrap();
+        let mut buf = [0u8; 16];
+
+        for &offset in &[600_000u64, 10, 262_140, 786_430] {
+            assert_eq!(file.seek(SeekFrom::Start(offset)).await.unwrap(), offset);
+            file.read_exact(&mut buf).await.unwrap();
+            let offset = offset as usize;
+            assert_eq!(&buf[..], &content[offset..offset + 16]);
+        }
+
+        assert_eq!(
+            file.seek(SeekFrom::Current(-32)).await.unwrap(),
+            786_430 + 16 - 32
+        );
+
+        let end = file.seek(SeekFrom::End(-4)).await.unwrap();
+        let mut rest = Vec::new();
+        file.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(&rest[..], &content[end as usize..]);
+
+        // reading past the end produces no bytes
+        file.seek(SeekFrom::End(10)).await.unwrap();
+        assert_eq!(file.read(&mut buf).await.unwrap(), 0);
+
+        assert!(file.seek(SeekFrom::Current(-(1 << 40))).await.is_err());
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn link_cache_is_bounded() {
+        let mut cache = super::LinkCache::default();
+        let ipfs = Node::new("test_node").await;
+
+        for i in 0..LINK_CACHE_SIZE + 1 {
+            let data = vec![i as u8];
+            let cid = added(&ipfs, &data).await;
+            cache.put(ipfs.get_block(&cid).await.unwrap());
+        }
+
+        assert_eq!(cache.blocks.len(), LINK_CACHE_SIZE);
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn directory_is_not_a_file() {
+        let ipfs = Node::new("test_node").await;
+        let dir = ipfs
+            .object()
+            .create(crate::object::ObjectTemplate::UnixFsDir)
+            .await
+            .unwrap();
+
+        assert!(ipfs.open_unixfs(dir).await.is_err());
+    }
+}
@@ -16,7 +16,7 @@ fn filter(i: usize) -> bool {
 async fn bitswap_stress_test() {
     tracing_subscriber::fmt::init();
 
-    let data = b"hello block\n".to_vec().into_boxed_slice();
+    let data = bytes::Bytes::from_static(b"hello block\n");
     let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
 
     const NODE_COUNT: usize = 3;
@@ -8,7 +8,7 @@ use tokio::time::timeout;
 async fn exchange_block() {
     tracing_subscriber::fmt::init();
 
-    let data = b"hello block\n".to_vec().into_boxed_slice();
+    let data = bytes::Bytes::from_static(b"hello block\n");
     let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
 
     let a = Node::new("a").await;
@@ -298,7 +298,7 @@ async fn dht_providing() {
     let last_index = CHAIN_LEN - if go_node.is_none() { 1 } else { 2 };
 
     // the last node puts a block in order to have something to provide
-    let data = b"hello block\n".to_vec().into_boxed_slice();
+    let data = bytes::Bytes::from_static(b"hello block\n");
     let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
     nodes[last_index]
         .put_block(Block {
@@ -16,6 +16,18 @@ pub fn node_data(block: &[u8]) -> Result<Option<&[u8]>, quick_protobuf::Error> {
     })
 }
 
+/// Returns the cumulative size of the dag-pb node, which is the length of the block added to the
+/// total sizes recorded on its links, along with the number of the links.
+pub fn cumulative_size(block: &[u8]) -> Result<(u64, usize), quick_protobuf::Error> {
+    let doc = PBNode::try_from(block)?;
+    let linked = doc
+        .Links
+        .iter()
+        .map(|link| link.Tsize.unwrap_or_default())
+        .sum::<u64>();
+    Ok((block.len() as u64 + linked, doc.Links.len()))
+}
+
 /// Creates a wrapper around the given block representation which does not consume the block
 /// representation but allows accessing the dag-pb node Data.
 pub fn wrap_node_data<T>(block: T) -> Result<NodeData<T>, quick_protobuf::Error>
@@ -100,9 +112,164 @@ impl<T: AsRef<[u8]>, B: AsRef<[u8]>> PartialEq<B> for NodeData<T> {
     }
 }
 
+/// Checks that the block is in the canonical form required by the [dag-pb spec]: the links come
+/// before the data, the fields of the links are in the order of `Hash`, `Name` and `Tsize`,
+/// every link has a `Hash`, the links are sorted by their names and there are no unknown or
+/// repeated fields. All of the varints must be minimally encoded.
+///
+/// Reading a block which passes this check and writing it back produces the same bytes, and so
+/// the same `Cid`. Regular protobuf decoding, such as [`node_data`], accepts the fields in any
+/// order.
+///
+/// [dag-pb spec]: https://github.com/ipld/specs/blob/master/block-layer/codecs/dag-pb.md
+pub fn check_canonical(block: &[u8]) -> Result<(), NonCanonical> {
+    let mut rest = block;
+    let mut seen_data = false;
+    let mut previous_name: Option<&[u8]> = None;
+
+    while !rest.is_empty() {
+        if seen_data {
+            return Err(NonCanonical::FieldAfterData);
+        }
+
+        match read_minimal_varint(&mut rest)? {
+            // Links, length-delimited
+            0x12 => {
+                let link = read_length_delimited(&mut rest)?;
+                let name = check_link(link)?.unwrap_or_default();
+
+                if let Some(previous) = previous_name {
+                    if previous > name {
+                        return Err(NonCanonical::UnsortedLinks);
+                    }
+                }
+                previous_name = Some(name);
+            }
+            // Data, length-delimited
+            0x0a => {
+                read_length_delimited(&mut rest)?;
+                seen_data = true;
+            }
+            key => return Err(NonCanonical::UnexpectedField(key)),
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks a single `PBLink`, returning the name if one was present.
+fn check_link(mut link: &[u8]) -> Result<Option<&[u8]>, NonCanonical> {
+    if read_minimal_varint(&mut link)? != 0x0a {
+        return Err(NonCanonical::MissingHash);
+    }
+    read_length_delimited(&mut link)?;
+
+    let mut name = None;
+    let mut next_allowed = 0x12;
+
+    while !link.is_empty() {
+        let key = read_minimal_varint(&mut link)?;
+        match key {
+            0x12 if key >= next_allowed => {
+                let bytes = read_length_delimited(&mut link)?;
+                core::str::from_utf8(bytes).map_err(|_| NonCanonical::InvalidName)?;
+                name = Some(bytes);
+                next_allowed = 0x18;
+            }
+            0x18 if key >= next_allowed => {
+                read_minimal_varint(&mut link)?;
+                next_allowed = u64::MAX;
+            }
+            key => return Err(NonCanonical::UnexpectedField(key)),
+        }
+    }
+
+    Ok(name)
+}
+
+/// Reads a protobuf varint from the start of `bytes`, advancing past it. Like any protobuf
+/// decoder this accepts varints encoded with more bytes than needed; the only error is
+/// `NonCanonical::Truncated`.
+pub fn read_varint(bytes: &mut &[u8]) -> Result<u64, NonCanonical> {
+    let mut value = 0u64;
+
+    for (i, &b) in bytes.iter().enumerate().take(10) {
+        value |= ((b & 0x7f) as u64) << (7 * i);
+
+        if b & 0x80 == 0 {
+            *bytes = &bytes[i + 1..];
+            return Ok(value);
+        }
+    }
+
+    Err(NonCanonical::Truncated)
+}
+
+/// Like [`read_varint`] but only accepts the minimal encoding of the value.
+fn read_minimal_varint(bytes: &mut &[u8]) -> Result<u64, NonCanonical> {
+    let start = *bytes;
+    let value = read_varint(bytes)?;
+    let len = start.len() - bytes.len();
+
+    if len > 1 && start[len - 1] == 0 {
+        // the same value would had fit into fewer bytes
+        return Err(NonCanonical::NonMinimalVarint);
+    }
+
+    Ok(value)
+}
+
+fn read_length_delimited<'a>(bytes: &mut &'a [u8]) -> Result<&'a [u8], NonCanonical> {
+    let len = read_minimal_varint(bytes)?;
+
+    if len > bytes.len() as u64 {
+        return Err(NonCanonical::Truncated);
+    }
+
+    let (taken, rest) = bytes.split_at(len as usize);
+    *bytes = rest;
+    Ok(taken)
+}
+
+/// Reasons for a dag-pb block failing [`check_canonical`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum NonCanonical {
+    /// The block ended in the middle of a field.
+    Truncated,
+    /// A varint was encoded with more bytes than needed.
+    NonMinimalVarint,
+    /// The field key was unknown, repeated, or appeared out of order.
+    UnexpectedField(u64),
+    /// There was a field after the `Data` field.
+    FieldAfterData,
+    /// A link did not start with the `Hash` field.
+    MissingHash,
+    /// The name of a link was not valid UTF-8.
+    InvalidName,
+    /// The links were not sorted by their names.
+    UnsortedLinks,
+}
+
+impl fmt::Display for NonCanonical {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use NonCanonical::*;
+        match self {
+            Truncated => write!(fmt, "truncated field"),
+            NonMinimalVarint => write!(fmt, "varint was not minimally encoded"),
+            UnexpectedField(key) => write!(fmt, "unexpected field with key {:#x}", key),
+            FieldAfterData => write!(fmt, "field after the Data field"),
+            MissingHash => write!(fmt, "link without Hash as the first field"),
+            InvalidName => write!(fmt, "link name was not valid UTF-8"),
+            UnsortedLinks => write!(fmt, "links were not sorted by name"),
+        }
+    }
+}
+
+impl std::error::Error for NonCanonical {}
+
 #[cfg(test)]
 mod tests {
-    use super::subslice_to_range;
+    use super::{check_canonical, cumulative_size, subslice_to_range, NonCanonical};
 
     #[test]
     fn subslice_ranges() {
@@ -136,4 +303,73 @@ mod tests {
         assert_eq!(subslice_to_range(a, b_sub), None);
         assert_eq!(subslice_to_range(b, a_sub), None);
     }
+
+    #[test]
+    fn canonical_blocks() {
+        // a directory with links "a" and "b", and an empty file
+        let dir = hex_literal::hex!("12290a221220fc7fac69ddb44e39686ecfd1ecc6c52ab653f4227e533ee74a2e238f8b2143d3120161180712290a221220b924ddb19181d159c29eec7c98ec506976a76d40241ccd203b226849ce6e0b72120162183d0a020801");
+        let file = hex_literal::hex!("0a0408021800");
+
+        assert_eq!(check_canonical(&dir), Ok(()));
+        assert_eq!(check_canonical(&file), Ok(()));
+        assert_eq!(check_canonical(&[]), Ok(()));
+    }
+
+    #[test]
+    fn cumulative_sizes() {
+        // the same directory as above, linking to subtrees of 7 and 61 bytes
+        let dir = hex_literal::hex!("12290a221220fc7fac69ddb44e39686ecfd1ecc6c52ab653f4227e533ee74a2e238f8b2143d3120161180712290a221220b924ddb19181d159c29eec7c98ec506976a76d40241ccd203b226849ce6e0b72120162183d0a020801");
+        let file = hex_literal::hex!("0a0408021800");
+
+        assert_eq!(cumulative_size(&dir).unwrap(), (dir.len() as u64 + 68, 2));
+        assert_eq!(cumulative_size(&file).unwrap(), (file.len() as u64, 0));
+    }
+
+    #[test]
+    fn non_canonical_blocks() {
+        let cases: &[(&[u8], NonCanonical)] = &[
+            // data before links
+            (
+                &hex_literal::hex!("0a020801 12040a020000"),
+                NonCanonical::FieldAfterData,
+            ),
+            // name before hash
+            (
+                &hex_literal::hex!("1206 120161 0a0100"),
+                NonCanonical::MissingHash,
+            ),
+            // tsize before name
+            (
+                &hex_literal::hex!("1208 0a0100 1806 120161"),
+                NonCanonical::UnexpectedField(0x12),
+            ),
+            // repeated name
+            (
+                &hex_literal::hex!("1209 0a0100 120161 120162"),
+                NonCanonical::UnexpectedField(0x12),
+            ),
+            // unknown field in node
+            (
+                &hex_literal::hex!("1800"),
+                NonCanonical::UnexpectedField(0x18),
+            ),
+            // links in the wrong order
+            (
+                &hex_literal::hex!("1206 0a0100 120162 1206 0a0100 120161"),
+                NonCanonical::UnsortedLinks,
+            ),
+            // data length with a redundant continuation byte
+            (&hex_literal::hex!("0a8100"), NonCanonical::NonMinimalVarint),
+            (&hex_literal::hex!("0a05 0801"), NonCanonical::Truncated),
+        ];
+
+        for (block, expected) in cases {
+            assert_eq!(
+                check_canonical(block).as_ref(),
+                Err(expected),
+                "{:02x?}",
+                block
+            );
+        }
+    }
 }
@@ -10,14 +10,19 @@ pub use sharded_lookup::{Cache, LookupError, ShardError, ShardedLookup};
 mod directory;
 pub(crate) use directory::{check_directory_supported, UnexpectedDirectoryProperties};
 
+mod listing;
+pub use listing::{list, EntryType, ListedLink, ListingError, ShardedListing};
+
 /// Directory tree builder.
 pub mod builder;
 
+/// Returns the HAMTShard node and the length of the bucket prefix of its link names, if the node
+/// is supported.
 pub(crate) fn check_hamtshard_supported(
     mut flat: FlatUnixFs<'_>,
-) -> Result<FlatUnixFs<'_>, ShardError> {
-    ShardedLookup::check_supported(&mut flat)?;
-    Ok(flat)
+) -> Result<(FlatUnixFs<'_>, usize), ShardError> {
+    let prefix_len = ShardedLookup::check_supported(&mut flat)?;
+    Ok((flat, prefix_len))
 }
 
 /// Resolves a single path segment on `dag-pb` or UnixFS directories (normal, sharded).
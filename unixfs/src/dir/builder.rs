@@ -1,3 +1,4 @@
+use crate::{CidOptions, Metadata};
 use cid::Cid;
 use core::fmt;
 
@@ -10,9 +11,15 @@ pub use iter::{OwnedTreeNode, PostOrderIterator, TreeNode};
 mod buffered;
 pub use buffered::BufferingTreeBuilder;
 
+mod streaming;
+pub use streaming::{StreamingTreeBuilder, StreamingTreeFailed};
+
 mod custom_pb;
 use custom_pb::CustomFlatUnixFs;
 
+mod hamt;
+pub(crate) use hamt::{is_supported_fanout, prefix_len as hamt_prefix_len, HASH_MURMUR3};
+
 enum Entry {
     Leaf(Leaf),
     Directory(DirBuilder),
@@ -55,6 +62,9 @@ impl fmt::Debug for Leaf {
 pub struct TreeOptions {
     block_size_limit: Option<u64>,
     wrap_with_directory: bool,
+    cid_options: CidOptions,
+    sharding_threshold: Option<u64>,
+    hamt_fanout: u64,
 }
 
 impl Default for TreeOptions {
@@ -63,6 +73,10 @@ impl Default for TreeOptions {
             // this is just a guess; our bitswap message limit is a bit more
             block_size_limit: Some(512 * 1024),
             wrap_with_directory: false,
+            cid_options: CidOptions::default(),
+            // same as go-ipfs
+            sharding_threshold: Some(256 * 1024),
+            hamt_fanout: hamt::DEFAULT_FANOUT,
         }
     }
 }
@@ -79,6 +93,50 @@ impl TreeOptions {
     pub fn wrap_with_directory(&mut self) {
         self.wrap_with_directory = true;
     }
+
+    /// Overrides the Cid version and multihash used for the directory blocks. Defaults to Cid
+    /// version 0 with sha2-256.
+    pub fn cid_options(&mut self, cid_options: CidOptions) {
+        self.cid_options = cid_options;
+    }
+
+    /// Overrides the threshold above which directories are created as HAMT sharded directories.
+    /// Like in go-ipfs, the size of a directory is estimated as the sum of the lengths of the
+    /// names and the binary Cids of its links, and the default threshold is 256 KiB. If the
+    /// threshold is set to `None`, no directory will be sharded.
+    pub fn sharding_threshold(&mut self, threshold: Option<u64>) {
+        self.sharding_threshold = threshold;
+    }
+
+    /// Overrides the fanout of the HAMT sharded directories, which defaults to 256 as in go-ipfs.
+    ///
+    /// # Panics
+    ///
+    /// If the fanout is not a power of two between 8 and 65536.
+    pub fn hamt_fanout(&mut self, fanout: u64) {
+        assert!(
+            is_supported_fanout(fanout),
+            "unsupported HAMT fanout: {}",
+            fanout
+        );
+        self.hamt_fanout = fanout;
+    }
+
+    /// Returns true if the links should be rendered as a HAMT sharded directory.
+    fn should_shard(&self, links: &[Option<NamedLeaf>]) -> bool {
+        let threshold = match self.sharding_threshold {
+            Some(threshold) => threshold,
+            None => return false,
+        };
+
+        let estimate = links
+            .iter()
+            .filter_map(|link| link.as_ref())
+            .map(|NamedLeaf(name, cid, _)| (name.len() + cid.to_bytes().len()) as u64)
+            .sum::<u64>();
+
+        estimate >= threshold
+    }
 }
 
 /// Tree building failure cases.
@@ -90,13 +148,16 @@ pub enum TreeBuildingFailed {
     RepeatSlashesInPath(String),
     /// The given full path ends in slash.
     PathEndsInSlash(String),
-    /// If the `BufferingTreeBuilder` was created without `TreeOptions` with the option
+    /// If the tree builder was created without `TreeOptions` with the option
     /// `wrap_with_directory` enabled, then there can be only a single element at the root.
     TooManyRootLevelEntries,
     /// The given full path had already been added.
     DuplicatePath(String),
     /// The given full path had already been added as a link to an opaque entry.
     LeafAsDirectory(String),
+    /// The given full path was not added in the sorted order required by the
+    /// `StreamingTreeBuilder`.
+    OutOfOrder(String),
 }
 
 impl fmt::Display for TreeBuildingFailed {
@@ -118,6 +179,7 @@ impl fmt::Display for TreeBuildingFailed {
                 "attempted to use already added leaf as a subdirectory: {:?}",
                 s
             ),
+            OutOfOrder(s) => write!(fmt, "path was not added in sorted order: {:?}", s),
         }
     }
 }
@@ -129,9 +191,11 @@ impl std::error::Error for TreeBuildingFailed {}
 pub enum TreeConstructionFailed {
     /// Failed to serialize the protobuf node for the directory
     Protobuf(quick_protobuf::Error),
-    /// The resulting directory would be too large and HAMT sharding is yet to be implemented or
-    /// denied.
+    /// The resulting directory block would be too large, even if sharded when sharding was
+    /// enabled.
     TooLargeBlock(u64),
+    /// The names hash to the same value and cannot both be placed in the HAMT sharded directory.
+    HashCollision(String, String),
 }
 
 impl fmt::Display for TreeConstructionFailed {
@@ -141,6 +205,11 @@ impl fmt::Display for TreeConstructionFailed {
         match self {
             Protobuf(e) => write!(fmt, "serialization failed: {}", e),
             TooLargeBlock(size) => write!(fmt, "attempted to create block of {} bytes", size),
+            HashCollision(a, b) => write!(
+                fmt,
+                "names {:?} and {:?} collide in the HAMT sharded directory",
+                a, b
+            ),
         }
     }
 }
@@ -149,3 +218,91 @@ impl std::error::Error for TreeConstructionFailed {}
 
 #[derive(Debug)]
 struct NamedLeaf(String, Cid, u64);
+
+/// Renders the links as a directory into the buffer, returning the link to it. When the options
+/// call for a HAMT sharded directory, the nested buckets are appended to `buckets` before their
+/// parents, and the metadata is stored only in the root of the sharded directory.
+fn render_directory(
+    links: &[Option<NamedLeaf>],
+    metadata: &Metadata,
+    buffer: &mut Vec<u8>,
+    buckets: &mut Vec<hamt::Bucket>,
+    opts: &TreeOptions,
+) -> Result<Leaf, TreeConstructionFailed> {
+    use crate::pb::{UnixFs, UnixFsType};
+
+    if opts.should_shard(links) {
+        return hamt::render_sharded(links, metadata, buffer, buckets, opts);
+    }
+
+    let mut node = CustomFlatUnixFs {
+        links,
+        data: UnixFs {
+            Type: UnixFsType::Directory,
+            ..Default::default()
+        },
+    };
+
+    metadata.write_into(&mut node.data);
+
+    render_node(&node, buffer, opts)
+}
+
+/// Renders the node into the buffer, returning the link to it.
+fn render_node(
+    node: &CustomFlatUnixFs<'_>,
+    buffer: &mut Vec<u8>,
+    opts: &TreeOptions,
+) -> Result<Leaf, TreeConstructionFailed> {
+    use quick_protobuf::{BytesWriter, MessageWrite, Writer};
+
+    let size = node.get_size();
+
+    if let Some(limit) = &opts.block_size_limit {
+        let size = size as u64;
+        if *limit < size {
+            // FIXME: this could probably be detected at builder
+            return Err(TreeConstructionFailed::TooLargeBlock(size));
+        }
+    }
+
+    let cap = buffer.capacity();
+
+    if let Some(additional) = size.checked_sub(cap) {
+        buffer.reserve(additional);
+    }
+
+    if let Some(mut needed_zeroes) = size.checked_sub(buffer.len()) {
+        let zeroes = [0; 8];
+
+        while needed_zeroes > 8 {
+            buffer.extend_from_slice(&zeroes[..]);
+            needed_zeroes -= zeroes.len();
+        }
+
+        buffer.extend(core::iter::repeat(0).take(needed_zeroes));
+    }
+
+    let mut writer = Writer::new(BytesWriter::new(&mut buffer[..]));
+    node.write_message(&mut writer)
+        .map_err(TreeConstructionFailed::Protobuf)?;
+
+    buffer.truncate(size);
+
+    let cid = opts.cid_options.cid(cid::Codec::DagProtobuf, buffer);
+
+    let combined_from_links = node
+        .links
+        .iter()
+        .map(|opt| {
+            opt.as_ref()
+                .map(|NamedLeaf(_, _, total_size)| total_size)
+                .unwrap()
+        })
+        .sum::<u64>();
+
+    Ok(Leaf {
+        link: cid,
+        total_size: buffer.len() as u64 + combined_from_links,
+    })
+}
@@ -293,6 +293,167 @@ mod tests {
         );
     }
 
+    #[test]
+    fn cid_options() {
+        use crate::CidOptions;
+        use multihash::Code;
+
+        // foobar\n
+        let five_block_foobar =
+            Cid::try_from("QmRJHYTNvC3hmd9gJQARxLR1QMEincccBV53bBw524yyq6").unwrap();
+
+        let mut opts = TreeOptions::default();
+        opts.wrap_with_directory();
+        opts.cid_options(CidOptions::new(cid::Version::V1, Code::Blake2b256));
+        let mut builder = BufferingTreeBuilder::new(opts);
+        builder
+            .put_link("a/b", five_block_foobar.clone(), 221)
+            .unwrap();
+        builder.put_link("c", five_block_foobar, 221).unwrap();
+
+        let nodes = builder.build().collect::<Result<Vec<_>, _>>().unwrap();
+
+        assert_eq!(nodes.len(), 2);
+
+        for OwnedTreeNode { cid, block, .. } in nodes {
+            assert_eq!(cid.version(), cid::Version::V1);
+            assert_eq!(cid.codec(), cid::Codec::DagProtobuf);
+            assert_eq!(cid.hash(), Code::Blake2b256.digest(&block).as_ref());
+        }
+    }
+
+    #[test]
+    fn sharded_directory_with_collisions() {
+        // same as the go-ipfs 0.5 produced fixture with all entries in nested buckets
+        let empty_file = Cid::try_from("QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH").unwrap();
+
+        let mut opts = TreeOptions::default();
+        opts.wrap_with_directory();
+        opts.sharding_threshold(Some(0));
+        let mut builder = BufferingTreeBuilder::new(opts);
+
+        for n in &[3, 4, 9, 16, 17, 25, 33, 34, 37, 38, 40, 41, 48, 49, 50, 58] {
+            builder
+                .put_link(&format!("long-named-file-{:03}", n), empty_file.clone(), 6)
+                .unwrap();
+        }
+
+        let fixtures = crate::test_support::FakeBlockstore::with_fixtures();
+
+        let nodes = builder.build().collect::<Result<Vec<_>, _>>().unwrap();
+
+        // eight buckets and the root
+        assert_eq!(nodes.len(), 9);
+
+        for node in &nodes {
+            assert_eq!(&node.block[..], fixtures.get_by_cid(&node.cid));
+            assert_eq!(node.path, "");
+        }
+
+        assert!(nodes[..8].iter().all(|node| node.bucket));
+
+        let root = nodes.last().unwrap();
+        assert!(!root.bucket);
+        assert_eq!(
+            root.cid.to_string(),
+            "QmZbFPTnDBMWbQ6iBxQAhuhLz8Nu9XptYS96e7cuf5wvbk"
+        );
+    }
+
+    #[test]
+    fn sharded_directory_with_single_entry() {
+        let dir = Cid::try_from("QmYmmkD3dGZjuozuqSzDYjU4ZyhAgc4T4P4SUgY6qjzBi8").unwrap();
+
+        let mut opts = TreeOptions::default();
+        opts.wrap_with_directory();
+        opts.sharding_threshold(Some(0));
+        let mut builder = BufferingTreeBuilder::new(opts);
+        builder.put_link("non_sharded_dir", dir, 67).unwrap();
+
+        let nodes = builder
+            .build()
+            .map(|res| res.map(|OwnedTreeNode { cid, bucket, .. }| (cid.to_string(), bucket)))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(
+            nodes,
+            &[(
+                "QmQXUANxYGpkwMTWQUdZBPx9jqfFP7acNgL4FHRWkndKCe".to_string(),
+                false
+            )]
+        );
+    }
+
+    #[test]
+    fn sharding_threshold() {
+        let mut opts = TreeOptions::default();
+        opts.wrap_with_directory();
+        // the estimated size of each link is 34 for the Cid and 3 for the name
+        opts.sharding_threshold(Some(37 * 10));
+        opts.hamt_fanout(16);
+        let mut builder = BufferingTreeBuilder::new(opts);
+
+        for n in 0..9 {
+            builder
+                .put_link(&format!("a/{:03}", n), some_cid(n), 1)
+                .unwrap();
+        }
+        for n in 0..10 {
+            builder
+                .put_link(&format!("b/{:03}", n), some_cid(n), 1)
+                .unwrap();
+        }
+
+        let nodes = builder.build().collect::<Result<Vec<_>, _>>().unwrap();
+
+        let kind = |path: &str| {
+            let node = nodes
+                .iter()
+                .rev()
+                .find(|node| node.path == path && !node.bucket)
+                .unwrap();
+            crate::pb::FlatUnixFs::try_from(&node.block[..])
+                .unwrap()
+                .data
+                .Type
+        };
+
+        assert_eq!(kind("a"), crate::pb::UnixFsType::Directory);
+        assert_eq!(kind("b"), crate::pb::UnixFsType::HAMTShard);
+
+        // all of the entries can be found through the buckets
+        let blocks = nodes
+            .iter()
+            .map(|node| (node.cid.clone(), &node.block[..]))
+            .collect::<std::collections::HashMap<_, _>>();
+
+        let root = nodes
+            .iter()
+            .find(|node| node.path == "b" && !node.bucket)
+            .unwrap();
+
+        for n in 0..10 {
+            use crate::dir::{resolve, MaybeResolved};
+
+            let needle = format!("{:03}", n);
+            let mut resolved = resolve(&root.block, &needle, &mut None).unwrap();
+
+            let found = loop {
+                match resolved {
+                    MaybeResolved::Found(cid) => break cid,
+                    MaybeResolved::NeedToLoadMore(lookup) => {
+                        let next = blocks[lookup.pending_links().0];
+                        resolved = lookup.continue_walk(next, &mut None).unwrap();
+                    }
+                    MaybeResolved::NotFound => panic!("not found: {}", needle),
+                }
+            };
+
+            assert_eq!(found, some_cid(n));
+        }
+    }
+
     #[test]
     fn single_wrapped_root() {
         // foobar\n
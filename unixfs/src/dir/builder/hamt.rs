@@ -0,0 +1,260 @@
+//! HAMT sharded directory building, compatible with the go-ipfs implementation.
+//!
+//! The entries are placed in a trie keyed by the murmur3 hash of their names, consuming
+//! `log2(fanout)` bits of the hash per level. A bucket is only split into a nested shard when
+//! multiple entries share the same bits, which makes the resulting tree independent of the order
+//! the entries were added in.
+
+use super::{render_node, CustomFlatUnixFs, Leaf, NamedLeaf, TreeConstructionFailed, TreeOptions};
+use crate::pb::{UnixFs, UnixFsType};
+use crate::Metadata;
+use alloc::borrow::Cow;
+use cid::Cid;
+
+/// The multicodec of the murmur3-x64-64 hash, the only hash supported for HAMT sharded
+/// directories.
+pub(crate) const HASH_MURMUR3: u64 = 0x22;
+
+/// The default fanout of HAMT sharded directories, as used by go-ipfs.
+pub(crate) const DEFAULT_FANOUT: u64 = 256;
+
+/// Returns true for the fanouts which can be used for HAMT sharded directories.
+pub(crate) fn is_supported_fanout(fanout: u64) -> bool {
+    fanout.is_power_of_two() && (8..=1 << 16).contains(&fanout)
+}
+
+/// Returns the length of the hex prefixes of the link names for the fanout.
+pub(crate) fn prefix_len(fanout: u64) -> usize {
+    debug_assert!(is_supported_fanout(fanout));
+    let bits = fanout.trailing_zeros() as usize;
+    bits.div_ceil(4)
+}
+
+/// A rendered HAMT bucket, which is not the root of the sharded directory.
+pub(super) struct Bucket {
+    pub(super) cid: Cid,
+    pub(super) block: Vec<u8>,
+    pub(super) total_size: u64,
+}
+
+struct Hashed<'a> {
+    hash: u64,
+    leaf: &'a NamedLeaf,
+}
+
+/// Renders the links as a HAMT sharded directory. The root of the directory is rendered into
+/// `buffer` with the metadata, and any nested buckets are appended to `buckets` before their
+/// parents.
+pub(super) fn render_sharded(
+    links: &[Option<NamedLeaf>],
+    metadata: &Metadata,
+    buffer: &mut Vec<u8>,
+    buckets: &mut Vec<Bucket>,
+    opts: &TreeOptions,
+) -> Result<Leaf, TreeConstructionFailed> {
+    let mut entries = links
+        .iter()
+        .map(|link| {
+            let leaf = link.as_ref().expect("all links have been rendered");
+            Hashed {
+                hash: murmur3_x64_64(leaf.0.as_bytes()),
+                leaf,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // the hash bits are consumed from the most significant, so sorting by the hash groups the
+    // entries by their index on all levels
+    entries.sort_unstable_by_key(|entry| entry.hash);
+
+    render_level(&entries, 0, Some(metadata), buffer, buckets, opts)
+}
+
+fn render_level(
+    entries: &[Hashed<'_>],
+    consumed: u32,
+    metadata: Option<&Metadata>,
+    buffer: &mut Vec<u8>,
+    buckets: &mut Vec<Bucket>,
+    opts: &TreeOptions,
+) -> Result<Leaf, TreeConstructionFailed> {
+    let fanout = opts.hamt_fanout;
+    let width = fanout.trailing_zeros();
+    let prefix_len = prefix_len(fanout);
+
+    let mut bitfield = vec![0u8; (fanout / 8) as usize];
+    let mut links = Vec::new();
+    let mut rest = entries;
+
+    while let Some(first) = rest.first() {
+        let index = hash_bits(first.hash, consumed, width);
+        let len = rest
+            .iter()
+            .take_while(|entry| hash_bits(entry.hash, consumed, width) == index)
+            .count();
+        let (group, tail) = rest.split_at(len);
+        rest = tail;
+
+        let last = bitfield.len() - 1;
+        bitfield[last - (index / 8) as usize] |= 1 << (index % 8);
+
+        let prefix = format!("{:0width$X}", index, width = prefix_len);
+
+        if let [single] = group {
+            let NamedLeaf(name, cid, total_size) = single.leaf;
+            links.push(Some(NamedLeaf(prefix + name, cid.clone(), *total_size)));
+            continue;
+        }
+
+        if consumed + 2 * width > 64 {
+            return Err(TreeConstructionFailed::HashCollision(
+                group[0].leaf.0.clone(),
+                group[1].leaf.0.clone(),
+            ));
+        }
+
+        let mut block = Vec::new();
+        let leaf = render_level(group, consumed + width, None, &mut block, buckets, opts)?;
+
+        links.push(Some(NamedLeaf(prefix, leaf.link.clone(), leaf.total_size)));
+
+        buckets.push(Bucket {
+            cid: leaf.link,
+            block,
+            total_size: leaf.total_size,
+        });
+    }
+
+    // leading zeroes are not stored, like with the big integer go-ipfs originally used
+    let first_set = bitfield
+        .iter()
+        .position(|&b| b != 0)
+        .unwrap_or(bitfield.len());
+    let bitfield = &bitfield[first_set..];
+
+    let mut node = CustomFlatUnixFs {
+        links: &links,
+        data: UnixFs {
+            Type: UnixFsType::HAMTShard,
+            Data: if bitfield.is_empty() {
+                None
+            } else {
+                Some(Cow::Borrowed(bitfield))
+            },
+            hashType: Some(HASH_MURMUR3),
+            fanout: Some(fanout),
+            ..Default::default()
+        },
+    };
+
+    if let Some(metadata) = metadata {
+        metadata.write_into(&mut node.data);
+    }
+
+    render_node(&node, buffer, opts)
+}
+
+/// Returns `width` bits of the hash after the `consumed` most significant bits.
+fn hash_bits(hash: u64, consumed: u32, width: u32) -> u64 {
+    debug_assert!(consumed + width <= 64);
+    (hash << consumed) >> (64 - width)
+}
+
+/// The first half of the 128-bit x64 variant of murmur3 with zero seed, as used by go-ipfs.
+fn murmur3_x64_64(data: &[u8]) -> u64 {
+    const C1: u64 = 0x87c3_7b91_1142_53d5;
+    const C2: u64 = 0x4cf5_ad43_2745_937f;
+
+    fn fmix(mut k: u64) -> u64 {
+        k ^= k >> 33;
+        k = k.wrapping_mul(0xff51_afd7_ed55_8ccd);
+        k ^= k >> 33;
+        k = k.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+        k ^ (k >> 33)
+    }
+
+    fn mix_k1(k1: u64) -> u64 {
+        k1.wrapping_mul(C1).rotate_left(31).wrapping_mul(C2)
+    }
+
+    fn mix_k2(k2: u64) -> u64 {
+        k2.wrapping_mul(C2).rotate_left(33).wrapping_mul(C1)
+    }
+
+    let mut h1 = 0u64;
+    let mut h2 = 0u64;
+
+    let mut blocks = data.chunks_exact(16);
+
+    for block in &mut blocks {
+        let mut k1 = [0u8; 8];
+        let mut k2 = [0u8; 8];
+        k1.copy_from_slice(&block[..8]);
+        k2.copy_from_slice(&block[8..]);
+
+        h1 ^= mix_k1(u64::from_le_bytes(k1));
+        h1 = h1
+            .rotate_left(27)
+            .wrapping_add(h2)
+            .wrapping_mul(5)
+            .wrapping_add(0x52dc_e729);
+
+        h2 ^= mix_k2(u64::from_le_bytes(k2));
+        h2 = h2
+            .rotate_left(31)
+            .wrapping_add(h1)
+            .wrapping_mul(5)
+            .wrapping_add(0x3849_5ab5);
+    }
+
+    let tail = blocks.remainder();
+
+    if tail.len() > 8 {
+        let k2 = tail[8..]
+            .iter()
+            .rev()
+            .fold(0u64, |acc, &b| (acc << 8) | u64::from(b));
+        h2 ^= mix_k2(k2);
+    }
+
+    if !tail.is_empty() {
+        let k1 = tail[..tail.len().min(8)]
+            .iter()
+            .rev()
+            .fold(0u64, |acc, &b| (acc << 8) | u64::from(b));
+        h1 ^= mix_k1(k1);
+    }
+
+    h1 ^= data.len() as u64;
+    h2 ^= data.len() as u64;
+
+    h1 = h1.wrapping_add(h2);
+    h2 = h2.wrapping_add(h1);
+
+    h1 = fmix(h1);
+    h2 = fmix(h2);
+
+    h1.wrapping_add(h2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{murmur3_x64_64, prefix_len};
+
+    #[test]
+    fn murmur3() {
+        assert_eq!(murmur3_x64_64(b""), 0);
+        assert_eq!(murmur3_x64_64(b"hello"), 0xcbd8_a7b3_41bd_9b02);
+        // longer inputs are covered by the go-ipfs fixtures of the sharded directory tests
+    }
+
+    #[test]
+    fn prefix_lengths() {
+        assert_eq!(prefix_len(8), 1);
+        assert_eq!(prefix_len(16), 1);
+        assert_eq!(prefix_len(32), 2);
+        assert_eq!(prefix_len(256), 2);
+        assert_eq!(prefix_len(512), 3);
+        assert_eq!(prefix_len(1 << 16), 4);
+    }
+}
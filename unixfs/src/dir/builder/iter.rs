@@ -1,6 +1,8 @@
+use super::hamt::Bucket;
 use super::{
-    CustomFlatUnixFs, DirBuilder, Entry, Leaf, NamedLeaf, TreeConstructionFailed, TreeOptions,
+    render_directory, DirBuilder, Entry, Leaf, NamedLeaf, TreeConstructionFailed, TreeOptions,
 };
+use crate::Metadata;
 use cid::Cid;
 use core::fmt;
 use std::collections::HashMap;
@@ -21,6 +23,12 @@ pub struct PostOrderIterator {
     reused_children: Vec<Visited>,
     cid: Option<Cid>,
     total_size: u64,
+    // buckets of the latest rendered HAMT sharded directory, in the reverse order of returning
+    buckets: Vec<Bucket>,
+    // the latest returned bucket
+    current_bucket: Option<Bucket>,
+    // true when the latest rendered directory has not been returned yet
+    rendered: bool,
     // from TreeOptions
     opts: TreeOptions,
 }
@@ -55,9 +63,11 @@ enum Visited {
         /// Leaves will be stored directly in this field when there are no DirBuilder descendants,
         /// in the `PostOrderIterator::persisted_cids` otherwise.
         leaves: LeafStorage,
+        metadata: Metadata,
     },
     PostRoot {
         leaves: LeafStorage,
+        metadata: Metadata,
     },
 }
 
@@ -73,96 +83,64 @@ impl PostOrderIterator {
             reused_children: Vec::new(),
             cid: None,
             total_size: 0,
+            buckets: Vec::new(),
+            current_bucket: None,
+            rendered: false,
             opts,
         }
     }
 
     fn render_directory(
         links: &[Option<NamedLeaf>],
+        metadata: &Metadata,
         buffer: &mut Vec<u8>,
-        block_size_limit: &Option<u64>,
+        buckets: &mut Vec<Bucket>,
+        opts: &TreeOptions,
     ) -> Result<Leaf, TreeConstructionFailed> {
-        use crate::pb::{UnixFs, UnixFsType};
-        use quick_protobuf::{BytesWriter, MessageWrite, Writer};
-        use sha2::{Digest, Sha256};
-
-        // FIXME: ideas on how to turn this into a HAMT sharding on some heuristic. we probably
-        // need to introduce states in to the "iterator":
-        //
-        // 1. bucketization
-        // 2. another post order visit of the buckets?
-        //
-        // the nested post order visit should probably re-use the existing infra ("message
-        // passing") and new ids can be generated by giving this iterator the counter from
-        // BufferedTreeBuilder.
-        //
-        // could also be that the HAMT shard building should start earlier, since the same
-        // heuristic can be detected *at* bufferedtreewriter. there the split would be easier, and
-        // this would "just" be a single node rendering, and not need any additional states..
-
-        let node = CustomFlatUnixFs {
-            links,
-            data: UnixFs {
-                Type: UnixFsType::Directory,
-                ..Default::default()
-            },
-        };
-
-        let size = node.get_size();
-
-        if let Some(limit) = block_size_limit {
-            let size = size as u64;
-            if *limit < size {
-                // FIXME: this could probably be detected at builder
-                return Err(TreeConstructionFailed::TooLargeBlock(size));
-            }
-        }
-
-        let cap = buffer.capacity();
-
-        if let Some(additional) = size.checked_sub(cap) {
-            buffer.reserve(additional);
-        }
-
-        if let Some(mut needed_zeroes) = size.checked_sub(buffer.len()) {
-            let zeroes = [0; 8];
+        let leaf = render_directory(links, metadata, buffer, buckets, opts)?;
+        // buckets are popped from the end but need to be returned before their parents
+        buckets.reverse();
+        Ok(leaf)
+    }
 
-            while needed_zeroes > 8 {
-                buffer.extend_from_slice(&zeroes[..]);
-                needed_zeroes -= zeroes.len();
+    /// Construct the next dag-pb node, if any.
+    ///
+    /// Returns a `TreeNode` of the latest constructed tree node. The buckets of a HAMT sharded
+    /// directory are returned before the directory itself.
+    pub fn next_borrowed(&mut self) -> Option<Result<TreeNode<'_>, TreeConstructionFailed>> {
+        if !self.rendered {
+            if let Err(e) = self.render_next()? {
+                return Some(Err(e));
             }
-
-            buffer.extend(core::iter::repeat(0).take(needed_zeroes));
+            self.rendered = true;
         }
 
-        let mut writer = Writer::new(BytesWriter::new(&mut buffer[..]));
-        node.write_message(&mut writer)
-            .map_err(TreeConstructionFailed::Protobuf)?;
-
-        buffer.truncate(size);
-
-        let mh = multihash::wrap(multihash::Code::Sha2_256, &Sha256::digest(&buffer));
-        let cid = Cid::new_v0(mh).expect("sha2_256 is the correct multihash for cidv0");
+        if let Some(bucket) = self.buckets.pop() {
+            self.current_bucket = Some(bucket);
+            let bucket = self.current_bucket.as_ref().unwrap();
+
+            return Some(Ok(TreeNode {
+                path: self.full_path.as_str(),
+                cid: &bucket.cid,
+                total_size: bucket.total_size,
+                block: &bucket.block,
+                bucket: true,
+            }));
+        }
 
-        let combined_from_links = links
-            .iter()
-            .map(|opt| {
-                opt.as_ref()
-                    .map(|NamedLeaf(_, _, total_size)| total_size)
-                    .unwrap()
-            })
-            .sum::<u64>();
+        self.rendered = false;
 
-        Ok(Leaf {
-            link: cid,
-            total_size: buffer.len() as u64 + combined_from_links,
-        })
+        Some(Ok(TreeNode {
+            path: self.full_path.as_str(),
+            cid: self.cid.as_ref().unwrap(),
+            total_size: self.total_size,
+            block: &self.block_buffer,
+            bucket: false,
+        }))
     }
 
-    /// Construct the next dag-pb node, if any.
-    ///
-    /// Returns a `TreeNode` of the latest constructed tree node.
-    pub fn next_borrowed(&mut self) -> Option<Result<TreeNode<'_>, TreeConstructionFailed>> {
+    /// Renders the next directory, if any.
+    fn render_next(&mut self) -> Option<Result<(), TreeConstructionFailed>> {
         while let Some(visited) = self.pending.pop() {
             let (name, depth) = match &visited {
                 Visited::DescentRoot(_) => (None, 0),
@@ -186,7 +164,10 @@ impl PostOrderIterator {
                         leaves.into()
                     };
 
-                    self.pending.push(Visited::PostRoot { leaves });
+                    self.pending.push(Visited::PostRoot {
+                        leaves,
+                        metadata: node.metadata,
+                    });
                     self.pending.extend(children.drain(..));
                 }
                 Visited::Descent {
@@ -213,6 +194,7 @@ impl PostOrderIterator {
                         depth,
                         leaves,
                         index,
+                        metadata: node.metadata,
                     });
 
                     self.pending.extend(children.drain(..));
@@ -222,6 +204,7 @@ impl PostOrderIterator {
                     name,
                     leaves,
                     index,
+                    metadata,
                     ..
                 } => {
                     let leaves = leaves.into_inner(&mut self.persisted_cids);
@@ -229,8 +212,10 @@ impl PostOrderIterator {
 
                     let leaf = match Self::render_directory(
                         &leaves,
+                        &metadata,
                         buffer,
-                        &self.opts.block_size_limit,
+                        &mut self.buckets,
+                        &self.opts,
                     ) {
                         Ok(leaf) => leaf,
                         Err(e) => return Some(Err(e)),
@@ -258,14 +243,9 @@ impl PostOrderIterator {
                         }
                     }
 
-                    return Some(Ok(TreeNode {
-                        path: self.full_path.as_str(),
-                        cid: self.cid.as_ref().unwrap(),
-                        total_size: self.total_size,
-                        block: &self.block_buffer,
-                    }));
+                    return Some(Ok(()));
                 }
-                Visited::PostRoot { leaves } => {
+                Visited::PostRoot { leaves, metadata } => {
                     let leaves = leaves.into_inner(&mut self.persisted_cids);
 
                     if !self.opts.wrap_with_directory {
@@ -276,8 +256,10 @@ impl PostOrderIterator {
 
                     let leaf = match Self::render_directory(
                         &leaves,
+                        &metadata,
                         buffer,
-                        &self.opts.block_size_limit,
+                        &mut self.buckets,
+                        &self.opts,
                     ) {
                         Ok(leaf) => leaf,
                         Err(e) => return Some(Err(e)),
@@ -286,12 +268,7 @@ impl PostOrderIterator {
                     self.cid = Some(leaf.link.clone());
                     self.total_size = leaf.total_size;
 
-                    return Some(Ok(TreeNode {
-                        path: self.full_path.as_str(),
-                        cid: self.cid.as_ref().unwrap(),
-                        total_size: self.total_size,
-                        block: &self.block_buffer,
-                    }));
+                    return Some(Ok(()));
                 }
             }
         }
@@ -318,6 +295,9 @@ pub struct TreeNode<'a> {
     pub total_size: u64,
     /// Raw dag-pb document.
     pub block: &'a [u8],
+    /// True for the buckets of the HAMT sharded directory at `path`. The buckets need to be stored
+    /// along with the directory but are not entries of the tree.
+    pub bucket: bool,
 }
 
 impl<'a> fmt::Debug for TreeNode<'a> {
@@ -327,6 +307,7 @@ impl<'a> fmt::Debug for TreeNode<'a> {
             .field("cid", &format_args!("{}", self.cid))
             .field("total_size", &self.total_size)
             .field("size", &self.block.len())
+            .field("bucket", &self.bucket)
             .finish()
     }
 }
@@ -339,6 +320,7 @@ impl TreeNode<'_> {
             cid: self.cid.to_owned(),
             total_size: self.total_size,
             block: self.block.into(),
+            bucket: self.bucket,
         }
     }
 }
@@ -353,6 +335,20 @@ pub struct OwnedTreeNode {
     pub total_size: u64,
     /// Raw dag-pb document.
     pub block: Box<[u8]>,
+    /// True for the buckets of the HAMT sharded directory at `path`.
+    pub bucket: bool,
+}
+
+impl fmt::Debug for OwnedTreeNode {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("OwnedTreeNode")
+            .field("path", &format_args!("{:?}", self.path))
+            .field("cid", &format_args!("{}", self.cid))
+            .field("total_size", &self.total_size)
+            .field("size", &self.block.len())
+            .field("bucket", &self.bucket)
+            .finish()
+    }
 }
 
 fn update_full_path(
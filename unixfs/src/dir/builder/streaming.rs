@@ -0,0 +1,558 @@
+use super::{
+    render_directory, NamedLeaf, OwnedTreeNode, TreeBuildingFailed, TreeConstructionFailed,
+    TreeOptions,
+};
+use crate::Metadata;
+use cid::Cid;
+use core::fmt;
+
+/// UnixFs directory tree builder which renders the directories as soon as all of their entries
+/// have been added, requiring the entries to be added in sorted order.
+///
+/// The paths must be added in the order of a depth-first walk visiting the entries of each
+/// directory sorted by their names, which is not the same as the order of the full path strings:
+/// `a/b` must come before `a.txt`. Because a directory is completed when the first path outside
+/// of it is added, only the directories on the path to the latest entry are kept in memory.
+#[derive(Debug)]
+pub struct StreamingTreeBuilder {
+    /// The currently open directories, starting from the root. At the root there can be only one
+    /// element, unless an option was given to create a new directory surrounding the root
+    /// elements.
+    stack: Vec<OpenDirectory>,
+    block_buffer: Vec<u8>,
+    opts: TreeOptions,
+}
+
+#[derive(Debug)]
+struct OpenDirectory {
+    name: String,
+    links: Vec<Option<NamedLeaf>>,
+    metadata: Metadata,
+}
+
+impl OpenDirectory {
+    fn new(name: String) -> Self {
+        OpenDirectory {
+            name,
+            links: Vec::new(),
+            metadata: Metadata::default(),
+        }
+    }
+}
+
+impl Default for StreamingTreeBuilder {
+    fn default() -> Self {
+        Self::new(TreeOptions::default())
+    }
+}
+
+impl StreamingTreeBuilder {
+    /// Construct a new tree builder with the given configuration.
+    pub fn new(opts: TreeOptions) -> Self {
+        StreamingTreeBuilder {
+            stack: vec![OpenDirectory::new(String::new())],
+            block_buffer: Vec::new(),
+            opts,
+        }
+    }
+
+    /// Registers the given path to be a link to the cid that follows. The target leaf should be
+    /// either a file, directory or symlink but could of course be anything. It will be treated as
+    /// an opaque link.
+    ///
+    /// Returns the nodes of the directories completed by moving on to this path, in post order.
+    pub fn put_link(
+        &mut self,
+        full_path: &str,
+        target: Cid,
+        total_size: u64,
+    ) -> Result<Vec<OwnedTreeNode>, StreamingTreeFailed> {
+        let (common, basename, _) = self.validate(full_path, false)?;
+        let completed = self.descend(full_path, common)?;
+
+        self.stack
+            .last_mut()
+            .expect("root is never popped")
+            .links
+            .push(Some(NamedLeaf(basename.to_string(), target, total_size)));
+
+        Ok(completed)
+    }
+
+    /// Directories get "put" implicitly through the put files, and directories need to be added
+    /// only when wanting them to have metadata or to exist even if empty.
+    ///
+    /// Returns the nodes of the directories completed by moving on to this path, in post order.
+    pub fn set_metadata(
+        &mut self,
+        full_path: &str,
+        metadata: Metadata,
+    ) -> Result<Vec<OwnedTreeNode>, StreamingTreeFailed> {
+        let (common, basename, open) = self.validate(full_path, true)?;
+
+        if open {
+            self.stack[common + 1].metadata = metadata;
+            return Ok(Vec::new());
+        }
+
+        let completed = self.descend(full_path, common)?;
+        let mut directory = OpenDirectory::new(basename.to_string());
+        directory.metadata = metadata;
+        self.stack.push(directory);
+
+        Ok(completed)
+    }
+
+    /// Completes the remaining open directories, returning their nodes in post order. The last
+    /// node is the root of the tree, unless the only root level entry was added as a link.
+    pub fn finish(mut self) -> Result<Vec<OwnedTreeNode>, TreeConstructionFailed> {
+        let mut completed = Vec::new();
+
+        while self.stack.len() > 1 {
+            self.close(&mut completed)?;
+        }
+
+        if self.opts.wrap_with_directory {
+            let root = self.stack.pop().expect("root is never popped");
+            self.render(String::new(), &root, &mut completed)?;
+        }
+
+        Ok(completed)
+    }
+
+    /// Checks that the path can be added next, returning the number of already open directories
+    /// along the path excluding the root, the last segment of the path and whether the path is an
+    /// already open directory.
+    fn validate<'a>(
+        &self,
+        full_path: &'a str,
+        directory: bool,
+    ) -> Result<(usize, &'a str, bool), TreeBuildingFailed> {
+        // check these before to avoid having to clean up the stack
+
+        if full_path.ends_with('/') {
+            return Err(TreeBuildingFailed::PathEndsInSlash(full_path.to_string()));
+        }
+
+        if full_path.contains("//") {
+            return Err(TreeBuildingFailed::RepeatSlashesInPath(
+                full_path.to_string(),
+            ));
+        }
+
+        if full_path.starts_with('/') {
+            return Err(TreeBuildingFailed::RootedPath(full_path.to_string()));
+        }
+
+        let segments = full_path.split('/').collect::<Vec<_>>();
+        let (basename, dirs) = segments.split_last().expect("split always returns one");
+
+        let common = dirs
+            .iter()
+            .zip(self.stack.iter().skip(1))
+            .take_while(|(segment, open)| **segment == open.name)
+            .count();
+
+        let next = segments[common];
+        let at_basename = common == dirs.len();
+        let parent = &self.stack[common];
+
+        let previous = if let Some(open) = self.stack.get(common + 1) {
+            Some((open.name.as_str(), true))
+        } else {
+            parent
+                .links
+                .last()
+                .map(|link| (link.as_ref().expect("links are rendered").0.as_str(), false))
+        };
+
+        if let Some((previous, open)) = previous {
+            if next < previous {
+                return Err(TreeBuildingFailed::OutOfOrder(full_path.to_string()));
+            }
+
+            if next == previous {
+                // a path along an open directory would have been counted in `common`
+                debug_assert!(at_basename || !open);
+
+                return match (at_basename, open, directory) {
+                    (true, true, true) => Ok((common, basename, true)),
+                    (true, _, false) => {
+                        Err(TreeBuildingFailed::DuplicatePath(full_path.to_string()))
+                    }
+                    _ => Err(TreeBuildingFailed::LeafAsDirectory(full_path.to_string())),
+                };
+            }
+
+            if common == 0 && !self.opts.wrap_with_directory {
+                return Err(TreeBuildingFailed::TooManyRootLevelEntries);
+            }
+        }
+
+        Ok((common, basename, false))
+    }
+
+    /// Completes the directories which are not along the path, and opens the directories leading
+    /// to the last segment of the path.
+    fn descend(
+        &mut self,
+        full_path: &str,
+        common: usize,
+    ) -> Result<Vec<OwnedTreeNode>, TreeConstructionFailed> {
+        let mut completed = Vec::new();
+
+        while self.stack.len() > common + 1 {
+            self.close(&mut completed)?;
+        }
+
+        if let Some((dirs, _)) = full_path.rsplit_once('/') {
+            for name in dirs.split('/').skip(common) {
+                self.stack.push(OpenDirectory::new(name.to_string()));
+            }
+        }
+
+        Ok(completed)
+    }
+
+    /// Renders the innermost open directory and links it to its parent.
+    fn close(&mut self, completed: &mut Vec<OwnedTreeNode>) -> Result<(), TreeConstructionFailed> {
+        let path = self
+            .stack
+            .iter()
+            .skip(1)
+            .map(|open| open.name.as_str())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        let directory = self.stack.pop().expect("root is never popped");
+
+        let (cid, total_size) = self.render(path, &directory, completed)?;
+
+        self.stack
+            .last_mut()
+            .expect("root is never popped")
+            .links
+            .push(Some(NamedLeaf(directory.name, cid, total_size)));
+
+        Ok(())
+    }
+
+    fn render(
+        &mut self,
+        path: String,
+        directory: &OpenDirectory,
+        completed: &mut Vec<OwnedTreeNode>,
+    ) -> Result<(Cid, u64), TreeConstructionFailed> {
+        let mut buckets = Vec::new();
+        let leaf = render_directory(
+            &directory.links,
+            &directory.metadata,
+            &mut self.block_buffer,
+            &mut buckets,
+            &self.opts,
+        )?;
+
+        completed.extend(buckets.into_iter().map(|bucket| OwnedTreeNode {
+            path: path.clone(),
+            cid: bucket.cid,
+            total_size: bucket.total_size,
+            block: bucket.block.into_boxed_slice(),
+            bucket: true,
+        }));
+
+        completed.push(OwnedTreeNode {
+            path,
+            cid: leaf.link.clone(),
+            total_size: leaf.total_size,
+            block: self.block_buffer.as_slice().into(),
+            bucket: false,
+        });
+
+        Ok((leaf.link, leaf.total_size))
+    }
+}
+
+/// Failure cases for `StreamingTreeBuilder`, which builds the tree while the entries are added.
+#[derive(Debug)]
+pub enum StreamingTreeFailed {
+    /// The path could not be added to the tree.
+    Building(TreeBuildingFailed),
+    /// A completed directory could not be rendered.
+    Construction(TreeConstructionFailed),
+}
+
+impl From<TreeBuildingFailed> for StreamingTreeFailed {
+    fn from(e: TreeBuildingFailed) -> Self {
+        StreamingTreeFailed::Building(e)
+    }
+}
+
+impl From<TreeConstructionFailed> for StreamingTreeFailed {
+    fn from(e: TreeConstructionFailed) -> Self {
+        StreamingTreeFailed::Construction(e)
+    }
+}
+
+impl fmt::Display for StreamingTreeFailed {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use StreamingTreeFailed::*;
+
+        match self {
+            Building(e) => write!(fmt, "{}", e),
+            Construction(e) => write!(fmt, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for StreamingTreeFailed {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use StreamingTreeFailed::*;
+
+        match self {
+            Building(e) => Some(e),
+            Construction(e) => Some(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{BufferingTreeBuilder, OwnedTreeNode},
+        Metadata, StreamingTreeBuilder, StreamingTreeFailed, TreeBuildingFailed, TreeOptions,
+    };
+    use cid::Cid;
+
+    /// Paths in the depth-first order, which differs from the order of the strings.
+    const SORTED_PATHS: &[&str] = &[
+        "a/b/c.txt",
+        "a/b/d/e.txt",
+        "a/b/d/f.txt",
+        "a/b.txt",
+        "a/c/g.txt",
+        "h.txt",
+        "i/j/k/l.txt",
+        "i/m.txt",
+    ];
+
+    #[test]
+    fn same_as_buffering() {
+        let mut opts = TreeOptions::default();
+        opts.wrap_with_directory();
+
+        assert_same_as_buffering(opts, SORTED_PATHS, &[]);
+    }
+
+    #[test]
+    fn same_as_buffering_with_empty_directories() {
+        let mut opts = TreeOptions::default();
+        opts.wrap_with_directory();
+
+        let paths = &["a/b/c.txt", "a/e/f.txt", "g/h.txt"];
+        let directories = &["a/b", "a/d", "a/e", "f"];
+
+        assert_same_as_buffering(opts, paths, directories);
+    }
+
+    #[test]
+    fn same_as_buffering_with_sharding() {
+        let mut opts = TreeOptions::default();
+        opts.wrap_with_directory();
+        opts.sharding_threshold(Some(200));
+        opts.hamt_fanout(16);
+
+        let names = (0..20).map(|n| format!("b/{:03}", n)).collect::<Vec<_>>();
+        let mut paths = vec!["a.txt"];
+        paths.extend(names.iter().map(|s| s.as_str()));
+        paths.push("c.txt");
+
+        assert_same_as_buffering(opts, &paths, &[]);
+    }
+
+    #[test]
+    fn completed_directories_are_returned_early() {
+        let mut builder = StreamingTreeBuilder::default();
+
+        let completed = builder.put_link("a/b/c.txt", some_cid(0), 1).unwrap();
+        assert!(completed.is_empty());
+
+        let completed = builder.put_link("a/b/d.txt", some_cid(1), 1).unwrap();
+        assert!(completed.is_empty());
+
+        let completed = builder.put_link("a/e/f.txt", some_cid(2), 1).unwrap();
+        assert_eq!(paths(&completed), &["a/b"]);
+
+        let completed = builder.put_link("a/g.txt", some_cid(3), 1).unwrap();
+        assert_eq!(paths(&completed), &["a/e"]);
+
+        let completed = builder.finish().unwrap();
+        assert_eq!(paths(&completed), &["a"]);
+    }
+
+    #[test]
+    fn single_root_link() {
+        let mut builder = StreamingTreeBuilder::default();
+        assert!(builder.put_link("a", some_cid(0), 1).unwrap().is_empty());
+        assert!(builder.finish().unwrap().is_empty());
+    }
+
+    #[test]
+    fn out_of_order() {
+        let mut opts = TreeOptions::default();
+        opts.wrap_with_directory();
+
+        let mut builder = StreamingTreeBuilder::new(opts.clone());
+        builder.put_link("b", some_cid(0), 1).unwrap();
+        let err = builder.put_link("a", some_cid(1), 1).unwrap_err();
+        assert_building_failed(err, |e| matches!(e, TreeBuildingFailed::OutOfOrder(_)));
+
+        // sorted as strings, but not in the depth-first order
+        let mut builder = StreamingTreeBuilder::new(opts);
+        builder.put_link("a.txt", some_cid(0), 1).unwrap();
+        let err = builder.put_link("a/b.txt", some_cid(1), 1).unwrap_err();
+        assert_building_failed(err, |e| matches!(e, TreeBuildingFailed::OutOfOrder(_)));
+    }
+
+    #[test]
+    fn duplicate_path() {
+        let mut builder = StreamingTreeBuilder::default();
+        builder.put_link("a/b.txt", some_cid(0), 1).unwrap();
+        let err = builder.put_link("a/b.txt", some_cid(1), 1).unwrap_err();
+        assert_building_failed(err, |e| matches!(e, TreeBuildingFailed::DuplicatePath(_)));
+
+        let err = builder.put_link("a", some_cid(1), 1).unwrap_err();
+        assert_building_failed(err, |e| matches!(e, TreeBuildingFailed::DuplicatePath(_)));
+    }
+
+    #[test]
+    fn using_leaf_as_node() {
+        let mut builder = StreamingTreeBuilder::default();
+        builder.put_link("a/b.txt", some_cid(0), 1).unwrap();
+        let err = builder
+            .put_link("a/b.txt/c.txt", some_cid(1), 1)
+            .unwrap_err();
+        assert_building_failed(err, |e| matches!(e, TreeBuildingFailed::LeafAsDirectory(_)));
+
+        let err = builder
+            .set_metadata("a/b.txt", Metadata::default())
+            .unwrap_err();
+        assert_building_failed(err, |e| matches!(e, TreeBuildingFailed::LeafAsDirectory(_)));
+    }
+
+    #[test]
+    fn denied_multiple_root_entries() {
+        let mut builder = StreamingTreeBuilder::default();
+        builder.put_link("a/b.txt", some_cid(0), 1).unwrap();
+        let err = builder.put_link("c.txt", some_cid(1), 1).unwrap_err();
+        assert_building_failed(err, |e| {
+            matches!(e, TreeBuildingFailed::TooManyRootLevelEntries)
+        });
+    }
+
+    #[test]
+    fn set_metadata_on_open_directory() {
+        let mut builder = StreamingTreeBuilder::default();
+        let mut completed = Vec::new();
+
+        completed.extend(builder.set_metadata("a/b/c", Metadata::default()).unwrap());
+        completed.extend(builder.put_link("a/b/c/d.txt", some_cid(0), 1).unwrap());
+        completed.extend(builder.set_metadata("a/b", Metadata::default()).unwrap());
+        completed.extend(builder.put_link("a/b/e.txt", some_cid(1), 1).unwrap());
+        completed.extend(builder.finish().unwrap());
+
+        assert_eq!(paths(&completed), &["a/b/c", "a/b", "a"]);
+    }
+
+    #[test]
+    fn metadata_is_stored() {
+        use crate::pb::FlatUnixFs;
+        use core::convert::TryFrom;
+
+        let metadata = Metadata::default().with_mode(0o755).with_mtime(-1, 0);
+
+        let mut builder = StreamingTreeBuilder::default();
+        builder.set_metadata("a/b", metadata.clone()).unwrap();
+        builder.put_link("a/b/c.txt", some_cid(0), 1).unwrap();
+
+        let completed = builder.finish().unwrap();
+        assert_eq!(paths(&completed), &["a/b", "a"]);
+
+        let flat = FlatUnixFs::try_from(&completed[0].block[..]).unwrap();
+        assert_eq!(Metadata::from(&flat.data), metadata);
+
+        let flat = FlatUnixFs::try_from(&completed[1].block[..]).unwrap();
+        assert_eq!(Metadata::from(&flat.data), Metadata::default());
+    }
+
+    fn assert_same_as_buffering(opts: TreeOptions, paths: &[&str], directories: &[&str]) {
+        let mut streaming = StreamingTreeBuilder::new(opts.clone());
+        let mut buffering = BufferingTreeBuilder::new(opts);
+
+        let mut entries = paths
+            .iter()
+            .enumerate()
+            .map(|(n, path)| (*path, Some(some_cid(n))))
+            .chain(directories.iter().map(|path| (*path, None)))
+            .collect::<Vec<_>>();
+
+        // depth-first order is the order of the segments
+        entries.sort_by(|a, b| a.0.split('/').cmp(b.0.split('/')));
+
+        let mut actual = Vec::new();
+
+        for (path, cid) in entries {
+            match cid {
+                Some(cid) => {
+                    buffering.put_link(path, cid.clone(), 1).unwrap();
+                    actual.extend(streaming.put_link(path, cid, 1).unwrap());
+                }
+                None => {
+                    buffering.set_metadata(path, Metadata::default()).unwrap();
+                    actual.extend(streaming.set_metadata(path, Metadata::default()).unwrap());
+                }
+            }
+        }
+
+        actual.extend(streaming.finish().unwrap());
+
+        let expected = buffering.build().collect::<Result<Vec<_>, _>>().unwrap();
+
+        let key = |node: &OwnedTreeNode| (node.path.clone(), node.cid.to_string(), node.bucket);
+
+        // the root is last with both
+        assert_eq!(
+            actual.last().map(key),
+            expected.last().map(key),
+            "different roots"
+        );
+
+        let mut actual = actual.iter().map(key).collect::<Vec<_>>();
+        let mut expected = expected.iter().map(key).collect::<Vec<_>>();
+
+        actual.sort();
+        expected.sort();
+
+        assert_eq!(actual, expected);
+    }
+
+    fn assert_building_failed(
+        err: StreamingTreeFailed,
+        check: impl FnOnce(&TreeBuildingFailed) -> bool,
+    ) {
+        match err {
+            StreamingTreeFailed::Building(ref e) if check(e) => {}
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    fn paths(nodes: &[OwnedTreeNode]) -> Vec<&str> {
+        nodes.iter().map(|node| node.path.as_str()).collect()
+    }
+
+    /// Returns a quick and dirty sha2-256 of the given number as a Cidv0
+    fn some_cid(number: usize) -> Cid {
+        use multihash::Sha2_256;
+        let mh = Sha2_256::digest(&number.to_le_bytes());
+        Cid::new_v0(mh).unwrap()
+    }
+}
@@ -0,0 +1,361 @@
+use super::{
+    check_directory_supported, try_convert_cid, ShardError, ShardedLookup,
+    UnexpectedDirectoryProperties,
+};
+use crate::pb::{FlatUnixFs, PBLink, PBNode, ParsingFailed, UnixFsType};
+use crate::{InvalidCidInLink, UnexpectedNodeType};
+use alloc::collections::VecDeque;
+use cid::{Cid, Codec};
+use core::convert::TryFrom;
+use core::fmt;
+
+/// A named link of a directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListedLink {
+    /// Name of the link, without the bucket prefix for the links of HAMT sharded directories.
+    pub name: String,
+    /// The target of the link.
+    pub cid: Cid,
+    /// The cumulative size of the target as recorded in the link, zero if missing.
+    pub total_size: u64,
+}
+
+/// Lists the links of a `dag-pb` node or a UnixFS directory (normal, sharded).
+///
+/// Returns the links found in the block, and a `ShardedListing` if the block was a HAMT sharded
+/// directory with nested buckets which need to be loaded in order to list the rest of the links.
+/// The links of a sharded directory are returned in the order of the buckets, not in the order of
+/// the names.
+pub fn list(block: &[u8]) -> Result<(Vec<ListedLink>, Option<ShardedListing>), ListingError> {
+    match FlatUnixFs::try_parse(block) {
+        Ok(hamt) if hamt.data.Type == UnixFsType::HAMTShard => {
+            ShardedListing::list_bucket(hamt, VecDeque::new())
+        }
+        Ok(flat) if flat.data.Type == UnixFsType::Directory => {
+            let links = check_directory_supported(flat)?.links;
+            Ok((convert_links(links)?, None))
+        }
+        Err(ParsingFailed::InvalidUnixFs(_, PBNode { Links: links, .. }))
+        | Err(ParsingFailed::NoData(PBNode { Links: links, .. })) => {
+            Ok((convert_links(links)?, None))
+        }
+        Ok(other) => Err(ListingError::UnexpectedType(other.data.Type.into())),
+        Err(ParsingFailed::InvalidDagPb(e)) => Err(ListingError::Read(Some(e))),
+    }
+}
+
+fn convert_links(links: Vec<PBLink<'_>>) -> Result<Vec<ListedLink>, InvalidCidInLink> {
+    links
+        .into_iter()
+        .enumerate()
+        .map(|(nth, link)| {
+            let name = link.Name.as_deref().unwrap_or_default().to_owned();
+            let total_size = link.Tsize.unwrap_or_default();
+            let cid = try_convert_cid(nth, link)?;
+            Ok(ListedLink {
+                name,
+                cid,
+                total_size,
+            })
+        })
+        .collect()
+}
+
+/// `ShardedListing` continues listing a HAMT sharded directory over the blocks of the nested
+/// buckets.
+pub struct ShardedListing {
+    buckets: VecDeque<Cid>,
+}
+
+impl fmt::Debug for ShardedListing {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "ShardedListing {{ buckets: {} }}", self.buckets.len())
+    }
+}
+
+impl ShardedListing {
+    /// Returns the next pending bucket and an iterator over the rest.
+    pub fn pending_links(&self) -> (&Cid, impl Iterator<Item = &Cid>) {
+        let mut iter = self.buckets.iter();
+        let first = iter.next().expect("Already validated there are buckets");
+        (first, iter)
+    }
+
+    /// Continues the listing with the block of the bucket returned by `pending_links`.
+    pub fn continue_walk(
+        mut self,
+        next: &[u8],
+    ) -> Result<(Vec<ListedLink>, Option<ShardedListing>), ListingError> {
+        self.buckets
+            .pop_front()
+            .expect("Already validated there are buckets");
+
+        match FlatUnixFs::try_from(next) {
+            Ok(hamt) if hamt.data.Type == UnixFsType::HAMTShard => {
+                Self::list_bucket(hamt, self.buckets)
+            }
+            Ok(other) => Err(ListingError::UnexpectedBucketType(other.data.Type.into())),
+            Err(ParsingFailed::InvalidDagPb(e)) | Err(ParsingFailed::InvalidUnixFs(e, _)) => {
+                Err(ListingError::Read(Some(e)))
+            }
+            Err(ParsingFailed::NoData(_)) => Err(ListingError::Read(None)),
+        }
+    }
+
+    fn list_bucket(
+        mut hamt: FlatUnixFs<'_>,
+        mut buckets: VecDeque<Cid>,
+    ) -> Result<(Vec<ListedLink>, Option<ShardedListing>), ListingError> {
+        let prefix_len = ShardedLookup::check_supported(&mut hamt)?;
+        let mut links = Vec::new();
+
+        for (nth, link) in hamt.links.into_iter().enumerate() {
+            let name = link.Name.as_deref().unwrap_or_default();
+
+            if name.len() == prefix_len {
+                buckets.push_back(try_convert_cid(nth, link)?);
+            } else if name.len() > prefix_len && name.is_char_boundary(prefix_len) {
+                let name = name[prefix_len..].to_owned();
+                let total_size = link.Tsize.unwrap_or_default();
+                let cid = try_convert_cid(nth, link)?;
+                links.push(ListedLink {
+                    name,
+                    cid,
+                    total_size,
+                });
+            } else {
+                return Err(ListingError::InvalidBucketLink(nth, name.to_owned()));
+            }
+        }
+
+        let rest = if buckets.is_empty() {
+            None
+        } else {
+            Some(ShardedListing { buckets })
+        };
+
+        Ok((links, rest))
+    }
+}
+
+/// The type of an entry, read from the first block of the entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryType {
+    /// A UnixFS file, or a raw block, of the given size.
+    File(u64),
+    /// A UnixFS directory, normal or sharded.
+    Directory,
+    /// A UnixFS symlink with the target path, which might be convertible to UTF-8, but this is
+    /// not specified in the spec.
+    Symlink(Vec<u8>),
+    /// Any other kind of a block.
+    Unknown,
+}
+
+impl EntryType {
+    /// Reads the type of the entry from its first block. Blocks which cannot be parsed are of
+    /// `EntryType::Unknown`.
+    pub fn from_block(cid: &Cid, block: &[u8]) -> Self {
+        match cid.codec() {
+            Codec::Raw => return EntryType::File(block.len() as u64),
+            Codec::DagProtobuf => {}
+            _ => return EntryType::Unknown,
+        }
+
+        let flat = match FlatUnixFs::try_from(block) {
+            Ok(flat) => flat,
+            Err(_) => return EntryType::Unknown,
+        };
+
+        let data_len = flat.data.Data.as_ref().map(|data| data.len() as u64);
+
+        match flat.data.Type {
+            UnixFsType::File | UnixFsType::Raw => {
+                EntryType::File(flat.data.filesize.or(data_len).unwrap_or_default())
+            }
+            UnixFsType::Directory | UnixFsType::HAMTShard => EntryType::Directory,
+            UnixFsType::Symlink => EntryType::Symlink(
+                flat.data
+                    .Data
+                    .map(|data| data.into_owned())
+                    .unwrap_or_default(),
+            ),
+            UnixFsType::Metadata => EntryType::Unknown,
+        }
+    }
+}
+
+/// Errors which can occur while listing a directory.
+#[derive(Debug)]
+pub enum ListingError {
+    /// The block was a UnixFS node other than a directory, e.g. a file.
+    UnexpectedType(UnexpectedNodeType),
+    /// A directory had unsupported properties.
+    UnexpectedDirProperties(UnexpectedDirectoryProperties),
+    /// Unexpected HAMT shard bucket type.
+    UnexpectedBucketType(UnexpectedNodeType),
+    /// Unsupported or unexpected property of the HAMT sharded directory.
+    Shard(ShardError),
+    /// A link of a HAMT sharded directory had a name which did not start with a bucket prefix.
+    InvalidBucketLink(usize, String),
+    /// A link had an invalid Cid.
+    InvalidCid(InvalidCidInLink),
+    /// Parsing failed or the inner dag-pb data was contained no bytes.
+    Read(Option<quick_protobuf::Error>),
+}
+
+impl From<UnexpectedDirectoryProperties> for ListingError {
+    fn from(e: UnexpectedDirectoryProperties) -> Self {
+        ListingError::UnexpectedDirProperties(e)
+    }
+}
+
+impl From<ShardError> for ListingError {
+    fn from(e: ShardError) -> Self {
+        ListingError::Shard(e)
+    }
+}
+
+impl From<InvalidCidInLink> for ListingError {
+    fn from(e: InvalidCidInLink) -> Self {
+        ListingError::InvalidCid(e)
+    }
+}
+
+impl fmt::Display for ListingError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ListingError::*;
+
+        match self {
+            UnexpectedType(ut) => write!(fmt, "unexpected type for a directory: {:?}", ut),
+            UnexpectedDirProperties(udp) => write!(fmt, "unsupported directory: {}", udp),
+            UnexpectedBucketType(ut) => write!(fmt, "unexpected type for HAMT bucket: {:?}", ut),
+            Shard(e) => write!(fmt, "{}", e),
+            InvalidBucketLink(nth, name) => write!(
+                fmt,
+                "link #{} of a HAMT bucket has an invalid name: {:?}",
+                nth, name
+            ),
+            InvalidCid(e) => write!(fmt, "Invalid link: {:?}", e),
+            Read(Some(e)) => write!(
+                fmt,
+                "failed to parse the block as unixfs or dag-pb node: {}",
+                e
+            ),
+            Read(None) => write!(fmt, "HAMTDirectory not found in empty dag-pb node"),
+        }
+    }
+}
+
+impl std::error::Error for ListingError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use ListingError::*;
+        match self {
+            Shard(e) => Some(e),
+            Read(Some(e)) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{list, EntryType, ListedLink, ListingError};
+    use crate::test_support::FakeBlockstore;
+    use cid::Cid;
+    use core::convert::TryFrom;
+
+    #[test]
+    fn flat_directory() {
+        let blocks = FakeBlockstore::with_fixtures();
+        let root = Cid::try_from("QmPTotyhVnnfCu9R4qwR4cdhpi5ENaiP8ZJfdqsm8Dw2jB").unwrap();
+
+        let (links, rest) = list(blocks.get_by_cid(&root)).unwrap();
+        assert!(rest.is_none());
+
+        assert_eq!(
+            links,
+            &[ListedLink {
+                name: "QmVkvLsSEm2uJx1h5Fqukje8mMPYg393o5C2kMCkF2bBTA".into(),
+                cid: Cid::try_from("QmVkvLsSEm2uJx1h5Fqukje8mMPYg393o5C2kMCkF2bBTA").unwrap(),
+                total_size: links[0].total_size,
+            }]
+        );
+        assert_ne!(links[0].total_size, 0);
+
+        let (links, rest) = list(blocks.get_by_cid(&links[0].cid)).unwrap();
+        assert!(rest.is_none());
+
+        let names = links.iter().map(|l| l.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, &["foobar.balanced", "foobar.trickle"]);
+
+        for link in &links {
+            assert_eq!(
+                EntryType::from_block(&link.cid, blocks.get_by_cid(&link.cid)),
+                EntryType::File(7)
+            );
+        }
+    }
+
+    #[test]
+    fn sharded_directory() {
+        let blocks = FakeBlockstore::with_fixtures();
+        let root = Cid::try_from("QmZbFPTnDBMWbQ6iBxQAhuhLz8Nu9XptYS96e7cuf5wvbk").unwrap();
+
+        assert_eq!(
+            EntryType::from_block(&root, blocks.get_by_cid(&root)),
+            EntryType::Directory
+        );
+
+        let (mut names, mut rest) = list(blocks.get_by_cid(&root))
+            .map(|(links, rest)| (links.into_iter().map(|l| l.name).collect::<Vec<_>>(), rest))
+            .unwrap();
+
+        // the root only has buckets
+        assert!(names.is_empty());
+
+        while let Some(listing) = rest {
+            let (next, _) = listing.pending_links();
+            let block = blocks.get_by_cid(next);
+            let (links, next_rest) = listing.continue_walk(block).unwrap();
+            names.extend(links.into_iter().map(|l| l.name));
+            rest = next_rest;
+        }
+
+        names.sort_unstable();
+
+        let mut expected = [38, 48, 50, 58, 9, 33, 4, 34, 17, 37, 40, 16, 41, 3, 25, 49]
+            .iter()
+            .map(|i| format!("long-named-file-{:03}", i))
+            .collect::<Vec<_>>();
+        expected.sort_unstable();
+
+        assert_eq!(names, expected);
+    }
+
+    #[test]
+    fn file_is_not_listed() {
+        let blocks = FakeBlockstore::with_fixtures();
+        let dir = Cid::try_from("QmVkvLsSEm2uJx1h5Fqukje8mMPYg393o5C2kMCkF2bBTA").unwrap();
+        let (links, _) = list(blocks.get_by_cid(&dir)).unwrap();
+
+        match list(blocks.get_by_cid(&links[0].cid)) {
+            Err(ListingError::UnexpectedType(ut)) if ut.is_file() => {}
+            x => unreachable!("{:?}", x),
+        }
+    }
+
+    #[test]
+    fn symlink_type() {
+        let mut block = Vec::new();
+        crate::symlink::serialize_symlink_block("../target", &mut block);
+
+        let mut blocks = FakeBlockstore::default();
+        let cid = blocks.insert_v0(&block);
+
+        assert_eq!(
+            EntryType::from_block(&cid, &block),
+            EntryType::Symlink(b"../target".to_vec())
+        );
+    }
+}
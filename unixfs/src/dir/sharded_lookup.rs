@@ -1,3 +1,4 @@
+use super::builder::{hamt_prefix_len, is_supported_fanout, HASH_MURMUR3};
 use super::{try_convert_cid, MaybeResolved, MultipleMatchingLinks, ResolveError};
 use crate::pb::{FlatUnixFs, PBLink, ParsingFailed, UnixFsType};
 use crate::{InvalidCidInLink, UnexpectedNodeType};
@@ -79,11 +80,12 @@ impl<'needle> ShardedLookup<'needle> {
             }
         };
 
-        Self::check_supported(&mut hamt)?;
+        let prefix_len = Self::check_supported(&mut hamt)?;
 
         let found = Self::partition(
             hamt.links.into_iter(),
             self.needle.as_ref(),
+            prefix_len,
             &mut self.links,
         )?;
 
@@ -114,11 +116,11 @@ impl<'needle> ShardedLookup<'needle> {
         needle: &'needle str,
         cache: &mut Option<Cache>,
     ) -> Result<MaybeResolved<'needle>, LookupError> {
-        Self::check_supported(&mut hamt)?;
+        let prefix_len = Self::check_supported(&mut hamt)?;
 
         let mut links = cache.take().map(|c| c.buffer).unwrap_or_default();
 
-        let found = Self::partition(hamt.links.into_iter(), needle, &mut links)?;
+        let found = Self::partition(hamt.links.into_iter(), needle, prefix_len, &mut links)?;
 
         if let Some(cid) = found {
             *cache = Some(links.into());
@@ -136,11 +138,14 @@ impl<'needle> ShardedLookup<'needle> {
 
     /// Takes the validated object as mutable reference to move data out of it in case of error.
     ///
-    /// Returns an error if we don't support the properties on the HAMTShard-typed node
-    pub(crate) fn check_supported(hamt: &mut FlatUnixFs<'_>) -> Result<(), ShardError> {
+    /// Returns an error if we don't support the properties on the HAMTShard-typed node, otherwise
+    /// the length of the bucket prefix of the link names.
+    pub(crate) fn check_supported(hamt: &mut FlatUnixFs<'_>) -> Result<usize, ShardError> {
         assert_eq!(hamt.data.Type, UnixFsType::HAMTShard);
 
-        if hamt.data.fanout != Some(256) || hamt.data.hashType != Some(34) {
+        let supported_fanout = hamt.data.fanout.map(is_supported_fanout).unwrap_or(false);
+
+        if !supported_fanout || hamt.data.hashType != Some(HASH_MURMUR3) {
             Err(ShardError::UnsupportedProperties {
                 hash_type: hamt.data.hashType,
                 fanout: hamt.data.fanout,
@@ -151,7 +156,7 @@ impl<'needle> ShardedLookup<'needle> {
                 blocksizes: core::mem::take(&mut hamt.data.blocksizes),
             })
         } else {
-            Ok(())
+            Ok(hamt_prefix_len(hamt.data.fanout.unwrap()))
         }
     }
 
@@ -162,6 +167,7 @@ impl<'needle> ShardedLookup<'needle> {
     fn partition<'a>(
         iter: impl Iterator<Item = PBLink<'a>>,
         needle: &str,
+        prefix_len: usize,
         work: &mut VecDeque<Cid>,
     ) -> Result<Option<Cid>, PartitioningError> {
         let mut found = None;
@@ -169,14 +175,13 @@ impl<'needle> ShardedLookup<'needle> {
         for (i, link) in iter.enumerate() {
             let name = link.Name.as_deref().unwrap_or_default();
 
-            if name.len() > 2 && &name[2..] == needle {
+            if name.len() > prefix_len && name.get(prefix_len..) == Some(needle) {
                 if let Some(first) = found.take() {
                     return Err(MultipleMatchingLinks::from((first, (i, link))).into());
                 } else {
                     found = Some((i, try_convert_cid(i, link)?));
                 }
-            } else if name.len() == 2 {
-                // the magic number of two comes from the fanout (256) probably
+            } else if name.len() == prefix_len {
                 let cid = try_convert_cid(i, link)?;
                 work.push_back(cid);
             } else {
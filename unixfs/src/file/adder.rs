@@ -1,24 +1,29 @@
-use cid::Cid;
+use cid::{Cid, Codec};
 
 use crate::pb::{FlatUnixFs, PBLink, UnixFs, UnixFsType};
+use crate::{CidOptions, Metadata};
 use alloc::borrow::Cow;
 use core::fmt;
 use quick_protobuf::{MessageWrite, Writer};
 
-use sha2::{Digest, Sha256};
+mod rabin;
+pub use rabin::Rabin;
 
 /// File tree builder. Implements [`core::default::Default`] which tracks the recent defaults.
 ///
 /// Custom file tree builder can be created with [`FileAdder::builder()`] and configuring the
 /// chunker and collector.
 ///
-/// Current implementation maintains an internal buffer for the block creation and uses a
-/// non-customizable hash function to produce Cid version 0 links. Currently does not support
-/// inline links.
+/// Current implementation maintains an internal buffer for the block creation. By default the
+/// leaves are wrapped in UnixFs and all blocks are linked to with Cid version 0 and sha2-256, which
+/// can be changed through the builder. Blocks up to the configured inline limit are instead linked
+/// to with Cid version 1 using the identity hash.
 #[derive(Default)]
 pub struct FileAdder {
     chunker: Chunker,
     collector: Collector,
+    opts: BlockOptions,
+    metadata: Metadata,
     block_buffer: Vec<u8>,
     // all unflushed links as a flat vec; this is compacted as we grow and need to create a link
     // block for the last N blocks, as decided by the collector.
@@ -109,11 +114,21 @@ impl fmt::Debug for Link {
     }
 }
 
+/// How the blocks are created and linked to, shared by the adder and the collectors.
+#[derive(Debug, Default, Clone, Copy)]
+struct BlockOptions {
+    cid_options: CidOptions,
+    inline_limit: Option<usize>,
+    raw_leaves: bool,
+}
+
 /// Convinience type to facilitate configuring [`FileAdder`]s.
 #[derive(Default)]
 pub struct FileAdderBuilder {
     chunker: Chunker,
     collector: Collector,
+    opts: BlockOptions,
+    metadata: Metadata,
 }
 
 impl FileAdderBuilder {
@@ -130,13 +145,47 @@ impl FileAdderBuilder {
         }
     }
 
+    /// Configures the builder to inline blocks of at most `limit` bytes into their Cids using the
+    /// identity hash. Such blocks are still returned, but need not be stored.
+    pub fn with_inline_limit(mut self, limit: usize) -> Self {
+        self.opts.inline_limit = Some(limit);
+        self
+    }
+
+    /// Configures the builder to use the given Cid version and multihash for all blocks.
+    pub fn with_cid_options(mut self, cid_options: CidOptions) -> Self {
+        self.opts.cid_options = cid_options;
+        self
+    }
+
+    /// Configures the builder to store the file contents as raw blocks instead of wrapping them
+    /// in UnixFs. Raw blocks are always linked to with Cid version 1.
+    pub fn with_raw_leaves(mut self, raw_leaves: bool) -> Self {
+        self.opts.raw_leaves = raw_leaves;
+        self
+    }
+
+    /// Configures the builder to store the given metadata in the root block of the file. A file
+    /// consisting of a single raw leaf is then wrapped in an UnixFs file block, as raw blocks
+    /// cannot hold the metadata.
+    pub fn with_metadata(self, metadata: Metadata) -> Self {
+        FileAdderBuilder { metadata, ..self }
+    }
+
     /// Returns a new FileAdder
     pub fn build(self) -> FileAdder {
-        let FileAdderBuilder { chunker, collector } = self;
+        let FileAdderBuilder {
+            chunker,
+            collector,
+            opts,
+            metadata,
+        } = self;
 
         FileAdder {
             chunker,
             collector,
+            opts,
+            metadata,
             ..Default::default()
         }
     }
@@ -171,7 +220,13 @@ impl FileAdder {
             // blocks and user takes care of chunking (and buffering)?
             //
             // cat file | my_awesome_chunker | my_brilliant_collector
-            let leaf = Self::flush_buffered_leaf(accepted, &mut self.unflushed_links, false);
+            let leaf = Self::flush_buffered_leaf(
+                accepted,
+                &mut self.unflushed_links,
+                false,
+                self.collector.leaf_type(),
+                &self.opts,
+            );
             assert!(leaf.is_some(), "chunk completed, must produce a new block");
             self.block_buffer.clear();
             let links = self.flush_buffered_links(false);
@@ -197,6 +252,8 @@ impl FileAdder {
                     self.block_buffer.as_slice(),
                     &mut self.unflushed_links,
                     false,
+                    self.collector.leaf_type(),
+                    &self.opts,
                 );
                 assert!(leaf.is_some(), "chunk completed, must produce a new block");
                 self.block_buffer.clear();
@@ -208,6 +265,33 @@ impl FileAdder {
         }
     }
 
+    /// Links an existing leaf block as the next chunk of the file instead of pushing its bytes,
+    /// for example when modifying a file. This is only possible at a chunk boundary, when none of
+    /// the pushed bytes are buffered, otherwise `None` is returned and the bytes of the leaf need
+    /// to be pushed instead.
+    ///
+    /// The `total_size` is the size of the leaf block, and the `file_size` the number of file
+    /// bytes in it. Returns the newly created link blocks.
+    pub fn push_leaf(
+        &mut self,
+        cid: Cid,
+        total_size: u64,
+        file_size: u64,
+    ) -> Option<impl Iterator<Item = (Cid, Vec<u8>)>> {
+        if !self.block_buffer.is_empty() {
+            return None;
+        }
+
+        self.unflushed_links.push(Link {
+            depth: 0,
+            target: cid,
+            total_size,
+            file_size,
+        });
+
+        Some(self.flush_buffered_links(false).into_iter())
+    }
+
     /// Called after the last [`FileAdder::push`] to finish the tree construction.
     ///
     /// Returns a list of Cids and their respective blocks.
@@ -219,10 +303,61 @@ impl FileAdder {
             &self.block_buffer.as_slice(),
             &mut self.unflushed_links,
             true,
+            self.collector.leaf_type(),
+            &self.opts,
         );
         let root_links = self.flush_buffered_links(true);
         // should probably error if there is neither?
-        last_leaf.into_iter().chain(root_links.into_iter())
+        let mut blocks = last_leaf
+            .into_iter()
+            .chain(root_links.into_iter())
+            .collect::<Vec<_>>();
+
+        if self.metadata != Metadata::default() {
+            Self::write_metadata(&mut blocks, &self.metadata, &self.opts);
+        }
+
+        blocks.into_iter()
+    }
+
+    /// Replaces the root block, which is the last one, with one holding the metadata. A raw root
+    /// block is kept and linked to from a new root block.
+    fn write_metadata(blocks: &mut Vec<(Cid, Vec<u8>)>, metadata: &Metadata, opts: &BlockOptions) {
+        use core::convert::TryFrom;
+
+        let (cid, block) = blocks.pop().expect("finish always produces the root block");
+
+        let root = if cid.codec() == Codec::Raw {
+            let size = block.len() as u64;
+
+            let mut flat = FlatUnixFs {
+                links: vec![PBLink {
+                    Hash: Some(cid.to_bytes().into()),
+                    Name: Some("".into()),
+                    Tsize: Some(size),
+                }],
+                data: UnixFs {
+                    Type: UnixFsType::File,
+                    filesize: Some(size),
+                    blocksizes: vec![size],
+                    ..Default::default()
+                },
+            };
+
+            metadata.write_into(&mut flat.data);
+            let root = render_and_hash(&flat, opts);
+
+            blocks.push((cid, block));
+            root
+        } else {
+            let mut flat =
+                FlatUnixFs::try_from(block.as_slice()).expect("the root block was just rendered");
+
+            metadata.write_into(&mut flat.data);
+            render_and_hash(&flat, opts)
+        };
+
+        blocks.push(root);
     }
 
     /// Returns `None` when the input is empty but there are links, otherwise a new Cid and a
@@ -231,17 +366,36 @@ impl FileAdder {
         input: &[u8],
         unflushed_links: &mut Vec<Link>,
         finishing: bool,
+        leaf_type: UnixFsType,
+        opts: &BlockOptions,
     ) -> Option<(Cid, Vec<u8>)> {
         if input.is_empty() && (!finishing || !unflushed_links.is_empty()) {
             return None;
         }
 
-        // for empty unixfs file the bytes is missing but filesize is present.
+        // the trickle layout, with the raw typed leaves, creates the empty file as an empty link
+        // block even with raw leaves
+        if opts.raw_leaves && !(input.is_empty() && leaf_type == UnixFsType::Raw) {
+            let vec = input.to_vec();
+            let cid = to_cid(Codec::Raw, &vec, opts);
+
+            unflushed_links.push(Link {
+                depth: 0,
+                target: cid.clone(),
+                total_size: vec.len() as u64,
+                file_size: vec.len() as u64,
+            });
 
-        let data = if !input.is_empty() {
-            Some(Cow::Borrowed(input))
+            return Some((cid, vec));
+        }
+
+        // for empty unixfs file the bytes is missing but filesize is present, and the type is
+        // always File as the block is the root of the file.
+
+        let (data, leaf_type) = if !input.is_empty() {
+            (Some(Cow::Borrowed(input)), leaf_type)
         } else {
-            None
+            (None, UnixFsType::File)
         };
 
         let filesize = Some(input.len() as u64);
@@ -249,7 +403,7 @@ impl FileAdder {
         let inner = FlatUnixFs {
             links: Vec::new(),
             data: UnixFs {
-                Type: UnixFsType::File,
+                Type: leaf_type,
                 Data: data,
                 filesize,
                 // no blocksizes as there are no links
@@ -257,7 +411,7 @@ impl FileAdder {
             },
         };
 
-        let (cid, vec) = render_and_hash(&inner);
+        let (cid, vec) = render_and_hash(&inner, opts);
 
         let total_size = vec.len();
 
@@ -275,7 +429,7 @@ impl FileAdder {
 
     fn flush_buffered_links(&mut self, finishing: bool) -> Vec<(Cid, Vec<u8>)> {
         self.collector
-            .flush_links(&mut self.unflushed_links, finishing)
+            .flush_links(&mut self.unflushed_links, finishing, &self.opts)
     }
 
     /// Test helper for collecting all of the produced blocks; probably not a good idea outside
@@ -307,7 +461,9 @@ impl FileAdder {
     }
 }
 
-fn render_and_hash(flat: &FlatUnixFs<'_>) -> (Cid, Vec<u8>) {
+/// Renders the block, which is linked to with an identity hashed Cid version 1 when it is no
+/// larger than the `inline_limit`.
+fn render_and_hash(flat: &FlatUnixFs<'_>, opts: &BlockOptions) -> (Cid, Vec<u8>) {
     // TODO: as shown in later dagger we don't really need to render the FlatUnixFs fully; we could
     // either just render a fixed header and continue with the body OR links, though the links are
     // a bit more complicated.
@@ -315,16 +471,34 @@ fn render_and_hash(flat: &FlatUnixFs<'_>) -> (Cid, Vec<u8>) {
     let mut writer = Writer::new(&mut out);
     flat.write_message(&mut writer)
         .expect("unsure how this could fail");
-    let mh = multihash::wrap(multihash::Code::Sha2_256, &Sha256::digest(&out));
-    let cid = Cid::new_v0(mh).expect("sha2_256 is the correct multihash for cidv0");
+
+    let cid = to_cid(Codec::DagProtobuf, &out, opts);
     (cid, out)
 }
 
+fn to_cid(codec: Codec, block: &[u8], opts: &BlockOptions) -> Cid {
+    if opts
+        .inline_limit
+        .map(|limit| block.len() <= limit)
+        .unwrap_or(false)
+    {
+        let mh = multihash::wrap(multihash::Code::Identity, block);
+        return Cid::new_v1(codec, mh);
+    }
+
+    opts.cid_options.cid(codec, block)
+}
+
+/// The largest chunk size accepted when parsing a [`Chunker`], same as in go-ipfs.
+pub const CHUNK_SIZE_LIMIT: usize = 1024 * 1024;
+
 /// Chunker strategy
 #[derive(Debug, Clone)]
 pub enum Chunker {
     /// Size based chunking
     Size(usize),
+    /// Content defined chunking with Rabin fingerprints, producing the same chunks as go-ipfs.
+    Rabin(Rabin),
 }
 
 impl core::default::Default for Chunker {
@@ -335,6 +509,16 @@ impl core::default::Default for Chunker {
 }
 
 impl Chunker {
+    /// Returns a Rabin chunker producing chunks of `min` to `max` bytes, on average `avg` bytes,
+    /// or `None` if the sizes are not in order or `avg` is zero.
+    pub fn rabin(min: usize, avg: usize, max: usize) -> Option<Self> {
+        if min <= avg && avg <= max && avg > 0 {
+            Some(Chunker::Rabin(Rabin::new(min, avg, max)))
+        } else {
+            None
+        }
+    }
+
     fn accept<'a>(&mut self, input: &'a [u8], buffered: &[u8]) -> (&'a [u8], bool) {
         use Chunker::*;
 
@@ -345,6 +529,7 @@ impl Chunker {
                 let ready = buffered.len() + l >= *max;
                 (accepted, ready)
             }
+            Rabin(rabin) => rabin.accept(input),
         }
     }
 
@@ -353,18 +538,111 @@ impl Chunker {
 
         match self {
             Size(max) => *max,
+            Rabin(rabin) => rabin.sizes().2,
+        }
+    }
+}
+
+impl core::str::FromStr for Chunker {
+    type Err = ChunkerParseError;
+
+    /// Parses the chunker parameter as accepted by go-ipfs: `size-<size>`, `rabin`,
+    /// `rabin-<avg>` or `rabin-<min>-<avg>-<max>`, where the last form also accepts the values
+    /// labeled as in `rabin-min:<min>-avg:<avg>-max:<max>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use ChunkerParseError::*;
+
+        let parse = |value: &str, label: &'static str| -> Result<usize, ChunkerParseError> {
+            let value = match value.find(':') {
+                Some(at) if &value[..at] == label => &value[at + 1..],
+                Some(_) => return Err(InvalidLabel(label)),
+                None => value,
+            };
+            value.parse::<usize>().map_err(|_| InvalidNumber)
+        };
+
+        let chunker = match s {
+            "" | "default" => Chunker::default(),
+            "buzhash" => return Err(Unsupported("buzhash")),
+            s if s.starts_with("size-") => match s[5..].parse().map_err(|_| InvalidNumber)? {
+                0 => return Err(InvalidSize),
+                size => Chunker::Size(size),
+            },
+            s if s.starts_with("rabin") => {
+                let parts = s.split('-').collect::<Vec<_>>();
+                let (min, avg, max) = match parts.as_slice() {
+                    ["rabin"] => rabin_sizes(256 * 1024),
+                    ["rabin", avg] => rabin_sizes(parse(avg, "avg")?),
+                    ["rabin", min, avg, max] => {
+                        (parse(min, "min")?, parse(avg, "avg")?, parse(max, "max")?)
+                    }
+                    _ => return Err(Unsupported("rabin")),
+                };
+
+                // same as the minimum of go-ipfs, the window has to fit in the minimum size
+                if min < rabin::WINDOW_SIZE {
+                    return Err(InvalidSize);
+                }
+
+                Chunker::rabin(min, avg, max).ok_or(InvalidSize)?
+            }
+            _ => return Err(Unsupported("unknown")),
+        };
+
+        if chunker.size_hint() > CHUNK_SIZE_LIMIT {
+            return Err(InvalidSize);
+        }
+
+        Ok(chunker)
+    }
+}
+
+/// The minimum and maximum sizes go-ipfs derives from the average size.
+fn rabin_sizes(avg: usize) -> (usize, usize, usize) {
+    (avg / 3, avg, avg + avg / 2)
+}
+
+/// Failure to parse a [`Chunker`] from a string.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ChunkerParseError {
+    /// The chunker or its format is not supported.
+    Unsupported(&'static str),
+    /// A size was not a number.
+    InvalidNumber,
+    /// A labeled size had the wrong label.
+    InvalidLabel(&'static str),
+    /// The sizes were zero, out of order or larger than [`CHUNK_SIZE_LIMIT`].
+    InvalidSize,
+}
+
+impl fmt::Display for ChunkerParseError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ChunkerParseError::*;
+        match self {
+            Unsupported(kind) => write!(fmt, "unsupported chunker: {}", kind),
+            InvalidNumber => write!(fmt, "invalid chunk size"),
+            InvalidLabel(label) => write!(fmt, "expected label {:?}", label),
+            InvalidSize => write!(
+                fmt,
+                "chunk sizes must be positive, in order and at most {} bytes",
+                CHUNK_SIZE_LIMIT
+            ),
         }
     }
 }
 
+impl std::error::Error for ChunkerParseError {}
+
 /// Collector or layout strategy. For more information, see the [Layout section of the spec].
-/// Currently only the default balanced collector/layout has been implemented.
+/// The balanced and trickle layouts of go-ipfs have been implemented.
 ///
 /// [Layout section of the spec]: https://github.com/ipfs/specs/blob/master/UNIXFS.md#layout
 #[derive(Debug, Clone)]
 pub enum Collector {
     /// Balanced trees.
     Balanced(BalancedCollector),
+    /// Trickle trees.
+    Trickle(TrickleCollector),
 }
 
 impl core::default::Default for Collector {
@@ -374,11 +652,25 @@ impl core::default::Default for Collector {
 }
 
 impl Collector {
-    fn flush_links(&mut self, pending: &mut Vec<Link>, finishing: bool) -> Vec<(Cid, Vec<u8>)> {
+    fn flush_links(
+        &mut self,
+        pending: &mut Vec<Link>,
+        finishing: bool,
+        opts: &BlockOptions,
+    ) -> Vec<(Cid, Vec<u8>)> {
         use Collector::*;
 
         match self {
-            Balanced(bc) => bc.flush_links(pending, finishing),
+            Balanced(bc) => bc.flush_links(pending, finishing, opts),
+            Trickle(tc) => tc.flush_links(pending, finishing, opts),
+        }
+    }
+
+    /// The UnixFs type of the leaf blocks, which differs between the go-ipfs layouts.
+    fn leaf_type(&self) -> UnixFsType {
+        match self {
+            Collector::Balanced(_) => UnixFsType::File,
+            Collector::Trickle(_) => UnixFsType::Raw,
         }
     }
 }
@@ -438,7 +730,12 @@ impl BalancedCollector {
     /// In-place compression of the `pending` links to a balanced hierarchy. When `finishing`, the
     /// links will be compressed iteratively from the lowest level to produce a single root link
     /// block.
-    fn flush_links(&mut self, pending: &mut Vec<Link>, finishing: bool) -> Vec<(Cid, Vec<u8>)> {
+    fn flush_links(
+        &mut self,
+        pending: &mut Vec<Link>,
+        finishing: bool,
+        opts: &BlockOptions,
+    ) -> Vec<(Cid, Vec<u8>)> {
         /*
 
         file    |- - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -|
@@ -557,7 +854,7 @@ impl BalancedCollector {
                         index + first_at
                     );
 
-                    Self::partition_link(
+                    partition_link(
                         link,
                         &mut reused_links,
                         &mut reused_blocksizes,
@@ -578,7 +875,7 @@ impl BalancedCollector {
                     },
                 };
 
-                let (cid, vec) = render_and_hash(&inner);
+                let (cid, vec) = render_and_hash(&inner, opts);
 
                 // start overwriting at the first index of this level, then continue forward on
                 // next iterations.
@@ -619,35 +916,240 @@ impl BalancedCollector {
 
         ret
     }
+}
+
+/// How many subtrees of each depth are added to a trickle link block, after the leaves.
+const TRICKLE_DEPTH_REPEAT: usize = 4;
+
+/// TrickleCollector creates trickle UnixFs trees, optimized for reading the file sequentially and
+/// for appending to it later. The trees match the ones created by `go-ipfs add --trickle`.
+///
+/// Every link block first links up to the branching factor of leaves, followed by
+/// `TRICKLE_DEPTH_REPEAT` subtrees of depth one, then as many of depth two and so on. Subtrees are
+/// limited to depths less than their own, while the root block has no such limit.
+#[derive(Clone)]
+pub struct TrickleCollector {
+    branching_factor: usize,
+    /// Link blocks under construction, from the root to the one currently receiving leaves.
+    frames: Vec<TrickleFrame>,
+    /// The number of pending links which have been placed into the frames.
+    placed: usize,
+}
+
+#[derive(Clone, Debug)]
+struct TrickleFrame {
+    /// Index of the first link of this block in the pending links.
+    start: usize,
+    /// The depth this block is limited to, `None` for the root block.
+    max_depth: Option<usize>,
+    /// Depth of the subtrees currently being linked, zero while linking leaves.
+    depth: usize,
+    /// The number of leaves or subtrees linked at the current depth.
+    count: usize,
+}
+
+impl TrickleFrame {
+    fn new(start: usize, max_depth: Option<usize>) -> Self {
+        TrickleFrame {
+            start,
+            max_depth,
+            depth: 0,
+            count: 0,
+        }
+    }
+
+    /// Returns true when no more links can be added to this block.
+    fn is_full(&self, branching_factor: usize) -> bool {
+        match self.max_depth {
+            None => false,
+            Some(1) => self.count == branching_factor,
+            Some(max_depth) => self.depth + 1 == max_depth && self.count == TRICKLE_DEPTH_REPEAT,
+        }
+    }
+}
+
+impl fmt::Debug for TrickleCollector {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            fmt,
+            "TrickleCollector {{ branching_factor: {}, frames: {} }}",
+            self.branching_factor,
+            self.frames.len()
+        )
+    }
+}
+
+impl core::default::Default for TrickleCollector {
+    /// Returns a default collector which matches go-ipfs 0.6, which uses the same branching factor
+    /// as for the balanced trees.
+    fn default() -> Self {
+        Self::with_branching_factor(174)
+    }
+}
+
+impl From<TrickleCollector> for Collector {
+    fn from(t: TrickleCollector) -> Self {
+        Collector::Trickle(t)
+    }
+}
+
+impl TrickleCollector {
+    /// Configure Trickle collector with the given branching factor.
+    pub fn with_branching_factor(branching_factor: usize) -> Self {
+        assert!(branching_factor > 0);
+
+        Self {
+            branching_factor,
+            frames: vec![TrickleFrame::new(0, None)],
+            placed: 0,
+        }
+    }
+
+    /// Places the new `pending` leaves in the tree, replacing the links of each completed link
+    /// block with a link to the block. When `finishing`, all of the remaining link blocks are
+    /// completed, ending with the root block.
+    fn flush_links(
+        &mut self,
+        pending: &mut Vec<Link>,
+        finishing: bool,
+        opts: &BlockOptions,
+    ) -> Vec<(Cid, Vec<u8>)> {
+        let mut ret = Vec::new();
+
+        if finishing && self.placed == 0 && pending.len() == 1 && pending[0].file_size == 0 {
+            // the empty file is a single block like with the balanced trees
+            return ret;
+        }
+
+        while self.placed < pending.len() {
+            self.place_leaf();
+
+            while self
+                .frames
+                .last()
+                .map(|frame| frame.is_full(self.branching_factor))
+                .unwrap_or(false)
+            {
+                self.close_frame(pending, opts, &mut ret);
+            }
+        }
+
+        if finishing {
+            while !self.frames.is_empty() {
+                self.close_frame(pending, opts, &mut ret);
+            }
+        }
+
+        ret
+    }
+
+    /// Accounts for the next pending leaf, creating the subtrees it needs to be placed in.
+    fn place_leaf(&mut self) {
+        let index = self.placed;
+        self.placed += 1;
 
-    /// Each link needs to be partitioned into the four mut arguments received by this function in
-    /// order to produce the expected UnixFs output.
-    fn partition_link(
-        link: &Link,
-        links: &mut Vec<PBLink<'static>>,
-        blocksizes: &mut Vec<u64>,
-        nested_size: &mut u64,
-        nested_total_size: &mut u64,
+        loop {
+            let top = self
+                .frames
+                .last_mut()
+                .expect("frames are only closed when finishing or full");
+
+            if top.depth == 0 && top.count < self.branching_factor {
+                top.count += 1;
+                return;
+            }
+
+            if top.depth == 0 || top.count == TRICKLE_DEPTH_REPEAT {
+                top.depth += 1;
+                top.count = 0;
+            }
+
+            debug_assert!(top.max_depth.map(|max| top.depth < max).unwrap_or(true));
+
+            top.count += 1;
+            let max_depth = Some(top.depth);
+            self.frames.push(TrickleFrame::new(index, max_depth));
+        }
+    }
+
+    /// Creates the link block for the innermost frame, replacing its links in `pending`.
+    fn close_frame(
+        &mut self,
+        pending: &mut Vec<Link>,
+        opts: &BlockOptions,
+        ret: &mut Vec<(Cid, Vec<u8>)>,
     ) {
-        links.push(PBLink {
-            Hash: Some(link.target.to_bytes().into()),
-            Name: Some("".into()),
-            Tsize: Some(link.total_size),
-        });
-        blocksizes.push(link.file_size);
-        *nested_size += link.file_size;
-        *nested_total_size += link.total_size;
+        let frame = self.frames.pop().expect("only called with frames");
+
+        let mut links = Vec::with_capacity(self.placed - frame.start);
+        let mut blocksizes = Vec::with_capacity(self.placed - frame.start);
+        let mut nested_size = 0;
+        let mut nested_total_size = 0;
+
+        for link in &pending[frame.start..self.placed] {
+            partition_link(
+                link,
+                &mut links,
+                &mut blocksizes,
+                &mut nested_size,
+                &mut nested_total_size,
+            );
+        }
+
+        let inner = FlatUnixFs {
+            links,
+            data: UnixFs {
+                Type: UnixFsType::File,
+                filesize: Some(nested_size),
+                blocksizes,
+                ..Default::default()
+            },
+        };
+
+        let (cid, vec) = render_and_hash(&inner, opts);
+
+        pending[frame.start] = Link {
+            depth: frame.depth + 1,
+            target: cid.clone(),
+            total_size: nested_total_size + vec.len() as u64,
+            file_size: nested_size,
+        };
+        pending.drain((frame.start + 1)..self.placed);
+        self.placed = frame.start + 1;
+
+        ret.push((cid, vec));
     }
 }
 
+/// Each link needs to be partitioned into the four mut arguments received by this function in
+/// order to produce the expected UnixFs output.
+fn partition_link(
+    link: &Link,
+    links: &mut Vec<PBLink<'static>>,
+    blocksizes: &mut Vec<u64>,
+    nested_size: &mut u64,
+    nested_total_size: &mut u64,
+) {
+    links.push(PBLink {
+        Hash: Some(link.target.to_bytes().into()),
+        Name: Some("".into()),
+        Tsize: Some(link.total_size),
+    });
+    blocksizes.push(link.file_size);
+    *nested_size += link.file_size;
+    *nested_total_size += link.total_size;
+}
+
 #[cfg(test)]
 mod tests {
 
-    use super::{BalancedCollector, Chunker, FileAdder};
+    use super::{BalancedCollector, Chunker, Collector, FileAdder, TrickleCollector};
     use crate::test_support::FakeBlockstore;
+    use crate::CidOptions;
     use cid::Cid;
     use core::convert::TryFrom;
     use hex_literal::hex;
+    use multihash::Code;
 
     #[test]
     fn test_size_chunker() {
@@ -695,6 +1197,50 @@ mod tests {
         );
     }
 
+    #[test]
+    fn pushed_leaves_are_linked_like_the_pushed_bytes() {
+        let content = b"foobarbazquux!";
+
+        let collectors: [Collector; 2] = [
+            BalancedCollector::with_branching_factor(2).into(),
+            TrickleCollector::with_branching_factor(2).into(),
+        ];
+
+        for collector in collectors.iter() {
+            let builder = || {
+                FileAdder::builder()
+                    .with_chunker(Chunker::Size(4))
+                    .with_collector(collector.clone())
+            };
+
+            let expected = builder().build().collect_blocks(content, 0);
+
+            // the leaves of the first two chunks come first
+            let leaves = expected[..2]
+                .iter()
+                .map(|(cid, block)| (cid.clone(), block.len() as u64))
+                .collect::<Vec<_>>();
+
+            let mut adder = builder().build();
+            let mut blocks = Vec::new();
+
+            for (cid, total_size) in &leaves {
+                blocks.extend(adder.push_leaf(cid.clone(), *total_size, 4).unwrap());
+            }
+
+            let (ready, pushed) = adder.push(&content[8..10]);
+            blocks.extend(ready);
+            assert_eq!(pushed, 2);
+
+            let (cid, total_size) = leaves[0].clone();
+            assert!(adder.push_leaf(cid, total_size, 4).is_none());
+
+            blocks.extend(adder.collect_blocks(&content[10..], 0));
+
+            assert_eq!(blocks.last(), expected.last(), "{:?}", collector);
+        }
+    }
+
     #[test]
     fn favourite_multi_block_file() {
         // root should be QmRJHYTNvC3hmd9gJQARxLR1QMEincccBV53bBw524yyq6
@@ -726,6 +1272,294 @@ mod tests {
         assert_eq!(blocks_received, expected);
     }
 
+    #[test]
+    fn inline_small_blocks() {
+        let content = b"foobar\n";
+        let adder = FileAdder::builder()
+            .with_chunker(Chunker::Size(2))
+            .with_inline_limit(32)
+            .build();
+
+        let blocks_received = adder.collect_blocks(content, 0);
+        assert_eq!(blocks_received.len(), 5);
+
+        let (root, leaves) = blocks_received.split_last().unwrap();
+
+        for (cid, block) in leaves {
+            assert_eq!(cid.version(), cid::Version::V1);
+            assert_eq!(cid.hash().algorithm(), multihash::Code::Identity);
+            assert_eq!(cid.hash().digest(), block.as_slice());
+        }
+
+        // the root links to the four leaves and is too large to be inlined
+        assert!(root.1.len() > 32);
+        assert_eq!(root.0.version(), cid::Version::V0);
+    }
+
+    #[test]
+    fn parse_chunkers() {
+        use core::str::FromStr;
+
+        let sizes = |s: &str| match Chunker::from_str(s) {
+            Ok(Chunker::Size(size)) => Ok((size, size, size)),
+            Ok(Chunker::Rabin(rabin)) => Ok(rabin.sizes()),
+            Err(e) => Err(e),
+        };
+
+        assert_eq!(sizes(""), Ok((256 * 1024, 256 * 1024, 256 * 1024)));
+        assert_eq!(sizes("size-1000"), Ok((1000, 1000, 1000)));
+        assert_eq!(sizes("rabin"), Ok((87381, 262144, 393216)));
+        assert_eq!(sizes("rabin-4096"), Ok((1365, 4096, 6144)));
+        assert_eq!(sizes("rabin-512-1024-2048"), Ok((512, 1024, 2048)));
+        assert_eq!(
+            sizes("rabin-min:512-avg:1024-max:2048"),
+            Ok((512, 1024, 2048))
+        );
+        // the average is rounded down to a power of two
+        assert_eq!(sizes("rabin-512-1500-2048"), Ok((512, 1024, 2048)));
+
+        use super::ChunkerParseError::*;
+        assert_eq!(sizes("size-0"), Err(InvalidSize));
+        assert_eq!(sizes("size-2000000"), Err(InvalidSize));
+        assert_eq!(sizes("size-a"), Err(InvalidNumber));
+        assert_eq!(sizes("rabin-2048-1024-512"), Err(InvalidSize));
+        assert_eq!(sizes("rabin-8-16-32"), Err(InvalidSize));
+        assert_eq!(
+            sizes("rabin-avg:512-avg:1024-max:2048"),
+            Err(InvalidLabel("min"))
+        );
+        assert_eq!(sizes("rabin-1-2"), Err(Unsupported("rabin")));
+        assert_eq!(sizes("buzhash"), Err(Unsupported("buzhash")));
+        assert_eq!(sizes("fixed"), Err(Unsupported("unknown")));
+    }
+
+    #[test]
+    fn rabin_chunks_within_limits() {
+        let mut content = vec![0u8; 100_000];
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        for b in content.iter_mut() {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            *b = state as u8;
+        }
+
+        let adder = FileAdder::builder()
+            .with_chunker(Chunker::rabin(1024, 4096, 8192).unwrap())
+            .build();
+
+        let blocks = adder.collect_blocks(&content, 3000);
+        let (_root, leaves) = blocks.split_last().unwrap();
+
+        let sizes = leaves
+            .iter()
+            .map(|(_, block)| {
+                crate::pb::FlatUnixFs::try_from(block.as_slice())
+                    .unwrap()
+                    .data
+                    .filesize
+            })
+            .map(|size| size.unwrap() as usize)
+            .collect::<Vec<_>>();
+
+        assert_eq!(sizes.iter().sum::<usize>(), content.len());
+        // all but the last chunk are within the limits
+        for size in &sizes[..sizes.len() - 1] {
+            assert!(*size >= 1024 && *size <= 8192, "{:?}", sizes);
+        }
+        // the sizes should vary with content defined chunking
+        assert!(sizes.iter().any(|size| *size != sizes[0]));
+
+        let whole = FileAdder::builder()
+            .with_chunker(Chunker::rabin(1024, 4096, 8192).unwrap())
+            .build()
+            .collect_blocks(&content, 0);
+        assert_eq!(whole.last().unwrap().0, blocks.last().unwrap().0);
+    }
+
+    #[test]
+    fn rabin_single_chunk_file() {
+        use core::str::FromStr;
+
+        // echo foobar > file1 && ipfs add file1; shorter than any of the minimum sizes the file
+        // is a single chunk with all of the chunkers
+        for chunker in &["rabin", "rabin-16-32-64", "size-262144"] {
+            let blocks = FileAdder::builder()
+                .with_chunker(Chunker::from_str(chunker).unwrap())
+                .build()
+                .collect_blocks(b"foobar\n", 0);
+
+            assert_eq!(blocks.len(), 1);
+            assert_eq!(
+                blocks[0].0.to_string(),
+                "QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL",
+                "{}",
+                chunker
+            );
+        }
+    }
+
+    #[test]
+    fn raw_leaves_single_block_file() {
+        let content = b"hello world";
+
+        let blocks = FileAdder::builder()
+            .with_raw_leaves(true)
+            .with_cid_options(CidOptions::new(cid::Version::V1, Code::Sha2_256))
+            .build()
+            .collect_blocks(content, 0);
+
+        // the single raw leaf is the root, like with go-ipfs add --cid-version=1
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].1.as_slice(), content);
+        assert_eq!(
+            blocks[0].0.to_string(),
+            "bafkreifzjut3te2nhyekklss27nh3k72ysco7y32koao5eei66wof36n5e"
+        );
+    }
+
+    #[test]
+    fn raw_leaves_multi_block_file() {
+        use crate::pb::FlatUnixFs;
+
+        let content = b"foobar\n";
+
+        let blocks = FileAdder::builder()
+            .with_chunker(Chunker::Size(2))
+            .with_raw_leaves(true)
+            .build()
+            .collect_blocks(content, 0);
+
+        assert_eq!(blocks.len(), 5);
+
+        let (leaves, root) = blocks.split_at(4);
+        for ((cid, block), expected) in leaves.iter().zip(content.chunks(2)) {
+            assert_eq!(cid.codec(), cid::Codec::Raw);
+            assert_eq!(cid.version(), cid::Version::V1);
+            assert_eq!(block.as_slice(), expected);
+        }
+
+        // the link block is still linked to with the configured cid version
+        let (cid, block) = &root[0];
+        assert_eq!(cid.version(), cid::Version::V0);
+        let flat = FlatUnixFs::try_from(block.as_slice()).unwrap();
+        let links = flat
+            .links
+            .iter()
+            .map(|link| Cid::try_from(link.Hash.as_deref().unwrap()).unwrap())
+            .collect::<Vec<_>>();
+        let leaf_cids = leaves
+            .iter()
+            .map(|(cid, _)| cid.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(links, leaf_cids);
+        assert_eq!(flat.data.blocksizes, &[2, 2, 2, 1]);
+        assert_eq!(flat.data.filesize, Some(7));
+    }
+
+    #[test]
+    fn cid_options_hash_all_blocks() {
+        let content = b"foobar\n";
+
+        let v0 = FileAdder::builder()
+            .with_chunker(Chunker::Size(2))
+            .build()
+            .collect_blocks(content, 0);
+
+        let v1 = FileAdder::builder()
+            .with_chunker(Chunker::Size(2))
+            .with_cid_options(CidOptions::new(cid::Version::V1, Code::Sha2_256))
+            .build()
+            .collect_blocks(content, 0);
+
+        // the leaf blocks are the same, only linked differently
+        assert_eq!(
+            v0[..4].iter().map(|(_, b)| b).collect::<Vec<_>>(),
+            v1[..4].iter().map(|(_, b)| b).collect::<Vec<_>>()
+        );
+
+        for (v1_cid, _) in &v1 {
+            assert_eq!(v1_cid.version(), cid::Version::V1);
+            assert_eq!(v1_cid.codec(), cid::Codec::DagProtobuf);
+        }
+
+        for ((v0_cid, _), (v1_cid, _)) in v0.iter().zip(v1.iter()).take(4) {
+            assert_eq!(v0_cid.hash(), v1_cid.hash());
+        }
+
+        let blake = FileAdder::builder()
+            .with_chunker(Chunker::Size(2))
+            .with_cid_options(CidOptions::new(cid::Version::V0, Code::Blake2b256))
+            .build()
+            .collect_blocks(content, 0);
+
+        for (cid, block) in blake {
+            // version 0 cannot be used with other hashes
+            assert_eq!(cid.version(), cid::Version::V1);
+            assert_eq!(cid.hash(), Code::Blake2b256.digest(&block).as_ref());
+        }
+    }
+
+    #[test]
+    fn trickle_raw_leaves_empty_file() {
+        let blocks = FileAdder::builder()
+            .with_collector(TrickleCollector::default())
+            .with_raw_leaves(true)
+            .build()
+            .collect_blocks(b"", 0);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].1.as_slice(), &hex!("0a 04 08 02 18 00"));
+
+        let blocks = FileAdder::builder()
+            .with_raw_leaves(true)
+            .build()
+            .collect_blocks(b"", 0);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].0.codec(), cid::Codec::Raw);
+        assert!(blocks[0].1.is_empty());
+    }
+
+    #[test]
+    fn metadata_in_root_block() {
+        use crate::pb::FlatUnixFs;
+        use crate::Metadata;
+
+        let metadata = Metadata::default()
+            .with_mode(0o644)
+            .with_mtime(1_600_000_000, 5);
+
+        let blocks = FileAdder::builder()
+            .with_metadata(metadata.clone())
+            .build()
+            .collect_blocks(b"foobar\n", 0);
+
+        assert_eq!(blocks.len(), 1);
+
+        let root = FlatUnixFs::try_from(blocks[0].1.as_slice()).unwrap();
+        assert_eq!(Metadata::from(&root.data), metadata);
+        assert_eq!(root.data.Data.as_deref(), Some(&b"foobar\n"[..]));
+
+        // raw leaves cannot hold the metadata, so a single raw leaf gets a new root
+        let blocks = FileAdder::builder()
+            .with_raw_leaves(true)
+            .with_metadata(metadata.clone())
+            .build()
+            .collect_blocks(b"foobar\n", 0);
+
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].0.codec(), cid::Codec::Raw);
+
+        let root = FlatUnixFs::try_from(blocks[1].1.as_slice()).unwrap();
+        assert_eq!(Metadata::from(&root.data), metadata);
+        assert_eq!(root.data.filesize, Some(7));
+        assert_eq!(root.data.blocksizes, &[7]);
+        assert_eq!(root.links.len(), 1);
+        assert_eq!(
+            Cid::try_from(root.links[0].Hash.as_deref().unwrap()).unwrap(),
+            blocks[0].0
+        );
+    }
+
     #[test]
     fn three_layers() {
         let content = b"Lorem ipsum dolor sit amet, sit enim montes aliquam. Cras non lorem, \
@@ -869,4 +1703,143 @@ mod tests {
 
         assert_eq!(blocks_count, 175);
     }
+
+    #[derive(Debug, PartialEq)]
+    enum Shape {
+        Leaf,
+        Links(Vec<Shape>),
+    }
+
+    /// The trickle layout as described by go-ipfs `trickle.Layout`, consuming `leaves`.
+    fn trickle_shape(leaves: &mut usize, max_depth: Option<usize>, branching: usize) -> Shape {
+        let mut children = Vec::new();
+        while children.len() < branching && *leaves > 0 {
+            *leaves -= 1;
+            children.push(Shape::Leaf);
+        }
+        let mut depth = 1;
+        while max_depth.map(|max| depth < max).unwrap_or(true) && *leaves > 0 {
+            for _ in 0..4 {
+                if *leaves == 0 {
+                    break;
+                }
+                children.push(trickle_shape(leaves, Some(depth), branching));
+            }
+            depth += 1;
+        }
+        Shape::Links(children)
+    }
+
+    /// Reads the shape of the tree, appending the leaf contents to `content`.
+    fn read_shape(
+        blocks: &std::collections::HashMap<Cid, Vec<u8>>,
+        cid: &Cid,
+        content: &mut Vec<u8>,
+    ) -> Shape {
+        use crate::pb::{FlatUnixFs, UnixFsType};
+
+        let flat = FlatUnixFs::try_from(blocks[cid].as_slice()).unwrap();
+        if flat.links.is_empty() {
+            assert_eq!(flat.data.Type, UnixFsType::Raw);
+            content.extend_from_slice(flat.data.Data.as_deref().unwrap());
+            return Shape::Leaf;
+        }
+
+        assert_eq!(flat.data.Type, UnixFsType::File);
+        assert_eq!(flat.links.len(), flat.data.blocksizes.len());
+
+        let before = content.len();
+        let children = flat
+            .links
+            .iter()
+            .map(|link| {
+                let cid = Cid::try_from(link.Hash.as_deref().unwrap()).unwrap();
+                read_shape(blocks, &cid, content)
+            })
+            .collect();
+        assert_eq!(flat.data.filesize, Some((content.len() - before) as u64));
+        Shape::Links(children)
+    }
+
+    #[test]
+    fn trickle_layouts() {
+        let content = (0..=255u8).cycle().take(700).collect::<Vec<_>>();
+
+        for &(branching, len) in &[
+            (2, 1),
+            (2, 2),
+            (2, 3),
+            (2, 30),
+            (2, 200),
+            (3, 700),
+            (174, 3),
+        ] {
+            let content = &content[..len];
+            let blocks = FileAdder::builder()
+                .with_chunker(Chunker::Size(1))
+                .with_collector(TrickleCollector::with_branching_factor(branching))
+                .build()
+                .collect_blocks(content, 0);
+
+            let root = blocks.last().unwrap().0.clone();
+            let blocks = blocks.into_iter().collect();
+
+            let mut read = Vec::new();
+            let shape = read_shape(&blocks, &root, &mut read);
+
+            let mut leaves = len;
+            let expected = trickle_shape(&mut leaves, None, branching);
+            assert_eq!(leaves, 0);
+            assert_eq!(shape, expected, "branching: {}, len: {}", branching, len);
+            assert_eq!(read, content);
+        }
+    }
+
+    #[test]
+    fn favourite_trickle_file() {
+        let blocks = FakeBlockstore::with_fixtures();
+        let content = b"foobar\n";
+
+        // go-ipfs 0.5 add --trickle -s size-2
+        let expected = "QmWfQ48ChJUj4vWKFsUDe4646xCBmXgdmNfhjz9T7crywd";
+
+        let received = FileAdder::builder()
+            .with_chunker(Chunker::Size(2))
+            .with_collector(TrickleCollector::default())
+            .build()
+            .collect_blocks(content, 0);
+
+        assert_eq!(received.len(), 5);
+        for (cid, block) in &received {
+            assert_eq!(block.as_slice(), blocks.get_by_cid(cid));
+        }
+        assert_eq!(received.last().unwrap().0.to_string(), expected);
+    }
+
+    #[test]
+    fn trickle_pushes_in_order() {
+        let content = (0..=255u8).cycle().take(1000).collect::<Vec<_>>();
+
+        let build = |amt| {
+            FileAdder::builder()
+                .with_chunker(Chunker::Size(7))
+                .with_collector(TrickleCollector::with_branching_factor(3))
+                .build()
+                .collect_blocks(&content, amt)
+        };
+
+        let whole = build(0);
+        for amt in &[1, 5, 7, 100] {
+            assert_eq!(whole, build(*amt));
+        }
+    }
+
+    #[test]
+    fn trickle_empty_file() {
+        let trickle = FileAdder::builder()
+            .with_collector(TrickleCollector::default())
+            .build()
+            .collect_blocks(b"", 0);
+        assert_eq!(trickle, FileAdder::default().collect_blocks(b"", 0));
+    }
 }
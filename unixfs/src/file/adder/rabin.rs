@@ -0,0 +1,443 @@
+//! Content defined chunking with Rabin fingerprints over a sliding window, following the chunker
+//! used by go-ipfs for the `rabin` chunkers so that the same input is split at the same offsets.
+
+use core::fmt;
+
+/// The size of the sliding window in bytes. go-ipfs uses the fork of the restic chunker at
+/// `whyrusleeping/chunker`, which has a smaller window than the original.
+pub(super) const WINDOW_SIZE: usize = 16;
+
+/// The irreducible polynomial go-ipfs uses for the fingerprints.
+const IPFS_POLYNOMIAL: u64 = 17_437_180_132_763_653;
+
+/// State of the Rabin chunker. Created through [`super::Chunker::rabin`] or by parsing a
+/// [`super::Chunker`].
+#[derive(Clone)]
+pub struct Rabin {
+    min: usize,
+    max: usize,
+    /// Chunk boundary is found when the masked bits of the digest are all zero.
+    mask: u64,
+    pol_shift: u32,
+    tables: Box<Tables>,
+    window: [u8; WINDOW_SIZE],
+    wpos: usize,
+    digest: u64,
+    /// Bytes accepted into the current chunk.
+    count: usize,
+}
+
+#[derive(Clone)]
+struct Tables {
+    /// Values to remove the byte sliding out of the window from the digest.
+    out: [u64; 256],
+    /// Values to reduce the digest modulo the polynomial after appending a byte.
+    modulo: [u64; 256],
+}
+
+impl fmt::Debug for Rabin {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            fmt,
+            "Rabin {{ min: {}, avg: {}, max: {}, count: {} }}",
+            self.min,
+            self.mask + 1,
+            self.max,
+            self.count
+        )
+    }
+}
+
+impl Rabin {
+    /// Creates a chunker producing chunks of `min` to `max` bytes, on average `avg` bytes.
+    pub(super) fn new(min: usize, avg: usize, max: usize) -> Self {
+        assert!(min <= avg && avg <= max && avg > 0);
+
+        let degree = degree(IPFS_POLYNOMIAL);
+        // like go-ipfs, the average is rounded down to a power of two
+        let size_pow = 63 - (avg as u64).leading_zeros();
+
+        let mut rabin = Rabin {
+            min,
+            max,
+            mask: (1 << size_pow) - 1,
+            pol_shift: degree - 8,
+            tables: Box::new(Tables::new(IPFS_POLYNOMIAL)),
+            window: [0; WINDOW_SIZE],
+            wpos: 0,
+            digest: 0,
+            count: 0,
+        };
+        rabin.reset();
+        rabin
+    }
+
+    /// Returns the minimum, average and maximum chunk sizes. The average is a power of two.
+    pub fn sizes(&self) -> (usize, usize, usize) {
+        (self.min, self.mask as usize + 1, self.max)
+    }
+
+    /// Returns the prefix of the input which belongs to the current chunk, and true if the chunk
+    /// was completed.
+    pub(super) fn accept<'a>(&mut self, input: &'a [u8]) -> (&'a [u8], bool) {
+        // the bytes before the last window of the minimum size never affect the boundary
+        let skipped = self.min.saturating_sub(WINDOW_SIZE);
+
+        for (i, &b) in input.iter().enumerate() {
+            self.count += 1;
+
+            if self.count <= skipped {
+                continue;
+            }
+
+            self.slide(b);
+
+            if self.count < self.min {
+                continue;
+            }
+
+            if self.digest & self.mask == 0 || self.count >= self.max {
+                self.reset();
+                return (&input[..=i], true);
+            }
+        }
+
+        (input, false)
+    }
+
+    fn reset(&mut self) {
+        self.window = [0; WINDOW_SIZE];
+        self.wpos = 0;
+        self.digest = 0;
+        self.count = 0;
+        // part of the algorithm as implemented by go-ipfs
+        self.slide(1);
+    }
+
+    fn slide(&mut self, b: u8) {
+        let out = self.window[self.wpos];
+        self.window[self.wpos] = b;
+        self.digest ^= self.tables.out[out as usize];
+        self.wpos = (self.wpos + 1) % WINDOW_SIZE;
+
+        let index = (self.digest >> self.pol_shift) as usize;
+        self.digest <<= 8;
+        self.digest |= b as u64;
+        self.digest ^= self.tables.modulo[index];
+    }
+}
+
+impl Tables {
+    fn new(pol: u64) -> Self {
+        let mut out = [0u64; 256];
+        let mut modulo = [0u64; 256];
+        let degree = degree(pol);
+
+        for b in 0..256u64 {
+            // the hash of the byte followed by a window worth of zeroes, which can be added to
+            // cancel out the byte leaving the window
+            let mut h = append_byte(0, b as u8, pol);
+            for _ in 0..(WINDOW_SIZE - 1) {
+                h = append_byte(h, 0, pol);
+            }
+            out[b as usize] = h;
+
+            // the reduction of the 8 bits shifted above the degree, combined with the same bits to
+            // cancel them out in a single xor
+            modulo[b as usize] = polynomial_mod(b << degree, pol) | (b << degree);
+        }
+
+        Tables { out, modulo }
+    }
+}
+
+/// Degree of the polynomial over GF(2).
+fn degree(x: u64) -> u32 {
+    63 - x.leading_zeros()
+}
+
+/// Remainder of the polynomial division over GF(2).
+fn polynomial_mod(mut x: u64, pol: u64) -> u64 {
+    let d = degree(pol);
+    while x != 0 && degree(x) >= d {
+        x ^= pol << (degree(x) - d);
+    }
+    x
+}
+
+fn append_byte(hash: u64, b: u8, pol: u64) -> u64 {
+    polynomial_mod((hash << 8) | b as u64, pol)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn polynomial_arithmetic() {
+        assert_eq!(degree(IPFS_POLYNOMIAL), 53);
+        assert_eq!(polynomial_mod(IPFS_POLYNOMIAL, IPFS_POLYNOMIAL), 0);
+        // x^54 = x * (pol - x^53) mod pol
+        let x54 = polynomial_mod(1 << 54, IPFS_POLYNOMIAL);
+        let expected = polynomial_mod((IPFS_POLYNOMIAL ^ (1 << 53)) << 1, IPFS_POLYNOMIAL);
+        assert_eq!(x54, expected);
+        assert!(x54 < 1 << 53);
+    }
+
+    /// Remainder of the 128-bit polynomial divided by the polynomial of go-ipfs over GF(2).
+    fn wide_mod(mut x: u128) -> u64 {
+        let pol = IPFS_POLYNOMIAL as u128;
+        for shift in (0..=(128 - 54)).rev() {
+            if x & (1 << (shift + 53)) != 0 {
+                x ^= pol << shift;
+            }
+        }
+        x as u64
+    }
+
+    #[test]
+    fn polynomial_is_irreducible() {
+        // Ben-Or: irreducible when x^(2^i) - x shares no factors with the polynomial for all i up
+        // to half of the degree
+        let mul_mod = |a: u64, b: u64| {
+            (0..64)
+                .filter(|i| b & (1 << i) != 0)
+                .fold(0u128, |acc, i| acc ^ ((a as u128) << i))
+        };
+        let gcd = |mut a: u64, mut b: u64| {
+            while b != 0 {
+                let r = polynomial_mod(a, b);
+                a = b;
+                b = r;
+            }
+            a
+        };
+
+        let x = 2;
+        let mut h = x;
+        for _ in 0..degree(IPFS_POLYNOMIAL) / 2 {
+            h = wide_mod(mul_mod(h, h));
+            assert_eq!(gcd(IPFS_POLYNOMIAL, h ^ x), 1);
+        }
+    }
+
+    /// The chunk lengths produced by `Chunker::Next` of `whyrusleeping/chunker`, ported line by
+    /// line including the reading of the input through a 512 KiB buffer.
+    fn reference_chunks(input: &[u8], min: usize, avg: usize, max: usize) -> Vec<usize> {
+        const BUF_SIZE: usize = 512 * 1024;
+
+        let mut rabin = Rabin::new(min, avg, max);
+        let mut reader = input.chunks(BUF_SIZE);
+        let mut buf: &[u8] = &[];
+        let mut bpos = 0;
+        let mut chunks = Vec::new();
+
+        rabin.reset();
+        let mut pre = min - WINDOW_SIZE;
+
+        loop {
+            if bpos >= buf.len() {
+                match reader.next() {
+                    Some(next) => {
+                        buf = next;
+                        bpos = 0;
+                    }
+                    None => {
+                        if rabin.count > 0 {
+                            chunks.push(rabin.count);
+                        }
+                        return chunks;
+                    }
+                }
+            }
+
+            if pre > 0 {
+                let n = buf.len() - bpos;
+                if pre > n {
+                    pre -= n;
+                    rabin.count += n;
+                    bpos = buf.len();
+                    continue;
+                }
+
+                bpos += pre;
+                rabin.count += pre;
+                pre = 0;
+            }
+
+            let mut add = rabin.count;
+            let mut cut = false;
+            for &b in &buf[bpos..] {
+                rabin.slide(b);
+                add += 1;
+                if add < min {
+                    continue;
+                }
+
+                if rabin.digest & rabin.mask == 0 || add >= max {
+                    bpos += add - rabin.count;
+                    chunks.push(add);
+                    rabin.reset();
+                    pre = min - WINDOW_SIZE;
+                    cut = true;
+                    break;
+                }
+            }
+
+            if !cut {
+                rabin.count += buf.len() - bpos;
+                bpos = buf.len();
+            }
+        }
+    }
+
+    /// The chunk lengths following the definition of the chunker instead of its implementation:
+    /// a chunk ends at the first offset of at least `min` bytes where the fingerprint of the
+    /// window ending at the offset has the low bits of the average size cleared, or at `max`
+    /// bytes. The fingerprints are computed from scratch with 128-bit arithmetic for every offset,
+    /// without the tables or the sliding of [`Rabin`].
+    fn defined_chunks(input: &[u8], min: usize, avg: usize, max: usize) -> Vec<usize> {
+        let fingerprint =
+            |window: &[u8]| wide_mod(window.iter().fold(0, |acc, &b| acc << 8 | b as u128));
+
+        // the average rounded down to a power of two
+        let mask = (avg as u64 + 1).next_power_of_two() / 2 - 1;
+        let mut chunks = Vec::new();
+        let mut start = 0;
+
+        while start < input.len() {
+            let len = (min..=max)
+                .find(|&len| {
+                    let end = start + len;
+                    end >= input.len()
+                        || len == max
+                        || fingerprint(&input[end - WINDOW_SIZE..end]) & mask == 0
+                })
+                .unwrap();
+            let len = len.min(input.len() - start);
+            chunks.push(len);
+            start += len;
+        }
+
+        chunks
+    }
+
+    fn pseudo_random(len: usize) -> Vec<u8> {
+        let mut content = vec![0u8; len];
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        for b in content.iter_mut() {
+            // xorshift to have something resembling random data
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            *b = state as u8;
+        }
+        content
+    }
+
+    fn streamed_chunks(input: &[u8], min: usize, avg: usize, max: usize) -> Vec<usize> {
+        let mut rabin = Rabin::new(min, avg, max);
+        let mut chunks = Vec::new();
+        let mut current = 0;
+        let mut slice = input;
+
+        while !slice.is_empty() {
+            let (accepted, ready) = rabin.accept(slice);
+            current += accepted.len();
+            slice = &slice[accepted.len()..];
+            if ready {
+                chunks.push(current);
+                current = 0;
+            }
+        }
+
+        if current > 0 {
+            chunks.push(current);
+        }
+        chunks
+    }
+
+    #[test]
+    fn same_chunks_as_the_reference() {
+        let content = pseudo_random(3 * 1024 * 1024 + 12345);
+
+        // the sizes of `rabin`, `rabin-4096` and the smallest accepted minimum
+        for &(min, avg, max) in &[
+            (87381, 262_144, 393_216),
+            (1365, 4096, 6144),
+            (16, 1024, 2048),
+        ] {
+            let expected = reference_chunks(&content, min, avg, max);
+            assert!(expected.len() > 5, "too few chunks: {:?}", expected);
+            assert_eq!(expected.iter().sum::<usize>(), content.len());
+            assert_eq!(streamed_chunks(&content, min, avg, max), expected);
+        }
+    }
+
+    #[test]
+    fn same_chunks_as_defined() {
+        let content = pseudo_random(256 * 1024 + 123);
+
+        for &(min, avg, max) in &[(16, 1024, 2048), (100, 3000, 5000), (1365, 4096, 6144)] {
+            let expected = defined_chunks(&content, min, avg, max);
+            assert!(expected.len() > 20, "too few chunks: {:?}", expected);
+            assert!(
+                expected[..expected.len() - 1].iter().any(|&len| len < max),
+                "no content defined boundaries: {:?}",
+                expected
+            );
+            assert_eq!(streamed_chunks(&content, min, avg, max), expected);
+        }
+    }
+
+    #[test]
+    fn boundaries_depend_on_content_only() {
+        let mut content = vec![0u8; 256 * 1024];
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        for b in content.iter_mut() {
+            // xorshift to have something resembling random data
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            *b = state as u8;
+        }
+
+        let boundaries = |input: &[u8], step: usize| {
+            let mut rabin = Rabin::new(512, 2048, 8192);
+            let mut chunks = Vec::new();
+            let mut current = 0;
+            let mut offset = 0;
+            for slice in input.chunks(step) {
+                let mut slice = slice;
+                while !slice.is_empty() {
+                    let (accepted, ready) = rabin.accept(slice);
+                    current += accepted.len();
+                    offset += accepted.len();
+                    slice = &slice[accepted.len()..];
+                    if ready {
+                        chunks.push(offset);
+                        assert!((512..=8192).contains(&current), "{}", current);
+                        current = 0;
+                    }
+                }
+            }
+            chunks
+        };
+
+        let whole = boundaries(&content, content.len());
+        assert!(whole.len() > 10, "too few chunks: {}", whole.len());
+        assert_eq!(whole, boundaries(&content, 1000));
+        assert_eq!(whole, boundaries(&content, 1));
+
+        // inserting bytes to the front only changes the first few chunks
+        let mut shifted = vec![0xff; 100];
+        shifted.extend_from_slice(&content);
+        let shifted = boundaries(&shifted, 4096)
+            .into_iter()
+            .map(|offset| offset - 100)
+            .collect::<Vec<_>>();
+
+        let common = whole.iter().filter(|o| shifted.contains(o)).count();
+        assert!(common >= whole.len() - 2, "{} of {}", common, whole.len());
+    }
+}
@@ -4,7 +4,7 @@ use core::ops::Range;
 
 use crate::file::reader::{FileContent, FileReader, Traversal};
 use crate::file::{FileReadFailed, Metadata};
-use crate::pb::{merkledag::PBLink, FlatUnixFs};
+use crate::pb::{merkledag::PBLink, FlatUnixFs, UnixFsType};
 use crate::InvalidCidInLink;
 
 /// IdleFileVisit represents a prepared file visit over a tree. The user has to know the CID and be
@@ -97,6 +97,44 @@ impl IdleFileVisit {
     }
 }
 
+/// The contents of a single block of a file, returned by [`read_block`].
+#[derive(Debug)]
+pub enum FileBlock<'a> {
+    /// The bytes of a leaf.
+    Bytes(&'a [u8]),
+    /// The bytes of a leaf of the `Raw` UnixFS type, which the trickle layout uses instead of the
+    /// `File` type unless the leaves are raw blocks.
+    RawBytes(&'a [u8]),
+    /// The links of a link block with the cumulative sizes of the linked subtrees, and the ranges
+    /// of the file bytes they cover relative to the start of the block.
+    Links(Vec<(Cid, u64, Range<u64>)>),
+}
+
+/// Reads a single block of a file without visiting the linked blocks, returning its contents and
+/// metadata. Unlike with [`IdleFileVisit`], the leaves can be told apart from the link blocks, for
+/// example to link to the unchanged subtrees when modifying a file.
+pub fn read_block(block: &[u8]) -> Result<(FileBlock<'_>, Metadata), FileReadFailed> {
+    let inner = FlatUnixFs::try_from(block)?;
+    let is_raw = inner.data.Type == UnixFsType::Raw;
+    let fr = FileReader::from_parsed(inner)?;
+    let metadata = fr.as_ref().to_owned();
+
+    let content = match fr.content().0 {
+        FileContent::Bytes(bytes) if is_raw => FileBlock::RawBytes(bytes),
+        FileContent::Bytes(bytes) => FileBlock::Bytes(bytes),
+        FileContent::Links(iter) => FileBlock::Links(
+            iter.enumerate()
+                .map(|(i, (link, range))| {
+                    let total_size = link.Tsize.unwrap_or_default();
+                    to_pending(i, link, range).map(|(cid, range)| (cid, total_size, range))
+                })
+                .collect::<Result<_, _>>()?,
+        ),
+    };
+
+    Ok((content, metadata))
+}
+
 /// Optional cache for datastructures which can be re-used without re-allocation between walks of
 /// different files.
 #[derive(Default)]
@@ -35,6 +35,57 @@ pub mod walk;
 #[cfg(test)]
 pub(crate) mod test_support;
 
+/// The Cid version and the multihash used to link to the blocks created by
+/// [`file::adder::FileAdder`] and [`dir::builder::BufferingTreeBuilder`]. Defaults to Cid version
+/// 0 with sha2-256.
+///
+/// Cid version 0 can only be used for dag-pb blocks hashed with sha2-256, so other blocks are
+/// always linked to with Cid version 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CidOptions {
+    version: cid::Version,
+    hash: multihash::Code,
+}
+
+impl Default for CidOptions {
+    fn default() -> Self {
+        CidOptions {
+            version: cid::Version::V0,
+            hash: multihash::Code::Sha2_256,
+        }
+    }
+}
+
+impl CidOptions {
+    /// Creates options using the given Cid version and multihash.
+    pub fn new(version: cid::Version, hash: multihash::Code) -> Self {
+        CidOptions { version, hash }
+    }
+
+    /// Returns the configured Cid version.
+    pub fn version(&self) -> cid::Version {
+        self.version
+    }
+
+    /// Returns the configured multihash.
+    pub fn hash(&self) -> multihash::Code {
+        self.hash
+    }
+
+    /// Hashes the block, returning the Cid to link to it with. Cid version 0 is only used for
+    /// dag-pb blocks, others are linked to with Cid version 1.
+    pub fn cid(&self, codec: cid::Codec, block: &[u8]) -> cid::Cid {
+        let mh = self.hash.digest(block);
+
+        match (self.version, codec, self.hash) {
+            (cid::Version::V0, cid::Codec::DagProtobuf, multihash::Code::Sha2_256) => {
+                cid::Cid::new_v0(mh).expect("sha2_256 is the correct multihash for cidv0")
+            }
+            _ => cid::Cid::new_v1(codec, mh),
+        }
+    }
+}
+
 /// A link could not be transformed into a Cid.
 #[derive(Debug)]
 pub struct InvalidCidInLink {
@@ -157,6 +208,35 @@ impl Metadata {
         self.mtime
     }
 
+    /// Returns the metadata with the given full file mode, see [`Metadata::mode`].
+    pub fn with_mode(self, mode: u32) -> Self {
+        Metadata {
+            mode: Some(mode),
+            ..self
+        }
+    }
+
+    /// Returns the metadata with the given raw timestamp of last modification time, see
+    /// [`Metadata::mtime`].
+    pub fn with_mtime(self, seconds: i64, nanos: u32) -> Self {
+        Metadata {
+            mtime: Some((seconds, nanos)),
+            ..self
+        }
+    }
+
+    /// Writes the metadata into the fields of the UnixFs message.
+    pub(crate) fn write_into(&self, data: &mut UnixFs<'_>) {
+        data.mode = self.mode;
+        data.mtime = self
+            .mtime
+            .map(|(seconds, nanos)| crate::pb::unixfs::UnixTime {
+                Seconds: seconds,
+                // like go-ipfs, the zero nanoseconds are not stored
+                FractionalNanoseconds: if nanos != 0 { Some(nanos) } else { None },
+            });
+    }
+
     /// Returns the mtime metadata as a `FileTime`. Enabled only in the `filetime` feature.
     #[cfg(feature = "filetime")]
     pub fn mtime_as_filetime(&self) -> Option<filetime::FileTime> {
@@ -52,6 +52,7 @@ fn convert_link(
 fn convert_sharded_link(
     nested_depth: usize,
     sibling_depth: usize,
+    prefix_len: usize,
     nth: usize,
     link: PBLink<'_>,
 ) -> Result<(Cid, String, usize), InvalidCidInLink> {
@@ -61,8 +62,10 @@ fn convert_sharded_link(
         Err(e) => return Err(InvalidCidInLink::from((nth, link, e))),
     };
     let (depth, name) = match link.Name {
-        Some(Cow::Borrowed(s)) if s.len() > 2 => (nested_depth, s[2..].to_owned()),
-        Some(Cow::Borrowed(s)) if s.len() == 2 => (sibling_depth, String::from("")),
+        Some(Cow::Borrowed(s)) if s.len() > prefix_len && s.is_char_boundary(prefix_len) => {
+            (nested_depth, s[prefix_len..].to_owned())
+        }
+        Some(Cow::Borrowed(s)) if s.len() == prefix_len => (sibling_depth, String::from("")),
         None | Some(Cow::Borrowed(_)) => todo!("link cannot be empty"),
         Some(Cow::Owned(_s)) => unreachable!("FlatUnixFs is never transformed to owned"),
     };
@@ -186,7 +189,7 @@ impl Walker {
                 })
             }
             UnixFsType::HAMTShard => {
-                let flat = crate::dir::check_hamtshard_supported(flat)?;
+                let (flat, prefix_len) = crate::dir::check_hamtshard_supported(flat)?;
                 let (cid, name, depth) = next.take().expect("validated at start and this method");
 
                 // similar to directory, the depth is +1 for nested entries, but the sibling buckets
@@ -195,7 +198,9 @@ impl Walker {
                     .links
                     .into_iter()
                     .enumerate()
-                    .map(|(nth, link)| convert_sharded_link(depth + 1, depth, nth, link))
+                    .map(|(nth, link)| {
+                        convert_sharded_link(depth + 1, depth, prefix_len, nth, link)
+                    })
                     .rev();
 
                 // TODO: it might be worthwhile to lose the `rev` and sort the pushed links using